@@ -0,0 +1,9 @@
+fn main() -> std::io::Result<()> {
+    // protoc is vendored so that it doesn't need to be installed to build
+    let protoc = protoc_bin_vendored::protoc_bin_path()
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::NotFound, err.to_string()))?;
+    std::env::set_var("PROTOC", protoc);
+    prost_build::Config::new()
+        .type_attribute(".", "#[derive(serde::Serialize)]")
+        .compile_protos(&["proto/alert_event.proto"], &["proto/"])
+}
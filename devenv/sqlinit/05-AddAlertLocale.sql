@@ -0,0 +1,2 @@
+USE `dalamud`;
+ALTER TABLE `users_alerts_next` ADD COLUMN `locale` VARCHAR(16) DEFAULT NULL;
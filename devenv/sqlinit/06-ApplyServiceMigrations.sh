@@ -0,0 +1,7 @@
+# Creates the tables the alerts service owns from its own migrations, so that
+# they're only defined in one place. The service counts these as applied the
+# first time it runs with UNIVERSALIS_ALERTS_RUN_MIGRATIONS=true.
+for migration in /migrations/*.sql; do
+  echo "$0: applying $migration"
+  docker_process_sql < "$migration"
+done
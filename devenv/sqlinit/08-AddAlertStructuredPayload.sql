@@ -0,0 +1,2 @@
+USE `dalamud`;
+ALTER TABLE `users_alerts_next` ADD COLUMN `structured_payload` TINYINT(1) NOT NULL DEFAULT 0;
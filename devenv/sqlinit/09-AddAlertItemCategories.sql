@@ -0,0 +1,4 @@
+USE `dalamud`;
+-- Category-scoped alerts use the wildcard item ID (-1) and set one or both of these
+ALTER TABLE `users_alerts_next` ADD COLUMN `item_ui_category` INT DEFAULT NULL;
+ALTER TABLE `users_alerts_next` ADD COLUMN `item_search_category` INT DEFAULT NULL;
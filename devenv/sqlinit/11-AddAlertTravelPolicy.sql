@@ -0,0 +1,3 @@
+USE `dalamud`;
+-- One of 'ignore', 'annotate', or 'suppress'
+ALTER TABLE `users_alerts_next` ADD COLUMN `travel_policy` VARCHAR(16) DEFAULT NULL;
@@ -0,0 +1,2 @@
+USE `dalamud`;
+ALTER TABLE `users_alerts_next` ADD COLUMN `edit_in_place` BOOLEAN DEFAULT NULL;
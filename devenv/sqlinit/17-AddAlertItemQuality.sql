@@ -0,0 +1,3 @@
+USE `dalamud`;
+-- One of 'hq' or 'nq', or NULL for both
+ALTER TABLE `users_alerts_next` ADD COLUMN `item_quality` VARCHAR(2) DEFAULT NULL;
@@ -0,0 +1,5 @@
+USE `dalamud`;
+-- Override the user's preferences for one alert; 'off' turns quiet hours off
+ALTER TABLE `users_alerts_next` ADD COLUMN `utc_offset_minutes` INT DEFAULT NULL;
+ALTER TABLE `users_alerts_next` ADD COLUMN `quiet_hours` VARCHAR(11) DEFAULT NULL;
+ALTER TABLE `users_alerts_next` ADD COLUMN `mention_style` VARCHAR(16) DEFAULT NULL;
@@ -0,0 +1,13 @@
+CREATE TABLE `users_alerts_outbox` (
+  `id` BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+  `alert_id` CHAR(36) NOT NULL,
+  `discord_webhook` TEXT NOT NULL,
+  `payload` LONGTEXT NOT NULL,
+  `attempts` INT NOT NULL DEFAULT 0,
+  `claimed_by` VARCHAR(64) DEFAULT NULL,
+  `created_at` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  `next_attempt_at` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (`id`),
+  KEY (`next_attempt_at`),
+  KEY (`claimed_by`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
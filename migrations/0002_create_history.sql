@@ -0,0 +1,12 @@
+CREATE TABLE `users_alerts_history` (
+  `id` BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+  `alert_id` CHAR(36) NOT NULL,
+  `user_id` CHAR(36) DEFAULT NULL,
+  `alert_name` TEXT NOT NULL,
+  `item_id` INT NOT NULL,
+  `world_id` INT NOT NULL,
+  `value` FLOAT NOT NULL,
+  `sent_at` BIGINT NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY (`user_id`, `sent_at`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,2 @@
+-- A JSON array with the Discord message ID for each of the alert's webhooks
+ALTER TABLE `users_alerts_history` ADD COLUMN `message_ids` TEXT DEFAULT NULL;
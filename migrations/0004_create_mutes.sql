@@ -0,0 +1,7 @@
+CREATE TABLE `users_alerts_mutes` (
+  `kind` VARCHAR(16) NOT NULL,
+  `target` VARCHAR(255) NOT NULL,
+  `reason` TEXT DEFAULT NULL,
+  `muted_at` BIGINT NOT NULL,
+  PRIMARY KEY (`kind`, `target`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,8 @@
+CREATE TABLE `users_alerts_snapshots` (
+  `notification_id` CHAR(36) NOT NULL,
+  `created_at` BIGINT NOT NULL,
+  -- Gzipped JSON of the listings that matched the alert
+  `snapshot` MEDIUMBLOB NOT NULL,
+  PRIMARY KEY (`notification_id`),
+  KEY (`created_at`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,2 @@
+-- The ID that the notification's snapshot is saved under
+ALTER TABLE `users_alerts_history` ADD COLUMN `notification_id` CHAR(36) DEFAULT NULL;
@@ -0,0 +1,14 @@
+CREATE TABLE `users_alerts_daily_stats` (
+  `id` BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+  `period_start` BIGINT NOT NULL,
+  `period_end` BIGINT NOT NULL,
+  `events` BIGINT UNSIGNED NOT NULL,
+  `matched` BIGINT UNSIGNED NOT NULL,
+  `delivered` BIGINT UNSIGNED NOT NULL,
+  `delivery_failures` BIGINT UNSIGNED NOT NULL,
+  -- JSON of the whole summary, including the failure breakdown and the
+  -- noisiest alerts and items
+  `summary` TEXT NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY (`period_end`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,18 @@
+CREATE TABLE `users_alerts_trigger_stats` (
+  `id` BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+  `computed_at` BIGINT NOT NULL,
+  -- The item's ItemUICategory, -1 for wildcard alerts, or NULL if unknown
+  `item_category` INT DEFAULT NULL,
+  `filters` VARCHAR(64) NOT NULL,
+  `mapper` VARCHAR(32) NOT NULL,
+  `take` BOOLEAN NOT NULL,
+  `reducer` VARCHAR(32) NOT NULL,
+  `comparison` VARCHAR(32) NOT NULL,
+  `target` VARCHAR(32) NOT NULL,
+  `threshold_range` VARCHAR(32) DEFAULT NULL,
+  `scheduled` BOOLEAN NOT NULL,
+  `alerts` BIGINT UNSIGNED NOT NULL,
+  `users` BIGINT UNSIGNED NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY (`item_category`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
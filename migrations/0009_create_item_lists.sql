@@ -0,0 +1,11 @@
+CREATE TABLE `users_alerts_item_lists` (
+  -- Either 'allow' or 'deny'
+  `list` VARCHAR(16) NOT NULL,
+  `item_id` INT NOT NULL,
+  `reason` TEXT DEFAULT NULL,
+  `added_at` BIGINT NOT NULL,
+  -- When the entry stops applying, or NULL if it never does
+  `expires_at` BIGINT DEFAULT NULL,
+  PRIMARY KEY (`list`, `item_id`),
+  KEY (`expires_at`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,11 @@
+CREATE TABLE `users_alert_preferences` (
+  `user_id` CHAR(36) NOT NULL,
+  `locale` VARCHAR(16) DEFAULT NULL,
+  -- The offset of the user's time zone from UTC, in minutes
+  `utc_offset_minutes` INT DEFAULT NULL,
+  -- A daily window like '22:00-07:00' in the user's time zone
+  `quiet_hours` VARCHAR(11) DEFAULT NULL,
+  -- One of 'none', 'user', or 'here'
+  `mention_style` VARCHAR(16) DEFAULT NULL,
+  PRIMARY KEY (`user_id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,3 @@
+-- When the event behind a notification was received, in milliseconds since
+-- the Unix epoch, so that the delivery worker can tell how late it is
+ALTER TABLE `users_alerts_outbox` ADD COLUMN `received_at` BIGINT DEFAULT NULL;
@@ -0,0 +1,748 @@
+use std::convert::Infallible;
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc, OnceLock};
+use std::thread;
+
+use crate::alerts::MAX_TRIGGER_VERSION;
+use crate::connection_history::*;
+use crate::eligibility::*;
+use crate::errors::*;
+use crate::event_stats::*;
+use crate::features::*;
+use crate::history::*;
+use crate::item_lists::*;
+use crate::metrics_export::*;
+use crate::metrics_registry::*;
+use crate::mutes::*;
+use crate::pipeline::*;
+use crate::subscriptions::*;
+use crate::universalis::*;
+use crate::validate::*;
+use crate::xivapi::*;
+use base64::Engine;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use itertools::Itertools;
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tokio::runtime::Handle;
+
+/// Configuration for the admin/health HTTP server.
+pub struct AdminConfig {
+    pub addr: SocketAddr,
+    /// The expected value of the `Authorization` header, if basic auth is enabled.
+    pub authorization: Option<String>,
+    pub tls: Option<native_tls::Identity>,
+}
+
+impl AdminConfig {
+    /// Reads the admin server configuration. The server is only enabled if
+    /// `UNIVERSALIS_ALERTS_ADMIN_ADDR` is set. Basic auth is enabled by
+    /// `UNIVERSALIS_ALERTS_ADMIN_USER` and `UNIVERSALIS_ALERTS_ADMIN_PASSWORD`,
+    /// and TLS by `UNIVERSALIS_ALERTS_ADMIN_TLS_IDENTITY` (a PKCS #12 file)
+    /// and `UNIVERSALIS_ALERTS_ADMIN_TLS_PASSWORD`.
+    pub fn from_env() -> Result<Option<Self>> {
+        let addr = match env::var("UNIVERSALIS_ALERTS_ADMIN_ADDR") {
+            Ok(addr) => addr
+                .parse()
+                .chain_err(|| "failed to parse UNIVERSALIS_ALERTS_ADMIN_ADDR")?,
+            Err(_) => return Ok(None),
+        };
+
+        let authorization = match (
+            env::var("UNIVERSALIS_ALERTS_ADMIN_USER"),
+            env::var("UNIVERSALIS_ALERTS_ADMIN_PASSWORD"),
+        ) {
+            (Ok(user), Ok(password)) => Some(format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password))
+            )),
+            _ => None,
+        };
+
+        let tls = match env::var("UNIVERSALIS_ALERTS_ADMIN_TLS_IDENTITY") {
+            Ok(path) => {
+                let identity = fs::read(&path).chain_err(|| "failed to read TLS identity")?;
+                let password =
+                    env::var("UNIVERSALIS_ALERTS_ADMIN_TLS_PASSWORD").unwrap_or_default();
+                Some(
+                    native_tls::Identity::from_pkcs12(&identity, &password)
+                        .chain_err(|| "failed to parse TLS identity")?,
+                )
+            }
+            Err(_) => None,
+        };
+
+        Ok(Some(Self {
+            addr,
+            authorization,
+            tls,
+        }))
+    }
+}
+
+/// The event pipeline, as seen from the admin server.
+pub struct PipelineHandle {
+    pub ctx: Arc<Context>,
+    /// The runtime the pipeline's connections and pools belong to.
+    pub runtime: Handle,
+}
+
+/// State shared with admin request handlers. The pipeline is attached once
+/// it has been set up, since the admin server starts before it.
+#[derive(Default)]
+pub struct AdminState {
+    pipeline: OnceLock<PipelineHandle>,
+}
+
+impl AdminState {
+    pub fn attach(&self, ctx: Arc<Context>, runtime: Handle) {
+        if self.pipeline.set(PipelineHandle { ctx, runtime }).is_err() {
+            warn!("admin pipeline handle was already attached");
+        }
+    }
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    match serde_json::to_vec(body) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap(),
+        Err(err) => {
+            error!("failed to serialize admin response: {:?}", err);
+            text_response(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+        }
+    }
+}
+
+fn has_query_flag(req: &Request<Body>, flag: &str) -> bool {
+    req.uri()
+        .query()
+        .map(|q| {
+            q.split('&')
+                .any(|p| p == flag || p == format!("{}=true", flag))
+        })
+        .unwrap_or(false)
+}
+
+/// How far back notification exports go if no start time is given.
+const DEFAULT_EXPORT_SECS: i64 = 30 * 24 * 60 * 60;
+
+fn query_param<'a>(req: &'a Request<Body>, name: &str) -> Option<&'a str> {
+    req.uri().query()?.split('&').find_map(|p| {
+        p.split_once('=')
+            .filter(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    })
+}
+
+#[derive(Serialize)]
+struct ConnectionReport {
+    regions: Vec<RegionConnection>,
+    recent: Vec<ConnectionEvent>,
+    subscriptions: Vec<SubscriptionActivity>,
+}
+
+#[derive(Serialize)]
+struct ValidationReport {
+    valid: bool,
+    /// Why the trigger is invalid, if it is.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct EvaluationReport {
+    world_id: i32,
+    item_id: i32,
+    dry_run: bool,
+    listings: usize,
+    quarantined: Option<&'static str>,
+    alerts: Vec<AlertOutcome>,
+}
+
+/// Fetches the current listings for an item and runs them through the
+/// pipeline as if they had arrived from the websocket.
+async fn evaluate_on_demand(
+    world_id: i32,
+    item_id: i32,
+    dry_run: bool,
+    pipeline: &PipelineHandle,
+) -> Result<EvaluationReport> {
+    let ctx = pipeline.ctx.clone();
+    pipeline
+        .runtime
+        .spawn(async move {
+            let listings = get_current_listings(world_id, item_id, &ctx.universalis_client).await?;
+            let ev = ListingsAddEvent {
+                item_id,
+                world_id,
+                listings,
+                channel: MarketChannel::ListingsAdd,
+                received_at: None,
+            };
+            let quarantined = ctx.quarantine.check(&ev);
+            let alerts = match quarantined {
+                Some(_) => Vec::new(),
+                None => evaluate_event("admin", &ev, dry_run, &ctx).await?,
+            };
+            Ok(EvaluationReport {
+                world_id,
+                item_id,
+                dry_run,
+                listings: ev.listings.len(),
+                quarantined,
+                alerts,
+            })
+        })
+        .await
+        .chain_err(|| "evaluation task failed")?
+}
+
+/// Reports the items with the most events, named in a single XIVAPI request.
+/// Items are left unnamed if XIVAPI can't be reached.
+async fn event_stats_report(limit: usize, pipeline: &PipelineHandle) -> Result<EventStatsReport> {
+    let ctx = pipeline.ctx.clone();
+    pipeline
+        .runtime
+        .spawn(async move {
+            let mut report = ctx.event_stats.report(limit);
+            let ids = report.items.iter().map(|rate| rate.item_id).collect_vec();
+            match get_items(&ids).await {
+                Ok(mut names) => {
+                    for rate in &mut report.items {
+                        rate.item_name = names
+                            .get_mut(&rate.item_id)
+                            .and_then(Option::take)
+                            .map(|item| item.name);
+                    }
+                }
+                Err(err) => warn!("failed to look up item names: {:?}", err),
+            }
+            report
+        })
+        .await
+        .chain_err(|| "event stats task failed")
+}
+
+/// Works out what's stopping an alert from firing, if anything.
+async fn eligibility_report(
+    alert_id: String,
+    pipeline: &PipelineHandle,
+) -> Result<Option<Eligibility>> {
+    let ctx = pipeline.ctx.clone();
+    pipeline
+        .runtime
+        .spawn(async move { alert_eligibility(&alert_id, &ctx).await })
+        .await
+        .chain_err(|| "eligibility task failed")?
+}
+
+fn parse_mute_kind(kind: &str) -> Option<MuteKind> {
+    match kind {
+        "users" => Some(MuteKind::User),
+        "webhooks" => Some(MuteKind::Webhook),
+        _ => None,
+    }
+}
+
+/// Mutes a user or webhook straight away, saving the mute so that it
+/// survives restarts (unless there's no database, in standalone mode).
+async fn apply_mute(mute: Mute, pipeline: &PipelineHandle) -> Result<()> {
+    let ctx = pipeline.ctx.clone();
+    pipeline
+        .runtime
+        .spawn(async move {
+            if !ctx.standalone {
+                save_mute(&mute, &ctx.pool).await?;
+            }
+            ctx.mutes.mute(mute);
+            Ok(())
+        })
+        .await
+        .chain_err(|| "mute task failed")?
+}
+
+/// Lifts a mute, returning whether there was one.
+async fn lift_mute(kind: MuteKind, target: String, pipeline: &PipelineHandle) -> Result<bool> {
+    let ctx = pipeline.ctx.clone();
+    pipeline
+        .runtime
+        .spawn(async move {
+            if !ctx.standalone {
+                delete_mute(kind, &target, &ctx.pool).await?;
+            }
+            Ok(ctx.mutes.unmute(kind, &target))
+        })
+        .await
+        .chain_err(|| "unmute task failed")?
+}
+
+/// Puts an item on a list straight away, saving the entry so that it
+/// survives restarts (unless there's no database, in standalone mode).
+async fn apply_item_list_entry(entry: ItemListEntry, pipeline: &PipelineHandle) -> Result<()> {
+    let ctx = pipeline.ctx.clone();
+    pipeline
+        .runtime
+        .spawn(async move {
+            if !ctx.standalone {
+                save_item_list_entry(&entry, &ctx.pool).await?;
+            }
+            ctx.item_lists.add(entry);
+            Ok(())
+        })
+        .await
+        .chain_err(|| "item list task failed")?
+}
+
+/// Takes an item off a list, returning whether it was on it.
+async fn lift_item_list_entry(
+    list: ItemListKind,
+    item_id: i32,
+    pipeline: &PipelineHandle,
+) -> Result<bool> {
+    let ctx = pipeline.ctx.clone();
+    pipeline
+        .runtime
+        .spawn(async move {
+            if !ctx.standalone {
+                delete_item_list_entry(list, item_id, &ctx.pool).await?;
+            }
+            Ok(ctx.item_lists.remove(list, item_id))
+        })
+        .await
+        .chain_err(|| "item list task failed")?
+}
+
+fn text_response(status: StatusCode, body: &'static str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+async fn route(
+    req: Request<Body>,
+    authorization: Arc<Option<String>>,
+    state: Arc<AdminState>,
+) -> Response<Body> {
+    // Health checks are always unauthenticated so that orchestrators can probe them
+    if req.method() == Method::GET && req.uri().path() == "/healthz" {
+        return text_response(StatusCode::OK, "ok");
+    }
+    // The metrics documentation is as public as the metrics themselves
+    if req.method() == Method::GET && req.uri().path() == "/metrics/docs" {
+        return json_response(StatusCode::OK, &METRICS.as_slice());
+    }
+    // Readiness fails until the pipeline is up, and while the database is down
+    if req.method() == Method::GET && req.uri().path() == "/readyz" {
+        return match state.pipeline.get() {
+            Some(pipeline) if pipeline.ctx.retries.is_database_down() => {
+                text_response(StatusCode::SERVICE_UNAVAILABLE, "database unavailable")
+            }
+            Some(_) => text_response(StatusCode::OK, "ok"),
+            None => text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+        };
+    }
+
+    if let Some(expected) = authorization.as_ref() {
+        let authorized = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .map(|v| v.as_bytes() == expected.as_bytes())
+            .unwrap_or(false);
+        if !authorized {
+            let mut res = text_response(StatusCode::UNAUTHORIZED, "unauthorized");
+            res.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                header::HeaderValue::from_static("Basic realm=\"universalis-alerts\""),
+            );
+            return res;
+        }
+    }
+
+    let segments = req
+        .uri()
+        .path()
+        .trim_matches('/')
+        .split('/')
+        .collect::<Vec<_>>();
+    match (req.method(), segments.as_slice()) {
+        (&Method::POST, ["admin", "evaluate", world_id, item_id]) => {
+            let (world_id, item_id) = match (world_id.parse(), item_id.parse()) {
+                (Ok(world_id), Ok(item_id)) => (world_id, item_id),
+                _ => return text_response(StatusCode::BAD_REQUEST, "invalid world or item ID"),
+            };
+            let pipeline = match state.pipeline.get() {
+                Some(pipeline) => pipeline,
+                None => return text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+            };
+            let dry_run = has_query_flag(&req, "dryRun");
+            match evaluate_on_demand(world_id, item_id, dry_run, pipeline).await {
+                Ok(report) => json_response(StatusCode::OK, &report),
+                Err(err) => {
+                    error!("on-demand evaluation failed: {:?}", err);
+                    text_response(StatusCode::BAD_GATEWAY, "evaluation failed")
+                }
+            }
+        }
+        (&Method::GET, ["admin", "connections"]) => match state.pipeline.get() {
+            Some(pipeline) => json_response(
+                StatusCode::OK,
+                &ConnectionReport {
+                    regions: pipeline.ctx.connections.regions(),
+                    recent: pipeline.ctx.connections.recent_events(),
+                    subscriptions: pipeline.ctx.subscriptions.activity(),
+                },
+            ),
+            None => text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+        },
+        (&Method::GET, ["admin", "cache"]) => match state.pipeline.get() {
+            Some(pipeline) => json_response(StatusCode::OK, &pipeline.ctx.alert_cache.report()),
+            None => text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+        },
+        (&Method::GET, ["admin", "stats", "events"]) => match state.pipeline.get() {
+            Some(pipeline) => {
+                let limit = query_param(&req, "limit")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(100);
+                match event_stats_report(limit, pipeline).await {
+                    Ok(report) => json_response(StatusCode::OK, &report),
+                    Err(err) => {
+                        error!("failed to report event stats: {:?}", err);
+                        text_response(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+                    }
+                }
+            }
+            None => text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+        },
+        (&Method::GET, ["admin", "stats", "events", world_id, item_id]) => {
+            let (world_id, item_id) = match (world_id.parse(), item_id.parse()) {
+                (Ok(world_id), Ok(item_id)) => (world_id, item_id),
+                _ => return text_response(StatusCode::BAD_REQUEST, "invalid world or item ID"),
+            };
+            match state.pipeline.get() {
+                Some(pipeline) => json_response(
+                    StatusCode::OK,
+                    &pipeline.ctx.event_stats.item(world_id, item_id),
+                ),
+                None => text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+            }
+        }
+        (&Method::GET, ["admin", "alerts", alert_id, "eligibility"]) => {
+            let pipeline = match state.pipeline.get() {
+                Some(pipeline) => pipeline,
+                None => return text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+            };
+            match eligibility_report(alert_id.to_string(), pipeline).await {
+                Ok(Some(report)) => json_response(StatusCode::OK, &report),
+                Ok(None) => text_response(StatusCode::NOT_FOUND, "no such alert"),
+                Err(err) => {
+                    error!("failed to check alert eligibility: {:?}", err);
+                    text_response(StatusCode::BAD_GATEWAY, "failed to check eligibility")
+                }
+            }
+        }
+        (&Method::GET, ["admin", "alerts", "rejected"]) => match state.pipeline.get() {
+            Some(pipeline) => json_response(StatusCode::OK, &pipeline.ctx.rejected_alerts.list()),
+            None => text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+        },
+        (&Method::GET, ["admin", "mutes"]) => match state.pipeline.get() {
+            Some(pipeline) => json_response(StatusCode::OK, &pipeline.ctx.mutes.list()),
+            None => text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+        },
+        (&Method::PUT, ["admin", "mutes", kind, target]) => {
+            let kind = match parse_mute_kind(kind) {
+                Some(kind) => kind,
+                None => return text_response(StatusCode::NOT_FOUND, "not found"),
+            };
+            let pipeline = match state.pipeline.get() {
+                Some(pipeline) => pipeline,
+                None => return text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+            };
+            let target = webhook_key(target).to_owned();
+            // The body, if any, is the reason for the mute
+            let body = match hyper::body::to_bytes(req.into_body()).await {
+                Ok(body) => body,
+                Err(_) => return text_response(StatusCode::BAD_REQUEST, "failed to read body"),
+            };
+            let reason = String::from_utf8_lossy(&body).trim().to_owned();
+            let mute = Mute {
+                kind,
+                target,
+                reason: Some(reason).filter(|r| !r.is_empty()),
+                muted_at: unix_now(),
+            };
+            match apply_mute(mute, pipeline).await {
+                Ok(()) => text_response(StatusCode::OK, "muted"),
+                Err(err) => {
+                    error!("failed to save mute: {:?}", err);
+                    text_response(StatusCode::BAD_GATEWAY, "failed to save mute")
+                }
+            }
+        }
+        (&Method::DELETE, ["admin", "mutes", kind, target]) => {
+            let kind = match parse_mute_kind(kind) {
+                Some(kind) => kind,
+                None => return text_response(StatusCode::NOT_FOUND, "not found"),
+            };
+            let pipeline = match state.pipeline.get() {
+                Some(pipeline) => pipeline,
+                None => return text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+            };
+            match lift_mute(kind, webhook_key(target).to_owned(), pipeline).await {
+                Ok(true) => text_response(StatusCode::OK, "unmuted"),
+                Ok(false) => text_response(StatusCode::NOT_FOUND, "not muted"),
+                Err(err) => {
+                    error!("failed to delete mute: {:?}", err);
+                    text_response(StatusCode::BAD_GATEWAY, "failed to delete mute")
+                }
+            }
+        }
+        (&Method::GET, ["admin", "items"]) => match state.pipeline.get() {
+            Some(pipeline) => json_response(StatusCode::OK, &pipeline.ctx.item_lists.list()),
+            None => text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+        },
+        (&Method::PUT, ["admin", "items", list, item_id]) => {
+            let (list, item_id) = match (ItemListKind::parse(list), item_id.parse()) {
+                (Some(list), Ok(item_id)) => (list, item_id),
+                _ => return text_response(StatusCode::NOT_FOUND, "not found"),
+            };
+            let pipeline = match state.pipeline.get() {
+                Some(pipeline) => pipeline,
+                None => return text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+            };
+            let expires_in = match query_param(&req, "expiresIn").map(str::parse::<i64>) {
+                Some(Ok(secs)) if secs > 0 => Some(secs),
+                None => None,
+                Some(_) => return text_response(StatusCode::BAD_REQUEST, "invalid expiresIn"),
+            };
+            // The body, if any, is the reason for the entry
+            let body = match hyper::body::to_bytes(req.into_body()).await {
+                Ok(body) => body,
+                Err(_) => return text_response(StatusCode::BAD_REQUEST, "failed to read body"),
+            };
+            let reason = String::from_utf8_lossy(&body).trim().to_owned();
+            let now = unix_now();
+            let entry = ItemListEntry {
+                list,
+                item_id,
+                reason: Some(reason).filter(|r| !r.is_empty()),
+                added_at: now,
+                expires_at: expires_in.map(|secs| now + secs),
+            };
+            match apply_item_list_entry(entry, pipeline).await {
+                Ok(()) => text_response(StatusCode::OK, "added"),
+                Err(err) => {
+                    error!("failed to save item list entry: {:?}", err);
+                    text_response(StatusCode::BAD_GATEWAY, "failed to save item list entry")
+                }
+            }
+        }
+        (&Method::DELETE, ["admin", "items", list, item_id]) => {
+            let (list, item_id) = match (ItemListKind::parse(list), item_id.parse()) {
+                (Some(list), Ok(item_id)) => (list, item_id),
+                _ => return text_response(StatusCode::NOT_FOUND, "not found"),
+            };
+            let pipeline = match state.pipeline.get() {
+                Some(pipeline) => pipeline,
+                None => return text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+            };
+            match lift_item_list_entry(list, item_id, pipeline).await {
+                Ok(true) => text_response(StatusCode::OK, "removed"),
+                Ok(false) => text_response(StatusCode::NOT_FOUND, "not listed"),
+                Err(err) => {
+                    error!("failed to delete item list entry: {:?}", err);
+                    text_response(StatusCode::BAD_GATEWAY, "failed to delete item list entry")
+                }
+            }
+        }
+        (&Method::GET, ["admin", "slo"]) => match state.pipeline.get() {
+            Some(pipeline) => json_response(StatusCode::OK, &pipeline.ctx.delivery_slo.windows()),
+            None => text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+        },
+        (&Method::GET, ["admin", "destinations"]) => match state.pipeline.get() {
+            Some(pipeline) => json_response(StatusCode::OK, &pipeline.ctx.scoreboard.report()),
+            None => text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+        },
+        (&Method::GET, ["admin", "users", user_id, "notifications"]) => {
+            let pipeline = match state.pipeline.get() {
+                Some(pipeline) => pipeline,
+                None => return text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+            };
+            let to = query_param(&req, "to")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_else(unix_now);
+            let from = query_param(&req, "from")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(to - DEFAULT_EXPORT_SECS);
+            let records =
+                match get_notification_history(user_id, from, to, &pipeline.ctx.pool).await {
+                    Ok(records) => records,
+                    Err(err) => {
+                        error!("failed to export notification history: {:?}", err);
+                        return text_response(StatusCode::BAD_GATEWAY, "export failed");
+                    }
+                };
+            match query_param(&req, "format") {
+                Some("csv") => Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "text/csv")
+                    .body(Body::from(to_csv(&records)))
+                    .unwrap(),
+                _ => json_response(StatusCode::OK, &records),
+            }
+        }
+        (&Method::GET, ["admin", "notifications", notification_id, "snapshot"]) => {
+            let pipeline = match state.pipeline.get() {
+                Some(pipeline) => pipeline,
+                None => return text_response(StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+            };
+            match pipeline.ctx.snapshots.load(notification_id).await {
+                Ok(Some(snapshot)) => json_response(StatusCode::OK, &snapshot),
+                Ok(None) => text_response(StatusCode::NOT_FOUND, "no snapshot"),
+                Err(err) => {
+                    error!("failed to load event snapshot: {:?}", err);
+                    text_response(StatusCode::BAD_GATEWAY, "failed to load snapshot")
+                }
+            }
+        }
+        (&Method::POST, ["admin", "validate"]) => {
+            let trigger_version = query_param(&req, "version")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(MAX_TRIGGER_VERSION);
+            let body = match hyper::body::to_bytes(req.into_body()).await {
+                Ok(body) => body,
+                Err(_) => return text_response(StatusCode::BAD_REQUEST, "failed to read body"),
+            };
+            let trigger = String::from_utf8_lossy(&body);
+            let checked = parse_trigger(&trigger, trigger_version).and_then(|parsed| {
+                features()
+                    .check_trigger(&parsed, trigger_version)
+                    .map_err(|disabled| disabled.to_string())
+            });
+            match checked {
+                Ok(()) => json_response(
+                    StatusCode::OK,
+                    &ValidationReport {
+                        valid: true,
+                        error: None,
+                    },
+                ),
+                Err(error) => json_response(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    &ValidationReport {
+                        valid: false,
+                        error: Some(error),
+                    },
+                ),
+            }
+        }
+        _ => text_response(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+async fn serve_admin(config: AdminConfig, state: Arc<AdminState>) -> Result<()> {
+    let authorization = Arc::new(config.authorization);
+    let listener = TcpListener::bind(config.addr).await?;
+    let tls = match config.tls {
+        Some(identity) => Some(tokio_native_tls::TlsAcceptor::from(
+            native_tls::TlsAcceptor::new(identity).chain_err(|| "failed to build TLS acceptor")?,
+        )),
+        None => None,
+    };
+    info!("Admin server listening on {}", config.addr);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let authorization = authorization.clone();
+        let state = state.clone();
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req| {
+                let authorization = authorization.clone();
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(route(req, authorization, state).await) }
+            });
+            let served = match tls {
+                Some(tls) => match tls.accept(stream).await {
+                    Ok(stream) => Http::new().serve_connection(stream, service).await,
+                    Err(err) => {
+                        warn!("admin TLS handshake failed: {:?}", err);
+                        return;
+                    }
+                },
+                None => Http::new().serve_connection(stream, service).await,
+            };
+            if let Err(err) = served {
+                warn!("admin connection error: {:?}", err);
+            }
+        });
+    }
+}
+
+/// Starts the metrics exporter and the admin server on a dedicated thread
+/// with its own runtime, so that scrapes and health checks can't be starved
+/// by event processing. Returns once the metrics recorder is installed, or
+/// with the error if it couldn't be, in which case the admin server is still
+/// started.
+pub fn spawn_observability_thread(
+    metrics: MetricsExporter,
+    admin: Option<AdminConfig>,
+    state: Arc<AdminState>,
+) -> Result<()> {
+    let (installed_tx, installed_rx) = mpsc::channel::<Result<()>>();
+    thread::Builder::new()
+        .name("observability".to_owned())
+        .spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(err) => {
+                    let _ = installed_tx.send(Err(err.into()));
+                    return;
+                }
+            };
+
+            runtime.block_on(async move {
+                let exporter = match metrics.install() {
+                    Ok(exporter) => {
+                        let _ = installed_tx.send(Ok(()));
+                        exporter
+                    }
+                    Err(err) => {
+                        let _ = installed_tx.send(Err(err));
+                        None
+                    }
+                };
+
+                let admin = async move {
+                    if let Some(config) = admin {
+                        if let Err(err) = serve_admin(config, state).await {
+                            error!("admin server failed: {:?}", err);
+                        }
+                    }
+                };
+                let exporter = async move {
+                    if let Some(exporter) = exporter {
+                        if let Err(err) = exporter.await {
+                            error!("metrics exporter failed: {:?}", err);
+                        }
+                    }
+                };
+                tokio::join!(exporter, admin);
+            });
+        })?;
+
+    installed_rx
+        .recv()
+        .chain_err(|| "observability thread exited early")?
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::alerts::*;
+use crate::config::env_or;
+use crate::errors::*;
+use crate::metrics_registry::*;
+use crate::trigger::*;
+use futures_util::future::BoxFuture;
+use itertools::Itertools;
+use metrics::{counter, gauge};
+use serde::Serialize;
+
+/// How many of the most-hit keys are reported.
+const HOTTEST_KEYS: usize = 10;
+
+/// A (world, item) pair.
+type AlertKey = (i32, i32);
+
+struct CacheEntry {
+    loaded_at: Instant,
+    alerts: Vec<(UserAlert, AlertTrigger)>,
+    /// A rough estimate of the memory the alerts use.
+    approx_bytes: usize,
+    hits: u64,
+}
+
+fn approx_bytes(alerts: &[(UserAlert, AlertTrigger)]) -> usize {
+    alerts
+        .iter()
+        .map(|(alert, _)| {
+            size_of::<(UserAlert, AlertTrigger)>()
+                + alert.id.len()
+                + alert.user_id.as_ref().map_or(0, String::len)
+                + alert.name.len()
+                + alert.discord_webhook.as_ref().map_or(0, String::len)
+                + alert.trigger.len()
+        })
+        .sum()
+}
+
+/// How often a cached key was used.
+#[derive(Serialize, Debug, Clone)]
+pub struct KeyHotness {
+    pub world_id: i32,
+    pub item_id: i32,
+    pub hits: u64,
+    pub alerts: usize,
+}
+
+/// A summary of how well the alert cache is working.
+#[derive(Serialize, Debug, Clone)]
+pub struct AlertCacheReport {
+    pub enabled: bool,
+    pub ttl_secs: u64,
+    pub capacity: usize,
+    pub entries: usize,
+    pub approx_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Lookups that found an entry, but one that had expired.
+    pub stale: u64,
+    /// Triggers that were served already parsed instead of being parsed again.
+    pub parses_saved: u64,
+    pub hottest: Vec<KeyHotness>,
+}
+
+/// Caches the alerts loaded for each (world, item) pair for a short time, so
+/// that busy items don't query the database for every event. Triggers are
+/// kept parsed, so hits don't parse them again either. Changes to alerts take
+/// up to the TTL to be picked up.
+pub struct AlertCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<AlertKey, CacheEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    stale: AtomicU64,
+    parses_saved: AtomicU64,
+}
+
+impl AlertCache {
+    /// Reads how long alerts are cached for from
+    /// `UNIVERSALIS_ALERTS_ALERT_CACHE_SECS` (0 by default, which disables
+    /// the cache), and how many keys are kept from
+    /// `UNIVERSALIS_ALERTS_ALERT_CACHE_SIZE` (10000 by default).
+    pub fn from_env() -> Self {
+        Self {
+            ttl: Duration::from_secs(env_or("UNIVERSALIS_ALERTS_ALERT_CACHE_SECS", 0)),
+            capacity: env_or("UNIVERSALIS_ALERTS_ALERT_CACHE_SIZE", 10000),
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            stale: AtomicU64::new(0),
+            parses_saved: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero() && self.capacity > 0
+    }
+
+    fn get(&self, key: AlertKey) -> Option<Vec<(UserAlert, AlertTrigger)>> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(&key) {
+            Some(entry) if entry.loaded_at.elapsed() < self.ttl => {
+                entry.hits += 1;
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                counter!(ALERT_CACHE_REQUESTS.name, 1, "outcome" => "hit");
+                let parses_saved = entry.alerts.len() as u64;
+                self.parses_saved.fetch_add(parses_saved, Ordering::Relaxed);
+                counter!(ALERT_CACHE_PARSES_SAVED.name, parses_saved);
+                Some(entry.alerts.clone())
+            }
+            Some(_) => {
+                self.stale.fetch_add(1, Ordering::Relaxed);
+                counter!(ALERT_CACHE_REQUESTS.name, 1, "outcome" => "stale");
+                None
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                counter!(ALERT_CACHE_REQUESTS.name, 1, "outcome" => "miss");
+                None
+            }
+        }
+    }
+
+    fn insert(&self, key: AlertKey, alerts: Vec<(UserAlert, AlertTrigger)>) {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(&key) && entries.len() >= self.capacity {
+            // Make room by dropping the entry that was loaded longest ago
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.loaded_at)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+                counter!(ALERT_CACHE_EVICTIONS.name, 1);
+            }
+        }
+        entries.insert(
+            key,
+            CacheEntry {
+                loaded_at: Instant::now(),
+                approx_bytes: approx_bytes(&alerts),
+                alerts,
+                hits: 0,
+            },
+        );
+    }
+
+    /// Summarizes the cache, including its most-hit keys.
+    pub fn report(&self) -> AlertCacheReport {
+        let entries = self.entries.lock().unwrap();
+        let hottest = entries
+            .iter()
+            .filter(|(_, entry)| entry.hits > 0)
+            .sorted_by_key(|(_, entry)| std::cmp::Reverse(entry.hits))
+            .take(HOTTEST_KEYS)
+            .map(|((world_id, item_id), entry)| KeyHotness {
+                world_id: *world_id,
+                item_id: *item_id,
+                hits: entry.hits,
+                alerts: entry.alerts.len(),
+            })
+            .collect();
+        AlertCacheReport {
+            enabled: self.is_enabled(),
+            ttl_secs: self.ttl.as_secs(),
+            capacity: self.capacity,
+            entries: entries.len(),
+            approx_bytes: entries.values().map(|entry| entry.approx_bytes).sum(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            stale: self.stale.load(Ordering::Relaxed),
+            parses_saved: self.parses_saved.load(Ordering::Relaxed),
+            hottest,
+        }
+    }
+
+    /// Exports the cache's size as gauges and logs its hottest keys.
+    pub fn report_metrics(&self) {
+        let report = self.report();
+        gauge!(ALERT_CACHE_ENTRIES.name, report.entries as f64);
+        gauge!(ALERT_CACHE_APPROX_BYTES.name, report.approx_bytes as f64);
+        if !report.hottest.is_empty() {
+            info!(
+                "hottest alert cache keys (world/item: hits): {}",
+                report
+                    .hottest
+                    .iter()
+                    .map(|k| format!("{}/{}: {}", k.world_id, k.item_id, k.hits))
+                    .join(", ")
+            );
+        }
+    }
+}
+
+/// Serves alerts from an [`AlertCache`], loading them from another
+/// repository when they aren't cached.
+pub struct CachedAlerts {
+    inner: Box<dyn AlertRepository>,
+    cache: Arc<AlertCache>,
+}
+
+impl CachedAlerts {
+    pub fn new(inner: Box<dyn AlertRepository>, cache: Arc<AlertCache>) -> Self {
+        Self { inner, cache }
+    }
+}
+
+impl AlertRepository for CachedAlerts {
+    fn alerts_for_world_item(
+        &self,
+        world_id: i32,
+        item_id: i32,
+        limits: AlertLimits,
+    ) -> BoxFuture<'_, Result<Vec<(UserAlert, AlertTrigger)>>> {
+        Box::pin(async move {
+            let key = (world_id, item_id);
+            if let Some(alerts) = self.cache.get(key) {
+                return Ok(alerts);
+            }
+            let alerts = self
+                .inner
+                .alerts_for_world_item(world_id, item_id, limits)
+                .await?;
+            self.cache.insert(key, alerts.clone());
+            Ok(alerts)
+        })
+    }
+
+    fn alert(&self, alert_id: &str) -> BoxFuture<'_, Result<Option<StoredAlert>>> {
+        self.inner.alert(alert_id)
+    }
+}
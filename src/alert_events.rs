@@ -0,0 +1,138 @@
+//! Triggered-alert events, as they're published for other services to
+//! consume. Events are encoded as JSON or as protobuf, with the schema in
+//! `proto/alert_event.proto`, so that consumers in other languages can
+//! generate types for them instead of parsing notifications.
+
+use std::env;
+
+use crate::alerts::UserAlert;
+use crate::errors::*;
+use crate::universalis::MarketChannel;
+use prost::Message;
+
+/// The types generated from `proto/alert_event.proto`.
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/universalis.alerts.v1.rs"));
+}
+
+pub use proto::TriggeredAlert;
+
+impl TriggeredAlert {
+    pub fn new(
+        alert: &UserAlert,
+        item_id: i32,
+        world_id: i32,
+        region: &str,
+        channel: MarketChannel,
+        value: f32,
+        received_at: i64,
+    ) -> Self {
+        Self {
+            alert_id: alert.id.clone(),
+            user_id: alert.user_id.clone(),
+            item_id,
+            world_id,
+            region: region.to_owned(),
+            channel: channel.name().to_owned(),
+            value,
+            received_at,
+        }
+    }
+}
+
+/// How published events are encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EventEncoding {
+    /// A JSON object with the same field names as the protobuf schema.
+    #[default]
+    Json,
+    /// The `TriggeredAlert` protobuf message.
+    Protobuf,
+}
+
+impl EventEncoding {
+    /// Reads the encoding from `UNIVERSALIS_ALERTS_EVENT_ENCODING`: `json`
+    /// (the default) or `protobuf`.
+    pub fn from_env() -> Result<Self> {
+        match env::var("UNIVERSALIS_ALERTS_EVENT_ENCODING").as_deref() {
+            Err(_) | Ok("") | Ok("json") => Ok(Self::Json),
+            Ok("protobuf") => Ok(Self::Protobuf),
+            Ok(other) => Err(format!(
+                "unknown event encoding '{}'; expected json or protobuf",
+                other
+            )
+            .into()),
+        }
+    }
+
+    /// Returns the content type that events in this encoding are published
+    /// with.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Protobuf => "application/x-protobuf",
+        }
+    }
+
+    pub fn encode(&self, event: &TriggeredAlert) -> Result<Vec<u8>> {
+        match self {
+            Self::Json => Ok(serde_json::to_vec(event)?),
+            Self::Protobuf => Ok(event.encode_to_vec()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event() -> TriggeredAlert {
+        TriggeredAlert {
+            alert_id: "alert".to_owned(),
+            user_id: None,
+            item_id: 5,
+            world_id: 74,
+            region: "North-America".to_owned(),
+            channel: MarketChannel::SalesAdd.name().to_owned(),
+            value: 105.0,
+            received_at: 1_700_000_000_000,
+        }
+    }
+
+    #[test]
+    fn both_encodings_carry_the_same_fields() {
+        let event = event();
+        let decoded =
+            TriggeredAlert::decode(EventEncoding::Protobuf.encode(&event).unwrap().as_slice())
+                .unwrap();
+        assert_eq!(decoded, event);
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&EventEncoding::Json.encode(&event).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "alert_id": "alert",
+                "user_id": null,
+                "item_id": 5,
+                "world_id": 74,
+                "region": "North-America",
+                "channel": "sales/add",
+                "value": 105.0,
+                "received_at": 1_700_000_000_000i64,
+            })
+        );
+    }
+
+    #[test]
+    fn protobuf_fields_keep_their_numbers() {
+        // Field 1 (alert_id) as a length-delimited string, then field 3
+        // (item_id) as a varint; unset fields aren't written
+        let event = TriggeredAlert {
+            alert_id: "a".to_owned(),
+            item_id: 5,
+            ..Default::default()
+        };
+        assert_eq!(event.encode_to_vec(), vec![0x0a, 0x01, b'a', 0x18, 0x05]);
+    }
+}
@@ -0,0 +1,829 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "chaos")]
+use crate::chaos::*;
+use crate::config::env_or;
+use crate::daily_summary::*;
+use crate::errors::*;
+use crate::features::*;
+use crate::metrics_registry::*;
+use crate::preferences::*;
+use crate::quarantine::RejectedAlerts;
+use crate::timeouts::*;
+use crate::trigger::*;
+use crate::universalis::Listing;
+use crate::validate::*;
+use crate::xivapi::ItemCategories;
+use futures_util::future::BoxFuture;
+use itertools::Itertools;
+use metrics::{counter, gauge};
+use mysql_async::{params, prelude::*, Pool, Row};
+
+pub const MIN_TRIGGER_VERSION: i32 = 0;
+pub const MAX_TRIGGER_VERSION: i32 = 1;
+
+/// The columns of `users_alerts_next` that this service depends on.
+const EXPECTED_COLUMNS: [&str; 8] = [
+    "id",
+    "user_id",
+    "name",
+    "item_id",
+    "world_id",
+    "discord_webhook",
+    "trigger_version",
+    "trigger",
+];
+
+/// Columns that newer features read if they exist. Alerts fall back to
+/// defaults when these are missing.
+const OPTIONAL_COLUMNS: [&str; 13] = [
+    "locale",
+    "reference_price",
+    "structured_payload",
+    "item_ui_category",
+    "item_search_category",
+    "include_outliers",
+    "travel_policy",
+    "edit_in_place",
+    "note",
+    "item_quality",
+    "utc_offset_minutes",
+    "quiet_hours",
+    "mention_style",
+];
+
+/// The most characters of an alert's note that are shown in notifications.
+pub const MAX_NOTE_CHARS: usize = 200;
+
+/// What to do with notifications for worlds that players may not be able to
+/// travel to right now.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TravelPolicy {
+    /// Send notifications regardless of world status.
+    #[default]
+    Ignore,
+    /// Add a travel hint to the notification.
+    Annotate,
+    /// Don't send notifications while the world is restricted.
+    Suppress,
+}
+
+impl TravelPolicy {
+    fn parse(value: Option<&str>) -> Self {
+        match value {
+            Some("annotate") => Self::Annotate,
+            Some("suppress") => Self::Suppress,
+            _ => Self::Ignore,
+        }
+    }
+}
+
+/// Which of an item's qualities an alert watches. HQ and NQ are treated as
+/// separate logical items, so a user can hold different thresholds for each
+/// without writing two triggers that differ only by a filter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ItemQuality {
+    /// Both HQ and NQ listings.
+    #[default]
+    Any,
+    Hq,
+    Nq,
+}
+
+impl ItemQuality {
+    /// Parses an alert's quality. Alerts without one, or with one that isn't
+    /// known, watch both.
+    fn parse(value: Option<&str>) -> Self {
+        match value {
+            None => Self::Any,
+            Some("hq") => Self::Hq,
+            Some("nq") => Self::Nq,
+            Some(other) => {
+                warn!("unknown item quality {:?}, watching both qualities", other);
+                Self::Any
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Any => "any",
+            Self::Hq => "hq",
+            Self::Nq => "nq",
+        }
+    }
+
+    /// Returns whether a listing is of this quality.
+    pub fn matches(&self, listing: &Listing<'_>) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Hq => listing.hq,
+            Self::Nq => !listing.hq,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UserAlert {
+    pub id: String,
+    pub user_id: Option<String>,
+    pub name: String,
+    pub discord_webhook: Option<String>,
+    pub trigger_version: i32,
+    pub trigger: String,
+    pub locale: Option<String>,
+    pub reference_price: Option<f64>,
+    /// Whether notifications should include a machine-readable copy of the event.
+    pub structured_payload: bool,
+    /// Restricts a wildcard alert to items in this UI category.
+    pub item_ui_category: Option<i32>,
+    /// Restricts a wildcard alert to items in this market board search category.
+    pub item_search_category: Option<i32>,
+    /// Whether the alert sees listings outside of the service's price bounds.
+    pub include_outliers: bool,
+    pub travel_policy: TravelPolicy,
+    /// Whether repeated matches edit the alert's last message instead of
+    /// posting new ones, until the condition stops matching.
+    pub edit_in_place: bool,
+    /// Free text from the user about why the alert exists, shown in its
+    /// notifications.
+    pub note: Option<String>,
+    /// Limits the alert to the HQ or NQ listings of its item.
+    pub item_quality: ItemQuality,
+    /// The offset from UTC, in minutes, that the alert's quiet hours are in.
+    pub utc_offset_minutes: Option<i32>,
+    /// When the alert's notifications are held back each day.
+    pub quiet_hours: Option<QuietHours>,
+    pub mention_style: Option<MentionStyle>,
+    /// The Discord account of the alert's user, which is only known once
+    /// their preferences have been applied.
+    pub discord_id: Option<String>,
+    /// An earlier alert of the same user's that this one is identical to,
+    /// which is only known once the alerts for an item are loaded together.
+    pub duplicate_of: Option<DuplicateOf>,
+}
+
+/// An alert that another alert is identical to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateOf {
+    pub id: String,
+    pub name: String,
+}
+
+/// Takes a column out of a row by name, reporting which column was
+/// missing or could not be converted.
+fn take_column<T: FromValue>(row: &mut Row, column: &'static str) -> Result<T> {
+    match row.take_opt::<T, _>(column) {
+        Some(Ok(value)) => Ok(value),
+        Some(Err(err)) => Err(ErrorKind::AlertColumn(column, format!("{:?}", err)).into()),
+        None => Err(ErrorKind::AlertColumn(column, "column not found".to_owned()).into()),
+    }
+}
+
+/// Takes a column out of a row by name if it exists, reporting conversion
+/// failures the same way as [`take_column`].
+fn take_optional_column<T: FromValue>(row: &mut Row, column: &'static str) -> Result<Option<T>> {
+    match row.take_opt::<T, _>(column) {
+        Some(Ok(value)) => Ok(Some(value)),
+        Some(Err(err)) => Err(ErrorKind::AlertColumn(column, format!("{:?}", err)).into()),
+        None => Ok(None),
+    }
+}
+
+impl UserAlert {
+    /// Returns the key the alert's cooldown and in-place message are tracked
+    /// under for an item on a world. Wildcard alerts match many items, so
+    /// each one is tracked separately. Alerts limited to one quality get
+    /// their own key, so that switching an alert between HQ and NQ starts it
+    /// afresh.
+    pub fn state_key(&self, world_id: i32, item_id: i32) -> String {
+        match self.item_quality {
+            ItemQuality::Any => format!("{}:{}:{}", self.id, world_id, item_id),
+            quality => format!("{}:{}:{}:{}", self.id, world_id, item_id, quality.as_str()),
+        }
+    }
+
+    /// Returns the webhooks this alert notifies. The `discord_webhook` column
+    /// holds either a single URL or a JSON array of URLs.
+    pub fn webhooks(&self) -> Vec<String> {
+        let discord_webhook = match self.discord_webhook.as_deref().map(str::trim) {
+            Some(w) if !w.is_empty() => w,
+            _ => return Vec::new(),
+        };
+        if discord_webhook.starts_with('[') {
+            match serde_json::from_str::<Vec<String>>(discord_webhook) {
+                Ok(webhooks) => webhooks,
+                Err(err) => {
+                    error!("failed to parse webhooks for alert {}: {:?}", self.id, err);
+                    Vec::new()
+                }
+            }
+        } else {
+            vec![discord_webhook.to_owned()]
+        }
+    }
+
+    /// Returns the values this alert supplies to its trigger at evaluation time.
+    pub fn trigger_parameters(&self) -> TriggerParameters {
+        TriggerParameters {
+            reference: self.reference_price.map(|p| p as f32),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the alert's note as it should be shown in a notification, with
+    /// control characters and runs of whitespace collapsed into single spaces,
+    /// mass mentions defused, and at most [`MAX_NOTE_CHARS`] characters long.
+    pub fn display_note(&self) -> Option<String> {
+        let note = self
+            .note
+            .as_deref()?
+            .split(|c: char| c.is_whitespace() || c.is_control())
+            .filter(|word| !word.is_empty())
+            .join(" ");
+        if note.is_empty() {
+            return None;
+        }
+
+        let mut note = if note.chars().count() > MAX_NOTE_CHARS {
+            let truncated = note.chars().take(MAX_NOTE_CHARS - 1).collect::<String>();
+            format!("{}\u{2026}", truncated.trim_end())
+        } else {
+            note
+        };
+        // A zero-width space keeps @everyone and @here from pinging the channel
+        for mention in ["@everyone", "@here"] {
+            note = note.replace(mention, &format!("@\u{200B}{}", &mention[1..]));
+        }
+        Some(note)
+    }
+
+    /// Returns whether the alert's notifications are being held back for its
+    /// quiet hours at a time, in seconds since the Unix epoch.
+    pub fn is_quiet(&self, now: i64) -> bool {
+        self.quiet_hours
+            .is_some_and(|q| q.is_quiet(now, self.utc_offset_minutes.unwrap_or(0)))
+    }
+
+    /// Returns the mention that the alert's notifications start with, if
+    /// any. Users are only mentioned if they've linked a Discord account.
+    pub fn mention(&self) -> Option<String> {
+        match self.mention_style.unwrap_or_default() {
+            MentionStyle::None => None,
+            MentionStyle::User => self.discord_id.as_ref().map(|id| format!("<@{}>", id)),
+            MentionStyle::Here => Some("@here".to_owned()),
+        }
+    }
+
+    /// Returns whether this alert only applies to items in certain categories.
+    pub fn has_category_scope(&self) -> bool {
+        self.item_ui_category.is_some() || self.item_search_category.is_some()
+    }
+
+    /// Returns whether an item with the given categories is in this alert's
+    /// scope. Items that couldn't be categorized are only in the scope of
+    /// alerts without a category.
+    pub fn matches_categories(&self, categories: Option<&ItemCategories>) -> bool {
+        let matches =
+            |scope: Option<i32>, category: Option<i32>| scope.is_none() || scope == category;
+        match categories {
+            Some(categories) => {
+                matches(self.item_ui_category, categories.ui_category)
+                    && matches(self.item_search_category, categories.search_category)
+            }
+            None => !self.has_category_scope(),
+        }
+    }
+
+    fn from_row(mut row: Row) -> Result<Self> {
+        Ok(Self {
+            id: take_column(&mut row, "id")?,
+            user_id: take_column(&mut row, "user_id")?,
+            name: take_column(&mut row, "name")?,
+            discord_webhook: take_column(&mut row, "discord_webhook")?,
+            trigger_version: take_column(&mut row, "trigger_version")?,
+            trigger: take_column(&mut row, "trigger")?,
+            locale: take_optional_column::<Option<String>>(&mut row, "locale")?.flatten(),
+            reference_price: take_optional_column::<Option<f64>>(&mut row, "reference_price")?
+                .flatten(),
+            structured_payload: take_optional_column::<Option<bool>>(
+                &mut row,
+                "structured_payload",
+            )?
+            .flatten()
+            .unwrap_or(false),
+            item_ui_category: take_optional_column::<Option<i32>>(&mut row, "item_ui_category")?
+                .flatten(),
+            item_search_category: take_optional_column::<Option<i32>>(
+                &mut row,
+                "item_search_category",
+            )?
+            .flatten(),
+            include_outliers: take_optional_column::<Option<bool>>(&mut row, "include_outliers")?
+                .flatten()
+                .unwrap_or(false),
+            travel_policy: TravelPolicy::parse(
+                take_optional_column::<Option<String>>(&mut row, "travel_policy")?
+                    .flatten()
+                    .as_deref(),
+            ),
+            edit_in_place: take_optional_column::<Option<bool>>(&mut row, "edit_in_place")?
+                .flatten()
+                .unwrap_or(false),
+            note: take_optional_column::<Option<String>>(&mut row, "note")?.flatten(),
+            item_quality: ItemQuality::parse(
+                take_optional_column::<Option<String>>(&mut row, "item_quality")?
+                    .flatten()
+                    .as_deref(),
+            ),
+            utc_offset_minutes: take_optional_column::<Option<i32>>(
+                &mut row,
+                "utc_offset_minutes",
+            )?
+            .flatten(),
+            quiet_hours: take_optional_column::<Option<String>>(&mut row, "quiet_hours")?
+                .flatten()
+                .as_deref()
+                .and_then(QuietHours::parse),
+            mention_style: MentionStyle::parse(
+                take_optional_column::<Option<String>>(&mut row, "mention_style")?
+                    .flatten()
+                    .as_deref(),
+            ),
+            discord_id: None,
+            duplicate_of: None,
+        })
+    }
+}
+
+#[cfg(test)]
+impl UserAlert {
+    /// An alert that notifies a webhook, with every optional setting left at
+    /// its default.
+    pub(crate) fn for_test(id: &str, discord_webhook: &str) -> Self {
+        Self {
+            id: id.to_owned(),
+            user_id: Some("user".to_owned()),
+            name: format!("Alert {}", id),
+            discord_webhook: Some(discord_webhook.to_owned()),
+            trigger_version: MAX_TRIGGER_VERSION,
+            trigger: String::new(),
+            locale: None,
+            reference_price: None,
+            structured_payload: false,
+            item_ui_category: None,
+            item_search_category: None,
+            include_outliers: false,
+            travel_policy: TravelPolicy::default(),
+            edit_in_place: false,
+            note: None,
+            item_quality: ItemQuality::default(),
+            utc_offset_minutes: None,
+            quiet_hours: None,
+            mention_style: None,
+            discord_id: None,
+            duplicate_of: None,
+        }
+    }
+}
+
+/// Compares the columns of `users_alerts_next` against the columns this
+/// service expects, logging any that are missing. Extra columns are fine.
+pub async fn check_alerts_schema(pool: &Pool) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+    let columns: Vec<String> = r"SELECT `COLUMN_NAME` FROM `information_schema`.`COLUMNS` WHERE `TABLE_SCHEMA` = DATABASE() AND `TABLE_NAME` = 'users_alerts_next'"
+        .fetch(&mut conn)
+        .await?;
+
+    let missing = EXPECTED_COLUMNS
+        .iter()
+        .filter(|expected| !columns.iter().any(|c| c == *expected))
+        .collect_vec();
+    if missing.is_empty() {
+        info!("users_alerts_next schema check passed");
+    } else {
+        error!(
+            "users_alerts_next is missing expected column(s): {}; found: {}",
+            missing.iter().join(", "),
+            columns.iter().join(", ")
+        );
+    }
+
+    let missing_optional = OPTIONAL_COLUMNS
+        .iter()
+        .filter(|optional| !columns.iter().any(|c| c == *optional))
+        .collect_vec();
+    if !missing_optional.is_empty() {
+        info!(
+            "users_alerts_next is missing optional column(s), defaults will be used: {}",
+            missing_optional.iter().join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Counts the alerts stored for each trigger version and exports them as
+/// gauges, so that the progress of trigger migrations can be tracked. Rows
+/// outside of the supported version range are never loaded, so they are
+/// called out explicitly.
+pub async fn report_trigger_versions(pool: &Pool) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+    let versions: Vec<(i32, i64)> =
+        r"SELECT `trigger_version`, COUNT(*) FROM `users_alerts_next` GROUP BY `trigger_version`"
+            .fetch(&mut conn)
+            .await?;
+
+    for (trigger_version, count) in versions {
+        gauge!(
+            TRIGGER_VERSION_ALERTS.name,
+            count as f64,
+            "trigger_version" => trigger_version.to_string()
+        );
+
+        if !(MIN_TRIGGER_VERSION..=MAX_TRIGGER_VERSION).contains(&trigger_version) {
+            warn!(
+                "{} alert(s) have unsupported trigger version {} (supported: {}-{}) and will be ignored",
+                count, trigger_version, MIN_TRIGGER_VERSION, MAX_TRIGGER_VERSION
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts the supported alerts registered for each world, including how many
+/// of them are wildcard alerts, and exports them as gauges.
+pub async fn report_world_alerts(pool: &Pool) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+    let worlds: Vec<(i32, i64, i64)> = r"SELECT `world_id`, COUNT(*), CAST(SUM(`item_id` = -1) AS SIGNED) FROM `users_alerts_next` WHERE `trigger_version` >= :min_trigger_version AND `trigger_version` <= :max_trigger_version GROUP BY `world_id`"
+        .with(params! {
+            "min_trigger_version" => MIN_TRIGGER_VERSION,
+            "max_trigger_version" => MAX_TRIGGER_VERSION,
+        })
+        .fetch(&mut conn)
+        .await?;
+
+    for (world_id, count, wildcards) in worlds {
+        let world = world_id.to_string();
+        gauge!(REGISTERED.name, count as f64, "world" => world.clone());
+        gauge!(REGISTERED_WILDCARD.name, wildcards as f64, "world" => world);
+    }
+
+    Ok(())
+}
+
+/// Returns the worlds that have at least one alert which could match NQ
+/// listings. Alerts with triggers that can't be parsed are assumed to.
+pub async fn get_worlds_with_nq_alerts(pool: &Pool) -> Result<HashSet<i32>> {
+    let mut conn = pool.get_conn().await?;
+    let alerts: Vec<(i32, i32, String)> = r"SELECT `world_id`, `trigger_version`, `trigger` FROM `users_alerts_next` WHERE `trigger_version` >= :min_trigger_version AND `trigger_version` <= :max_trigger_version"
+        .with(params! {
+            "min_trigger_version" => MIN_TRIGGER_VERSION,
+            "max_trigger_version" => MAX_TRIGGER_VERSION,
+        })
+        .fetch(&mut conn)
+        .await?;
+
+    Ok(alerts
+        .into_iter()
+        .filter(|(_, trigger_version, trigger)| {
+            parse_trigger(trigger, *trigger_version)
+                .map(|t| !t.is_hq_only())
+                .unwrap_or(true)
+        })
+        .map(|(world_id, _, _)| world_id)
+        .collect())
+}
+
+/// Caps on how many alerts are evaluated for a single event, so that a user
+/// (or a bug) creating thousands of alerts for one item can't stall the
+/// pipeline. Alerts beyond the caps are dropped in order of their IDs.
+#[derive(Debug, Clone, Copy)]
+pub struct AlertLimits {
+    /// The most alerts evaluated for a (world, item) pair.
+    pub per_key: usize,
+    /// The most alerts evaluated for a single user on a (world, item) pair.
+    pub per_user: usize,
+}
+
+impl AlertLimits {
+    /// Reads the caps from `UNIVERSALIS_ALERTS_MAX_ALERTS_PER_KEY` (1000 by
+    /// default) and `UNIVERSALIS_ALERTS_MAX_ALERTS_PER_USER` (50 by default).
+    pub fn from_env() -> Self {
+        Self {
+            per_key: env_or("UNIVERSALIS_ALERTS_MAX_ALERTS_PER_KEY", 1000),
+            per_user: env_or("UNIVERSALIS_ALERTS_MAX_ALERTS_PER_USER", 50),
+        }
+    }
+}
+
+/// Drops each user's alerts beyond the per-user cap.
+fn cap_alerts_per_user(
+    world_id: i32,
+    item_id: i32,
+    alerts: Vec<UserAlert>,
+    per_user: usize,
+) -> Vec<UserAlert> {
+    let mut counts = HashMap::new();
+    let before = alerts.len();
+    let alerts = alerts
+        .into_iter()
+        .filter(|alert| {
+            let count = counts.entry(alert.user_id.clone()).or_insert(0usize);
+            *count += 1;
+            *count <= per_user
+        })
+        .collect_vec();
+
+    let excess = before - alerts.len();
+    if excess > 0 {
+        counter!(TRUNCATED_ALERTS.name, excess as u64, "reason" => "per_user");
+        counter!(NOT_FIRED.name, excess as u64, "reason" => "quota_exceeded");
+        daily_stats().record_not_fired("quota_exceeded", excess as u64);
+        warn!(
+            "skipped {} alerts over the per-user cap for item {} on world {}",
+            excess, item_id, world_id
+        );
+    }
+    alerts
+}
+
+#[tracing::instrument(skip(limits, rejected, pool))]
+pub async fn get_alerts_for_world_item(
+    world_id: i32,
+    item_id: i32,
+    limits: &AlertLimits,
+    rejected: &RejectedAlerts,
+    pool: &Pool,
+) -> Result<Vec<(UserAlert, AlertTrigger)>> {
+    #[cfg(feature = "chaos")]
+    database_fault()?;
+    let mut conn = pool.get_conn().await?;
+    // Columns are read by name, so selecting everything lets optional columns
+    // be picked up when they exist without breaking when they don't. One
+    // alert over the cap is fetched to tell whether any were left out.
+    let mut rows: Vec<Row> = r"SELECT * FROM `users_alerts_next` WHERE `world_id` = :world_id AND (`item_id` = :item_id OR `item_id` = -1) AND `trigger_version` >= :min_trigger_version AND `trigger_version` <= :max_trigger_version ORDER BY `id` LIMIT :limit".with(params! {
+        "world_id" => world_id,
+        "item_id" => item_id,
+        "min_trigger_version" => MIN_TRIGGER_VERSION,
+        "max_trigger_version" => MAX_TRIGGER_VERSION,
+        "limit" => limits.per_key as u64 + 1,
+    })
+        .fetch(&mut conn)
+        .await?;
+    if rows.len() > limits.per_key {
+        rows.truncate(limits.per_key);
+        counter!(TRUNCATED_ALERTS.name, 1, "reason" => "per_key");
+        // Only the first alert past the cap is fetched, so this is a lower bound
+        counter!(NOT_FIRED.name, 1, "reason" => "quota_exceeded");
+        daily_stats().record_not_fired("quota_exceeded", 1);
+        warn!(
+            "item {} on world {} has more than {} alerts; skipping the rest",
+            item_id, world_id, limits.per_key
+        );
+    }
+
+    let alerts = rows
+        .into_iter()
+        .filter_map(|row| match UserAlert::from_row(row) {
+            Ok(alert) => Some(alert),
+            Err(err) => {
+                error!("{:?}", err);
+                None
+            }
+        })
+        .collect_vec();
+    let mut alerts = cap_alerts_per_user(world_id, item_id, alerts, limits.per_user)
+        .into_iter()
+        .filter_map(|alert| {
+            let trigger_version = alert.trigger_version.to_string();
+            counter!(TRIGGER_VERSION_LOADED.name, 1, "trigger_version" => trigger_version.clone());
+
+            counter!(TRIGGER_PARSES.name, 1);
+            let alert_trigger = parse_trigger(&alert.trigger, alert.trigger_version);
+            match alert_trigger {
+                Ok(at) => match features().check_alert(&alert, &at) {
+                    Ok(()) => Some((alert, at)),
+                    Err(disabled) => {
+                        counter!(DISABLED_FEATURE_REJECTIONS.name, 1, "feature" => disabled.0.clone());
+                        counter!(NOT_FIRED.name, 1, "reason" => "feature_disabled");
+                        daily_stats().record_not_fired("feature_disabled", 1);
+                        if rejected.record(&alert.id, "feature_disabled", disabled.to_string()) {
+                            warn!("rejecting alert {}: {}", alert.id, disabled);
+                        }
+                        None
+                    }
+                },
+                Err(err) => {
+                    counter!(TRIGGER_VERSION_PARSE_FAILURES.name, 1, "trigger_version" => trigger_version);
+                    if rejected.record(&alert.id, "invalid_trigger", err.clone()) {
+                        error!("invalid trigger for alert {}: {}", alert.id, err);
+                    }
+                    None
+                }
+            }
+        })
+        .collect_vec();
+    mark_duplicate_alerts(&mut alerts);
+    Ok(alerts)
+}
+
+/// An alert with the world and item it watches.
+#[derive(Debug, Clone)]
+pub struct StoredAlert {
+    pub world_id: i32,
+    /// The item the alert watches, or -1 for every item.
+    pub item_id: i32,
+    pub alert: UserAlert,
+}
+
+/// Loads a single alert by its ID, whatever its trigger version.
+#[tracing::instrument(skip(pool))]
+pub async fn get_alert(alert_id: &str, pool: &Pool) -> Result<Option<StoredAlert>> {
+    let mut conn = pool.get_conn().await?;
+    let row: Option<Row> = r"SELECT * FROM `users_alerts_next` WHERE `id` = :id LIMIT 1"
+        .with(params! { "id" => alert_id })
+        .first(&mut conn)
+        .await?;
+    match row {
+        Some(mut row) => Ok(Some(StoredAlert {
+            world_id: take_column(&mut row, "world_id")?,
+            item_id: take_column(&mut row, "item_id")?,
+            alert: UserAlert::from_row(row)?,
+        })),
+        None => Ok(None),
+    }
+}
+
+/// Finds alerts that are identical to an earlier one for the same item: the
+/// same user, webhook, and quality, with triggers that canonicalize to the
+/// same trigger. Each such alert is marked with the alert it duplicates.
+/// Triggers are only canonicalized here, when alerts are loaded, and are
+/// left as their users wrote them.
+pub fn mark_duplicate_alerts(alerts: &mut [(UserAlert, AlertTrigger)]) {
+    let keys = alerts
+        .iter()
+        .map(|(alert, trigger)| {
+            alert.user_id.is_some().then(|| {
+                (
+                    alert.user_id.clone(),
+                    alert.discord_webhook.clone(),
+                    alert.item_quality,
+                    trigger.canonical_key(),
+                )
+            })
+        })
+        .collect_vec();
+    let mut seen: HashMap<_, usize> = HashMap::new();
+    for (index, key) in keys.into_iter().enumerate() {
+        let key = match key {
+            Some(key) => key,
+            None => continue,
+        };
+        match seen.get(&key) {
+            Some(&original) => {
+                let original = &alerts[original].0;
+                let duplicate_of = DuplicateOf {
+                    id: original.id.clone(),
+                    name: original.name.clone(),
+                };
+                alerts[index].0.duplicate_of = Some(duplicate_of);
+            }
+            None => {
+                seen.insert(key, index);
+            }
+        }
+    }
+}
+
+/// Where the alerts for an event are loaded from.
+pub trait AlertRepository: Send + Sync {
+    /// Loads the alerts for an item on a world, including wildcard alerts,
+    /// with their parsed triggers.
+    fn alerts_for_world_item(
+        &self,
+        world_id: i32,
+        item_id: i32,
+        limits: AlertLimits,
+    ) -> BoxFuture<'_, Result<Vec<(UserAlert, AlertTrigger)>>>;
+
+    /// Loads a single alert by its ID, without parsing its trigger.
+    fn alert(&self, alert_id: &str) -> BoxFuture<'_, Result<Option<StoredAlert>>>;
+}
+
+/// Loads alerts from `users_alerts_next`.
+pub struct DatabaseAlerts {
+    pool: Pool,
+    rejected: Arc<RejectedAlerts>,
+    /// The (original, duplicate) pairs of identical alerts found so far, so
+    /// that each pair is only counted once however often it's loaded.
+    duplicates: Mutex<HashSet<(String, String)>>,
+}
+
+impl DatabaseAlerts {
+    pub fn new(pool: Pool, rejected: Arc<RejectedAlerts>) -> Self {
+        Self {
+            pool,
+            rejected,
+            duplicates: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Counts the pairs of identical alerts among loaded alerts that haven't
+    /// been seen before, returning how many there were.
+    fn record_duplicates(&self, alerts: &[(UserAlert, AlertTrigger)]) -> usize {
+        let mut duplicates = self.duplicates.lock().unwrap();
+        let mut found = 0;
+        for (alert, _) in alerts {
+            let original = match &alert.duplicate_of {
+                Some(original) => original,
+                None => continue,
+            };
+            if duplicates.insert((original.id.clone(), alert.id.clone())) {
+                info!("alert {} is identical to alert {}", alert.id, original.id);
+                found += 1;
+            }
+        }
+        if found > 0 {
+            counter!(DUPLICATE_ALERTS.name, found as u64);
+        }
+        found
+    }
+}
+
+impl AlertRepository for DatabaseAlerts {
+    fn alerts_for_world_item(
+        &self,
+        world_id: i32,
+        item_id: i32,
+        limits: AlertLimits,
+    ) -> BoxFuture<'_, Result<Vec<(UserAlert, AlertTrigger)>>> {
+        Box::pin(with_timeout(Service::Database, async move {
+            let alerts =
+                get_alerts_for_world_item(world_id, item_id, &limits, &self.rejected, &self.pool)
+                    .await?;
+            self.record_duplicates(&alerts);
+            Ok(alerts)
+        }))
+    }
+
+    fn alert(&self, alert_id: &str) -> BoxFuture<'_, Result<Option<StoredAlert>>> {
+        let alert_id = alert_id.to_owned();
+        Box::pin(with_timeout(Service::Database, async move {
+            get_alert(&alert_id, &self.pool).await
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_keys_are_per_item_and_quality() {
+        let mut alert = UserAlert::for_test("alert", "https://example.com");
+        assert_ne!(alert.state_key(73, 5), alert.state_key(73, 6));
+        assert_ne!(alert.state_key(73, 5), alert.state_key(74, 5));
+
+        let any = alert.state_key(73, 5);
+        alert.item_quality = ItemQuality::Hq;
+        assert_ne!(alert.state_key(73, 5), any);
+    }
+
+    #[test]
+    fn identical_alerts_are_marked_and_counted_once() {
+        let alert = |id: &str, webhook: &str, trigger: &str| {
+            let trigger = parse_trigger(trigger, MAX_TRIGGER_VERSION).unwrap();
+            (UserAlert::for_test(id, webhook), trigger)
+        };
+        let mut alerts = vec![
+            alert("a", "https://example.com/1", "min(pricePerUnit) < 1000"),
+            alert("b", "https://example.com/1", "min(pricePerUnit) < 999.5"),
+            alert("c", "https://example.com/2", "min(pricePerUnit) < 1000"),
+        ];
+        mark_duplicate_alerts(&mut alerts);
+        assert_eq!(alerts[0].0.duplicate_of, None);
+        assert_eq!(
+            alerts[1].0.duplicate_of,
+            Some(DuplicateOf {
+                id: "a".to_owned(),
+                name: "Alert a".to_owned(),
+            })
+        );
+        assert_eq!(alerts[2].0.duplicate_of, None);
+        // Triggers are kept as they were written
+        assert_eq!(
+            alerts[1].1.to_expression().as_deref(),
+            Some("min(pricePerUnit) < 999.5")
+        );
+
+        let repository = DatabaseAlerts::new(
+            Pool::new(mysql_async::Opts::default()),
+            Arc::new(RejectedAlerts::default()),
+        );
+        assert_eq!(repository.record_duplicates(&alerts), 1);
+        assert_eq!(repository.record_duplicates(&alerts), 0);
+    }
+}
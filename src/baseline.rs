@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::env_or;
+use crate::errors::*;
+use crate::metrics_registry::*;
+use crate::redis::RedisClient;
+use crate::timeouts::*;
+use crate::trigger::Baseline;
+use crate::universalis::*;
+use crate::xivapi::*;
+use futures_util::future::BoxFuture;
+use metrics::counter;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+/// How far back sales are averaged for [`Baseline::SevenDayAverageSalePrice`].
+const SEVEN_DAYS_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// The number of cached baselines or market data above which expired ones
+/// are pruned. Until then, expired values are kept to be served if they
+/// can't be refreshed.
+const CACHE_PRUNE_THRESHOLD: usize = 100_000;
+
+/// A baseline for an item on a world.
+type BaselineKey = (Baseline, i32, i32);
+
+/// An item on a world.
+type MarketKey = (i32, i32);
+
+/// Looks up the market values that triggers can be compared against.
+pub trait BaselineProvider: Send + Sync {
+    /// Resolves a baseline for an item on a world, returning `None` if it
+    /// isn't available (e.g. an item that has never sold).
+    fn resolve(
+        &self,
+        baseline: Baseline,
+        world_id: i32,
+        item_id: i32,
+    ) -> BoxFuture<'_, Result<Option<f32>>>;
+
+    /// Loads the market data for several items on a world ahead of resolving
+    /// their baselines, so that they can be fetched together.
+    fn prefetch(&self, _world_id: i32, _item_ids: &[i32]) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+struct CacheEntry<T> {
+    fetched_at: Instant,
+    value: T,
+}
+
+/// The aggregated market data for an item on a world, as it's shared
+/// through Redis.
+#[derive(Serialize, Deserialize)]
+struct SharedMarketData {
+    /// When the data was fetched, in seconds since the Unix epoch.
+    fetched_at: i64,
+    data: Option<AggregatedMarketData>,
+}
+
+/// Resolves baselines from Universalis and XIVAPI. The market statistics
+/// behind most baselines come from the Universalis aggregated endpoint in a
+/// single request per item, and are cached in memory and, if configured, in
+/// Redis, so that they're shared by every instance. Values that can't be
+/// refreshed are served past their TTL rather than not at all.
+pub struct MarketBaselines {
+    client: Client,
+    ttl: Duration,
+    /// How long ago a world's market data may have been uploaded for
+    /// baselines to be taken from it.
+    max_data_age: Option<Duration>,
+    cache: Mutex<HashMap<BaselineKey, CacheEntry<Option<f32>>>>,
+    market_data: Mutex<HashMap<MarketKey, CacheEntry<Option<AggregatedMarketData>>>>,
+    redis: Option<RedisClient>,
+}
+
+impl MarketBaselines {
+    /// Reads how long resolved baselines are cached for from
+    /// `UNIVERSALIS_ALERTS_BASELINE_CACHE_SECS` (an hour by default), the
+    /// Redis server to share them through from `UNIVERSALIS_ALERTS_BASELINE_REDIS`
+    /// (e.g. `redis://localhost:6379/0`), and how old market data may be from
+    /// `UNIVERSALIS_ALERTS_BASELINE_MAX_DATA_AGE_SECS` (unlimited by default).
+    pub fn from_env() -> Result<Self> {
+        let redis = match env::var("UNIVERSALIS_ALERTS_BASELINE_REDIS") {
+            Ok(url) if !url.is_empty() => Some(RedisClient::from_url(&url)?),
+            _ => None,
+        };
+        Ok(Self {
+            client: timeouts().client(Service::Universalis),
+            ttl: Duration::from_secs(env_or("UNIVERSALIS_ALERTS_BASELINE_CACHE_SECS", 3600)),
+            max_data_age: Some(env_or("UNIVERSALIS_ALERTS_BASELINE_MAX_DATA_AGE_SECS", 0))
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            cache: Mutex::new(HashMap::new()),
+            market_data: Mutex::new(HashMap::new()),
+            redis,
+        })
+    }
+
+    /// Returns a cached value, and whether it's still fresh.
+    fn cached<K: Eq + std::hash::Hash, T: Copy>(
+        &self,
+        cache: &Mutex<HashMap<K, CacheEntry<T>>>,
+        key: &K,
+    ) -> Option<(T, bool)> {
+        let cache = cache.lock().unwrap();
+        cache
+            .get(key)
+            .map(|entry| (entry.value, entry.fetched_at.elapsed() < self.ttl))
+    }
+
+    /// Caches a value that was just fetched, or was fetched `age` ago.
+    fn insert<K: Eq + std::hash::Hash, T>(
+        &self,
+        cache: &Mutex<HashMap<K, CacheEntry<T>>>,
+        key: K,
+        value: T,
+        age: Duration,
+    ) {
+        let mut cache = cache.lock().unwrap();
+        if cache.len() >= CACHE_PRUNE_THRESHOLD {
+            cache.retain(|_, entry| entry.fetched_at.elapsed() < self.ttl);
+        }
+        let fetched_at = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+        cache.insert(key, CacheEntry { fetched_at, value });
+    }
+
+    fn shared_key((world_id, item_id): MarketKey) -> String {
+        format!("universalis_alerts:market_data:{}:{}", world_id, item_id)
+    }
+
+    /// Looks for an item's market data in Redis, caching it in memory if
+    /// it's found.
+    async fn load_shared(&self, key: MarketKey) -> Option<Option<AggregatedMarketData>> {
+        let redis = self.redis.as_ref()?;
+        let value = match redis.get(&Self::shared_key(key)).await {
+            Ok(value) => value?,
+            Err(err) => {
+                counter!(BASELINE_REDIS_FAILURES.name, 1, "operation" => "get");
+                warn!("failed to read market data from Redis: {:?}", err);
+                return None;
+            }
+        };
+        let shared: SharedMarketData = serde_json::from_slice(&value).ok()?;
+        let age = Duration::from_secs(unix_now().saturating_sub(shared.fetched_at).max(0) as u64);
+        if age >= self.ttl {
+            return None;
+        }
+        self.insert(&self.market_data, key, shared.data, age);
+        Some(shared.data)
+    }
+
+    /// Caches freshly fetched market data in memory and in Redis.
+    async fn store(&self, key: MarketKey, data: Option<AggregatedMarketData>) {
+        self.insert(&self.market_data, key, data, Duration::ZERO);
+        if let Some(redis) = &self.redis {
+            let shared = SharedMarketData {
+                fetched_at: unix_now(),
+                data,
+            };
+            let value = serde_json::to_vec(&shared).unwrap_or_default();
+            if let Err(err) = redis.set_ex(&Self::shared_key(key), &value, self.ttl).await {
+                counter!(BASELINE_REDIS_FAILURES.name, 1, "operation" => "set");
+                warn!("failed to write market data to Redis: {:?}", err);
+            }
+        }
+    }
+
+    /// Fetches the market data for items on a world that aren't cached in
+    /// either tier.
+    async fn refresh(&self, world_id: i32, item_ids: &[i32]) -> Result<()> {
+        let mut missing = Vec::new();
+        for &item_id in item_ids {
+            let key = (world_id, item_id);
+            match self.cached(&self.market_data, &key) {
+                Some((_, true)) => counter!(BASELINE_CACHE_HITS.name, 1, "tier" => "memory"),
+                _ if self.load_shared(key).await.is_some() => {
+                    counter!(BASELINE_CACHE_HITS.name, 1, "tier" => "redis")
+                }
+                _ => missing.push(item_id),
+            }
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        counter!(BASELINE_CACHE_MISSES.name, missing.len() as u64);
+        let mut fetched = get_aggregated_market_data(world_id, &missing, &self.client).await?;
+        for item_id in missing {
+            self.store((world_id, item_id), fetched.remove(&item_id))
+                .await;
+        }
+        Ok(())
+    }
+
+    /// Returns an item's market data, refreshing it if it has expired.
+    async fn market_data(
+        &self,
+        world_id: i32,
+        item_id: i32,
+    ) -> Result<Option<AggregatedMarketData>> {
+        let key = (world_id, item_id);
+        let refreshed = self.refresh(world_id, &[item_id]).await;
+        match (refreshed, self.cached(&self.market_data, &key)) {
+            (Ok(()), Some((data, _))) => Ok(data),
+            (Ok(()), None) => Ok(None),
+            (Err(err), Some((data, _))) => {
+                counter!(BASELINE_STALE_SERVED.name, 1);
+                warn!(
+                    "serving expired market data for item {} on world {}: {:?}",
+                    item_id, world_id, err
+                );
+                Ok(data)
+            }
+            (Err(err), None) => Err(err),
+        }
+    }
+
+    /// Picks a baseline out of an item's market data, unless the data is
+    /// too old to go by.
+    fn pick_baseline(&self, baseline: Baseline, data: &AggregatedMarketData) -> Option<f32> {
+        let uploaded_at = data.uploaded_at.unwrap_or(0);
+        let outdated = self
+            .max_data_age
+            .is_some_and(|max| unix_now().saturating_sub(uploaded_at) > max.as_secs() as i64);
+        if outdated {
+            counter!(BASELINE_OUTDATED.name, 1);
+            return None;
+        }
+        match baseline {
+            Baseline::AverageSalePrice => data.average_sale_price,
+            Baseline::SaleVelocity => data.daily_sale_velocity,
+            Baseline::DataCenterMin => data.dc_min,
+            Baseline::GlobalMin => data.region_min,
+            Baseline::SevenDayAverageSalePrice | Baseline::VendorPrice => None,
+        }
+    }
+
+    async fn fetch(&self, baseline: Baseline, world_id: i32, item_id: i32) -> Result<Option<f32>> {
+        match baseline {
+            Baseline::SevenDayAverageSalePrice => {
+                get_average_sale_price(world_id, item_id, SEVEN_DAYS_SECS, &self.client).await
+            }
+            Baseline::VendorPrice => Ok(get_vendor_price(item_id).await?.map(|p| p as f32)),
+            _ => Ok(self
+                .market_data(world_id, item_id)
+                .await?
+                .and_then(|data| self.pick_baseline(baseline, &data))),
+        }
+    }
+}
+
+impl BaselineProvider for MarketBaselines {
+    fn resolve(
+        &self,
+        baseline: Baseline,
+        world_id: i32,
+        item_id: i32,
+    ) -> BoxFuture<'_, Result<Option<f32>>> {
+        Box::pin(async move {
+            let aggregated = !matches!(
+                baseline,
+                Baseline::SevenDayAverageSalePrice | Baseline::VendorPrice
+            );
+            if aggregated {
+                // Market data has a cache of its own
+                return self.fetch(baseline, world_id, item_id).await;
+            }
+
+            // Vendor prices are the same everywhere
+            let world_id = match baseline {
+                Baseline::VendorPrice => 0,
+                _ => world_id,
+            };
+            let key = (baseline, world_id, item_id);
+            let cached = self.cached(&self.cache, &key);
+            if let Some((value, true)) = cached {
+                counter!(BASELINE_CACHE_HITS.name, 1, "tier" => "memory");
+                return Ok(value);
+            }
+
+            counter!(BASELINE_CACHE_MISSES.name, 1);
+            let value = match (self.fetch(baseline, world_id, item_id).await, cached) {
+                (Ok(value), _) => value,
+                (Err(err), Some((value, _))) => {
+                    counter!(BASELINE_STALE_SERVED.name, 1);
+                    warn!("serving expired {} baseline: {:?}", baseline, err);
+                    return Ok(value);
+                }
+                (Err(err), None) => return Err(err),
+            };
+            self.insert(&self.cache, key, value, Duration::ZERO);
+            Ok(value)
+        })
+    }
+
+    fn prefetch(&self, world_id: i32, item_ids: &[i32]) -> BoxFuture<'_, Result<()>> {
+        let item_ids = item_ids.to_vec();
+        Box::pin(async move { self.refresh(world_id, &item_ids).await })
+    }
+}
@@ -0,0 +1,128 @@
+#![allow(clippy::result_large_err)]
+
+use std::env;
+
+use universalis_alerts::alerts::MAX_TRIGGER_VERSION;
+use universalis_alerts::errors::*;
+use universalis_alerts::loadgen::*;
+use universalis_alerts::telemetry::init_logging;
+use universalis_alerts::timeouts::*;
+use universalis_alerts::trigger::*;
+use universalis_alerts::universalis::*;
+use universalis_alerts::validate::*;
+use universalis_alerts::xivapi::*;
+
+const USAGE: &str = "usage:
+  alerts-cli items search <name>
+  alerts-cli worlds list
+  alerts-cli validate <trigger> [trigger version]
+  alerts-cli canonicalize <trigger> [trigger version]
+  alerts-cli simulate <world id> <item id> <trigger>
+  alerts-cli loadgen <config.yaml>";
+
+fn parse_id(arg: &str, name: &str) -> Result<i32> {
+    arg.parse().chain_err(|| format!("invalid {}", name))
+}
+
+/// Prints the ID and name of each matching item, one per line.
+async fn search(name: &str, client: &reqwest::Client) -> Result<()> {
+    let items = search_items(name, client).await?;
+    if items.is_empty() {
+        eprintln!("No items found matching '{}'", name);
+    }
+    for item in items {
+        println!("{}\t{}", item.id, item.name);
+    }
+    Ok(())
+}
+
+/// Prints the ID, name and data center of each public world, one per line.
+async fn list_worlds(client: &reqwest::Client) -> Result<()> {
+    let mut worlds = get_worlds(client).await?;
+    worlds.sort_by(|a, b| a.data_center().cmp(&b.data_center()).then(a.id.cmp(&b.id)));
+    for world in worlds {
+        println!(
+            "{}\t{}\t{}",
+            world.id,
+            world.name,
+            world.data_center().unwrap_or("")
+        );
+    }
+    Ok(())
+}
+
+fn validate(trigger: &str, trigger_version: i32) -> Result<AlertTrigger> {
+    parse_trigger(trigger, trigger_version)
+        .map(|t| t.canonicalize())
+        .map_err(Error::from)
+}
+
+/// Runs a trigger over an item's current listings on a world.
+async fn simulate(
+    world_id: i32,
+    item_id: i32,
+    trigger: &str,
+    client: &reqwest::Client,
+) -> Result<()> {
+    let trigger = validate(trigger, MAX_TRIGGER_VERSION)?;
+    let listings = get_current_listings(world_id, item_id, client).await?;
+    let evaluation = listings.iter().apply_trigger(&trigger);
+    println!("{}\n", trigger);
+    println!(
+        "{} listings, {} passed filters, {} reduced",
+        evaluation.listings, evaluation.passed_filters, evaluation.reduced
+    );
+    match evaluation.value {
+        Some(value) => println!(
+            "{} ({})",
+            trigger.format_result(value, "en"),
+            if evaluation.matched {
+                "matched"
+            } else {
+                "not matched"
+            }
+        ),
+        None => println!("No value (not matched)"),
+    }
+    Ok(())
+}
+
+/// Tools for building triggers: finding item and world IDs, and checking
+/// triggers against the market before saving them. Also runs synthetic load
+/// tests for capacity planning.
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let client = timeouts().client(Service::Universalis);
+
+    match args[..] {
+        ["items", "search", ref name @ ..] if !name.is_empty() => {
+            search(&name.join(" "), &client).await
+        }
+        ["worlds", "list"] => list_worlds(&client).await,
+        ["validate", trigger] => validate(trigger, MAX_TRIGGER_VERSION).map(|t| println!("{}", t)),
+        ["validate", trigger, version] => {
+            let version = version.parse().chain_err(|| "invalid trigger version")?;
+            validate(trigger, version).map(|t| println!("{}", t))
+        }
+        ["canonicalize", trigger] => {
+            validate(trigger, MAX_TRIGGER_VERSION).map(|t| println!("{}", t.canonical_key()))
+        }
+        ["canonicalize", trigger, version] => {
+            let version = version.parse().chain_err(|| "invalid trigger version")?;
+            validate(trigger, version).map(|t| println!("{}", t.canonical_key()))
+        }
+        ["simulate", world_id, item_id, trigger] => {
+            let world_id = parse_id(world_id, "world id")?;
+            let item_id = parse_id(item_id, "item id")?;
+            simulate(world_id, item_id, trigger, &client).await
+        }
+        ["loadgen", path] => {
+            dotenv::dotenv().ok();
+            init_logging();
+            run_load(&read_config(path)?).await
+        }
+        _ => Err(USAGE.into()),
+    }
+}
@@ -0,0 +1,279 @@
+#![allow(clippy::result_large_err)]
+
+#[macro_use]
+extern crate log;
+
+use std::env;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dotenv::dotenv;
+use metrics::{counter, gauge};
+use mysql_async::Pool;
+use universalis_alerts::admin::*;
+use universalis_alerts::delivery::*;
+use universalis_alerts::errors::*;
+use universalis_alerts::metrics_registry::*;
+use universalis_alerts::mutes::*;
+use universalis_alerts::ops::*;
+use universalis_alerts::outbox::*;
+use universalis_alerts::slo::*;
+use universalis_alerts::startup::*;
+use universalis_alerts::telemetry::*;
+use universalis_alerts::timeouts::*;
+use universalis_alerts::universalis::unix_now_ms;
+
+/// How many outbox entries are claimed at once.
+const BATCH_SIZE: u32 = 10;
+
+/// How much longer than sending a whole batch can take claimed entries are
+/// reserved for, to leave time for the outbox updates between sends.
+const LEASE_HEADROOM: Duration = Duration::from_secs(30);
+
+/// How many delivery attempts are made before an entry is dropped.
+const MAX_ATTEMPTS: i32 = 5;
+
+/// How often the size of the backlog is checked, mutes are reloaded, and
+/// the delivery SLO is reported.
+const BACKLOG_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How many times updating an entry in the outbox is attempted.
+const UPDATE_ATTEMPTS: u32 = 3;
+
+/// Returns how long claimed entries are reserved for this worker: long
+/// enough to send the whole batch even if every send takes as long as it's
+/// allowed to, so that no other worker claims entries that are still to be
+/// sent.
+fn lease(send_timeout: Duration) -> Duration {
+    send_timeout * BATCH_SIZE + LEASE_HEADROOM
+}
+
+/// Updates an entry in the outbox, retrying briefly. Updates that still fail
+/// are logged and given up on, since the rest of the batch has already been
+/// claimed; the entry is picked up again once its lease runs out.
+async fn update_entry<F, Fut>(id: u64, action: &str, consequence: &str, update: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    for attempt in 1..=UPDATE_ATTEMPTS {
+        match update().await {
+            Ok(()) => return,
+            Err(err) if attempt == UPDATE_ATTEMPTS => {
+                error!(
+                    "failed to {} notification {}, so {}: {:?}",
+                    action, id, consequence, err
+                );
+            }
+            Err(err) => {
+                warn!("failed to {} notification {}: {:?}", action, id, err);
+                tokio::time::sleep(Duration::from_millis(100 * attempt as u64)).await;
+            }
+        }
+    }
+}
+
+/// Removes a finished entry from the outbox.
+async fn complete(id: u64, pool: &Pool) {
+    update_entry(id, "remove from the outbox", "it may be sent again", || {
+        complete_notification(id, pool)
+    })
+    .await;
+}
+
+/// Schedules another attempt at sending an entry after `retry_secs` seconds.
+async fn reschedule(id: u64, retry_secs: u32, pool: &Pool) {
+    update_entry(
+        id,
+        "reschedule",
+        "it will be retried without backing off",
+        || fail_notification(id, retry_secs, pool),
+    )
+    .await;
+}
+
+/// Marks a notification as delayed if its event is older than the deadline
+/// by the time it's sent. If it can't be, it's sent as it is.
+fn mark_if_late(entry: &OutboxEntry, event_deadline: Duration) -> Option<Notification> {
+    let age = Duration::from_millis((unix_now_ms() - entry.received_at?).max(0) as u64);
+    if age <= event_deadline {
+        return None;
+    }
+    counter!(LATE_DELIVERIES.name, 1);
+    match annotate_late(&entry.notification, age) {
+        Ok(notification) => Some(notification),
+        Err(err) => {
+            warn!(
+                "failed to mark notification {} as delayed: {:?}",
+                entry.id, err
+            );
+            None
+        }
+    }
+}
+
+/// Claims a batch of entries and sends them one after another. Sending stops
+/// early if the lease could run out during the next send, so that the
+/// entries left over are only ever sent by whichever worker claims them
+/// next.
+async fn deliver_batch(
+    worker_id: &str,
+    pool: &Pool,
+    client: &reqwest::Client,
+    send_timeout: Duration,
+    mutes: &MuteList,
+    slo: &DeliverySlo,
+    event_deadline: Duration,
+) -> Result<usize> {
+    let lease = lease(send_timeout);
+    let claimed_at = Instant::now();
+    let entries = claim_notifications(worker_id, BATCH_SIZE, lease.as_secs() as u32, pool).await?;
+    let claimed = entries.len();
+
+    for (handled, entry) in entries.into_iter().enumerate() {
+        if claimed_at.elapsed() + send_timeout >= lease {
+            warn!(
+                "lease is running out, leaving {} notification(s) for the next claim",
+                claimed - handled
+            );
+            break;
+        }
+
+        // Users and webhooks may have been muted since this was queued
+        if entry.is_muted(mutes) {
+            counter!(DESTINATION_DELIVERIES.name, 1, "outcome" => "muted");
+            complete(entry.id, pool).await;
+            continue;
+        }
+
+        let late = mark_if_late(&entry, event_deadline);
+        // Nothing reads per-destination stats from the worker
+        let notification = late.as_ref().unwrap_or(&entry.notification);
+        let sent = send_notification(notification, client, None).await;
+        if let Some(received_at) = entry.received_at {
+            let latency = (unix_now_ms() - received_at).max(0) as u64;
+            slo.record(Duration::from_millis(latency), sent.is_ok());
+        }
+        match sent {
+            Ok(_) => {
+                counter!(OUTBOX_DELIVERED.name, 1);
+                complete(entry.id, pool).await;
+            }
+            Err(err) if entry.attempts + 1 >= MAX_ATTEMPTS => {
+                counter!(OUTBOX_DROPPED.name, 1);
+                error!(
+                    "dropping notification {} for alert {} after {} attempts: {:?}",
+                    entry.id,
+                    entry.notification.alert_id,
+                    entry.attempts + 1,
+                    err
+                );
+                complete(entry.id, pool).await;
+            }
+            Err(err) => {
+                counter!(OUTBOX_FAILED.name, 1);
+                error!("{:?}", err);
+
+                // Back off exponentially between attempts
+                let retry_secs = 10 * 2u32.pow(entry.attempts as u32);
+                reschedule(entry.id, retry_secs, pool).await;
+            }
+        }
+    }
+
+    Ok(claimed)
+}
+
+/// Reports the number of entries waiting to be delivered, raising an
+/// incident if there are more than `threshold`.
+async fn check_backlog(threshold: u64, pool: &Pool, client: &reqwest::Client, ops: &OpsNotifier) {
+    match count_due_notifications(pool).await {
+        Ok(backlog) => {
+            gauge!(OUTBOX_BACKLOG.name, backlog as f64);
+            if backlog > threshold {
+                let message = format!("{} notifications are waiting in the outbox", backlog);
+                ops.incident("outbox_backlog", &message, client).await;
+            }
+        }
+        Err(err) => error!("failed to count outbox backlog: {:?}", err),
+    }
+}
+
+/// Replaces the mutes with the ones in the database, keeping the previous
+/// ones if they can't be loaded.
+async fn reload_mutes(mutes: &MuteList, pool: &Pool) {
+    match load_mutes(pool).await {
+        Ok(loaded) => mutes.replace(loaded),
+        Err(err) => error!("failed to load mutes: {:?}", err),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+
+    init_logging();
+
+    init_observability(
+        "universalis_alerts_delivery",
+        Arc::new(AdminState::default()),
+    );
+
+    let phase = StartupPhase::begin("database");
+    let pool = database_pool_from_env()?;
+    // Batches are retried until the database is back, so there's no need to
+    // wait for it
+    if let Err(err) = wait_for_database(&pool, database_startup_timeout()).await {
+        error!("starting without the database: {:?}", err);
+    }
+    phase.finish();
+    let client = timeouts().client(Service::Discord);
+    let send_timeout = timeouts().get(Service::Discord).total;
+    let ops = OpsNotifier::from_env();
+    let backlog_threshold = env::var("UNIVERSALIS_ALERTS_OPS_BACKLOG_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1000);
+
+    // Each worker claims entries under its own ID so several can run at once
+    let worker_id = format!("{}-{}", std::process::id(), uuid::Uuid::new_v4());
+    info!("Delivery worker {} started", worker_id);
+
+    let mutes = MuteList::default();
+    reload_mutes(&mutes, &pool).await;
+    let slo = DeliverySlo::from_env();
+    let event_deadline = event_deadline_from_env();
+
+    let mut backlog_checked_at = Instant::now();
+    loop {
+        if backlog_checked_at.elapsed() >= BACKLOG_CHECK_INTERVAL {
+            check_backlog(backlog_threshold, &pool, &client, &ops).await;
+            reload_mutes(&mutes, &pool).await;
+            slo.report();
+            backlog_checked_at = Instant::now();
+        }
+
+        let delivered = deliver_batch(
+            &worker_id,
+            &pool,
+            &client,
+            send_timeout,
+            &mutes,
+            &slo,
+            event_deadline,
+        )
+        .await;
+        match delivered {
+            // Keep going immediately if there may be more work
+            Ok(claimed) if claimed as u32 == BATCH_SIZE => continue,
+            Ok(_) => {}
+            Err(err) => {
+                error!("{:?}", err);
+                let message = format!("Delivery worker {} failed: {}", worker_id, err);
+                ops.incident("database", &message, &client).await;
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashSet;
+use std::env;
+
+use crate::universalis::Channel;
+use tokio::sync::watch;
+
+/// The worlds that need the general (unfiltered) channel, because some of
+/// their alerts could match NQ listings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChannelDemand {
+    /// Whether the alerts have been loaded yet. Until they have, every
+    /// channel is subscribed to in full.
+    loaded: bool,
+    nq_worlds: HashSet<i32>,
+}
+
+/// Subscribes to Universalis's HQ-filtered channels instead of the general
+/// ones when every alert they would serve only matches HQ listings.
+pub struct ChannelSelector {
+    enabled: bool,
+    demand: watch::Sender<ChannelDemand>,
+    /// Kept so that updates are accepted even while no connection is open.
+    receiver: watch::Receiver<ChannelDemand>,
+}
+
+impl ChannelSelector {
+    /// Reads whether HQ-filtered channels may be used from
+    /// `UNIVERSALIS_ALERTS_HQ_CHANNELS`.
+    pub fn from_env() -> Self {
+        let enabled = env::var("UNIVERSALIS_ALERTS_HQ_CHANNELS")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let (demand, receiver) = watch::channel(ChannelDemand::default());
+        Self {
+            enabled,
+            demand,
+            receiver,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Records which worlds have alerts that could match NQ listings,
+    /// notifying connections if that changed.
+    pub fn update(&self, nq_worlds: HashSet<i32>) {
+        let demand = ChannelDemand {
+            loaded: true,
+            nq_worlds,
+        };
+        self.demand.send_if_modified(|current| {
+            let modified = *current != demand;
+            *current = demand;
+            modified
+        });
+    }
+
+    /// Returns a receiver that is notified whenever the selected channels
+    /// may have changed.
+    pub fn watch(&self) -> watch::Receiver<ChannelDemand> {
+        self.receiver.clone()
+    }
+
+    /// Chooses the channel to subscribe to in place of each configured one.
+    /// Channels that are already filtered by quality are left as they are.
+    pub fn select(&self, channels: &[Channel], demand: &ChannelDemand) -> Vec<Channel> {
+        channels
+            .iter()
+            .map(|channel| {
+                let hq_only = self.enabled
+                    && demand.loaded
+                    && channel.hq.is_none()
+                    && match channel.world {
+                        Some(world_id) => !demand.nq_worlds.contains(&world_id),
+                        None => demand.nq_worlds.is_empty(),
+                    };
+                if hq_only {
+                    channel.clone().hq(true)
+                } else {
+                    channel.clone()
+                }
+            })
+            .collect()
+    }
+}
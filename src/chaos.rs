@@ -0,0 +1,118 @@
+//! Fault injection for exercising retries, the outbox, and reconnects in
+//! integration tests and staging. Only built with the `chaos` feature; each
+//! fault is off unless its rate is set in the environment.
+
+use std::env;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::errors::*;
+use rand::Rng;
+
+/// How often each fault is injected, as a fraction of the operations it
+/// applies to.
+pub struct ChaosConfig {
+    /// Database queries that fail.
+    pub database_failure_rate: f64,
+    /// Deliveries that are held up, and for at most how long.
+    pub delivery_delay_rate: f64,
+    pub max_delivery_delay: Duration,
+    /// Websocket frames that have some of their bytes overwritten.
+    pub frame_corruption_rate: f64,
+    /// Websocket frames that drop the connection instead of being handled.
+    pub disconnect_rate: f64,
+}
+
+impl ChaosConfig {
+    /// Reads each fault's rate from `UNIVERSALIS_ALERTS_CHAOS_DB_FAILURE_RATE`,
+    /// `UNIVERSALIS_ALERTS_CHAOS_DELIVERY_DELAY_RATE`,
+    /// `UNIVERSALIS_ALERTS_CHAOS_FRAME_CORRUPTION_RATE`, and
+    /// `UNIVERSALIS_ALERTS_CHAOS_DISCONNECT_RATE`, all 0 by default. Delays
+    /// last up to `UNIVERSALIS_ALERTS_CHAOS_DELIVERY_DELAY_MS` (5 seconds by
+    /// default).
+    pub fn from_env() -> Self {
+        let rate = |name: &str| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<f64>().ok())
+                .unwrap_or(0.0)
+                .clamp(0.0, 1.0)
+        };
+        let config = Self {
+            database_failure_rate: rate("UNIVERSALIS_ALERTS_CHAOS_DB_FAILURE_RATE"),
+            delivery_delay_rate: rate("UNIVERSALIS_ALERTS_CHAOS_DELIVERY_DELAY_RATE"),
+            max_delivery_delay: Duration::from_millis(
+                env::var("UNIVERSALIS_ALERTS_CHAOS_DELIVERY_DELAY_MS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(5000),
+            ),
+            frame_corruption_rate: rate("UNIVERSALIS_ALERTS_CHAOS_FRAME_CORRUPTION_RATE"),
+            disconnect_rate: rate("UNIVERSALIS_ALERTS_CHAOS_DISCONNECT_RATE"),
+        };
+        warn!(
+            "Chaos enabled: database failures {}, delivery delays {} (up to {}ms), frame corruption {}, disconnects {}",
+            config.database_failure_rate,
+            config.delivery_delay_rate,
+            config.max_delivery_delay.as_millis(),
+            config.frame_corruption_rate,
+            config.disconnect_rate
+        );
+        config
+    }
+}
+
+/// Returns the faults read from the environment.
+pub fn chaos() -> &'static ChaosConfig {
+    static CHAOS: OnceLock<ChaosConfig> = OnceLock::new();
+    CHAOS.get_or_init(ChaosConfig::from_env)
+}
+
+fn roll(rate: f64) -> bool {
+    rate > 0.0 && rand::thread_rng().gen_bool(rate)
+}
+
+/// Fails some database queries, to be called before running one.
+pub fn database_fault() -> Result<()> {
+    if roll(chaos().database_failure_rate) {
+        debug!("chaos: failing a database query");
+        return Err("chaos: injected database failure".into());
+    }
+    Ok(())
+}
+
+/// Holds up some deliveries, to be awaited before sending one.
+pub async fn delivery_fault() {
+    let config = chaos();
+    if roll(config.delivery_delay_rate) {
+        let delay = config
+            .max_delivery_delay
+            .mul_f64(rand::thread_rng().gen_range(0.0..=1.0));
+        debug!("chaos: delaying a delivery by {}ms", delay.as_millis());
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Overwrites a few bytes of some websocket frames.
+pub fn corrupt_frame(data: &mut [u8]) {
+    if data.is_empty() || !roll(chaos().frame_corruption_rate) {
+        return;
+    }
+    let mut rng = rand::thread_rng();
+    let bytes = rng.gen_range(1..=data.len().min(8));
+    for _ in 0..bytes {
+        let i = rng.gen_range(0..data.len());
+        data[i] = rng.gen();
+    }
+    debug!("chaos: corrupted {} bytes of a frame", bytes);
+}
+
+/// Returns whether to drop the connection instead of handling a websocket
+/// frame.
+pub fn disconnect_fault() -> bool {
+    let disconnect = roll(chaos().disconnect_rate);
+    if disconnect {
+        debug!("chaos: dropping the connection");
+    }
+    disconnect
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::env_or;
+use crate::metrics_registry::*;
+use crate::universalis::MarketChannel;
+use metrics::counter;
+
+/// An item on a world, on one channel.
+type CoalesceKey = (i32, i32, MarketChannel);
+
+/// The events being held back for a key.
+struct Pending {
+    /// The most recent event seen.
+    latest: u64,
+    /// When the first event that's still being held back arrived.
+    since: Instant,
+}
+
+/// Holds events back for a short window, so that a burst of events for the
+/// same item (as Universalis sends during upload bursts) is evaluated once,
+/// with the latest of them, instead of notifying and loading alerts for each.
+/// No event is held back for longer than the maximum wait, so an item that
+/// keeps being updated is still evaluated while the burst lasts.
+pub struct EventCoalescer {
+    window: Duration,
+    max_wait: Duration,
+    pending: Mutex<HashMap<CoalesceKey, Pending>>,
+    next: Mutex<u64>,
+}
+
+impl EventCoalescer {
+    /// Reads the coalescing window from `UNIVERSALIS_ALERTS_COALESCE_MS`, and
+    /// the longest an item's events are held back for from
+    /// `UNIVERSALIS_ALERTS_COALESCE_MAX_WAIT_MS`, which is never shorter
+    /// than the window. By default events aren't held back at all.
+    pub fn from_env() -> Self {
+        let window = env_or("UNIVERSALIS_ALERTS_COALESCE_MS", 0);
+        let max_wait = env_or("UNIVERSALIS_ALERTS_COALESCE_MAX_WAIT_MS", 0).max(window);
+        Self::new(
+            Duration::from_millis(window),
+            Duration::from_millis(max_wait),
+        )
+    }
+
+    pub fn new(window: Duration, max_wait: Duration) -> Self {
+        Self {
+            window,
+            max_wait,
+            pending: Mutex::new(HashMap::new()),
+            next: Mutex::new(0),
+        }
+    }
+
+    /// Waits out the window for an event, returning whether it should still
+    /// be evaluated, which is only if no newer event for the same item
+    /// arrived in the meantime. Once the first of the item's events has been
+    /// held back for the maximum wait, the latest one is let through.
+    pub async fn settle(&self, world_id: i32, item_id: i32, channel: MarketChannel) -> bool {
+        if self.window.is_zero() {
+            return true;
+        }
+
+        let key = (world_id, item_id, channel);
+        let id = {
+            let mut next = self.next.lock().unwrap();
+            *next += 1;
+            *next
+        };
+        let wait = {
+            let mut pending = self.pending.lock().unwrap();
+            let now = Instant::now();
+            let pending = pending
+                .entry(key)
+                .and_modify(|pending| pending.latest = id)
+                .or_insert(Pending {
+                    latest: id,
+                    since: now,
+                });
+            let deadline = pending.since + self.max_wait;
+            self.window.min(deadline.saturating_duration_since(now))
+        };
+        tokio::time::sleep(wait).await;
+
+        let mut pending = self.pending.lock().unwrap();
+        if pending
+            .get(&key)
+            .is_some_and(|pending| pending.latest == id)
+        {
+            pending.remove(&key);
+            true
+        } else {
+            counter!(COALESCED_EVENTS.name, 1);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn items_that_keep_updating_are_still_evaluated() {
+        let coalescer = Arc::new(EventCoalescer::new(
+            Duration::from_millis(50),
+            Duration::from_millis(100),
+        ));
+        // Events arrive far more often than the window, for longer than the
+        // maximum wait
+        let mut settled = Vec::new();
+        for _ in 0..30 {
+            let coalescer = coalescer.clone();
+            settled.push(tokio::spawn(async move {
+                coalescer.settle(1, 2, MarketChannel::ListingsAdd).await
+            }));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let mut evaluated = 0;
+        for settled in settled {
+            if settled.await.unwrap() {
+                evaluated += 1;
+            }
+        }
+        // At least one event is let through while the burst lasts, and the
+        // last one once it ends
+        assert!(evaluated >= 2, "only {} event(s) were evaluated", evaluated);
+        assert!(evaluated < 30);
+    }
+}
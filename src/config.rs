@@ -0,0 +1,82 @@
+use std::env;
+use std::time::Duration;
+
+use crate::errors::*;
+use crate::universalis::Channel;
+
+/// Reads a setting from an environment variable, or returns the default if
+/// it isn't set or can't be parsed.
+pub fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// A websocket endpoint and the channels to subscribe to on it.
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub name: String,
+    pub url: url::Url,
+    pub channels: Vec<Channel>,
+    /// How long to wait after subscribing for the first event or reply
+    /// before giving up on the connection, if at all.
+    pub subscribe_timeout: Option<Duration>,
+}
+
+fn parse_channels(channels: &str) -> Result<Vec<Channel>> {
+    channels
+        .split(';')
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+        .map(Channel::parse)
+        .collect()
+}
+
+fn get_region(name: &str, ws_var: &str, channel_var: &str) -> Result<Region> {
+    let connect_addr = env::var(ws_var).chain_err(|| format!("{} not set", ws_var))?;
+    let url = url::Url::parse(&connect_addr)
+        .chain_err(|| format!("failed to parse server address for region {}", name))?;
+    let channels = env::var(channel_var).chain_err(|| format!("{} not set", channel_var))?;
+    let subscribe_timeout = env_or("UNIVERSALIS_ALERTS_SUBSCRIBE_TIMEOUT_SECS", 300);
+    Ok(Region {
+        name: name.to_owned(),
+        url,
+        channels: parse_channels(&channels)
+            .chain_err(|| format!("invalid {} for region {}", channel_var, name))?,
+        subscribe_timeout: Some(subscribe_timeout)
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs),
+    })
+}
+
+/// Reads the configured regions. If `UNIVERSALIS_ALERTS_REGIONS` is not set,
+/// a single "global" region is read from `UNIVERSALIS_ALERTS_WS` and
+/// `UNIVERSALIS_ALERTS_CHANNEL`. Otherwise, each comma-separated region name
+/// is read from `UNIVERSALIS_ALERTS_WS_<NAME>` and
+/// `UNIVERSALIS_ALERTS_CHANNEL_<NAME>`. Channels are separated by semicolons.
+/// Connections that don't receive anything within
+/// `UNIVERSALIS_ALERTS_SUBSCRIBE_TIMEOUT_SECS` of subscribing (5 minutes by
+/// default, or never if 0) are reconnected.
+pub fn get_regions() -> Result<Vec<Region>> {
+    match env::var("UNIVERSALIS_ALERTS_REGIONS") {
+        Ok(names) => names
+            .split(',')
+            .map(|n| n.trim())
+            .filter(|n| !n.is_empty())
+            .map(|name| {
+                let suffix = name.to_uppercase();
+                get_region(
+                    name,
+                    &format!("UNIVERSALIS_ALERTS_WS_{}", suffix),
+                    &format!("UNIVERSALIS_ALERTS_CHANNEL_{}", suffix),
+                )
+            })
+            .collect(),
+        Err(_) => Ok(vec![get_region(
+            "global",
+            "UNIVERSALIS_ALERTS_WS",
+            "UNIVERSALIS_ALERTS_CHANNEL",
+        )?]),
+    }
+}
@@ -0,0 +1,197 @@
+#[cfg(feature = "chaos")]
+use crate::chaos::*;
+use crate::config::*;
+use crate::errors::*;
+use crate::metrics_registry::*;
+use crate::pipeline::*;
+use crate::timeouts::*;
+use crate::universalis::*;
+use futures_util::{pin_mut, SinkExt, StreamExt};
+use metrics::counter;
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+use tokio_tungstenite::connect_async_with_config;
+use tokio_tungstenite::tungstenite::protocol::{Message, WebSocketConfig};
+
+fn serialize_event(ev: &SubscribeEvent) -> Result<Vec<u8>> {
+    let serialized = bson::to_bson(&ev)?;
+    let mut v: Vec<u8> = Vec::new();
+    serialized
+        .clone()
+        .as_document()
+        .map_or(Err(ErrorKind::NotADocument(serialized).into()), |d| {
+            d.to_writer(&mut v)?;
+            Ok(v)
+        })
+}
+
+async fn send_event<S>(write: &mut S, event: &str, channel: &str) -> Result<()>
+where
+    S: SinkExt<Message, Error = tungstenite::Error> + Unpin,
+{
+    let serialized = serialize_event(&SubscribeEvent { event, channel })?;
+    write.send(Message::Binary(serialized)).await?;
+    Ok(())
+}
+
+pub async fn connect_and_process(region: &Region, ctx: &Context) -> Result<()> {
+    info!(
+        "Connecting to WebSocket server for region {} at {}",
+        region.name, region.url
+    );
+    let config = WebSocketConfig {
+        max_message_size: Some(ctx.event_limits.max_message_bytes),
+        max_frame_size: Some(ctx.event_limits.max_message_bytes),
+        ..Default::default()
+    };
+    let (ws_stream, _) = with_timeout(Service::Websocket, async {
+        Ok(connect_async_with_config(region.url.clone(), Some(config)).await?)
+    })
+    .await?;
+    info!("WebSocket handshake completed for region {}", region.name);
+    ctx.connections.record_established(&region.name);
+
+    let (mut write, read) = ws_stream.split();
+
+    let mut demand = ctx.channels.watch();
+    let mut subscribed = ctx
+        .channels
+        .select(&region.channels, &demand.borrow_and_update());
+    ctx.subscriptions.reset(&region.name);
+    for channel in &subscribed {
+        // TODO: Ping the connection so it doesn't die
+        send_event(&mut write, "subscribe", &channel.to_string()).await?;
+        ctx.subscriptions.subscribe(&region.name, channel);
+    }
+
+    // Switch channels whenever the loaded alerts call for different ones,
+    // subscribing to the new channels before leaving the old ones. Channels
+    // that go quiet for longer than expected are resubscribed to on their
+    // own, without disturbing the rest of the connection.
+    let resubscribe = async {
+        let check_interval = ctx.subscriptions.check_interval();
+        let mut check = tokio::time::interval(check_interval.unwrap_or(Duration::from_secs(60)));
+        loop {
+            tokio::select! {
+                changed = demand.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                    let wanted = ctx
+                        .channels
+                        .select(&region.channels, &demand.borrow_and_update());
+                    for channel in wanted.iter().filter(|c| !subscribed.contains(c)) {
+                        info!("[{}] Subscribing to {}", region.name, channel);
+                        send_event(&mut write, "subscribe", &channel.to_string()).await?;
+                        ctx.subscriptions.subscribe(&region.name, channel);
+                    }
+                    for channel in subscribed.iter().filter(|c| !wanted.contains(c)) {
+                        info!("[{}] Unsubscribing from {}", region.name, channel);
+                        send_event(&mut write, "unsubscribe", &channel.to_string()).await?;
+                        ctx.subscriptions.unsubscribe(&region.name, channel);
+                    }
+                    subscribed = wanted;
+                }
+                _ = check.tick(), if check_interval.is_some() => {
+                    for channel in ctx.subscriptions.take_quiet(&region.name) {
+                        warn!("[{}] Nothing received on {} for longer than expected, resubscribing", region.name, channel);
+                        counter!(WS_CHANNEL_RESUBSCRIPTIONS.name, 1, "region" => region.name.clone(), "channel" => channel.to_string());
+                        let channel = channel.to_string();
+                        send_event(&mut write, "unsubscribe", &channel).await?;
+                        send_event(&mut write, "subscribe", &channel).await?;
+                    }
+                }
+            }
+        }
+        // The selector lives as long as the service, so this doesn't happen
+        futures_util::future::pending::<Result<()>>().await
+    };
+
+    // A rejected or misdirected subscription would otherwise look just like
+    // a quiet market, so the connection is dropped if the server rejects a
+    // subscription or sends nothing at all after subscribing
+    let received = Notify::new();
+    let (replies, mut replies_rx) = mpsc::unbounded_channel::<SubscriptionReply>();
+    let verify = async {
+        if let Some(timeout) = region.subscribe_timeout {
+            if tokio::time::timeout(timeout, received.notified())
+                .await
+                .is_err()
+            {
+                counter!(WS_SUBSCRIPTION_FAILURES.name, 1, "region" => region.name.clone(), "reason" => "timeout");
+                return Err(ErrorKind::SubscriptionFailed(format!(
+                    "nothing was received within {}s of subscribing",
+                    timeout.as_secs()
+                ))
+                .into());
+            }
+        }
+        while let Some(reply) = replies_rx.recv().await {
+            let channel = reply.channel.as_deref().unwrap_or("(unknown channel)");
+            if reply.is_error() {
+                counter!(WS_SUBSCRIPTION_FAILURES.name, 1, "region" => region.name.clone(), "reason" => "rejected");
+                return Err(ErrorKind::SubscriptionFailed(format!(
+                    "the server rejected {}: {}",
+                    channel,
+                    reply.message.as_deref().unwrap_or("(no message)")
+                ))
+                .into());
+            }
+            info!(
+                "[{}] Server confirmed {} {}",
+                region.name, reply.event, channel
+            );
+        }
+        futures_util::future::pending::<Result<()>>().await
+    };
+
+    // Injected disconnects end the stream the way the server closing it would
+    #[cfg(feature = "chaos")]
+    let read = read
+        .take_while(|_| futures_util::future::ready(!disconnect_fault()))
+        .map(|message| match message {
+            Ok(Message::Binary(mut data)) => {
+                corrupt_frame(&mut data);
+                Ok(Message::Binary(data))
+            }
+            other => other,
+        });
+
+    let on_message = {
+        read.for_each_concurrent(None, |message| async {
+            let result = match message {
+                Ok(m) => {
+                    for name in WS_MESSAGES_RECEIVED.names() {
+                        counter!(name, 1, "region" => region.name.clone());
+                    }
+                    if m.is_binary() || m.is_text() {
+                        received.notify_one();
+                    }
+                    process(&region.name, m, false, ctx).await.map(|reply| {
+                        if let Some(reply) = reply {
+                            let _ = replies.send(reply);
+                        }
+                    })
+                }
+                Err(err) => {
+                    counter!(WS_ERRORS.name, 1, "region" => region.name.clone());
+                    Err(ErrorKind::Tungstenite(err).into())
+                }
+            };
+            if let Err(err) = result {
+                error!("{:?}", err);
+            }
+        })
+    };
+
+    pin_mut!(on_message);
+    pin_mut!(resubscribe);
+    pin_mut!(verify);
+    tokio::select! {
+        _ = on_message => {}
+        result = resubscribe => result?,
+        result = verify => result?,
+    }
+
+    Err(ErrorKind::ConnectionClosed("the connection was closed".to_owned()).into())
+}
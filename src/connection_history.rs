@@ -0,0 +1,122 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::metrics_registry::*;
+use crate::redact::*;
+use crate::universalis::unix_now;
+use metrics::gauge;
+use serde::Serialize;
+
+/// How many connection events are kept.
+const HISTORY_SIZE: usize = 100;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionEventKind {
+    Established,
+    Lost,
+}
+
+/// A websocket connection being established or lost.
+#[derive(Serialize, Debug, Clone)]
+pub struct ConnectionEvent {
+    pub region: String,
+    pub kind: ConnectionEventKind,
+    /// Seconds since the Unix epoch.
+    pub at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// The current state of a region's connection.
+#[derive(Serialize, Debug, Clone)]
+pub struct RegionConnection {
+    pub region: String,
+    /// When the current connection was established, if there is one.
+    pub connected_since: Option<i64>,
+    pub uptime_secs: i64,
+}
+
+/// Keeps track of when each region's websocket connection was established
+/// and lost, so that gaps in event coverage can be found after the fact.
+#[derive(Default)]
+pub struct ConnectionHistory {
+    connected_since: Mutex<HashMap<String, Option<i64>>>,
+    events: Mutex<VecDeque<ConnectionEvent>>,
+}
+
+impl ConnectionHistory {
+    fn push(&self, event: ConnectionEvent) {
+        let mut events = self.events.lock().unwrap();
+        if events.len() >= HISTORY_SIZE {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Records that a region's connection was established.
+    pub fn record_established(&self, region: &str) {
+        let now = unix_now();
+        self.connected_since
+            .lock()
+            .unwrap()
+            .insert(region.to_owned(), Some(now));
+        self.push(ConnectionEvent {
+            region: region.to_owned(),
+            kind: ConnectionEventKind::Established,
+            at: now,
+            reason: None,
+        });
+    }
+
+    /// Records that a region's connection was lost, or couldn't be made.
+    pub fn record_lost(&self, region: &str, reason: &str) {
+        let was_connected = self
+            .connected_since
+            .lock()
+            .unwrap()
+            .insert(region.to_owned(), None)
+            .flatten()
+            .is_some();
+        // Repeated failures to connect aren't interesting on their own
+        if !was_connected {
+            return;
+        }
+        self.push(ConnectionEvent {
+            region: region.to_owned(),
+            kind: ConnectionEventKind::Lost,
+            at: unix_now(),
+            reason: Some(redact(reason).into_owned()),
+        });
+    }
+
+    /// Returns the current state of each region's connection.
+    pub fn regions(&self) -> Vec<RegionConnection> {
+        let now = unix_now();
+        let mut regions = self
+            .connected_since
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(region, since)| RegionConnection {
+                region: region.clone(),
+                connected_since: *since,
+                uptime_secs: since.map(|s| now - s).unwrap_or(0),
+            })
+            .collect::<Vec<_>>();
+        regions.sort_by(|a, b| a.region.cmp(&b.region));
+        regions
+    }
+
+    /// Returns the most recent connection events, oldest first.
+    pub fn recent_events(&self) -> Vec<ConnectionEvent> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Exports the uptime of each region's connection.
+    pub fn report_uptime(&self) {
+        for region in self.regions() {
+            gauge!(WS_UPTIME_SECONDS.name, region.uptime_secs as f64, "region" => region.region);
+        }
+    }
+}
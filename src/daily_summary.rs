@@ -0,0 +1,265 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::errors::*;
+use crate::universalis::unix_now;
+use crate::xivapi::*;
+use itertools::Itertools;
+use mysql_async::{params, prelude::*, Pool};
+use serde::Serialize;
+
+/// How many of the noisiest alerts and items are listed in a summary.
+const TOP_N: usize = 10;
+
+/// How many alerts and items are counted individually each day. Once this
+/// many have been seen, new ones only count towards the totals.
+const MAX_TRACKED: usize = 100_000;
+
+#[derive(Default)]
+struct Tally {
+    since: i64,
+    events: u64,
+    matched: u64,
+    delivered: u64,
+    delivery_failures: u64,
+    not_fired: BTreeMap<&'static str, u64>,
+    alerts: HashMap<String, u64>,
+    items: HashMap<(i32, i32), u64>,
+}
+
+/// Totals over the course of a day, for the daily summary. Unlike metrics,
+/// these can be read back and reset.
+pub struct DailyStats {
+    tally: Mutex<Tally>,
+}
+
+impl DailyStats {
+    fn new() -> Self {
+        Self {
+            tally: Mutex::new(Tally {
+                since: unix_now(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn record_event(&self, world_id: i32, item_id: i32) {
+        let mut tally = self.tally.lock().unwrap();
+        tally.events += 1;
+        let tracked = tally.items.len() < MAX_TRACKED;
+        match tally.items.get_mut(&(world_id, item_id)) {
+            Some(count) => *count += 1,
+            None if tracked => {
+                tally.items.insert((world_id, item_id), 1);
+            }
+            None => {}
+        }
+    }
+
+    pub fn record_match(&self, alert_id: &str) {
+        let mut tally = self.tally.lock().unwrap();
+        tally.matched += 1;
+        let tracked = tally.alerts.len() < MAX_TRACKED;
+        match tally.alerts.get_mut(alert_id) {
+            Some(count) => *count += 1,
+            None if tracked => {
+                tally.alerts.insert(alert_id.to_owned(), 1);
+            }
+            None => {}
+        }
+    }
+
+    pub fn record_delivery(&self, delivered: bool) {
+        let mut tally = self.tally.lock().unwrap();
+        if delivered {
+            tally.delivered += 1;
+        } else {
+            tally.delivery_failures += 1;
+        }
+    }
+
+    pub fn record_not_fired(&self, reason: &'static str, count: u64) {
+        *self
+            .tally
+            .lock()
+            .unwrap()
+            .not_fired
+            .entry(reason)
+            .or_default() += count;
+    }
+
+    /// Summarizes everything recorded since the last summary, and starts
+    /// counting afresh.
+    pub fn take(&self) -> DailySummary {
+        let now = unix_now();
+        let tally = std::mem::replace(
+            &mut *self.tally.lock().unwrap(),
+            Tally {
+                since: now,
+                ..Default::default()
+            },
+        );
+        let noisiest_alerts = tally
+            .alerts
+            .into_iter()
+            .sorted_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)))
+            .take(TOP_N)
+            .map(|(alert_id, matches)| AlertMatches { alert_id, matches })
+            .collect();
+        let noisiest_items = tally
+            .items
+            .into_iter()
+            .sorted_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)))
+            .take(TOP_N)
+            .map(|((world_id, item_id), events)| ItemEvents {
+                world_id,
+                item_id,
+                item_name: None,
+                events,
+            })
+            .collect();
+        DailySummary {
+            from: tally.since,
+            to: now,
+            events: tally.events,
+            matched: tally.matched,
+            delivered: tally.delivered,
+            delivery_failures: tally.delivery_failures,
+            not_fired: tally.not_fired,
+            noisiest_alerts,
+            noisiest_items,
+        }
+    }
+}
+
+/// Returns the process's daily stats, which the pipeline records into.
+pub fn daily_stats() -> &'static DailyStats {
+    static DAILY_STATS: OnceLock<DailyStats> = OnceLock::new();
+    DAILY_STATS.get_or_init(DailyStats::new)
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AlertMatches {
+    pub alert_id: String,
+    pub matches: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ItemEvents {
+    pub world_id: i32,
+    pub item_id: i32,
+    pub item_name: Option<String>,
+    pub events: u64,
+}
+
+/// What the service did over a day.
+#[derive(Serialize, Debug, Clone)]
+pub struct DailySummary {
+    /// The start and end of the period, in seconds since the Unix epoch.
+    pub from: i64,
+    pub to: i64,
+    pub events: u64,
+    /// How many times alerts matched, including ones that weren't delivered.
+    pub matched: u64,
+    /// Notifications delivered to each destination, and the ones that failed.
+    pub delivered: u64,
+    pub delivery_failures: u64,
+    /// Why matching alerts, or alerts that could have matched, didn't fire.
+    pub not_fired: BTreeMap<&'static str, u64>,
+    pub noisiest_alerts: Vec<AlertMatches>,
+    pub noisiest_items: Vec<ItemEvents>,
+}
+
+impl DailySummary {
+    /// Names the noisiest items, leaving them unnamed if XIVAPI can't be
+    /// reached.
+    pub async fn name_items(&mut self) {
+        let ids = self.noisiest_items.iter().map(|i| i.item_id).collect_vec();
+        match get_items(&ids).await {
+            Ok(mut names) => {
+                for item in &mut self.noisiest_items {
+                    item.item_name = names
+                        .get_mut(&item.item_id)
+                        .and_then(Option::take)
+                        .map(|item| item.name);
+                }
+            }
+            Err(err) => warn!("failed to look up item names: {:?}", err),
+        }
+    }
+
+    /// Renders the summary as a plain-text message for the ops webhook.
+    pub fn to_message(&self) -> String {
+        let hours = (self.to - self.from) as f64 / 3600.0;
+        let mut message = format!(
+            "**Daily summary** (last {:.1} hours)\nEvents: {}\nMatched: {}\nDelivered: {} ({} failed)",
+            hours, self.events, self.matched, self.delivered, self.delivery_failures
+        );
+        if !self.not_fired.is_empty() {
+            let reasons = self
+                .not_fired
+                .iter()
+                .sorted_by(|a, b| b.1.cmp(a.1))
+                .map(|(reason, count)| format!("{} {}", reason, count))
+                .join(", ");
+            let _ = write!(message, "\nNot fired: {}", reasons);
+        }
+        if !self.noisiest_alerts.is_empty() {
+            message.push_str("\nNoisiest alerts:");
+            for alert in &self.noisiest_alerts {
+                let _ = write!(message, "\n- {}: {}", alert.alert_id, alert.matches);
+            }
+        }
+        if !self.noisiest_items.is_empty() {
+            message.push_str("\nNoisiest items:");
+            for item in &self.noisiest_items {
+                let name = item
+                    .item_name
+                    .clone()
+                    .unwrap_or_else(|| format!("item {}", item.item_id));
+                let _ = write!(
+                    message,
+                    "\n- {} on world {}: {}",
+                    name, item.world_id, item.events
+                );
+            }
+        }
+        message
+    }
+}
+
+/// Records a summary in `users_alerts_daily_stats`.
+pub async fn save_summary(summary: &DailySummary, pool: &Pool) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+    r"INSERT INTO `users_alerts_daily_stats` (`period_start`, `period_end`, `events`, `matched`, `delivered`, `delivery_failures`, `summary`) VALUES (:period_start, :period_end, :events, :matched, :delivered, :delivery_failures, :summary)"
+        .with(params! {
+            "period_start" => summary.from,
+            "period_end" => summary.to,
+            "events" => summary.events,
+            "matched" => summary.matched,
+            "delivered" => summary.delivered,
+            "delivery_failures" => summary.delivery_failures,
+            "summary" => serde_json::to_string(summary)?,
+        })
+        .ignore(&mut conn)
+        .await?;
+    Ok(())
+}
+
+/// Returns how long to wait until the daily summary is next due, at the
+/// hour (UTC) set by `UNIVERSALIS_ALERTS_DAILY_SUMMARY_HOUR`. Daily summaries
+/// are disabled if it isn't set.
+pub fn next_summary_in() -> Option<Duration> {
+    let hour: i64 = env::var("UNIVERSALIS_ALERTS_DAILY_SUMMARY_HOUR")
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|hour| (0..24).contains(hour))?;
+    let now = unix_now();
+    let due = now.div_euclid(86400) * 86400 + hour * 3600;
+    let due = if due > now { due } else { due + 86400 };
+    Some(Duration::from_secs((due - now) as u64))
+}
@@ -0,0 +1,170 @@
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::errors::*;
+use crate::state::*;
+use serde::{Deserialize, Serialize};
+
+/// The state store namespace the last notifications are kept under.
+const NAMESPACE: &str = "dedupe";
+
+/// The last notification sent for an alert.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct LastNotification {
+    value: f32,
+    /// Seconds since the Unix epoch.
+    sent_at: u64,
+}
+
+/// Suppresses repeated notifications for an alert whose trigger keeps
+/// evaluating to the same value. The last notification for each alert is
+/// kept in the state store, so it survives restarts or is shared between
+/// instances if the store is set up to.
+pub struct NotificationDedupe {
+    window: Duration,
+    state: Arc<StateStore>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl NotificationDedupe {
+    /// Reads the dedupe window from `UNIVERSALIS_ALERTS_DEDUPE_SECS`. A
+    /// window of zero (the default) disables deduplication.
+    pub fn from_env(state: Arc<StateStore>) -> Self {
+        let window = env::var("UNIVERSALIS_ALERTS_DEDUPE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        Self::new(Duration::from_secs(window), state)
+    }
+
+    pub fn new(window: Duration, state: Arc<StateStore>) -> Self {
+        Self { window, state }
+    }
+
+    async fn last(&self, alert_id: &str) -> Result<Option<LastNotification>> {
+        self.state.get_json(NAMESPACE, alert_id).await
+    }
+
+    /// Returns whether a notification with this value would repeat the last
+    /// one sent for an alert within a dedupe window of at least
+    /// `min_window`. If the state store can't be read, it's assumed not to.
+    pub async fn is_repeat(&self, alert_id: &str, value: f32, min_window: Duration) -> bool {
+        let window = self.window.max(min_window);
+        if window.is_zero() {
+            return false;
+        }
+        match self.last(alert_id).await {
+            Ok(previous) => previous.is_some_and(|previous| {
+                now_secs().saturating_sub(previous.sent_at) < window.as_secs()
+                    && previous.value == value
+            }),
+            Err(err) => {
+                warn!(
+                    "failed to read the last notification for {}: {:?}",
+                    alert_id, err
+                );
+                false
+            }
+        }
+    }
+
+    /// Records that a notification was sent for an alert, for as long as the
+    /// dedupe window lasts. This should only be called once it's been
+    /// delivered, so that a failed send can be retried straight away.
+    pub async fn record(&self, alert_id: &str, value: f32, min_window: Duration) {
+        let window = self.window.max(min_window);
+        if window.is_zero() {
+            return;
+        }
+        let last = LastNotification {
+            value,
+            sent_at: now_secs(),
+        };
+        if let Err(err) = self
+            .state
+            .set_json(NAMESPACE, alert_id, &last, Some(window))
+            .await
+        {
+            warn!(
+                "failed to record the last notification for {}: {:?}",
+                alert_id, err
+            );
+        }
+    }
+
+    /// Returns the value of an alert's last notification and when repeats of
+    /// it stop being suppressed, in seconds since the Unix epoch, if they're
+    /// currently suppressed.
+    pub async fn cooldown(
+        &self,
+        alert_id: &str,
+        min_window: Duration,
+    ) -> Result<Option<(f32, u64)>> {
+        let window = self.window.max(min_window).as_secs();
+        Ok(self.last(alert_id).await?.and_then(|previous| {
+            let until = previous.sent_at + window;
+            (now_secs() < until).then_some((previous.value, until))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dedupe(window: Duration) -> NotificationDedupe {
+        NotificationDedupe::new(
+            window,
+            Arc::new(StateStore::new(Box::<MemoryState>::default())),
+        )
+    }
+
+    #[tokio::test]
+    async fn repeats_are_only_suppressed_once_recorded() {
+        let dedupe = dedupe(Duration::from_secs(60));
+        assert!(!dedupe.is_repeat("alert", 100.0, Duration::ZERO).await);
+        // Checking alone, as when the send then fails, leaves no record
+        assert!(!dedupe.is_repeat("alert", 100.0, Duration::ZERO).await);
+        assert!(dedupe
+            .cooldown("alert", Duration::ZERO)
+            .await
+            .unwrap()
+            .is_none());
+
+        dedupe.record("alert", 100.0, Duration::ZERO).await;
+        assert!(dedupe.is_repeat("alert", 100.0, Duration::ZERO).await);
+        assert!(!dedupe.is_repeat("alert", 90.0, Duration::ZERO).await);
+        assert!(!dedupe.is_repeat("other", 100.0, Duration::ZERO).await);
+        assert!(dedupe
+            .cooldown("alert", Duration::ZERO)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn min_window_extends_a_disabled_window() {
+        let dedupe = dedupe(Duration::ZERO);
+        dedupe.record("alert", 100.0, Duration::ZERO).await;
+        assert!(
+            !dedupe
+                .is_repeat("alert", 100.0, Duration::from_secs(60))
+                .await
+        );
+
+        dedupe.record("alert", 100.0, Duration::from_secs(60)).await;
+        assert!(!dedupe.is_repeat("alert", 100.0, Duration::ZERO).await);
+        assert!(
+            dedupe
+                .is_repeat("alert", 100.0, Duration::from_secs(60))
+                .await
+        );
+    }
+}
@@ -0,0 +1,449 @@
+use std::env;
+use std::time::{Duration, Instant};
+
+use crate::alerts::*;
+#[cfg(feature = "chaos")]
+use crate::chaos::*;
+use crate::discord::*;
+use crate::errors::*;
+use crate::format::format_duration_minutes;
+use crate::scoreboard::*;
+use crate::timeouts::*;
+use crate::trigger::*;
+use crate::universalis::{unix_now, MarketChannel};
+use crate::world_status::*;
+use crate::xivapi::*;
+use bytes::Bytes;
+use itertools::Itertools;
+use reqwest::Client;
+use serde::Serialize;
+
+/// How notifications are delivered once an alert has matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryMode {
+    /// Send webhooks directly from the event processor.
+    Direct,
+    /// Write rendered notifications to the outbox table, to be sent by the
+    /// `alerts-delivery` worker.
+    Outbox,
+}
+
+impl DeliveryMode {
+    /// Reads the delivery mode from `UNIVERSALIS_ALERTS_DELIVERY`, which may
+    /// be `direct` (the default) or `outbox`.
+    pub fn from_env() -> Result<Self> {
+        match env::var("UNIVERSALIS_ALERTS_DELIVERY").as_deref() {
+            Err(_) | Ok("direct") => Ok(Self::Direct),
+            Ok("outbox") => Ok(Self::Outbox),
+            Ok(other) => Err(format!("unknown delivery mode: {}", other).into()),
+        }
+    }
+}
+
+/// A rendered notification, ready to be posted to a webhook. The payload is
+/// shared between all of an alert's webhooks rather than copied for each.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub alert_id: String,
+    pub discord_webhook: String,
+    pub payload: Bytes,
+}
+
+/// Reads how old an event can be before its notifications are marked as
+/// delayed from `UNIVERSALIS_ALERTS_EVENT_DEADLINE_SECS` (30 by default).
+pub fn event_deadline_from_env() -> Duration {
+    Duration::from_secs(
+        env::var("UNIVERSALIS_ALERTS_EVENT_DEADLINE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30),
+    )
+}
+
+/// The notice added to notifications for events that are this old, since
+/// their prices may have changed.
+fn late_notice(age: Duration) -> String {
+    format!(
+        "\u{23F1}\u{FE0F} Delayed notification \u{2014} this data is {} old, so prices may have changed.",
+        format_duration_minutes((age.as_secs_f32() / 60.0).max(1.0))
+    )
+}
+
+/// Marks an already rendered notification as delayed, for notifications
+/// that only turn out to be late once they're sent, such as those that
+/// waited in the outbox.
+pub fn annotate_late(notification: &Notification, age: Duration) -> Result<Notification> {
+    let mut payload: serde_json::Value = serde_json::from_slice(&notification.payload)?;
+    let description = payload
+        .pointer_mut("/embeds/0/description")
+        .and_then(|description| match description {
+            serde_json::Value::String(description) => Some(description),
+            _ => None,
+        })
+        .ok_or("notification has no embed description")?;
+    description.push_str("\n\n");
+    description.push_str(&late_notice(age));
+    Ok(Notification {
+        payload: Bytes::from(serde_json::to_vec(&payload)?),
+        ..notification.clone()
+    })
+}
+
+/// The branding applied to notification embeds, so that self-hosted
+/// deployments can use their own.
+#[derive(Debug, Clone)]
+pub struct Branding {
+    pub author_name: String,
+    pub author_icon_url: String,
+    pub footer_text: String,
+    pub footer_icon_url: String,
+    pub color: u32,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Self {
+            author_name: "Universalis Alert!".to_owned(),
+            author_icon_url: "https://cdn.discordapp.com/emojis/474543539771015168.png".to_owned(),
+            footer_text: "universalis.app".to_owned(),
+            footer_icon_url: "https://universalis.app/favicon.png".to_owned(),
+            color: 0xBD983A,
+        }
+    }
+}
+
+impl Branding {
+    /// Reads embed branding from `UNIVERSALIS_ALERTS_BRAND_AUTHOR_NAME`,
+    /// `UNIVERSALIS_ALERTS_BRAND_AUTHOR_ICON`, `UNIVERSALIS_ALERTS_BRAND_FOOTER_TEXT`,
+    /// `UNIVERSALIS_ALERTS_BRAND_FOOTER_ICON`, and `UNIVERSALIS_ALERTS_BRAND_COLOR`
+    /// (a hex color such as `BD983A`), using the Universalis branding for
+    /// anything that isn't set.
+    pub fn from_env() -> Result<Self> {
+        let defaults = Self::default();
+        let color = match env::var("UNIVERSALIS_ALERTS_BRAND_COLOR") {
+            Ok(color) => u32::from_str_radix(color.trim_start_matches('#'), 16)
+                .chain_err(|| "failed to parse UNIVERSALIS_ALERTS_BRAND_COLOR")?,
+            Err(_) => defaults.color,
+        };
+        Ok(Self {
+            author_name: env::var("UNIVERSALIS_ALERTS_BRAND_AUTHOR_NAME")
+                .unwrap_or(defaults.author_name),
+            author_icon_url: env::var("UNIVERSALIS_ALERTS_BRAND_AUTHOR_ICON")
+                .unwrap_or(defaults.author_icon_url),
+            footer_text: env::var("UNIVERSALIS_ALERTS_BRAND_FOOTER_TEXT")
+                .unwrap_or(defaults.footer_text),
+            footer_icon_url: env::var("UNIVERSALIS_ALERTS_BRAND_FOOTER_ICON")
+                .unwrap_or(defaults.footer_icon_url),
+            color,
+        })
+    }
+}
+
+/// A compact, machine-readable copy of a notification, for bots that react
+/// to alert messages.
+#[derive(Serialize, Debug)]
+struct StructuredEvent<'a> {
+    alert_id: &'a str,
+    item_id: i32,
+    world_id: i32,
+    region: &'a str,
+    channel: MarketChannel,
+    value: f32,
+}
+
+fn get_universalis_url(item_id: i32, world_name: &str) -> String {
+    format!(
+        "https://universalis.app/market/{}?server={}",
+        item_id, world_name
+    )
+}
+
+/// Describes why a player may not be able to get to a world.
+fn travel_hint(world_name: &str, status: &WorldStatus) -> Option<String> {
+    let creation = if status.character_creation_allowed {
+        ""
+    } else {
+        " Character creation there is also restricted."
+    };
+    if !status.travel_allowed {
+        Some(format!(
+            "\u{26A0}\u{FE0F} Data center travel to {} is currently unavailable.{}",
+            world_name, creation
+        ))
+    } else if status.is_congested() {
+        Some(format!(
+            "\u{26A0}\u{FE0F} {} is congested, so you may not be able to travel there.{}",
+            world_name, creation
+        ))
+    } else if status.category == WorldCategory::New {
+        Some(format!(
+            "\u{26A0}\u{FE0F} {} is a new world, so data center travel there may be restricted.{}",
+            world_name, creation
+        ))
+    } else if !status.character_creation_allowed {
+        Some(format!(
+            "\u{2139}\u{FE0F} Character creation on {} is currently restricted.",
+            world_name
+        ))
+    } else {
+        None
+    }
+}
+
+/// Renders the Discord message for a matched alert, once for each of the
+/// alert's webhooks. If the world's status is given, the message includes a
+/// hint about travelling there. If the message updates one that was posted
+/// when the condition started matching, `ongoing_since` is when that was.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    skip(region, alert, trigger, trigger_result, branding, world_status, ongoing_since),
+    fields(alert_id = alert.id.as_str())
+)]
+pub async fn render_discord_message(
+    region: &str,
+    item_id: i32,
+    world_id: i32,
+    channel: MarketChannel,
+    alert: &UserAlert,
+    trigger: &AlertTrigger,
+    trigger_result: f32,
+    branding: &Branding,
+    world_status: Option<&WorldStatus>,
+    ongoing_since: Option<i64>,
+    late_by: Option<Duration>,
+) -> Result<Vec<Notification>> {
+    let webhooks = alert.webhooks();
+    if webhooks.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Items that have been removed from (or not yet added to) XIVAPI still
+    // get a notification, just without a name
+    let item_name = match get_item(item_id).await? {
+        Some(item) => item.name,
+        None => format!("\u{26A0}\u{FE0F} Item {}", item_id),
+    };
+    let world = get_world(world_id).await?;
+    let market_url = get_universalis_url(item_id, &world.name);
+    let embed_title = format!("Alert triggered for {} on {}", item_name, world.name);
+    let embed_footer_text = format!(
+        "{} | {} | {} | All prices include GST",
+        branding.footer_text, region, alert.name
+    );
+    let locale = alert.locale.as_deref().unwrap_or("en");
+    let formatted_result = trigger.format_result(trigger_result, locale);
+    let mut embed_description = format!("One of your alerts has been triggered by {} for the following reason(s):\n```c\n{}\n\n{}```\nYou can view the item page on Universalis by clicking [this link]({}).", channel.describe(), trigger.describe_for_embed(locale), formatted_result, market_url);
+    if let Some(hint) = world_status.and_then(|status| travel_hint(&world.name, status)) {
+        embed_description.push_str("\n\n");
+        embed_description.push_str(&hint);
+    }
+    if let Some(age) = late_by {
+        embed_description.push_str("\n\n");
+        embed_description.push_str(&late_notice(age));
+    }
+    if let Some(original) = &alert.duplicate_of {
+        embed_description.push_str(&format!(
+            "\n\nThis alert is identical to your alert \"{}\", so you may be notified twice. You can delete one of them.",
+            original.name
+        ));
+    }
+    // Discord renders these timestamps relative to the reader's clock
+    if let Some(since) = ongoing_since {
+        embed_description.push_str(&format!(
+            "\n\nStill matching since <t:{}:R>, last updated <t:{}:R>.",
+            since,
+            unix_now()
+        ));
+    }
+    // Bots can parse this instead of the English description; it's wrapped in
+    // a spoiler so that it stays out of the way for people.
+    let structured_content = if alert.structured_payload {
+        let structured = serde_json::to_string(&StructuredEvent {
+            alert_id: &alert.id,
+            item_id,
+            world_id,
+            region,
+            channel,
+            value: trigger_result,
+        })?;
+        Some(format!("||{}||", structured))
+    } else {
+        None
+    };
+    let content = [alert.mention(), structured_content]
+        .into_iter()
+        .flatten()
+        .join("\n");
+    let note = alert.display_note();
+    let fields = note
+        .as_deref()
+        .map(|value| DiscordEmbedField {
+            name: "Note",
+            value,
+        })
+        .into_iter()
+        .collect();
+    let payload = DiscordWebhookPayload {
+        content: (!content.is_empty()).then_some(content.as_str()),
+        embeds: [DiscordEmbed {
+            url: &market_url,
+            title: &embed_title,
+            description: &embed_description,
+            color: branding.color,
+            footer: DiscordEmbedFooter {
+                text: &embed_footer_text,
+                icon_url: &branding.footer_icon_url,
+            },
+            author: DiscordEmbedAuthor {
+                name: &branding.author_name,
+                icon_url: &branding.author_icon_url,
+            },
+            fields,
+        }]
+        .to_vec(),
+    };
+    let serialized = Bytes::from(serde_json::to_vec(&payload)?);
+
+    Ok(webhooks
+        .into_iter()
+        .map(|discord_webhook| Notification {
+            alert_id: alert.id.clone(),
+            discord_webhook,
+            payload: serialized.clone(),
+        })
+        .collect())
+}
+
+/// The most of an error response that is kept in the error.
+const MAX_ERROR_BODY: usize = 500;
+
+async fn post_webhook(notification: &Notification, client: &Client) -> Result<Option<String>> {
+    // Waiting for the message to be created makes Discord report errors with
+    // it, and return its ID
+    let mut url = url::Url::parse(&notification.discord_webhook)?;
+    if !url.query_pairs().any(|(key, _)| key == "wait") {
+        url.query_pairs_mut().append_pair("wait", "true");
+    }
+
+    let res = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(notification.payload.clone())
+        .send()
+        .await
+        .count_timeout(Service::Discord)?;
+    let status = res.status();
+    let body = res.text().await.count_timeout(Service::Discord)?;
+    if !status.is_success() {
+        let body = body.chars().take(MAX_ERROR_BODY).collect();
+        return Err(ErrorKind::Webhook(status.as_u16(), body).into());
+    }
+
+    // Webhooks that aren't Discord's might not return a message
+    Ok(serde_json::from_str::<DiscordMessage>(&body)
+        .ok()
+        .map(|message| message.id))
+}
+
+async fn patch_webhook_message(
+    notification: &Notification,
+    message_id: &str,
+    client: &Client,
+) -> Result<()> {
+    let mut url = url::Url::parse(&notification.discord_webhook)?;
+    url.path_segments_mut()
+        .map_err(|_| "webhook URL can't have messages")?
+        .extend(["messages", message_id]);
+
+    let res = client
+        .patch(url)
+        .header("Content-Type", "application/json")
+        .body(notification.payload.clone())
+        .send()
+        .await
+        .count_timeout(Service::Discord)?;
+    let status = res.status();
+    if !status.is_success() {
+        let body = res
+            .text()
+            .await
+            .count_timeout(Service::Discord)?
+            .chars()
+            .take(MAX_ERROR_BODY)
+            .collect();
+        return Err(ErrorKind::Webhook(status.as_u16(), body).into());
+    }
+    Ok(())
+}
+
+/// Replaces a message that a webhook posted with a rendered notification,
+/// recording how it went in the destination scoreboard, if there is one.
+#[tracing::instrument(skip(notification, client, scoreboard), fields(alert_id = notification.alert_id.as_str()))]
+pub async fn edit_notification(
+    notification: &Notification,
+    message_id: &str,
+    client: &Client,
+    scoreboard: Option<&DestinationScoreboard>,
+) -> Result<()> {
+    let started_at = Instant::now();
+    let edited = patch_webhook_message(notification, message_id, client).await;
+    if let Some(scoreboard) = scoreboard {
+        scoreboard.record(
+            &notification.discord_webhook,
+            started_at.elapsed(),
+            edited.as_ref().err().map(|err| err.to_string()).as_deref(),
+        );
+    }
+    edited
+}
+
+/// Posts a rendered notification to its webhook, returning the ID of the
+/// message it created, if any, and recording how it went in the destination
+/// scoreboard, if there is one.
+#[tracing::instrument(skip(notification, client, scoreboard), fields(alert_id = notification.alert_id.as_str()))]
+pub async fn send_notification(
+    notification: &Notification,
+    client: &Client,
+    scoreboard: Option<&DestinationScoreboard>,
+) -> Result<Option<String>> {
+    // Injected delays stand in for a slow service, not a slow destination
+    #[cfg(feature = "chaos")]
+    delivery_fault().await;
+    let started_at = Instant::now();
+    let sent = post_webhook(notification, client).await;
+    if let Some(scoreboard) = scoreboard {
+        scoreboard.record(
+            &notification.discord_webhook,
+            started_at.elapsed(),
+            sent.as_ref().err().map(|err| err.to_string()).as_deref(),
+        );
+    }
+    sent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn late_notifications_are_annotated_after_rendering() {
+        let notification = Notification {
+            alert_id: "alert".to_owned(),
+            discord_webhook: "https://example.com".to_owned(),
+            payload: Bytes::from_static(br#"{"embeds":[{"description":"Triggered"}]}"#),
+        };
+        let annotated = annotate_late(&notification, Duration::from_secs(5 * 60)).unwrap();
+        let payload: serde_json::Value = serde_json::from_slice(&annotated.payload).unwrap();
+        assert_eq!(
+            payload["embeds"][0]["description"],
+            format!("Triggered\n\n{}", late_notice(Duration::from_secs(5 * 60)))
+        );
+        assert_eq!(annotated.alert_id, "alert");
+
+        let no_embed = Notification {
+            payload: Bytes::from_static(b"{}"),
+            ..notification
+        };
+        assert!(annotate_late(&no_embed, Duration::from_secs(60)).is_err());
+    }
+}
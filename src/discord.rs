@@ -1,4 +1,4 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Debug, Clone)]
 pub struct DiscordEmbedFooter<'a> {
@@ -12,6 +12,12 @@ pub struct DiscordEmbedAuthor<'a> {
     pub icon_url: &'a str,
 }
 
+#[derive(Serialize, Debug, Clone)]
+pub struct DiscordEmbedField<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub struct DiscordEmbed<'a> {
     pub url: &'a str,
@@ -20,9 +26,19 @@ pub struct DiscordEmbed<'a> {
     pub color: u32,
     pub footer: DiscordEmbedFooter<'a>,
     pub author: DiscordEmbedAuthor<'a>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<DiscordEmbedField<'a>>,
 }
 
 #[derive(Serialize, Debug)]
 pub struct DiscordWebhookPayload<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<&'a str>,
     pub embeds: Vec<DiscordEmbed<'a>>,
 }
+
+/// The message a webhook posted, returned when it's called with `?wait=true`.
+#[derive(Deserialize, Debug)]
+pub struct DiscordMessage {
+    pub id: String,
+}
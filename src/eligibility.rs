@@ -0,0 +1,191 @@
+use crate::alerts::*;
+use crate::errors::*;
+use crate::features::*;
+use crate::item_lists::*;
+use crate::pipeline::Context;
+use crate::universalis::unix_now;
+use crate::validate::*;
+use serde::Serialize;
+
+/// Something that's stopping an alert from firing. Reasons are named like
+/// the ones that `universalis_alerts_not_fired` counts, where there is one.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Suppression {
+    /// The alert's trigger can't be parsed, so it's never evaluated.
+    InvalidTrigger { error: String },
+    /// The alert uses a feature that's turned off on this deployment.
+    FeatureDisabled { feature: String },
+    /// The alert's user, or every one of its webhooks, has been muted.
+    Muted { target: &'static str },
+    /// The alert has repeatedly stalled processing.
+    Quarantined { until: i64 },
+    /// The alert's schedule doesn't allow it to be evaluated right now. A
+    /// schedule that's never active has no end.
+    Unscheduled { until: Option<i64> },
+    /// The alert is in its or its user's quiet hours.
+    QuietHours { until: i64 },
+    /// The alert is past the per-user or per-item cap on alerts.
+    QuotaExceeded,
+    /// The world is closed to travel and the alert skips such worlds.
+    TravelSuppressed,
+    /// Events are being skipped during announced maintenance.
+    MaintenancePaused { until: i64 },
+    /// Events for the item are being skipped, because it's on the denylist
+    /// or the allowlist is in use without it. Entries without an expiry have
+    /// no end.
+    ItemListed {
+        list: ItemListKind,
+        until: Option<i64>,
+    },
+    /// Notifications with the same value as the last one aren't sent again
+    /// until the cooldown ends; a different value can still be sent.
+    CooldownActive { value: f32, until: i64 },
+}
+
+impl Suppression {
+    /// Returns when the suppression ends, if it ends by itself.
+    fn until(&self) -> Option<i64> {
+        match self {
+            Self::Quarantined { until }
+            | Self::QuietHours { until }
+            | Self::MaintenancePaused { until }
+            | Self::CooldownActive { until, .. } => Some(*until),
+            Self::Unscheduled { until } | Self::ItemListed { until, .. } => *until,
+            _ => None,
+        }
+    }
+}
+
+/// Whether an alert can fire, and if not, why not and when it next can.
+#[derive(Serialize, Debug, Clone)]
+pub struct Eligibility {
+    pub alert_id: String,
+    pub world_id: i32,
+    pub item_id: i32,
+    /// Whether nothing is stopping the alert from firing right now.
+    pub eligible: bool,
+    /// When the alert can next fire, in seconds since the Unix epoch. This is
+    /// unset if something is suppressing it that only an administrator or
+    /// the alert's owner can lift.
+    pub next_eligible_at: Option<i64>,
+    pub suppressions: Vec<Suppression>,
+}
+
+/// Works out what's stopping an alert from firing, from the cooldowns,
+/// schedules, quotas, mutes and quarantines that the pipeline applies.
+/// Returns nothing if the alert doesn't exist.
+pub async fn alert_eligibility(alert_id: &str, ctx: &Context) -> Result<Option<Eligibility>> {
+    let StoredAlert {
+        world_id,
+        item_id,
+        mut alert,
+    } = match ctx.alerts.alert(alert_id).await? {
+        Some(stored) => stored,
+        None => return Ok(None),
+    };
+    ctx.preferences.apply(&mut alert);
+    let now = unix_now();
+    let mut suppressions = Vec::new();
+
+    match parse_trigger(&alert.trigger, alert.trigger_version) {
+        Ok(trigger) => {
+            if let Err(disabled) = features().check_alert(&alert, &trigger) {
+                suppressions.push(Suppression::FeatureDisabled {
+                    feature: disabled.0,
+                });
+            }
+            match trigger.next_scheduled(now) {
+                Some(next) if next == now => {}
+                until => suppressions.push(Suppression::Unscheduled { until }),
+            }
+        }
+        Err(error) => suppressions.push(Suppression::InvalidTrigger { error }),
+    }
+
+    if let Some(quiet_hours) = alert.quiet_hours.filter(|_| alert.is_quiet(now)) {
+        suppressions.push(Suppression::QuietHours {
+            until: quiet_hours.ends_at(now, alert.utc_offset_minutes.unwrap_or(0)),
+        });
+    }
+
+    if ctx.mutes.is_user_muted(alert.user_id.as_deref()) {
+        suppressions.push(Suppression::Muted { target: "user" });
+    } else {
+        let webhooks = alert.webhooks();
+        if !webhooks.is_empty() && webhooks.iter().all(|w| ctx.mutes.is_webhook_muted(w)) {
+            suppressions.push(Suppression::Muted { target: "webhook" });
+        }
+    }
+
+    if let Some(remaining) = ctx.poison.quarantine_remaining(&alert.id) {
+        suppressions.push(Suppression::Quarantined {
+            until: now + remaining.as_secs() as i64,
+        });
+    }
+
+    // Alerts past the caps are left out when the item's alerts are loaded.
+    // Wildcard alerts are capped separately for every item, so they're skipped.
+    let loadable = suppressions.iter().all(|s| {
+        !matches!(
+            s,
+            Suppression::InvalidTrigger { .. } | Suppression::FeatureDisabled { .. }
+        )
+    });
+    if item_id != -1 && loadable {
+        let loaded = ctx
+            .alerts
+            .alerts_for_world_item(world_id, item_id, ctx.alert_limits)
+            .await?;
+        if !loaded.iter().any(|(a, _)| a.id == alert.id) {
+            suppressions.push(Suppression::QuotaExceeded);
+        }
+    }
+
+    let restricted = ctx
+        .world_status
+        .get(world_id)
+        .is_some_and(|s| s.is_restricted());
+    if restricted && alert.travel_policy == TravelPolicy::Suppress {
+        suppressions.push(Suppression::TravelSuppressed);
+    }
+
+    if let Some(until) = ctx.maintenance.paused_until() {
+        suppressions.push(Suppression::MaintenancePaused { until });
+    }
+
+    // Wildcard alerts match every item, so no one entry stops them
+    if item_id != -1 {
+        if let Some(entry) = ctx.item_lists.blocking(item_id) {
+            suppressions.push(Suppression::ItemListed {
+                list: entry.list,
+                until: entry.expires_at,
+            });
+        }
+    }
+
+    // Wildcard alerts have a cooldown for each item they've matched
+    if item_id != -1 {
+        let min_window = ctx.event_stats.dedupe_window(world_id, item_id);
+        let state_key = alert.state_key(world_id, item_id);
+        if let Some((value, until)) = ctx.dedupe.cooldown(&state_key, min_window).await? {
+            suppressions.push(Suppression::CooldownActive {
+                value,
+                until: until as i64,
+            });
+        }
+    }
+
+    // The alert can fire once every suppression has ended
+    let next_eligible_at = suppressions
+        .iter()
+        .try_fold(now, |next, s| s.until().map(|until| next.max(until)));
+    Ok(Some(Eligibility {
+        alert_id: alert.id,
+        world_id,
+        item_id,
+        eligible: suppressions.is_empty(),
+        next_eligible_at,
+        suppressions,
+    }))
+}
@@ -1,3 +1,5 @@
+#![allow(unexpected_cfgs)]
+
 use error_chain::error_chain;
 
 error_chain! {
@@ -9,6 +11,7 @@ error_chain! {
         BsonDe(bson::de::Error);
         BsonSer(bson::ser::Error);
         Json(serde_json::Error);
+        Yaml(serde_yaml::Error);
         Database(mysql_async::Error);
         Env(std::env::VarError);
     }
@@ -23,5 +26,30 @@ error_chain! {
             description("connection closed"),
             display("connection closed: {}", msg),
         }
+
+        SubscriptionFailed(msg: String) {
+            description("subscription failed"),
+            display("subscription failed: {}", msg),
+        }
+
+        AlertColumn(column: &'static str, msg: String) {
+            description("failed to read alert column"),
+            display("failed to read alert column `{}`: {}", column, msg),
+        }
+
+        AlertsUnavailable {
+            description("alerts could not be loaded"),
+            display("alerts could not be loaded"),
+        }
+
+        TimedOut(service: &'static str) {
+            description("request timed out"),
+            display("{} request timed out", service),
+        }
+
+        Webhook(status: u16, body: String) {
+            description("webhook request failed"),
+            display("webhook responded with {}: {}", status, body),
+        }
     }
 }
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config::env_or;
+use crate::metrics_registry::*;
+use crate::universalis::unix_now;
+use metrics::gauge;
+use serde::Serialize;
+
+/// How long each bucket of event counts covers, in seconds.
+const BUCKET_SECS: i64 = 60;
+
+/// The number of buckets kept for each item, which is how many minutes the
+/// event rate is measured over.
+const BUCKETS: usize = 15;
+
+/// The event counts for one (world, item) pair, in a ring of one-minute
+/// buckets.
+#[derive(Debug, Clone, Default)]
+struct ItemBuckets {
+    counts: [u32; BUCKETS],
+    /// The bucket number (minutes since the Unix epoch) of the latest event.
+    latest: i64,
+}
+
+impl ItemBuckets {
+    /// Clears the buckets that have passed since the latest event.
+    fn advance(&mut self, bucket: i64) {
+        if bucket <= self.latest {
+            return;
+        }
+        let passed = (bucket - self.latest).min(BUCKETS as i64);
+        for b in (bucket - passed + 1)..=bucket {
+            self.counts[b.rem_euclid(BUCKETS as i64) as usize] = 0;
+        }
+        self.latest = bucket;
+    }
+
+    fn record(&mut self, bucket: i64) {
+        self.advance(bucket);
+        self.counts[bucket.rem_euclid(BUCKETS as i64) as usize] += 1;
+    }
+
+    /// Returns the number of events within the window ending at `bucket`.
+    fn total(&self, bucket: i64) -> u32 {
+        // Buckets older than the window would've been cleared by advancing
+        let stale = (bucket - self.latest).clamp(0, BUCKETS as i64) as usize;
+        if stale == BUCKETS {
+            return 0;
+        }
+        (0..BUCKETS - stale)
+            .map(|age| self.counts[(self.latest - age as i64).rem_euclid(BUCKETS as i64) as usize])
+            .sum()
+    }
+}
+
+/// The recent event rate of an item on a world.
+#[derive(Serialize, Debug, Clone)]
+pub struct ItemEventRate {
+    pub world_id: i32,
+    pub item_id: i32,
+    /// The item's name, where it's been looked up.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub item_name: Option<String>,
+    /// Events per minute, averaged over the last 15 minutes.
+    pub events_per_minute: f32,
+    /// Whether the item is updated often enough to be handled as chatty.
+    pub chatty: bool,
+}
+
+#[derive(Serialize)]
+pub struct EventStatsReport {
+    /// How many items have had events recently.
+    pub tracked: usize,
+    pub chatty_threshold: f32,
+    /// The items with the most events, busiest first.
+    pub items: Vec<ItemEventRate>,
+}
+
+/// Rolling per-(world, item) event rates, which the rest of the pipeline
+/// uses to adapt to items that are updated far more often than most. Chatty
+/// items are sampled first when load is shed, and can have a longer dedupe
+/// window so their alerts don't repeat with every upload.
+pub struct EventStats {
+    chatty_threshold: f32,
+    /// The shortest dedupe window for alerts on chatty items.
+    chatty_dedupe: Duration,
+    items: Mutex<HashMap<(i32, i32), ItemBuckets>>,
+}
+
+impl EventStats {
+    /// Reads the rate above which items are chatty from
+    /// `UNIVERSALIS_ALERTS_CHATTY_EVENTS_PER_MINUTE` (default 20), and the
+    /// dedupe window for them from `UNIVERSALIS_ALERTS_CHATTY_DEDUPE_SECS`
+    /// (default 0, which leaves it the same as for other items).
+    pub fn from_env() -> Self {
+        Self {
+            chatty_threshold: env_or("UNIVERSALIS_ALERTS_CHATTY_EVENTS_PER_MINUTE", 20.0),
+            chatty_dedupe: Duration::try_from_secs_f32(env_or(
+                "UNIVERSALIS_ALERTS_CHATTY_DEDUPE_SECS",
+                0.0,
+            ))
+            .unwrap_or_default(),
+            items: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn rate_of(buckets: &ItemBuckets, bucket: i64) -> f32 {
+        buckets.total(bucket) as f32 / BUCKETS as f32
+    }
+
+    /// Records an event for an item on a world, returning whether the item
+    /// is chatty.
+    pub fn record(&self, world_id: i32, item_id: i32) -> bool {
+        let bucket = unix_now().div_euclid(BUCKET_SECS);
+        let mut items = self.items.lock().unwrap();
+        let buckets = items.entry((world_id, item_id)).or_default();
+        buckets.record(bucket);
+        Self::rate_of(buckets, bucket) >= self.chatty_threshold
+    }
+
+    /// Returns an item's recent event rate, in events per minute.
+    pub fn rate(&self, world_id: i32, item_id: i32) -> f32 {
+        let bucket = unix_now().div_euclid(BUCKET_SECS);
+        self.items
+            .lock()
+            .unwrap()
+            .get(&(world_id, item_id))
+            .map_or(0.0, |buckets| Self::rate_of(buckets, bucket))
+    }
+
+    pub fn is_chatty(&self, world_id: i32, item_id: i32) -> bool {
+        self.rate(world_id, item_id) >= self.chatty_threshold
+    }
+
+    /// Returns the dedupe window that alerts on an item need at least, which
+    /// is zero unless the item is chatty.
+    pub fn dedupe_window(&self, world_id: i32, item_id: i32) -> Duration {
+        if !self.chatty_dedupe.is_zero() && self.is_chatty(world_id, item_id) {
+            self.chatty_dedupe
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// Forgets items that haven't had any events within the window. This is
+    /// run periodically so that items that stop being updated don't stay
+    /// tracked forever.
+    pub fn decay(&self) {
+        let bucket = unix_now().div_euclid(BUCKET_SECS);
+        let mut items = self.items.lock().unwrap();
+        items.retain(|_, buckets| buckets.total(bucket) > 0);
+        let chatty = items
+            .values()
+            .filter(|buckets| Self::rate_of(buckets, bucket) >= self.chatty_threshold)
+            .count();
+        gauge!(EVENT_STATS_TRACKED_ITEMS.name, items.len() as f64);
+        gauge!(EVENT_STATS_CHATTY_ITEMS.name, chatty as f64);
+    }
+
+    fn item_rate(&self, (world_id, item_id): (i32, i32), events_per_minute: f32) -> ItemEventRate {
+        ItemEventRate {
+            world_id,
+            item_id,
+            item_name: None,
+            events_per_minute,
+            chatty: events_per_minute >= self.chatty_threshold,
+        }
+    }
+
+    /// Returns the rate of one item on a world.
+    pub fn item(&self, world_id: i32, item_id: i32) -> ItemEventRate {
+        self.item_rate((world_id, item_id), self.rate(world_id, item_id))
+    }
+
+    /// Reports the busiest `limit` items.
+    pub fn report(&self, limit: usize) -> EventStatsReport {
+        let bucket = unix_now().div_euclid(BUCKET_SECS);
+        let items = self.items.lock().unwrap();
+        let mut rates = items
+            .iter()
+            .map(|(key, buckets)| (*key, Self::rate_of(buckets, bucket)))
+            .filter(|(_, rate)| *rate > 0.0)
+            .collect::<Vec<_>>();
+        rates.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        EventStatsReport {
+            tracked: rates.len(),
+            chatty_threshold: self.chatty_threshold,
+            items: rates
+                .into_iter()
+                .take(limit)
+                .map(|(key, rate)| self.item_rate(key, rate))
+                .collect(),
+        }
+    }
+}
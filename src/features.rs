@@ -0,0 +1,138 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::sync::OnceLock;
+
+use crate::alerts::UserAlert;
+use crate::errors::*;
+use crate::trigger::AlertTrigger;
+
+/// The capabilities that are turned off on this deployment. Everything is
+/// enabled unless it's listed, so self-hosted deployments get every trigger
+/// feature, while the hosted service can roll risky ones out gradually.
+///
+/// Features are named after how they're written in the trigger format:
+/// `reducer:gap`, `mapper:pricePerUnitLessMateria`, `comparison:below_rest`,
+/// `baseline:global_min`, `filter_mode:any`, `take`, `schedule`, and so on
+/// (see [`AlertTrigger::features`]). Trigger versions are turned off with
+/// `trigger_version:<version>`, and per-alert delivery options with
+/// `sink:edit_in_place` and `sink:structured_payload`.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureFlags {
+    disabled: BTreeSet<String>,
+}
+
+impl FeatureFlags {
+    /// Reads the disabled features from `UNIVERSALIS_ALERTS_DISABLED_FEATURES`
+    /// (comma-separated), and from the file named by
+    /// `UNIVERSALIS_ALERTS_FEATURES_FILE`, which lists one feature per line,
+    /// ignoring blank lines and lines starting with `#`.
+    pub fn from_env() -> Result<Self> {
+        let mut disabled = env::var("UNIVERSALIS_ALERTS_DISABLED_FEATURES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+            .map(str::to_owned)
+            .collect::<BTreeSet<_>>();
+        if let Ok(path) = env::var("UNIVERSALIS_ALERTS_FEATURES_FILE") {
+            let contents = fs::read_to_string(&path)
+                .chain_err(|| format!("failed to read feature flags from {}", path))?;
+            disabled.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .map(str::to_owned),
+            );
+        }
+        Ok(Self { disabled })
+    }
+
+    pub fn is_enabled(&self, feature: &str) -> bool {
+        !self.disabled.contains(feature)
+    }
+
+    /// Returns the features that are turned off, in order.
+    pub fn disabled(&self) -> impl Iterator<Item = &str> {
+        self.disabled.iter().map(String::as_str)
+    }
+
+    /// Checks that a trigger only uses enabled features, returning the first
+    /// disabled one it uses.
+    pub fn check_trigger(
+        &self,
+        trigger: &AlertTrigger,
+        trigger_version: i32,
+    ) -> std::result::Result<(), DisabledFeature> {
+        if self.disabled.is_empty() {
+            return Ok(());
+        }
+        let version = format!("trigger_version:{}", trigger_version);
+        if !self.is_enabled(&version) {
+            return Err(DisabledFeature(version));
+        }
+        match trigger.features().into_iter().find(|f| !self.is_enabled(f)) {
+            Some(feature) => Err(DisabledFeature(feature.to_owned())),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that an alert's trigger and delivery options only use enabled
+    /// features, in the same way as [`FeatureFlags::check_trigger`].
+    pub fn check_alert(
+        &self,
+        alert: &UserAlert,
+        trigger: &AlertTrigger,
+    ) -> std::result::Result<(), DisabledFeature> {
+        self.check_trigger(trigger, alert.trigger_version)?;
+        let sinks = [
+            ("sink:edit_in_place", alert.edit_in_place),
+            ("sink:structured_payload", alert.structured_payload),
+        ];
+        match sinks
+            .into_iter()
+            .find(|(sink, used)| *used && !self.is_enabled(sink))
+        {
+            Some((sink, _)) => Err(DisabledFeature(sink.to_owned())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A feature that's been turned off, which something tried to use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisabledFeature(pub String);
+
+impl Display for DisabledFeature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self.0.strip_prefix("trigger_version:") {
+            Some(version) => f.write_fmt(format_args!(
+                "trigger version {} is not enabled on this deployment",
+                version
+            )),
+            None => f.write_fmt(format_args!(
+                "'{}' is not enabled on this deployment",
+                self.0
+            )),
+        }
+    }
+}
+
+static FEATURES: OnceLock<FeatureFlags> = OnceLock::new();
+
+/// Sets the feature flags for the rest of the process, which can only be
+/// done once, and before [`features`] is first called.
+pub fn init_features(flags: FeatureFlags) -> Result<()> {
+    FEATURES
+        .set(flags)
+        .map_err(|_| "feature flags were already initialized".into())
+}
+
+/// Returns the feature flags the process was started with. Processes that
+/// don't initialize them with [`init_features`], such as tools, have every
+/// feature enabled.
+pub fn features() -> &'static FeatureFlags {
+    FEATURES.get_or_init(FeatureFlags::default)
+}
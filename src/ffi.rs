@@ -0,0 +1,333 @@
+//! A C ABI over the trigger engine, so that other Universalis components can
+//! parse, validate, and evaluate triggers in-process with exactly the same
+//! semantics as this service.
+//!
+//! Strings are passed as NUL-terminated UTF-8, and structured values as JSON.
+//! Every string returned by these functions must be freed with
+//! [`universalis_alerts_string_free`], and every trigger with
+//! [`universalis_alerts_trigger_free`]. None of the functions block, and
+//! none of them unwind into the caller: a panic is reported as an error like
+//! any other.
+
+use std::any::Any;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::trigger::*;
+use crate::universalis::Listing;
+use crate::validate::*;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct ValidationReport {
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Reads a string argument, which may not be null.
+unsafe fn read_str<'a>(value: *const c_char, name: &str) -> Result<&'a str, String> {
+    if value.is_null() {
+        return Err(format!("{} is null", name));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| format!("{} is not valid UTF-8", name))
+}
+
+/// Hands a string to the caller, who must free it. Strings with a NUL in
+/// them can't be, since the caller would only see up to it.
+fn into_c_string(value: String) -> Result<*mut c_char, String> {
+    CString::new(value)
+        .map(CString::into_raw)
+        .map_err(|err| format!("result contains a NUL at byte {}", err.nul_position()))
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map_or("unknown panic", String::as_str),
+    }
+}
+
+/// Runs the body of an exported function, turning a panic into an error so
+/// that it doesn't unwind across the C ABI.
+fn guard<T>(body: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(body))
+        .unwrap_or_else(|panic| Err(format!("internal error: {}", panic_message(panic.as_ref()))))
+}
+
+/// Stores an error message in `error`, if the caller asked for one. Any NULs
+/// in it are escaped, so that the message always gets through.
+unsafe fn set_error(error: *mut *mut c_char, message: String) {
+    if !error.is_null() {
+        let message = message.replace('\0', "\\0");
+        *error = into_c_string(message).unwrap_or(ptr::null_mut());
+    }
+}
+
+/// Hands the result of an exported function to the caller, or null if it
+/// failed, storing why in `error`.
+unsafe fn respond(result: Result<*mut c_char, String>, error: *mut *mut c_char) -> *mut c_char {
+    match result {
+        Ok(value) => value,
+        Err(message) => {
+            set_error(error, message);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Validates a trigger, written as JSON or as an expression, returning a JSON
+/// report of the form `{"valid": false, "error": "..."}`. Returns null only
+/// if the report itself can't be built.
+///
+/// # Safety
+///
+/// `trigger` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn universalis_alerts_validate(
+    trigger: *const c_char,
+    trigger_version: i32,
+) -> *mut c_char {
+    let checked = guard(|| {
+        let trigger = read_str(trigger, "trigger")?;
+        parse_trigger(trigger, trigger_version).map(|_| ())
+    });
+    let report = ValidationReport {
+        valid: checked.is_ok(),
+        error: checked.err(),
+    };
+    let report = guard(|| serde_json::to_string(&report).map_err(|err| err.to_string()));
+    report.and_then(into_c_string).unwrap_or(ptr::null_mut())
+}
+
+/// Parses a trigger, written as JSON or as an expression. Returns null if it
+/// is invalid, storing why in `error` if that isn't null.
+///
+/// # Safety
+///
+/// `trigger` must be null or point to a NUL-terminated string, and `error`
+/// must be null or point to writable memory for a string pointer.
+#[no_mangle]
+pub unsafe extern "C" fn universalis_alerts_trigger_parse(
+    trigger: *const c_char,
+    trigger_version: i32,
+    error: *mut *mut c_char,
+) -> *mut AlertTrigger {
+    let parsed = guard(|| {
+        let trigger = read_str(trigger, "trigger")?;
+        parse_trigger(trigger, trigger_version)
+    });
+    match parsed {
+        Ok(parsed) => Box::into_raw(Box::new(parsed)),
+        Err(message) => {
+            set_error(error, message);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Describes a parsed trigger the way notifications do. Returns null if it
+/// can't be described, storing why in `error` if that isn't null.
+///
+/// # Safety
+///
+/// `trigger` must be a trigger returned by [`universalis_alerts_trigger_parse`]
+/// that hasn't been freed, and `error` must be null or point to writable
+/// memory for a string pointer.
+#[no_mangle]
+pub unsafe extern "C" fn universalis_alerts_trigger_describe(
+    trigger: *const AlertTrigger,
+    error: *mut *mut c_char,
+) -> *mut c_char {
+    let described = guard(|| {
+        let trigger = trigger.as_ref().ok_or("trigger is null")?;
+        into_c_string(trigger.to_string())
+    });
+    respond(described, error)
+}
+
+/// Returns a key that is the same for any two triggers that mean the same
+/// thing, such as two alerts that would always fire together. Returns null
+/// if there isn't one, storing why in `error` if that isn't null.
+///
+/// # Safety
+///
+/// `trigger` must be a trigger returned by [`universalis_alerts_trigger_parse`]
+/// that hasn't been freed, and `error` must be null or point to writable
+/// memory for a string pointer.
+#[no_mangle]
+pub unsafe extern "C" fn universalis_alerts_trigger_canonical_key(
+    trigger: *const AlertTrigger,
+    error: *mut *mut c_char,
+) -> *mut c_char {
+    let key = guard(|| {
+        let trigger = trigger.as_ref().ok_or("trigger is null")?;
+        into_c_string(trigger.canonical_key())
+    });
+    respond(key, error)
+}
+
+/// Evaluates a parsed trigger against a JSON array of listings, in the same
+/// format as the Universalis websocket's, and a JSON object of parameters
+/// (`reference`, `baselines`, and `materia_prices`, all optional), which may
+/// be null. Returns the evaluation as JSON, or null if the arguments are
+/// invalid, storing why in `error` if that isn't null.
+///
+/// # Safety
+///
+/// `trigger` must be a trigger returned by [`universalis_alerts_trigger_parse`]
+/// that hasn't been freed, `listings` and `parameters` must be null or point
+/// to NUL-terminated strings, and `error` must be null or point to writable
+/// memory for a string pointer.
+#[no_mangle]
+pub unsafe extern "C" fn universalis_alerts_trigger_evaluate(
+    trigger: *const AlertTrigger,
+    listings: *const c_char,
+    parameters: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut c_char {
+    let evaluated = guard(|| {
+        let trigger = trigger.as_ref().ok_or("trigger is null")?;
+        let listings = read_str(listings, "listings")?;
+        let listings = serde_json::from_str::<Vec<Listing>>(listings)
+            .map_err(|err| format!("invalid listings: {}", err))?;
+        let parameters = if parameters.is_null() {
+            TriggerParameters::default()
+        } else {
+            serde_json::from_str(read_str(parameters, "parameters")?)
+                .map_err(|err| format!("invalid parameters: {}", err))?
+        };
+        let evaluation = listings.iter().apply_trigger_with(trigger, &parameters);
+        let evaluation = serde_json::to_string(&evaluation).map_err(|err| err.to_string())?;
+        into_c_string(evaluation)
+    });
+    respond(evaluated, error)
+}
+
+/// Frees a trigger returned by [`universalis_alerts_trigger_parse`].
+///
+/// # Safety
+///
+/// `trigger` must be null or a trigger that hasn't already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn universalis_alerts_trigger_free(trigger: *mut AlertTrigger) {
+    if !trigger.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(trigger))));
+    }
+}
+
+/// Frees a string returned by any of these functions.
+///
+/// # Safety
+///
+/// `value` must be null or a string that hasn't already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn universalis_alerts_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(CString::from_raw(value))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c_string(value: &str) -> CString {
+        CString::new(value).unwrap()
+    }
+
+    /// Takes ownership of a string returned to the caller.
+    unsafe fn take(value: *mut c_char) -> Option<String> {
+        (!value.is_null()).then(|| CString::from_raw(value).into_string().unwrap())
+    }
+
+    unsafe fn parse(trigger: &str) -> Result<*mut AlertTrigger, String> {
+        let trigger = c_string(trigger);
+        let mut error = ptr::null_mut();
+        let parsed = universalis_alerts_trigger_parse(trigger.as_ptr(), 1, &mut error);
+        match take(error) {
+            Some(error) => Err(error),
+            None => Ok(parsed),
+        }
+    }
+
+    #[test]
+    fn parses_describes_and_evaluates_triggers() {
+        unsafe {
+            let trigger = parse("min(pricePerUnit where hq) < 1000").unwrap();
+            let mut error = ptr::null_mut();
+            assert!(take(universalis_alerts_trigger_describe(trigger, &mut error)).is_some());
+            assert!(take(universalis_alerts_trigger_canonical_key(
+                trigger, &mut error
+            ))
+            .is_some());
+
+            let listings = c_string(
+                r#"[{"pricePerUnit": 900, "quantity": 1, "total": 900, "tax": 0, "hq": true}]"#,
+            );
+            let evaluation = take(universalis_alerts_trigger_evaluate(
+                trigger,
+                listings.as_ptr(),
+                ptr::null(),
+                &mut error,
+            ));
+            assert!(evaluation.unwrap().contains("900"));
+            assert!(take(error).is_none());
+            universalis_alerts_trigger_free(trigger);
+        }
+    }
+
+    #[test]
+    fn reports_invalid_arguments() {
+        unsafe {
+            let report = take(universalis_alerts_validate(ptr::null(), 1)).unwrap();
+            assert_eq!(report, r#"{"valid":false,"error":"trigger is null"}"#);
+            assert!(parse("mn(pricePerUnit) < 5").is_err());
+
+            let trigger = parse("min(pricePerUnit) < 5").unwrap();
+            let listings = c_string("not json");
+            let mut error = ptr::null_mut();
+            let evaluation = universalis_alerts_trigger_evaluate(
+                trigger,
+                listings.as_ptr(),
+                ptr::null(),
+                &mut error,
+            );
+            assert!(evaluation.is_null());
+            assert!(take(error).unwrap().starts_with("invalid listings"));
+
+            let mut error = ptr::null_mut();
+            assert!(universalis_alerts_trigger_describe(ptr::null(), &mut error).is_null());
+            assert_eq!(take(error).as_deref(), Some("trigger is null"));
+            universalis_alerts_trigger_free(trigger);
+        }
+    }
+
+    #[test]
+    fn refuses_to_truncate_strings_at_a_nul() {
+        assert!(into_c_string("Ku\0po".to_owned()).is_err());
+        unsafe {
+            let trigger = parse(
+                r#"{"filters": [{"retainerName": {"equals": "Ku\u0000po"}}], "mapper": "pricePerUnit", "reducer": "min", "comparison": {"lt": {"target": 5}}}"#,
+            )
+            .unwrap();
+            let mut error = ptr::null_mut();
+            assert!(universalis_alerts_trigger_describe(trigger, &mut error).is_null());
+            assert!(take(error).unwrap().contains("NUL"));
+            universalis_alerts_trigger_free(trigger);
+        }
+    }
+
+    #[test]
+    fn catches_panics() {
+        let caught: Result<(), String> = guard(|| panic!("kupo"));
+        assert_eq!(caught, Err("internal error: kupo".to_owned()));
+        let caught: Result<(), String> = guard(|| panic!("{}", "formatted"));
+        assert_eq!(caught, Err("internal error: formatted".to_owned()));
+    }
+}
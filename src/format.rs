@@ -0,0 +1,86 @@
+/// The digit grouping and decimal separators used by a locale.
+struct Separators {
+    group: &'static str,
+    decimal: char,
+}
+
+fn get_separators(locale: &str) -> Separators {
+    // Only the language part of the locale matters here
+    let language = locale.split(['-', '_']).next().unwrap_or("");
+    match language {
+        "de" => Separators {
+            group: ".",
+            decimal: ',',
+        },
+        "fr" => Separators {
+            group: "\u{202F}",
+            decimal: ',',
+        },
+        _ => Separators {
+            group: ",",
+            decimal: '.',
+        },
+    }
+}
+
+/// Formats a number with the digit grouping of the specified locale,
+/// keeping at most two decimal places.
+pub fn format_number(value: f32, locale: &str) -> String {
+    let separators = get_separators(locale);
+    let formatted = format!("{:.2}", value.abs());
+    let (integer, fraction) = formatted.split_once('.').unwrap_or((&formatted, ""));
+    let fraction = fraction.trim_end_matches('0');
+
+    let mut grouped = String::new();
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push_str(separators.group);
+        }
+        grouped.push(digit);
+    }
+
+    let sign = if value < 0.0 { "-" } else { "" };
+    if fraction.is_empty() {
+        format!("{}{}", sign, grouped)
+    } else {
+        format!("{}{}{}{}", sign, grouped, separators.decimal, fraction)
+    }
+}
+
+/// What a trigger result measures, which determines how it is rounded
+/// and rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    /// A price, rounded to whole gil.
+    Gil,
+    /// A plain quantity, rounded to a whole number.
+    Count,
+    /// A duration in minutes, rendered as a relative time.
+    Minutes,
+    /// A percentage, kept to at most two decimal places.
+    Percent,
+}
+
+/// Rounds and formats a value for display to users.
+pub fn format_value(value: f32, kind: ValueKind, locale: &str) -> String {
+    match kind {
+        ValueKind::Gil => format!("{} gil", format_number(value.round(), locale)),
+        ValueKind::Count => format_number(value.round(), locale),
+        ValueKind::Minutes => format_duration_minutes(value),
+        ValueKind::Percent => format!("{}%", format_number(value, locale)),
+    }
+}
+
+/// Formats a duration in minutes as e.g. "45 minutes", "2h 5m", or "3d 4h".
+pub fn format_duration_minutes(minutes: f32) -> String {
+    let minutes = minutes.max(0.0).round() as u64;
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    match (days, hours, minutes) {
+        (0, 0, 1) => "1 minute".to_owned(),
+        (0, 0, m) => format!("{} minutes", m),
+        (0, h, 0) => format!("{}h", h),
+        (0, h, m) => format!("{}h {}m", h, m),
+        (d, 0, _) => format!("{}d", d),
+        (d, h, _) => format!("{}d {}h", d, h),
+    }
+}
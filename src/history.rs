@@ -0,0 +1,138 @@
+#[cfg(feature = "chaos")]
+use crate::chaos::*;
+use crate::errors::*;
+use itertools::Itertools;
+use mysql_async::{params, prelude::*, Pool};
+use serde::Serialize;
+
+/// The most notifications returned by a single export.
+const MAX_EXPORT_ROWS: u32 = 100_000;
+
+/// A notification that was sent for an alert.
+#[derive(Serialize, Debug, Clone)]
+pub struct NotificationRecord {
+    pub alert_id: String,
+    pub user_id: Option<String>,
+    pub alert_name: String,
+    pub item_id: i32,
+    pub world_id: i32,
+    pub value: f32,
+    /// Seconds since the Unix epoch.
+    pub sent_at: i64,
+    /// The ID of the Discord message posted to each of the alert's webhooks,
+    /// in order, where one was.
+    pub message_ids: Vec<Option<String>>,
+    /// The ID the notification's snapshot is saved under, if snapshots were
+    /// enabled when it was sent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification_id: Option<String>,
+}
+
+/// Adds a sent notification to the history table.
+pub async fn record_notification(record: &NotificationRecord, pool: &Pool) -> Result<()> {
+    #[cfg(feature = "chaos")]
+    database_fault()?;
+    let mut conn = pool.get_conn().await?;
+    r"INSERT INTO `users_alerts_history` (`alert_id`, `user_id`, `alert_name`, `item_id`, `world_id`, `value`, `sent_at`, `message_ids`, `notification_id`) VALUES (:alert_id, :user_id, :alert_name, :item_id, :world_id, :value, :sent_at, :message_ids, :notification_id)"
+        .with(params! {
+            "alert_id" => &record.alert_id,
+            "user_id" => &record.user_id,
+            "alert_name" => &record.alert_name,
+            "item_id" => record.item_id,
+            "world_id" => record.world_id,
+            "value" => record.value,
+            "sent_at" => record.sent_at,
+            "message_ids" => serde_json::to_string(&record.message_ids)?,
+            "notification_id" => &record.notification_id,
+        })
+        .ignore(&mut conn)
+        .await?;
+    Ok(())
+}
+
+/// Fetches the notifications sent for a user's alerts between two times, in
+/// seconds since the Unix epoch, oldest first.
+pub async fn get_notification_history(
+    user_id: &str,
+    from: i64,
+    to: i64,
+    pool: &Pool,
+) -> Result<Vec<NotificationRecord>> {
+    #[cfg(feature = "chaos")]
+    database_fault()?;
+    let mut conn = pool.get_conn().await?;
+    let records = r"SELECT `alert_id`, `user_id`, `alert_name`, `item_id`, `world_id`, `value`, `sent_at`, `message_ids`, `notification_id` FROM `users_alerts_history` WHERE `user_id` = :user_id AND `sent_at` >= :from AND `sent_at` < :to ORDER BY `sent_at`, `id` LIMIT :limit"
+        .with(params! {
+            "user_id" => user_id,
+            "from" => from,
+            "to" => to,
+            "limit" => MAX_EXPORT_ROWS,
+        })
+        .map(
+            &mut conn,
+            |(
+                alert_id,
+                user_id,
+                alert_name,
+                item_id,
+                world_id,
+                value,
+                sent_at,
+                message_ids,
+                notification_id,
+            )| {
+                let message_ids: Option<String> = message_ids;
+                NotificationRecord {
+                    alert_id,
+                    user_id,
+                    alert_name,
+                    item_id,
+                    world_id,
+                    value,
+                    sent_at,
+                    message_ids: message_ids
+                        .and_then(|ids| serde_json::from_str(&ids).ok())
+                        .unwrap_or_default(),
+                    notification_id,
+                }
+            },
+        )
+        .await?;
+    Ok(records)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Renders notification records as CSV, with a header row.
+pub fn to_csv(records: &[NotificationRecord]) -> String {
+    let mut csv = String::from(
+        "alert_id,user_id,alert_name,item_id,world_id,value,sent_at,message_ids,notification_id\n",
+    );
+    for record in records {
+        // Destinations without a message are left empty, to keep positions
+        let message_ids = record
+            .message_ids
+            .iter()
+            .map(|id| id.as_deref().unwrap_or(""))
+            .join(";");
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{}\n",
+            csv_field(&record.alert_id),
+            csv_field(record.user_id.as_deref().unwrap_or("")),
+            csv_field(&record.alert_name),
+            record.item_id,
+            record.world_id,
+            record.value,
+            record.sent_at,
+            message_ids,
+            record.notification_id.as_deref().unwrap_or("")
+        ));
+    }
+    csv
+}
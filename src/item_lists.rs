@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::errors::*;
+use crate::universalis::unix_now;
+use mysql_async::{params, prelude::*, Pool};
+use serde::Serialize;
+
+/// Which list an item is on.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemListKind {
+    /// Events for the item are dropped.
+    Deny,
+    /// While any item is allowed, events for every other item are dropped.
+    Allow,
+}
+
+impl ItemListKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deny => "deny",
+            Self::Allow => "allow",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "deny" => Some(Self::Deny),
+            "allow" => Some(Self::Allow),
+            _ => None,
+        }
+    }
+}
+
+/// An item put on the allowlist or denylist by an operator, e.g. to quiet an
+/// item with a flood of uploads on patch day.
+#[derive(Serialize, Debug, Clone)]
+pub struct ItemListEntry {
+    pub list: ItemListKind,
+    pub item_id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub added_at: i64,
+    /// When the entry stops applying, in seconds since the Unix epoch, or
+    /// never if unset.
+    pub expires_at: Option<i64>,
+}
+
+impl ItemListEntry {
+    fn is_active(&self, now: i64) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+/// The items whose events are dropped before any alerts are matched against
+/// them. Entries made through the admin server apply immediately, entries in
+/// the database are picked up when it's reloaded, and expired entries are
+/// ignored.
+#[derive(Default)]
+pub struct ItemLists {
+    entries: RwLock<HashMap<(ItemListKind, i32), ItemListEntry>>,
+}
+
+impl ItemLists {
+    /// Returns the entry that keeps an item's events from being processed,
+    /// if there is one: the item's denylist entry, or, if the allowlist has
+    /// anything on it that the item isn't, the allowlist entry that expires
+    /// last.
+    pub fn blocking(&self, item_id: i32) -> Option<ItemListEntry> {
+        let entries = self.entries.read().unwrap();
+        if entries.is_empty() {
+            return None;
+        }
+        let now = unix_now();
+        let active = |kind| {
+            entries
+                .get(&(kind, item_id))
+                .filter(|entry| entry.is_active(now))
+        };
+        if let Some(entry) = active(ItemListKind::Deny) {
+            return Some(entry.clone());
+        }
+        if active(ItemListKind::Allow).is_some() {
+            return None;
+        }
+        entries
+            .values()
+            .filter(|entry| entry.list == ItemListKind::Allow && entry.is_active(now))
+            .max_by_key(|entry| entry.expires_at.unwrap_or(i64::MAX))
+            .cloned()
+    }
+
+    pub fn add(&self, entry: ItemListEntry) {
+        self.entries
+            .write()
+            .unwrap()
+            .insert((entry.list, entry.item_id), entry);
+    }
+
+    /// Takes an item off a list, returning whether it was on it.
+    pub fn remove(&self, list: ItemListKind, item_id: i32) -> bool {
+        self.entries
+            .write()
+            .unwrap()
+            .remove(&(list, item_id))
+            .is_some()
+    }
+
+    /// Replaces every entry with the ones loaded from the database.
+    pub fn replace(&self, entries: Vec<ItemListEntry>) {
+        *self.entries.write().unwrap() = entries
+            .into_iter()
+            .map(|entry| ((entry.list, entry.item_id), entry))
+            .collect();
+    }
+
+    /// Returns every entry that hasn't expired, most recent first.
+    pub fn list(&self) -> Vec<ItemListEntry> {
+        let now = unix_now();
+        let mut entries = self
+            .entries
+            .read()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.is_active(now))
+            .cloned()
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.added_at));
+        entries
+    }
+}
+
+/// A row of the item lists table: the list, item ID, reason, and when the
+/// entry was added and expires.
+type ItemListRow = (String, i32, Option<String>, i64, Option<i64>);
+
+/// Loads every entry that hasn't expired from the item lists table.
+pub async fn load_item_lists(pool: &Pool) -> Result<Vec<ItemListEntry>> {
+    let mut conn = pool.get_conn().await?;
+    let rows: Vec<ItemListRow> =
+        r"SELECT `list`, `item_id`, `reason`, `added_at`, `expires_at` FROM `users_alerts_item_lists` WHERE `expires_at` IS NULL OR `expires_at` > :now"
+            .with(params! { "now" => unix_now() })
+            .fetch(&mut conn)
+            .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(list, item_id, reason, added_at, expires_at)| {
+            Some(ItemListEntry {
+                list: ItemListKind::parse(&list)?,
+                item_id,
+                reason,
+                added_at,
+                expires_at,
+            })
+        })
+        .collect())
+}
+
+/// Adds an entry to the item lists table, replacing any existing entry for
+/// the item on the same list.
+pub async fn save_item_list_entry(entry: &ItemListEntry, pool: &Pool) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+    r"REPLACE INTO `users_alerts_item_lists` (`list`, `item_id`, `reason`, `added_at`, `expires_at`) VALUES (:list, :item_id, :reason, :added_at, :expires_at)"
+        .with(params! {
+            "list" => entry.list.as_str(),
+            "item_id" => entry.item_id,
+            "reason" => &entry.reason,
+            "added_at" => entry.added_at,
+            "expires_at" => entry.expires_at,
+        })
+        .ignore(&mut conn)
+        .await?;
+    Ok(())
+}
+
+/// Removes an entry from the item lists table.
+pub async fn delete_item_list_entry(list: ItemListKind, item_id: i32, pool: &Pool) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+    r"DELETE FROM `users_alerts_item_lists` WHERE `list` = :list AND `item_id` = :item_id"
+        .with(params! {
+            "list" => list.as_str(),
+            "item_id" => item_id,
+        })
+        .ignore(&mut conn)
+        .await?;
+    Ok(())
+}
+
+/// Deletes expired entries from the item lists table, returning how many
+/// there were.
+pub async fn expire_item_lists(pool: &Pool) -> Result<u64> {
+    let mut conn = pool.get_conn().await?;
+    r"DELETE FROM `users_alerts_item_lists` WHERE `expires_at` IS NOT NULL AND `expires_at` <= :now"
+        .with(params! { "now" => unix_now() })
+        .ignore(&mut conn)
+        .await?;
+    Ok(conn.affected_rows())
+}
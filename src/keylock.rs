@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::metrics_registry::*;
+use metrics::counter;
+use tokio::sync::OwnedMutexGuard;
+
+/// A (world, item) pair.
+type EventKey = (i32, i32);
+
+/// Serializes processing of events for the same world and item, so that
+/// state kept between events (such as dedupe entries and ongoing messages)
+/// sees them one at a time. Events for different keys still run
+/// concurrently.
+#[derive(Default)]
+pub struct KeyLocks {
+    locks: Mutex<HashMap<EventKey, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+/// Holds a key's lock until it's dropped.
+pub struct KeyGuard<'a> {
+    locks: &'a KeyLocks,
+    key: EventKey,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl KeyLocks {
+    /// Waits until no other event for the key is being processed.
+    pub async fn lock(&self, world_id: i32, item_id: i32) -> KeyGuard<'_> {
+        let key = (world_id, item_id);
+        let lock = self.locks.lock().unwrap().entry(key).or_default().clone();
+        let guard = match lock.clone().try_lock_owned() {
+            Ok(guard) => guard,
+            Err(_) => {
+                counter!(KEY_LOCK_WAITS.name, 1);
+                lock.lock_owned().await
+            }
+        };
+        KeyGuard {
+            locks: self,
+            key,
+            guard: Some(guard),
+        }
+    }
+}
+
+impl Drop for KeyGuard<'_> {
+    fn drop(&mut self) {
+        self.guard.take();
+        // Forget the lock once nothing else is waiting on it, so that locks
+        // don't accumulate for every item ever seen
+        let mut locks = self.locks.locks.lock().unwrap();
+        if locks
+            .get(&self.key)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(&self.key);
+        }
+    }
+}
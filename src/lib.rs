@@ -0,0 +1,64 @@
+#![allow(clippy::result_large_err)]
+
+#[macro_use]
+extern crate log;
+
+pub mod admin;
+pub mod alert_cache;
+pub mod alert_events;
+pub mod alerts;
+pub mod baseline;
+pub mod channels;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod coalesce;
+pub mod config;
+pub mod connection;
+pub mod connection_history;
+pub mod daily_summary;
+pub mod dedupe;
+pub mod delivery;
+pub mod discord;
+pub mod eligibility;
+pub mod errors;
+pub mod event_stats;
+pub mod features;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod format;
+pub mod history;
+pub mod item_lists;
+pub mod keylock;
+pub mod loadgen;
+pub mod maintenance;
+pub mod materia;
+pub mod metrics_export;
+pub mod metrics_registry;
+pub mod migrations;
+pub mod mutes;
+pub mod ongoing;
+pub mod ops;
+pub mod outbox;
+pub mod pipeline;
+pub mod poison;
+pub mod preferences;
+pub mod quarantine;
+pub mod redact;
+pub mod redis;
+pub mod retry;
+pub mod scoreboard;
+pub mod shedding;
+pub mod slo;
+pub mod snapshots;
+pub mod standalone;
+pub mod startup;
+pub mod state;
+pub mod subscriptions;
+pub mod telemetry;
+pub mod timeouts;
+pub mod trigger;
+pub mod trigger_stats;
+pub mod universalis;
+pub mod validate;
+pub mod world_status;
+pub mod xivapi;
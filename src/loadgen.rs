@@ -0,0 +1,326 @@
+//! Generates synthetic market board events for capacity testing, as
+//! described by a YAML file (see `loadgen.example.yaml`). Events are either
+//! run through the pipeline in-process or served to an alerts service over a
+//! local websocket, and throughput and latency are reported at the end.
+
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::errors::*;
+use crate::features::*;
+use crate::pipeline::*;
+use crate::universalis::unix_now;
+use bson::{doc, Document};
+use futures_util::{SinkExt, StreamExt};
+use mysql_async::Pool;
+use rand::Rng;
+use serde::Deserialize;
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+/// Where generated events are sent.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Mode {
+    /// Run events through the pipeline in this process, against the
+    /// configured database.
+    Pipeline,
+    /// Serve events from a local websocket server, for an alerts service
+    /// pointed at it with `UNIVERSALIS_ALERTS_WS`.
+    Websocket,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+struct Range {
+    min: u32,
+    max: u32,
+}
+
+/// Which items events are generated for. Lower item IDs are picked more
+/// often as `skew` increases, so that a few hot items get most of the events,
+/// like on the real market board.
+#[derive(Deserialize, Debug, Clone)]
+struct ItemDistribution {
+    min: i32,
+    max: i32,
+    #[serde(default)]
+    skew: f64,
+}
+
+/// A load test, as described by its configuration file.
+#[derive(Deserialize, Debug, Clone)]
+pub struct LoadConfig {
+    mode: Mode,
+    events_per_second: u32,
+    duration_secs: u64,
+    listings_per_event: Range,
+    worlds: Vec<i32>,
+    items: ItemDistribution,
+    /// Whether pipeline mode skips delivering notifications.
+    #[serde(default = "default_dry_run")]
+    dry_run: bool,
+    #[serde(default = "default_listen_addr")]
+    listen_addr: SocketAddr,
+}
+
+impl LoadConfig {
+    /// Checks everything that would otherwise only fail, or panic, once
+    /// events start being generated.
+    fn validate(&self) -> Result<()> {
+        if self.worlds.is_empty() {
+            return Err("at least one world must be configured".into());
+        }
+        if self.listings_per_event.min > self.listings_per_event.max {
+            return Err(format!(
+                "listings_per_event's min ({}) is greater than its max ({})",
+                self.listings_per_event.min, self.listings_per_event.max
+            )
+            .into());
+        }
+        if self.items.min > self.items.max {
+            return Err(format!(
+                "items' min ({}) is greater than its max ({})",
+                self.items.min, self.items.max
+            )
+            .into());
+        }
+        if self.interval().is_zero() {
+            return Err(format!(
+                "events_per_second must be between 1 and {}",
+                Duration::from_secs(1).as_nanos()
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// The time between events, which is zero if the rate can't be kept.
+    fn interval(&self) -> Duration {
+        Duration::from_secs(1)
+            .checked_div(self.events_per_second)
+            .unwrap_or_default()
+    }
+}
+
+fn default_dry_run() -> bool {
+    true
+}
+
+fn default_listen_addr() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 8765))
+}
+
+/// Generates a `listings/add` event with realistic-looking listings.
+fn generate_event(config: &LoadConfig, rng: &mut impl Rng) -> Result<Vec<u8>> {
+    let world_id = config.worlds[rng.gen_range(0..config.worlds.len())];
+    let span = (config.items.max - config.items.min + 1).max(1) as f64;
+    let position = rng.gen::<f64>().powf(1.0 + config.items.skew.max(0.0));
+    let item_id = config.items.min + (position * span) as i32;
+
+    let count = rng.gen_range(config.listings_per_event.min..=config.listings_per_event.max);
+    let now = unix_now();
+    let listings = (0..count)
+        .map(|_| {
+            // Prices are spread over several orders of magnitude
+            let unit_price = 10f64.powf(rng.gen_range(0.0..6.0)) as i32;
+            let quantity = rng.gen_range(1..=99);
+            doc! {
+                "pricePerUnit": unit_price,
+                "quantity": quantity,
+                "total": unit_price.saturating_mul(quantity),
+                "hq": rng.gen_bool(0.3),
+                "listingID": uuid::Uuid::new_v4().to_string(),
+                "sellerID": format!("{:016x}", rng.gen::<u64>()),
+                "lastReviewTime": now - rng.gen_range(0..86400),
+            }
+        })
+        .collect::<Vec<Document>>();
+
+    let event = doc! {
+        "event": "listings/add",
+        "item": item_id,
+        "world": world_id,
+        "listings": listings,
+    };
+    let mut data = Vec::new();
+    event.to_writer(&mut data)?;
+    Ok(data)
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[index]
+}
+
+async fn run_pipeline(config: &LoadConfig) -> Result<()> {
+    let database_url =
+        env::var("UNIVERSALIS_ALERTS_DB").chain_err(|| "UNIVERSALIS_ALERTS_DB not set")?;
+    // Alerts using disabled features are skipped, as they would be in the service
+    init_features(FeatureFlags::from_env()?)?;
+    let ctx = Arc::new(Context::from_env(Pool::new(database_url.as_str()))?);
+    let latencies = Arc::new(Mutex::new(Vec::new()));
+    let errors = Arc::new(Mutex::new(0usize));
+
+    let mut ticker = tokio::time::interval(config.interval());
+    let started = Instant::now();
+    let deadline = Duration::from_secs(config.duration_secs);
+    let mut tasks = Vec::new();
+    let mut rng = rand::thread_rng();
+    while started.elapsed() < deadline {
+        ticker.tick().await;
+        let data = generate_event(config, &mut rng)?;
+        let (ctx, latencies, errors, dry_run) = (
+            ctx.clone(),
+            latencies.clone(),
+            errors.clone(),
+            config.dry_run,
+        );
+        tasks.push(tokio::spawn(async move {
+            let event_started = Instant::now();
+            if let Err(err) = process("loadgen", Message::Binary(data), dry_run, &ctx).await {
+                debug!("{:?}", err);
+                *errors.lock().unwrap() += 1;
+            }
+            latencies.lock().unwrap().push(event_started.elapsed());
+        }));
+    }
+    for task in tasks {
+        task.await.chain_err(|| "event task failed")?;
+    }
+    let elapsed = started.elapsed();
+
+    let mut latencies = latencies.lock().unwrap().clone();
+    latencies.sort();
+    info!(
+        "Processed {} events in {:.1}s ({:.1}/s), {} failed",
+        latencies.len(),
+        elapsed.as_secs_f64(),
+        latencies.len() as f64 / elapsed.as_secs_f64(),
+        errors.lock().unwrap()
+    );
+    info!(
+        "Latency: p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+        percentile(&latencies, 0.5),
+        percentile(&latencies, 0.9),
+        percentile(&latencies, 0.99),
+        latencies.last().copied().unwrap_or_default()
+    );
+    Ok(())
+}
+
+async fn run_websocket(config: &LoadConfig) -> Result<()> {
+    let listener = TcpListener::bind(config.listen_addr).await?;
+    info!("Waiting for a connection on ws://{}", config.listen_addr);
+    let (stream, peer) = listener.accept().await?;
+    let ws_stream = tokio_tungstenite::accept_async(stream).await?;
+    info!("Accepted connection from {}", peer);
+
+    // Subscriptions are ignored; every client gets every event
+    let (mut write, mut read) = ws_stream.split();
+    tokio::spawn(async move { while read.next().await.is_some() {} });
+
+    let mut ticker = tokio::time::interval(config.interval());
+    let started = Instant::now();
+    let deadline = Duration::from_secs(config.duration_secs);
+    let mut sent = 0usize;
+    let mut rng = rand::thread_rng();
+    while started.elapsed() < deadline {
+        ticker.tick().await;
+        let data = generate_event(config, &mut rng)?;
+        write.send(Message::Binary(data)).await?;
+        sent += 1;
+    }
+    let elapsed = started.elapsed();
+
+    info!(
+        "Sent {} events in {:.1}s ({:.1}/s)",
+        sent,
+        elapsed.as_secs_f64(),
+        sent as f64 / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
+
+/// Reads a load test's configuration from a YAML file.
+pub fn read_config(path: &str) -> Result<LoadConfig> {
+    let source = fs::read_to_string(path).chain_err(|| format!("failed to read {}", path))?;
+    parse_config(&source).chain_err(|| format!("failed to parse {}", path))
+}
+
+fn parse_config(source: &str) -> Result<LoadConfig> {
+    let config: LoadConfig = serde_yaml::from_str(source)?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// Runs a load test, returning once every event has been generated and, in
+/// pipeline mode, processed.
+pub async fn run_load(config: &LoadConfig) -> Result<()> {
+    match config.mode {
+        Mode::Pipeline => run_pipeline(config).await,
+        Mode::Websocket => run_websocket(config).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(source: &str) -> std::result::Result<LoadConfig, String> {
+        parse_config(source).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn reads_the_example() {
+        let config = config(include_str!("../loadgen.example.yaml")).unwrap();
+        assert_eq!(config.mode, Mode::Pipeline);
+        assert_eq!(config.worlds, vec![74]);
+        assert_eq!(config.listings_per_event.max, 50);
+        assert_eq!(config.items.skew, 2.0);
+        assert_eq!(config.interval(), Duration::from_millis(5));
+        assert!(config.dry_run);
+    }
+
+    #[test]
+    fn reads_any_yaml() {
+        let loaded = config(
+            "mode: websocket # comment\nevents_per_second: 10\nduration_secs: 1\nworlds:\n  - 74\n  - 75\nlistings_per_event: {min: 1, max: 5}\nitems: {min: 2, max: 10}\nlisten_addr: \"127.0.0.1:9000\"\n",
+        )
+        .unwrap();
+        assert_eq!(loaded.worlds, vec![74, 75]);
+        assert_eq!(loaded.listings_per_event.max, 5);
+        assert_eq!(loaded.listen_addr, SocketAddr::from(([127, 0, 0, 1], 9000)));
+
+        // Anything that isn't a configuration is rejected
+        assert!(config("mode: [pipeline]\n").is_err());
+        assert!(config("a: 1\n  b: 2\n").is_err());
+        assert!(config("mode: pipeline\nmode: websocket\n").is_err());
+    }
+
+    #[test]
+    fn rejects_configurations_that_cannot_run() {
+        let base = "mode: websocket\nduration_secs: 1\nworlds: [74]\nitems:\n  min: 2\n  max: 10\n";
+        let with = |rest: &str| config(&format!("{}{}", base, rest));
+        assert!(with("events_per_second: 10\nlistings_per_event:\n  min: 1\n  max: 5\n").is_ok());
+        assert_eq!(
+            with("events_per_second: 10\nlistings_per_event:\n  min: 6\n  max: 5\n").unwrap_err(),
+            "listings_per_event's min (6) is greater than its max (5)"
+        );
+        for rate in [0u64, 1_000_000_001] {
+            let rest = format!(
+                "events_per_second: {}\nlistings_per_event:\n  min: 1\n  max: 5\n",
+                rate
+            );
+            assert_eq!(
+                with(&rest).unwrap_err(),
+                "events_per_second must be between 1 and 1000000000"
+            );
+        }
+    }
+}
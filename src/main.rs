@@ -1,278 +1,374 @@
+#![allow(clippy::result_large_err)]
+
 #[macro_use]
 extern crate log;
 
 use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::discord::*;
-use crate::errors::*;
-use crate::trigger::*;
-use crate::universalis::*;
-use crate::xivapi::*;
 use dotenv::dotenv;
-use futures_util::{pin_mut, SinkExt, StreamExt};
-use itertools::Itertools;
+use futures_util::future::join_all;
 use metrics::counter;
-use metrics_exporter_prometheus::PrometheusBuilder;
-use mysql_async::{params, prelude::*, Pool};
-use opentelemetry::global;
-use reqwest::Client;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
-use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
-use tracing_subscriber::util::SubscriberInitExt;
-
-mod discord;
-mod errors;
-mod trigger;
-mod universalis;
-mod xivapi;
-
-const MIN_TRIGGER_VERSION: i32 = 0;
-const MAX_TRIGGER_VERSION: i32 = 0;
-
-#[derive(Debug)]
-struct UserAlert {
-    user_id: Option<String>,
-    name: String,
-    discord_webhook: Option<String>,
-    trigger: String,
+use mysql_async::{Opts, Pool};
+use tokio::runtime::Handle;
+use tokio::signal::unix::{signal, SignalKind};
+use universalis_alerts::admin::*;
+use universalis_alerts::alerts::*;
+use universalis_alerts::config::*;
+use universalis_alerts::connection::*;
+use universalis_alerts::daily_summary::*;
+use universalis_alerts::errors::*;
+use universalis_alerts::features::*;
+use universalis_alerts::item_lists::*;
+use universalis_alerts::metrics_registry::*;
+use universalis_alerts::migrations::*;
+use universalis_alerts::mutes::*;
+use universalis_alerts::pipeline::*;
+use universalis_alerts::preferences::*;
+use universalis_alerts::standalone::*;
+use universalis_alerts::startup::*;
+use universalis_alerts::telemetry::*;
+use universalis_alerts::trigger_stats::*;
+
+/// How many consecutive websocket failures are reported as an incident.
+const SUSTAINED_DISCONNECT_FAILURES: u32 = 3;
+
+/// How long a websocket connection has to stay up to reset the failure count.
+const STABLE_CONNECTION: Duration = Duration::from_secs(60);
+
+/// Resolves when the process is asked to stop, via Ctrl+C or SIGTERM.
+async fn shutdown_signal() {
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(err) => {
+            error!("failed to listen for SIGTERM: {:?}", err);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
 }
 
-#[tracing::instrument(skip(pool))]
-async fn get_alerts_for_world_item(
-    world_id: i32,
-    item_id: i32,
-    pool: &Pool,
-) -> Result<Vec<(UserAlert, AlertTrigger)>> {
-    // TODO: Add caching for this?
-    let mut conn = pool.get_conn().await?;
-    let alerts = r"SELECT `user_id`, `name`, `discord_webhook`, `trigger` FROM `users_alerts_next` WHERE `world_id` = :world_id AND (`item_id` = :item_id OR `item_id` = -1) AND `trigger_version` >= :min_trigger_version AND `trigger_version` <= :max_trigger_version".with(params! {
-        "world_id" => world_id,
-        "item_id" => item_id,
-        "min_trigger_version" => MIN_TRIGGER_VERSION,
-        "max_trigger_version" => MAX_TRIGGER_VERSION,
-    })
-        .map(&mut conn, |(user_id, name, discord_webhook, trigger)| {
-            let alert = UserAlert {
-                user_id,
-                name,
-                discord_webhook,
-                trigger,
-            };
-            let alert_trigger = serde_json::from_str::<AlertTrigger>(&alert.trigger);
-            match alert_trigger {
-                Ok(at) => Some((alert, at)),
-                Err(err) => {
-                    error!("{:?}", err);
-                    None
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+
+    init_logging();
+
+    let admin_state = Arc::new(AdminState::default());
+    init_observability("universalis_alerts", admin_state.clone());
+
+    let feature_flags = FeatureFlags::from_env()?;
+    if feature_flags.disabled().next().is_some() {
+        info!(
+            "Disabled features: {}",
+            feature_flags.disabled().collect::<Vec<_>>().join(", ")
+        );
+    }
+    init_features(feature_flags)?;
+
+    // Pools only connect when they're first used, which never happens in
+    // standalone mode
+    let pool = if is_standalone() {
+        info!("Running in standalone mode; no database will be used");
+        Pool::new(Opts::default())
+    } else {
+        let phase = StartupPhase::begin("database");
+        let pool = database_pool_from_env()?;
+        if let Err(err) = wait_for_database(&pool, database_startup_timeout()).await {
+            // Nothing can use the schema until migrations have run
+            if should_run_migrations() {
+                return Err(err);
+            }
+            // Events whose alerts can't be loaded are retried, and readiness
+            // checks fail while the database is down
+            error!("starting without the database: {:?}", err);
+        }
+        if should_run_migrations() {
+            run_migrations(&pool).await?;
+        } else if let Err(err) = check_migrations(&pool).await {
+            error!("failed to check migrations: {:?}", err);
+        }
+        if let Err(err) = check_alerts_schema(&pool).await {
+            error!("failed to check users_alerts_next schema: {:?}", err);
+        }
+
+        // Periodically report how many alerts exist for each trigger version and world
+        let census_pool = pool.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = report_trigger_versions(&census_pool).await {
+                    error!("failed to report trigger versions: {:?}", err);
+                }
+                if let Err(err) = report_world_alerts(&census_pool).await {
+                    error!("failed to report world alert counts: {:?}", err);
                 }
+                tokio::time::sleep(Duration::from_secs(60)).await;
             }
-        })
-        .await?
-        .into_iter()
-        .filter_map(|t| t)
-        .collect_vec();
-    Ok(alerts)
-}
+        });
+        phase.finish();
+        pool
+    };
 
-fn get_universalis_url(item_id: i32, world_name: &str) -> String {
-    format!(
-        "https://universalis.app/market/{}?server={}",
-        item_id, world_name
-    )
-}
+    // Run one connection per region, all feeding the same pipeline
+    let phase = StartupPhase::begin("pipeline");
+    let regions = get_regions()?;
+    let ctx = Arc::new(Context::from_env(pool)?);
+    admin_state.attach(ctx.clone(), Handle::current());
+    phase.finish();
+
+    // Keep the connection uptime and channel silence gauges current
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                ctx.connections.report_uptime();
+                ctx.subscriptions.report_silence();
+                tokio::time::sleep(Duration::from_secs(15)).await;
+            }
+        });
+    }
 
-#[tracing::instrument(
-    skip(alert, trigger, trigger_result, client),
-    fields(
-        user_id = alert.user_id.as_ref().unwrap_or(&"".to_string())
-    )
-)]
-async fn send_discord_message(
-    item_id: i32,
-    world_id: i32,
-    alert: &UserAlert,
-    trigger: &AlertTrigger,
-    trigger_result: f32,
-    client: &Client,
-) -> Result<()> {
-    let discord_webhook = alert.discord_webhook.as_ref();
-    if discord_webhook.is_none() {
-        return Ok(());
+    // Keep the delivery SLO gauges current
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                ctx.delivery_slo.report();
+                tokio::time::sleep(Duration::from_secs(15)).await;
+            }
+        });
     }
-    let discord_webhook = discord_webhook.unwrap();
-
-    let item = get_item(item_id).await?;
-    let world = get_world(world_id).await?;
-    let market_url = get_universalis_url(item_id, &world.name);
-    let embed_title = format!("Alert triggered for {} on {}", item.name, world.name);
-    let embed_footer_text = format!("universalis.app | {} | All prices include GST", alert.name);
-    let embed_description = format!("One of your alerts has been triggered for the following reason(s):\n```c\n{}\n\nValue: {}```\nYou can view the item page on Universalis by clicking [this link]({}).", trigger, trigger_result, market_url);
-    let payload = DiscordWebhookPayload {
-        embeds: [DiscordEmbed {
-            url: &market_url,
-            title: &embed_title,
-            description: &embed_description,
-            color: 0xBD983A,
-            footer: DiscordEmbedFooter {
-                text: &embed_footer_text,
-                icon_url: "https://universalis.app/favicon.png",
-            },
-            author: DiscordEmbedAuthor {
-                name: "Universalis Alert!",
-                icon_url: "https://cdn.discordapp.com/emojis/474543539771015168.png",
-            },
-        }]
-        .to_vec(),
-    };
-    let serialized = serde_json::to_string(&payload)?;
 
-    client
-        .post(discord_webhook)
-        .header("Content-Type", "application/json")
-        .body(serialized)
-        .send()
-        .await?;
+    // Expire old state and save it, for backends that keep it in memory
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                if let Err(err) = ctx.state.maintain().await {
+                    warn!("failed to maintain state store: {:?}", err);
+                }
+            }
+        });
+    }
 
-    Ok(())
-}
+    // Report how well the alert cache is doing, if it's enabled
+    if ctx.alert_cache.is_enabled() {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                ctx.alert_cache.report_metrics();
+            }
+        });
+    }
 
-fn parse_event_from_message(data: &[u8]) -> Result<ListingsAddEvent> {
-    let ev: ListingsAddEvent = bson::from_slice(data)?;
-    Ok(ev)
-}
+    // Keep the choice of HQ-filtered channels in line with the loaded alerts
+    if ctx.channels.is_enabled() && !ctx.standalone {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                match get_worlds_with_nq_alerts(&ctx.pool).await {
+                    Ok(nq_worlds) => ctx.channels.update(nq_worlds),
+                    Err(err) => error!("failed to load alert channel demand: {:?}", err),
+                }
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+        });
+    }
 
-fn serialize_event(ev: &SubscribeEvent) -> Result<Vec<u8>> {
-    let serialized = bson::to_bson(&ev)?;
-    let mut v: Vec<u8> = Vec::new();
-    serialized
-        .clone()
-        .as_document()
-        .map_or(Err(ErrorKind::NotADocument(serialized).into()), |d| {
-            d.to_writer(&mut v)?;
-            Ok(v)
-        })
-}
+    // Retry events that arrived while alerts couldn't be loaded
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(ctx.retries.interval).await;
+                retry_buffered(&ctx).await;
+            }
+        });
+    }
 
-#[tracing::instrument(skip(message, pool, client))]
-async fn process(message: Message, pool: &Pool, client: &Client) -> Result<()> {
-    // Parse the message into an event
-    let data = message.into_data();
-    let ev = parse_event_from_message(&data)?;
-
-    // Fetch all matching alerts from the database
-    let alerts = get_alerts_for_world_item(ev.world_id, ev.item_id, &pool)
-        .await?
-        .into_iter()
-        .filter_map(|(alert, trigger)| {
-            // Evaluate if all trigger conditions were met
-            let trigger_result = trigger.evaluate(&ev.listings);
-            match trigger_result {
-                Some(tr) => Some((alert, trigger, tr)),
-                None => None,
+    // Forget event rates for items that are no longer being updated
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                ctx.event_stats.decay();
             }
-        })
-        .collect_vec();
-    counter!("universalis_alerts_matched", alerts.len() as u64);
+        });
+    }
 
-    // Send Discord notifications for each matching trigger
-    for (alert, trigger, tr) in alerts {
-        let sent =
-            send_discord_message(ev.item_id, ev.world_id, &alert, &trigger, tr, &client).await;
+    // Pick up mutes from the database, including ones made by other instances
+    if !ctx.standalone {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                match load_mutes(&ctx.pool).await {
+                    Ok(mutes) => ctx.mutes.replace(mutes),
+                    Err(err) => error!("failed to load mutes: {:?}", err),
+                }
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+        });
+    }
 
-        // Log any errors that happened while sending the message
-        if let Err(err) = sent {
-            error!("{:?}", err);
-        }
+    // Likewise for the item allowlist and denylist, clearing out entries
+    // once they've expired
+    if !ctx.standalone {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = expire_item_lists(&ctx.pool).await {
+                    error!("failed to expire item list entries: {:?}", err);
+                }
+                match load_item_lists(&ctx.pool).await {
+                    Ok(entries) => ctx.item_lists.replace(entries),
+                    Err(err) => error!("failed to load item lists: {:?}", err),
+                }
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+        });
     }
 
-    Ok(())
-}
+    // And users' notification preferences
+    if !ctx.standalone {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                match load_preferences(&ctx.pool).await {
+                    Ok(preferences) => ctx.preferences.replace(preferences),
+                    Err(err) => error!("failed to load notification preferences: {:?}", err),
+                }
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+        });
+    }
 
-async fn connect_and_process(url: url::Url, pool: &Pool) -> Result<()> {
-    info!("Connecting to WebSocket server at {}", url);
-    let (ws_stream, _) = connect_async(url).await?;
-    info!("WebSocket handshake completed");
+    // Delete event snapshots once they're past their retention period
+    if ctx.snapshots.is_enabled() {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = ctx.snapshots.expire().await {
+                    error!("failed to expire event snapshots: {:?}", err);
+                }
+                tokio::time::sleep(Duration::from_secs(3600)).await;
+            }
+        });
+    }
 
-    let (mut write, read) = ws_stream.split();
+    // Keep world statuses up to date, if there's a feed for them
+    if ctx.world_status.is_enabled() {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = ctx.world_status.refresh(&ctx.client).await {
+                    error!("failed to refresh world statuses: {:?}", err);
+                }
+                tokio::time::sleep(ctx.world_status.interval).await;
+            }
+        });
+    }
 
-    let event = SubscribeEvent {
-        event: "subscribe",
-        channel: &env::var("UNIVERSALIS_ALERTS_CHANNEL")
-            .chain_err(|| "UNIVERSALIS_ALERTS_CHANNEL not set")?,
-    };
-    let serialized = serialize_event(&event)?;
-
-    // TODO: Ping the connection so it doesn't die
-    write.send(Message::Binary(serialized)).await?;
-
-    let client = reqwest::Client::new();
-    let on_message = {
-        read.for_each_concurrent(None, |message| async {
-            let result = match message {
-                Ok(m) => {
-                    counter!("universalis_alerts_ws_messages_recieved", 1);
-                    process(m, &pool, &client).await
+    // Post a summary of the day to the ops webhook, and keep it for later
+    if let Some(first) = next_summary_in() {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let mut wait = first;
+            loop {
+                tokio::time::sleep(wait).await;
+                let mut summary = daily_stats().take();
+                summary.name_items().await;
+                if let Err(err) = ctx.ops.notify(&summary.to_message(), &ctx.client).await {
+                    error!("failed to post daily summary: {:?}", err);
                 }
-                Err(err) => {
-                    counter!("universalis_alerts_ws_errors", 1);
-                    Err(ErrorKind::Tungstenite(err).into())
+                if !ctx.standalone {
+                    if let Err(err) = save_summary(&summary, &ctx.pool).await {
+                        error!("failed to save daily summary: {:?}", err);
+                    }
                 }
-            };
-            if let Err(err) = result {
-                error!("{:?}", err);
+                wait = next_summary_in().unwrap_or(Duration::from_secs(86400));
             }
-        })
-    };
+        });
+    }
 
-    pin_mut!(on_message);
-    on_message.await;
+    // Count how triggers are written, if the deployment has opted in
+    if let Some(analytics) = TriggerAnalytics::from_env().filter(|_| !ctx.standalone) {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                match analytics.run(&ctx.pool).await {
+                    Ok(rows) => info!("recorded {} trigger shapes", rows),
+                    Err(err) => error!("failed to record trigger stats: {:?}", err),
+                }
+                tokio::time::sleep(analytics.interval).await;
+            }
+        });
+    }
 
-    Err(ErrorKind::ConnectionClosed("the connection was closed".to_owned()).into())
-}
+    // Cooldowns used to be snapshotted on their own, and now live in the
+    // state store
+    if env::var("UNIVERSALIS_ALERTS_STATE_FILE").is_ok() {
+        warn!("UNIVERSALIS_ALERTS_STATE_FILE is ignored; set UNIVERSALIS_ALERTS_STATE_STORE=file and UNIVERSALIS_ALERTS_STATE_STORE_FILE to keep state across restarts");
+    }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    dotenv().ok();
+    let connections = regions.into_iter().map(|region| {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let mut failures = 0;
+            loop {
+                let connected_at = Instant::now();
+                let err = match connect_and_process(&region, &ctx).await {
+                    Ok(_) => break,
+                    Err(err) => err,
+                };
+                counter!(WS_CLOSES.name, 1, "region" => region.name.clone());
+                error!("[{}] {:?}", region.name, err);
+                ctx.connections.record_lost(&region.name, &err.to_string());
+
+                // A connection that stayed up for a while was a blip, not an outage
+                if connected_at.elapsed() >= STABLE_CONNECTION {
+                    failures = 0;
+                }
+                failures += 1;
+                if failures == SUSTAINED_DISCONNECT_FAILURES {
+                    let message = format!(
+                        "[{}] Websocket connection failed {} times in a row: {}",
+                        region.name, failures, err
+                    );
+                    ctx.ops
+                        .incident(
+                            &format!("ws_disconnect:{}", region.name),
+                            &message,
+                            &ctx.client,
+                        )
+                        .await;
+                }
+            }
+        })
+    });
+    let connections = join_all(connections);
 
-    // Configure logging; set the log level to info
-    // if not specified.
-    if let Err(_) = env::var("RUST_LOG") {
-        env::set_var("RUST_LOG", "info")
+    tokio::select! {
+        results = connections => {
+            for result in results {
+                result.chain_err(|| "connection task failed")?;
+            }
+        }
+        _ = shutdown_signal() => {
+            info!("Shutting down");
+        }
     }
-    env_logger::init();
-
-    // Configure metrics
-    let metrics_builder = PrometheusBuilder::new();
-    metrics_builder
-        .install()
-        .chain_err(|| "failed to install metrics exporter")?;
-
-    // Configure tracing
-    let jaeger_agent_url = env::var("UNIVERSALIS_ALERTS_JAEGER_AGENT")
-        .chain_err(|| "UNIVERSALIS_ALERTS_JAEGER_AGENT not set")?;
-    global::set_text_map_propagator(opentelemetry_jaeger::Propagator::new());
-    let tracer = opentelemetry_jaeger::new_pipeline()
-        .with_agent_endpoint(jaeger_agent_url)
-        .with_service_name("universalis_alerts")
-        .install_simple()
-        .chain_err(|| "failed to install span processor")?;
-    let opentelemetry = tracing_opentelemetry::layer().with_tracer(tracer);
-    tracing_subscriber::registry()
-        .with(opentelemetry)
-        .try_init()
-        .chain_err(|| "failed to install tracing subscriber")?;
-
-    let database_url =
-        env::var("UNIVERSALIS_ALERTS_DB").chain_err(|| "UNIVERSALIS_ALERTS_DB not set")?;
-    let pool = Pool::new(database_url.as_str());
-
-    let connect_addr =
-        env::var("UNIVERSALIS_ALERTS_WS").chain_err(|| "UNIVERSALIS_ALERTS_WS not set")?;
-    let url = url::Url::parse(&connect_addr).chain_err(|| "failed to parse server address")?;
-
-    while let Err(err) = connect_and_process(url.clone(), &pool).await {
-        counter!("universalis_alerts_ws_closes", 1);
-        error!("{:?}", err)
+
+    // Save state so that the next run can pick up where this one left off
+    if let Err(err) = ctx.state.maintain().await {
+        error!("failed to save state: {:?}", err);
     }
 
     Ok(())
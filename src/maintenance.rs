@@ -0,0 +1,49 @@
+use std::env;
+use std::sync::Mutex;
+
+use crate::universalis::*;
+
+/// Tracks maintenance windows announced by Universalis broadcasts.
+pub struct MaintenanceState {
+    pause_processing: bool,
+    window: Mutex<Option<(i64, i64)>>,
+}
+
+impl MaintenanceState {
+    /// Reads whether processing should be paused during announced maintenance
+    /// from `UNIVERSALIS_ALERTS_PAUSE_DURING_MAINTENANCE`.
+    pub fn from_env() -> Self {
+        Self {
+            pause_processing: env::var("UNIVERSALIS_ALERTS_PAUSE_DURING_MAINTENANCE")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            window: Mutex::new(None),
+        }
+    }
+
+    /// Records the maintenance window announced by a broadcast, if it has one.
+    pub fn record(&self, broadcast: &BroadcastEvent) {
+        if let (Some(start), Some(end)) = (broadcast.start, broadcast.end) {
+            *self.window.lock().unwrap() = Some((start, end));
+        }
+    }
+
+    /// Returns whether events should currently be skipped.
+    pub fn is_paused(&self) -> bool {
+        self.paused_until().is_some()
+    }
+
+    /// Returns when the current pause ends, in seconds since the Unix epoch,
+    /// if events are currently being skipped.
+    pub fn paused_until(&self) -> Option<i64> {
+        if !self.pause_processing {
+            return None;
+        }
+        let now = unix_now();
+        self.window
+            .lock()
+            .unwrap()
+            .filter(|(start, end)| *start <= now && now < *end)
+            .map(|(_, end)| end)
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::env;
+
+use crate::alerts::*;
+use crate::baseline::*;
+use crate::errors::*;
+use crate::metrics_registry::*;
+use crate::trigger::*;
+use crate::universalis::*;
+use itertools::Itertools;
+use metrics::counter;
+
+/// The prices used for melded materia when working out what gear is worth
+/// without its melds.
+#[derive(Debug, Clone, Default)]
+pub struct MateriaPrices {
+    /// Fixed prices for materia, by item ID. Materia that aren't listed here
+    /// are priced at the lowest price in the world's region.
+    pub configured: HashMap<i32, f32>,
+}
+
+impl MateriaPrices {
+    /// Reads fixed materia prices from `UNIVERSALIS_ALERTS_MATERIA_PRICES`, a
+    /// comma-separated list of `item_id=price` pairs.
+    pub fn from_env() -> Result<Self> {
+        let configured = match env::var("UNIVERSALIS_ALERTS_MATERIA_PRICES") {
+            Ok(prices) => prices
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(|p| {
+                    p.split_once('=')
+                        .and_then(|(id, price)| {
+                            Some((id.trim().parse().ok()?, price.trim().parse().ok()?))
+                        })
+                        .ok_or_else(|| {
+                            Error::from(format!(
+                                "invalid UNIVERSALIS_ALERTS_MATERIA_PRICES entry: {}",
+                                p
+                            ))
+                        })
+                })
+                .collect::<Result<_>>()?,
+            Err(_) => HashMap::new(),
+        };
+        Ok(Self { configured })
+    }
+
+    /// Prices each materia melded into the event's listings, if any of the
+    /// alerts need them. Materia that can't be priced are left out, and
+    /// count as worthless.
+    pub async fn resolve(
+        &self,
+        alerts: &[(UserAlert, AlertTrigger)],
+        ev: &ListingsAddEvent<'_>,
+        baselines: &dyn BaselineProvider,
+    ) -> HashMap<i32, f32> {
+        if !alerts.iter().any(|(_, trigger)| trigger.uses_materia()) {
+            return HashMap::new();
+        }
+
+        let needed = ev
+            .listings
+            .iter()
+            .flat_map(|l| l.materia.iter().map(|m| m.materia_id))
+            .unique()
+            .collect_vec();
+
+        // Materia without a configured price are looked up together
+        let unpriced = needed
+            .iter()
+            .copied()
+            .filter(|id| !self.configured.contains_key(id))
+            .collect_vec();
+        if !unpriced.is_empty() {
+            if let Err(err) = baselines.prefetch(ev.world_id, &unpriced).await {
+                warn!("failed to prefetch materia prices: {:?}", err);
+            }
+        }
+
+        let mut prices = HashMap::new();
+        for materia_id in needed {
+            if let Some(price) = self.configured.get(&materia_id) {
+                prices.insert(materia_id, *price);
+                continue;
+            }
+            match baselines
+                .resolve(Baseline::GlobalMin, ev.world_id, materia_id)
+                .await
+            {
+                Ok(Some(price)) => {
+                    prices.insert(materia_id, price);
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    counter!(MATERIA_PRICE_FAILURES.name, 1);
+                    error!("failed to price materia {}: {:?}", materia_id, err);
+                }
+            }
+        }
+        prices
+    }
+}
@@ -0,0 +1,300 @@
+use std::env;
+use std::future::Future;
+use std::net::{SocketAddr, UdpSocket};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::errors::*;
+use crate::metrics_registry::describe_metrics;
+use itertools::Itertools;
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Recorder,
+    SharedString, Unit,
+};
+use metrics_exporter_prometheus::PrometheusBuilder;
+
+/// A future that serves or pushes metrics for as long as it runs.
+pub type ExporterFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/// How metrics are exported.
+#[derive(Debug, Clone)]
+pub enum MetricsExporter {
+    /// Serve metrics for Prometheus to scrape.
+    Prometheus { addr: SocketAddr },
+    /// Push metrics to a Prometheus Pushgateway, for runs that are over
+    /// before they could be scraped, like replays.
+    PushGateway {
+        endpoint: String,
+        interval: Duration,
+    },
+    /// Send metrics to a statsd server as they're recorded. With dogstatsd,
+    /// labels are sent as tags; otherwise, their values are appended to the
+    /// metric's name.
+    Statsd {
+        addr: SocketAddr,
+        prefix: Option<String>,
+        dogstatsd: bool,
+    },
+    /// Don't export metrics at all.
+    Disabled,
+}
+
+/// Reads the address the Prometheus exporter listens on from
+/// `UNIVERSALIS_ALERTS_METRICS_ADDR`, defaulting to `0.0.0.0:9000`.
+pub fn metrics_addr_from_env() -> Result<SocketAddr> {
+    env::var("UNIVERSALIS_ALERTS_METRICS_ADDR")
+        .unwrap_or_else(|_| "0.0.0.0:9000".to_owned())
+        .parse()
+        .chain_err(|| "failed to parse UNIVERSALIS_ALERTS_METRICS_ADDR")
+}
+
+/// Reads the statsd exporter's configuration, if its address is set in
+/// `UNIVERSALIS_ALERTS_METRICS_STATSD_ADDR`.
+fn statsd_from_env(dogstatsd: bool) -> Result<Option<MetricsExporter>> {
+    let addr = match env::var("UNIVERSALIS_ALERTS_METRICS_STATSD_ADDR") {
+        Ok(addr) => addr
+            .parse()
+            .chain_err(|| "failed to parse UNIVERSALIS_ALERTS_METRICS_STATSD_ADDR")?,
+        Err(_) => return Ok(None),
+    };
+    Ok(Some(MetricsExporter::Statsd {
+        addr,
+        // An empty prefix would leave a leading dot on every name
+        prefix: env::var("UNIVERSALIS_ALERTS_METRICS_STATSD_PREFIX")
+            .ok()
+            .filter(|prefix| !prefix.is_empty()),
+        dogstatsd,
+    }))
+}
+
+impl MetricsExporter {
+    /// Reads the exporter from `UNIVERSALIS_ALERTS_METRICS_EXPORTER`, which may
+    /// be `prometheus` (the default), `pushgateway`, `statsd`, `dogstatsd`,
+    /// or `none`. The Pushgateway's URL is read from
+    /// `UNIVERSALIS_ALERTS_METRICS_PUSHGATEWAY` and how often metrics are
+    /// pushed from `UNIVERSALIS_ALERTS_METRICS_PUSH_INTERVAL_SECS` (default
+    /// 10); statsd's address is read from `UNIVERSALIS_ALERTS_METRICS_STATSD_ADDR`
+    /// and an optional prefix for metric names from
+    /// `UNIVERSALIS_ALERTS_METRICS_STATSD_PREFIX`.
+    pub fn from_env() -> Result<Self> {
+        match env::var("UNIVERSALIS_ALERTS_METRICS_EXPORTER").as_deref() {
+            Err(_) | Ok("prometheus") => Ok(Self::Prometheus {
+                addr: metrics_addr_from_env()?,
+            }),
+            Ok("pushgateway") => Ok(Self::PushGateway {
+                endpoint: env::var("UNIVERSALIS_ALERTS_METRICS_PUSHGATEWAY")
+                    .chain_err(|| "UNIVERSALIS_ALERTS_METRICS_PUSHGATEWAY not set")?,
+                interval: Duration::from_secs(
+                    env::var("UNIVERSALIS_ALERTS_METRICS_PUSH_INTERVAL_SECS")
+                        .ok()
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(10),
+                ),
+            }),
+            Ok(kind @ ("statsd" | "dogstatsd")) => statsd_from_env(kind == "dogstatsd")?
+                .ok_or_else(|| "UNIVERSALIS_ALERTS_METRICS_STATSD_ADDR not set".into()),
+            Ok("none") => Ok(Self::Disabled),
+            Ok(other) => Err(format!("unknown metrics exporter: {}", other).into()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Prometheus { .. } => "prometheus",
+            Self::PushGateway { .. } => "pushgateway",
+            Self::Statsd {
+                dogstatsd: false, ..
+            } => "statsd",
+            Self::Statsd {
+                dogstatsd: true, ..
+            } => "dogstatsd",
+            Self::Disabled => "none",
+        }
+    }
+
+    /// Builds the exporter's recorder, and the future that exports its
+    /// metrics, if it needs one. Must be called within a Tokio runtime.
+    fn build(&self) -> Result<(Box<dyn Recorder>, Option<ExporterFuture>)> {
+        let prometheus = match self {
+            Self::Prometheus { addr } => PrometheusBuilder::new().with_http_listener(*addr),
+            Self::PushGateway { endpoint, interval } => PrometheusBuilder::new()
+                .with_push_gateway(endpoint, *interval)
+                .chain_err(|| "invalid UNIVERSALIS_ALERTS_METRICS_PUSHGATEWAY")?,
+            Self::Statsd {
+                addr,
+                prefix,
+                dogstatsd,
+            } => {
+                let recorder = StatsdRecorder::new(*addr, prefix.clone(), *dogstatsd)?;
+                return Ok((Box::new(recorder), None));
+            }
+            Self::Disabled => return Ok((Box::new(metrics::NoopRecorder), None)),
+        };
+        let (recorder, exporter) = prometheus
+            .build()
+            .chain_err(|| "failed to build metrics exporter")?;
+        let exporter: ExporterFuture = Box::pin(async move {
+            exporter
+                .await
+                .map_err(|_| Error::from("metrics exporter stopped"))
+        });
+        Ok((Box::new(recorder), Some(exporter)))
+    }
+
+    /// Installs the exporter's recorder, returning the future that exports
+    /// its metrics, if any. If the exporter can't be started, e.g. because
+    /// its port is taken, metrics fall back to statsd if its address is
+    /// configured, and are otherwise disabled, rather than stopping the
+    /// service from starting. Must be called within a Tokio runtime.
+    pub fn install(self) -> Result<Option<ExporterFuture>> {
+        let (recorder, exporter) = match self.build() {
+            Ok(built) => built,
+            Err(err) => {
+                let fallback = match statsd_from_env(false) {
+                    Ok(Some(statsd)) if !matches!(self, Self::Statsd { .. }) => statsd,
+                    _ => Self::Disabled,
+                };
+                error!(
+                    "failed to start the {} metrics exporter, falling back to {}: {:?}",
+                    self.name(),
+                    fallback.name(),
+                    err
+                );
+                fallback.build()?
+            }
+        };
+        metrics::set_boxed_recorder(recorder).chain_err(|| "failed to install metrics recorder")?;
+        describe_metrics();
+        Ok(exporter)
+    }
+}
+
+/// Sends each metric to statsd as it's recorded.
+struct StatsdRecorder {
+    socket: Arc<UdpSocket>,
+    prefix: Option<String>,
+    dogstatsd: bool,
+}
+
+impl StatsdRecorder {
+    fn new(addr: SocketAddr, prefix: Option<String>, dogstatsd: bool) -> Result<Self> {
+        let bind: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(bind).chain_err(|| "failed to bind statsd socket")?;
+        socket
+            .connect(addr)
+            .chain_err(|| format!("failed to connect to statsd at {}", addr))?;
+        // Metrics are recorded from async code, which mustn't block on them
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket: Arc::new(socket),
+            prefix,
+            dogstatsd,
+        })
+    }
+
+    fn metric(&self, key: &Key) -> Arc<StatsdMetric> {
+        let mut name = match &self.prefix {
+            Some(prefix) => format!("{}.{}", prefix, key.name()),
+            None => key.name().to_owned(),
+        };
+        let mut tags = String::new();
+        if self.dogstatsd {
+            if key.labels().len() > 0 {
+                tags = format!(
+                    "|#{}",
+                    key.labels()
+                        .map(|label| format!("{}:{}", label.key(), label.value()))
+                        .join(",")
+                );
+            }
+        } else {
+            for label in key.labels() {
+                name.push('.');
+                name.push_str(&label.value().replace([':', '|', '@', '.', '/'], "_"));
+            }
+        }
+        Arc::new(StatsdMetric {
+            socket: self.socket.clone(),
+            name,
+            tags,
+            histogram_kind: if self.dogstatsd { "h" } else { "ms" },
+        })
+    }
+}
+
+impl Recorder for StatsdRecorder {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key) -> Counter {
+        Counter::from_arc(self.metric(key))
+    }
+
+    fn register_gauge(&self, key: &Key) -> Gauge {
+        Gauge::from_arc(self.metric(key))
+    }
+
+    fn register_histogram(&self, key: &Key) -> Histogram {
+        Histogram::from_arc(self.metric(key))
+    }
+}
+
+struct StatsdMetric {
+    socket: Arc<UdpSocket>,
+    name: String,
+    tags: String,
+    /// dogstatsd has real histograms, while statsd only has timers.
+    histogram_kind: &'static str,
+}
+
+impl StatsdMetric {
+    fn send(&self, value: impl std::fmt::Display, kind: &str) {
+        let line = format!("{}:{}|{}{}", self.name, value, kind, self.tags);
+        // Metrics are best-effort; a full buffer or an unreachable server
+        // just drops them
+        let _ = self.socket.send(line.as_bytes());
+    }
+}
+
+impl CounterFn for StatsdMetric {
+    fn increment(&self, value: u64) {
+        self.send(value, "c");
+    }
+
+    fn absolute(&self, value: u64) {
+        // statsd counters are deltas, so absolute values are sent as gauges
+        self.send(value, "g");
+    }
+}
+
+impl GaugeFn for StatsdMetric {
+    fn increment(&self, value: f64) {
+        self.send(format_args!("+{}", value), "g");
+    }
+
+    fn decrement(&self, value: f64) {
+        self.send(format_args!("-{}", value), "g");
+    }
+
+    fn set(&self, value: f64) {
+        // A leading sign would make this a relative change
+        if value < 0.0 {
+            self.send(0, "g");
+        }
+        self.send(value, "g");
+    }
+}
+
+impl HistogramFn for StatsdMetric {
+    fn record(&self, value: f64) {
+        self.send(value, self.histogram_kind);
+    }
+}
@@ -0,0 +1,765 @@
+//! Every metric the service emits, in one place, so that names can't drift
+//! between call sites and the list served by `GET /metrics/docs` stays
+//! complete. New metrics go here, and are recorded with their definition's
+//! name, e.g. `counter!(EVENTS.name, 1, ...)`.
+
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+}
+
+/// A metric the service emits.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct MetricDef {
+    pub name: &'static str,
+    pub kind: MetricKind,
+    pub labels: &'static [&'static str],
+    /// What the metric measures, in one line.
+    pub help: &'static str,
+    /// The metric's previous name, which is still emitted alongside it
+    /// until dashboards have moved over.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub renamed_from: Option<&'static str>,
+}
+
+impl MetricDef {
+    /// Returns the names the metric is emitted under: its name, and its
+    /// previous name while it's being renamed.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> {
+        std::iter::once(self.name).chain(self.renamed_from)
+    }
+}
+
+pub const EVENTS: MetricDef = MetricDef {
+    name: "universalis_alerts_events",
+    kind: MetricKind::Counter,
+    labels: &["region", "channel"],
+    help: "Market events received, by region and channel.",
+    renamed_from: None,
+};
+
+pub const WS_MESSAGES_RECEIVED: MetricDef = MetricDef {
+    name: "universalis_alerts_ws_messages_received",
+    kind: MetricKind::Counter,
+    labels: &["region"],
+    help: "Messages read from the Universalis websocket.",
+    renamed_from: Some("universalis_alerts_ws_messages_recieved"),
+};
+
+pub const WS_ERRORS: MetricDef = MetricDef {
+    name: "universalis_alerts_ws_errors",
+    kind: MetricKind::Counter,
+    labels: &["region"],
+    help: "Errors reading from the Universalis websocket.",
+    renamed_from: None,
+};
+
+pub const WS_CLOSES: MetricDef = MetricDef {
+    name: "universalis_alerts_ws_closes",
+    kind: MetricKind::Counter,
+    labels: &["region"],
+    help: "Websocket connections that closed and were reconnected.",
+    renamed_from: None,
+};
+
+pub const WS_SUBSCRIPTION_FAILURES: MetricDef = MetricDef {
+    name: "universalis_alerts_ws_subscription_failures",
+    kind: MetricKind::Counter,
+    labels: &["region", "reason"],
+    help: "Connections dropped because a subscription was rejected or nothing arrived after subscribing.",
+    renamed_from: None,
+};
+
+pub const WS_UPTIME_SECONDS: MetricDef = MetricDef {
+    name: "universalis_alerts_ws_uptime_seconds",
+    kind: MetricKind::Gauge,
+    labels: &["region"],
+    help: "How long each region's current connection has been up.",
+    renamed_from: None,
+};
+
+pub const WS_CHANNEL_MESSAGES: MetricDef = MetricDef {
+    name: "universalis_alerts_ws_channel_messages",
+    kind: MetricKind::Counter,
+    labels: &["region", "channel"],
+    help: "Events received on each subscribed channel.",
+    renamed_from: None,
+};
+
+pub const WS_CHANNEL_SILENCE_SECONDS: MetricDef = MetricDef {
+    name: "universalis_alerts_ws_channel_silence_seconds",
+    kind: MetricKind::Gauge,
+    labels: &["region", "channel"],
+    help: "How long it's been since each subscribed channel last sent an event.",
+    renamed_from: None,
+};
+
+pub const WS_CHANNEL_RESUBSCRIPTIONS: MetricDef = MetricDef {
+    name: "universalis_alerts_ws_channel_resubscriptions",
+    kind: MetricKind::Counter,
+    labels: &["region", "channel"],
+    help: "Channels resubscribed to after going quiet for longer than expected.",
+    renamed_from: None,
+};
+
+pub const BROADCASTS: MetricDef = MetricDef {
+    name: "universalis_alerts_broadcasts",
+    kind: MetricKind::Counter,
+    labels: &["region"],
+    help: "Service announcements received from Universalis.",
+    renamed_from: None,
+};
+
+pub const OVERSIZED_EVENTS: MetricDef = MetricDef {
+    name: "universalis_alerts_oversized_events",
+    kind: MetricKind::Counter,
+    labels: &["reason"],
+    help: "Events dropped before decoding for being too large.",
+    renamed_from: None,
+};
+
+pub const MAINTENANCE_SKIPPED_EVENTS: MetricDef = MetricDef {
+    name: "universalis_alerts_maintenance_skipped_events",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Events skipped during announced maintenance.",
+    renamed_from: None,
+};
+
+pub const ITEM_LIST_SUPPRESSED_EVENTS: MetricDef = MetricDef {
+    name: "universalis_alerts_item_list_suppressed_events",
+    kind: MetricKind::Counter,
+    labels: &["list"],
+    help:
+        "Events dropped because their item is on the denylist, or isn't on a non-empty allowlist.",
+    renamed_from: None,
+};
+
+pub const COALESCED_EVENTS: MetricDef = MetricDef {
+    name: "universalis_alerts_coalesced_events",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Events skipped because a newer event for the same item arrived within the coalescing window.",
+    renamed_from: None,
+};
+
+pub const QUARANTINED_EVENTS: MetricDef = MetricDef {
+    name: "universalis_alerts_quarantined_events",
+    kind: MetricKind::Counter,
+    labels: &["reason"],
+    help: "Events dropped for looking like bad uploads.",
+    renamed_from: None,
+};
+
+pub const PRICE_GUARD_FILTERED_LISTINGS: MetricDef = MetricDef {
+    name: "universalis_alerts_price_guard_filtered_listings",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Listings hidden from alerts for being priced outside the price guard's bounds.",
+    renamed_from: None,
+};
+
+pub const SHED_EVENTS: MetricDef = MetricDef {
+    name: "universalis_alerts_shed_events",
+    kind: MetricKind::Counter,
+    labels: &["reason"],
+    help: "Events skipped because the service was overloaded.",
+    renamed_from: None,
+};
+
+pub const IN_FLIGHT_EVENTS: MetricDef = MetricDef {
+    name: "universalis_alerts_in_flight_events",
+    kind: MetricKind::Gauge,
+    labels: &[],
+    help: "Events currently being processed.",
+    renamed_from: None,
+};
+
+pub const EVENT_STATS_TRACKED_ITEMS: MetricDef = MetricDef {
+    name: "universalis_alerts_event_stats_tracked_items",
+    kind: MetricKind::Gauge,
+    labels: &[],
+    help: "Items with events in the last 15 minutes.",
+    renamed_from: None,
+};
+
+pub const EVENT_STATS_CHATTY_ITEMS: MetricDef = MetricDef {
+    name: "universalis_alerts_event_stats_chatty_items",
+    kind: MetricKind::Gauge,
+    labels: &[],
+    help: "Items currently updated often enough to be chatty.",
+    renamed_from: None,
+};
+
+pub const KEY_LOCK_WAITS: MetricDef = MetricDef {
+    name: "universalis_alerts_key_lock_waits",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Events that waited for an earlier event for the same world and item.",
+    renamed_from: None,
+};
+
+pub const RETRIED_EVENTS: MetricDef = MetricDef {
+    name: "universalis_alerts_retried_events",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Buffered events that were retried.",
+    renamed_from: None,
+};
+
+pub const RETRY_BUFFERED_EVENTS: MetricDef = MetricDef {
+    name: "universalis_alerts_retry_buffered_events",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Events buffered because their alerts couldn't be loaded.",
+    renamed_from: None,
+};
+
+pub const RETRY_DROPPED_EVENTS: MetricDef = MetricDef {
+    name: "universalis_alerts_retry_dropped_events",
+    kind: MetricKind::Counter,
+    labels: &["reason"],
+    help: "Buffered events dropped without being retried successfully.",
+    renamed_from: None,
+};
+
+pub const RETRY_BUFFER_DEPTH: MetricDef = MetricDef {
+    name: "universalis_alerts_retry_buffer_depth",
+    kind: MetricKind::Gauge,
+    labels: &[],
+    help: "Events waiting in the retry buffer.",
+    renamed_from: None,
+};
+
+pub const REGISTERED: MetricDef = MetricDef {
+    name: "universalis_alerts_registered",
+    kind: MetricKind::Gauge,
+    labels: &["world"],
+    help: "Alerts registered on each world.",
+    renamed_from: None,
+};
+
+pub const REGISTERED_WILDCARD: MetricDef = MetricDef {
+    name: "universalis_alerts_registered_wildcard",
+    kind: MetricKind::Gauge,
+    labels: &["world"],
+    help: "Wildcard (every item) alerts registered on each world.",
+    renamed_from: None,
+};
+
+pub const TRIGGER_VERSION_ALERTS: MetricDef = MetricDef {
+    name: "universalis_alerts_trigger_version_alerts",
+    kind: MetricKind::Gauge,
+    labels: &["trigger_version"],
+    help: "Alerts registered with each trigger version.",
+    renamed_from: None,
+};
+
+pub const TRIGGER_VERSION_LOADED: MetricDef = MetricDef {
+    name: "universalis_alerts_trigger_version_loaded",
+    kind: MetricKind::Counter,
+    labels: &["trigger_version"],
+    help: "Alerts loaded for evaluation, by trigger version.",
+    renamed_from: None,
+};
+
+pub const TRIGGER_VERSION_PARSE_FAILURES: MetricDef = MetricDef {
+    name: "universalis_alerts_trigger_version_parse_failures",
+    kind: MetricKind::Counter,
+    labels: &["trigger_version"],
+    help: "Alerts skipped because their trigger couldn't be parsed.",
+    renamed_from: None,
+};
+
+pub const TRIGGER_VERSION_MATCHED: MetricDef = MetricDef {
+    name: "universalis_alerts_trigger_version_matched",
+    kind: MetricKind::Counter,
+    labels: &["trigger_version"],
+    help: "Alerts whose trigger matched, by trigger version.",
+    renamed_from: None,
+};
+
+pub const TRIGGER_PARSES: MetricDef = MetricDef {
+    name: "universalis_alerts_trigger_parses",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Triggers parsed while loading alerts.",
+    renamed_from: None,
+};
+
+pub const DISABLED_FEATURE_REJECTIONS: MetricDef = MetricDef {
+    name: "universalis_alerts_disabled_feature_rejections",
+    kind: MetricKind::Counter,
+    labels: &["feature"],
+    help: "Alerts skipped for using a feature that's disabled on this deployment.",
+    renamed_from: None,
+};
+
+pub const DUPLICATE_ALERTS: MetricDef = MetricDef {
+    name: "universalis_alerts_duplicate_alerts",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Distinct alerts found to be identical to another of the same user's alerts.",
+    renamed_from: None,
+};
+
+pub const TRUNCATED_ALERTS: MetricDef = MetricDef {
+    name: "universalis_alerts_truncated_alerts",
+    kind: MetricKind::Counter,
+    labels: &["reason"],
+    help: "Loads that left out alerts for exceeding a quota.",
+    renamed_from: None,
+};
+
+pub const ALERT_CACHE_REQUESTS: MetricDef = MetricDef {
+    name: "universalis_alerts_alert_cache_requests",
+    kind: MetricKind::Counter,
+    labels: &["outcome"],
+    help: "Alert cache lookups, by whether they hit.",
+    renamed_from: None,
+};
+
+pub const ALERT_CACHE_PARSES_SAVED: MetricDef = MetricDef {
+    name: "universalis_alerts_alert_cache_parses_saved",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Trigger parses avoided by serving alerts from the cache.",
+    renamed_from: None,
+};
+
+pub const ALERT_CACHE_EVICTIONS: MetricDef = MetricDef {
+    name: "universalis_alerts_alert_cache_evictions",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Entries evicted from the alert cache to stay within its size.",
+    renamed_from: None,
+};
+
+pub const ALERT_CACHE_ENTRIES: MetricDef = MetricDef {
+    name: "universalis_alerts_alert_cache_entries",
+    kind: MetricKind::Gauge,
+    labels: &[],
+    help: "Worlds and items in the alert cache.",
+    renamed_from: None,
+};
+
+pub const ALERT_CACHE_APPROX_BYTES: MetricDef = MetricDef {
+    name: "universalis_alerts_alert_cache_approx_bytes",
+    kind: MetricKind::Gauge,
+    labels: &[],
+    help: "Approximate memory used by the alert cache.",
+    renamed_from: None,
+};
+
+pub const ALERT_QUARANTINE_SKIPPED: MetricDef = MetricDef {
+    name: "universalis_alerts_alert_quarantine_skipped",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Evaluations skipped because the alert is quarantined.",
+    renamed_from: None,
+};
+
+pub const ALERT_TIMEOUTS: MetricDef = MetricDef {
+    name: "universalis_alerts_alert_timeouts",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Alerts whose handling timed out.",
+    renamed_from: None,
+};
+
+pub const ALERT_QUARANTINES: MetricDef = MetricDef {
+    name: "universalis_alerts_alert_quarantines",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Alerts quarantined after timing out too many times.",
+    renamed_from: None,
+};
+
+pub const UNSCHEDULED_SKIPPED: MetricDef = MetricDef {
+    name: "universalis_alerts_unscheduled_skipped",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Evaluations skipped because the trigger's schedule was inactive.",
+    renamed_from: None,
+};
+
+pub const QUIET_HOURS_SKIPPED: MetricDef = MetricDef {
+    name: "universalis_alerts_quiet_hours_skipped",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Evaluations skipped because the alert was in its quiet hours.",
+    renamed_from: None,
+};
+
+pub const MATCHED: MetricDef = MetricDef {
+    name: "universalis_alerts_matched",
+    kind: MetricKind::Counter,
+    labels: &["region", "channel"],
+    help: "Alerts whose trigger matched an event.",
+    renamed_from: None,
+};
+
+pub const NOT_FIRED: MetricDef = MetricDef {
+    name: "universalis_alerts_not_fired",
+    kind: MetricKind::Counter,
+    labels: &["reason"],
+    help: "Alerts evaluated against an event without sending a notification, by the stage that stopped them.",
+    renamed_from: None,
+};
+
+pub const SHADOW_EVAL_AGREEMENTS: MetricDef = MetricDef {
+    name: "universalis_alerts_shadow_eval_agreements",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Shadow evaluations that agreed with the current engine.",
+    renamed_from: None,
+};
+
+pub const SHADOW_EVAL_DIVERGENCES: MetricDef = MetricDef {
+    name: "universalis_alerts_shadow_eval_divergences",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Shadow evaluations that disagreed with the current engine.",
+    renamed_from: None,
+};
+
+pub const BASELINE_CACHE_HITS: MetricDef = MetricDef {
+    name: "universalis_alerts_baseline_cache_hits",
+    kind: MetricKind::Counter,
+    labels: &["tier"],
+    help: "Market baselines served from the memory or Redis cache.",
+    renamed_from: None,
+};
+
+pub const BASELINE_CACHE_MISSES: MetricDef = MetricDef {
+    name: "universalis_alerts_baseline_cache_misses",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Market baselines that had to be fetched.",
+    renamed_from: None,
+};
+
+pub const BASELINE_FAILURES: MetricDef = MetricDef {
+    name: "universalis_alerts_baseline_failures",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Market baselines that couldn't be fetched.",
+    renamed_from: None,
+};
+
+pub const BASELINE_STALE_SERVED: MetricDef = MetricDef {
+    name: "universalis_alerts_baseline_stale_served",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Expired market baselines served because they couldn't be refreshed.",
+    renamed_from: None,
+};
+
+pub const BASELINE_OUTDATED: MetricDef = MetricDef {
+    name: "universalis_alerts_baseline_outdated",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help:
+        "Market baselines left out because the world's market data hadn't been uploaded recently.",
+    renamed_from: None,
+};
+
+pub const BASELINE_REDIS_FAILURES: MetricDef = MetricDef {
+    name: "universalis_alerts_baseline_redis_failures",
+    kind: MetricKind::Counter,
+    labels: &["operation"],
+    help: "Reads and writes of shared market data in Redis that failed.",
+    renamed_from: None,
+};
+
+pub const MATERIA_PRICE_FAILURES: MetricDef = MetricDef {
+    name: "universalis_alerts_materia_price_failures",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Materia prices that couldn't be fetched.",
+    renamed_from: None,
+};
+
+pub const TRAVEL_SUPPRESSED: MetricDef = MetricDef {
+    name: "universalis_alerts_travel_suppressed",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Notifications suppressed because the world can't be traveled to.",
+    renamed_from: None,
+};
+
+pub const DEDUPLICATED: MetricDef = MetricDef {
+    name: "universalis_alerts_deduplicated",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Notifications suppressed for repeating a recent one.",
+    renamed_from: None,
+};
+
+pub const LATE_DELIVERIES: MetricDef = MetricDef {
+    name: "universalis_alerts_late_deliveries",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Notifications sent after their event's deadline.",
+    renamed_from: None,
+};
+
+pub const DELIVERY_SLO_RATIO: MetricDef = MetricDef {
+    name: "universalis_alerts_delivery_slo_ratio",
+    kind: MetricKind::Gauge,
+    labels: &["window"],
+    help: "The fraction of recent notifications delivered within the SLO's latency target of their event.",
+    renamed_from: None,
+};
+
+pub const DELIVERY_SLO_BURN_RATE: MetricDef = MetricDef {
+    name: "universalis_alerts_delivery_slo_burn_rate",
+    kind: MetricKind::Gauge,
+    labels: &["window"],
+    help: "How fast the delivery SLO's error budget is being spent, where 1 spends it exactly.",
+    renamed_from: None,
+};
+
+pub const STATE_OPERATIONS: MetricDef = MetricDef {
+    name: "universalis_alerts_state_operations",
+    kind: MetricKind::Counter,
+    labels: &["backend", "operation", "outcome"],
+    help: "Reads and writes of the key-value state store, by outcome.",
+    renamed_from: None,
+};
+
+pub const STATE_KEYS: MetricDef = MetricDef {
+    name: "universalis_alerts_state_keys",
+    kind: MetricKind::Gauge,
+    labels: &["backend"],
+    help: "Values held by the key-value state store, for backends that can count them.",
+    renamed_from: None,
+};
+
+pub const DESTINATION_DELIVERIES: MetricDef = MetricDef {
+    name: "universalis_alerts_destination_deliveries",
+    kind: MetricKind::Counter,
+    labels: &["outcome"],
+    help: "Notifications sent to each webhook, by outcome.",
+    renamed_from: None,
+};
+
+pub const HISTORY_FAILURES: MetricDef = MetricDef {
+    name: "universalis_alerts_history_failures",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Sent notifications that couldn't be recorded in the history table.",
+    renamed_from: None,
+};
+
+pub const SNAPSHOTS_SAVED: MetricDef = MetricDef {
+    name: "universalis_alerts_snapshots_saved",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Snapshots of the listings that matched an alert, saved for sent notifications.",
+    renamed_from: None,
+};
+
+pub const SNAPSHOT_FAILURES: MetricDef = MetricDef {
+    name: "universalis_alerts_snapshot_failures",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Sent notifications whose snapshot couldn't be saved.",
+    renamed_from: None,
+};
+
+pub const SNAPSHOTS_EXPIRED: MetricDef = MetricDef {
+    name: "universalis_alerts_snapshots_expired",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Snapshots deleted after their retention period.",
+    renamed_from: None,
+};
+
+pub const OUTBOX_DELIVERED: MetricDef = MetricDef {
+    name: "universalis_alerts_outbox_delivered",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Outbox notifications delivered by the delivery worker.",
+    renamed_from: None,
+};
+
+pub const OUTBOX_FAILED: MetricDef = MetricDef {
+    name: "universalis_alerts_outbox_failed",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Outbox deliveries that failed and will be retried.",
+    renamed_from: None,
+};
+
+pub const OUTBOX_DROPPED: MetricDef = MetricDef {
+    name: "universalis_alerts_outbox_dropped",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Outbox notifications dropped after running out of attempts.",
+    renamed_from: None,
+};
+
+pub const OUTBOX_BACKLOG: MetricDef = MetricDef {
+    name: "universalis_alerts_outbox_backlog",
+    kind: MetricKind::Gauge,
+    labels: &[],
+    help: "Notifications waiting in the outbox.",
+    renamed_from: None,
+};
+
+pub const TIMEOUTS: MetricDef = MetricDef {
+    name: "universalis_alerts_timeouts",
+    kind: MetricKind::Counter,
+    labels: &["service"],
+    help: "Outbound requests that timed out, by service.",
+    renamed_from: None,
+};
+
+pub const XIVAPI_REQUESTS: MetricDef = MetricDef {
+    name: "universalis_alerts_xivapi_requests",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Requests made to XIVAPI.",
+    renamed_from: None,
+};
+
+pub const XIVAPI_UNKNOWN_ITEMS: MetricDef = MetricDef {
+    name: "universalis_alerts_xivapi_unknown_items",
+    kind: MetricKind::Counter,
+    labels: &["reason"],
+    help: "Items that XIVAPI couldn't describe.",
+    renamed_from: None,
+};
+
+pub const WORLD_STATUS_REFRESHES: MetricDef = MetricDef {
+    name: "universalis_alerts_world_status_refreshes",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Refreshes of the world status feed.",
+    renamed_from: None,
+};
+
+pub const WORLD_STATUS_RESTRICTED: MetricDef = MetricDef {
+    name: "universalis_alerts_world_status_restricted",
+    kind: MetricKind::Gauge,
+    labels: &[],
+    help: "Worlds that currently can't be traveled to.",
+    renamed_from: None,
+};
+
+pub const OPS_INCIDENTS: MetricDef = MetricDef {
+    name: "universalis_alerts_ops_incidents",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Incidents posted to the ops webhook.",
+    renamed_from: None,
+};
+
+pub const OPS_INCIDENTS_SUPPRESSED: MetricDef = MetricDef {
+    name: "universalis_alerts_ops_incidents_suppressed",
+    kind: MetricKind::Counter,
+    labels: &[],
+    help: "Incidents not posted because of the ops cooldown.",
+    renamed_from: None,
+};
+
+/// Every metric, in the order they're documented.
+pub const METRICS: [MetricDef; 79] = [
+    EVENTS,
+    WS_MESSAGES_RECEIVED,
+    WS_ERRORS,
+    WS_CLOSES,
+    WS_SUBSCRIPTION_FAILURES,
+    WS_UPTIME_SECONDS,
+    WS_CHANNEL_MESSAGES,
+    WS_CHANNEL_SILENCE_SECONDS,
+    WS_CHANNEL_RESUBSCRIPTIONS,
+    BROADCASTS,
+    OVERSIZED_EVENTS,
+    MAINTENANCE_SKIPPED_EVENTS,
+    ITEM_LIST_SUPPRESSED_EVENTS,
+    COALESCED_EVENTS,
+    QUARANTINED_EVENTS,
+    PRICE_GUARD_FILTERED_LISTINGS,
+    SHED_EVENTS,
+    IN_FLIGHT_EVENTS,
+    EVENT_STATS_TRACKED_ITEMS,
+    EVENT_STATS_CHATTY_ITEMS,
+    KEY_LOCK_WAITS,
+    RETRIED_EVENTS,
+    RETRY_BUFFERED_EVENTS,
+    RETRY_DROPPED_EVENTS,
+    RETRY_BUFFER_DEPTH,
+    REGISTERED,
+    REGISTERED_WILDCARD,
+    TRIGGER_VERSION_ALERTS,
+    TRIGGER_VERSION_LOADED,
+    TRIGGER_VERSION_PARSE_FAILURES,
+    TRIGGER_VERSION_MATCHED,
+    TRIGGER_PARSES,
+    DISABLED_FEATURE_REJECTIONS,
+    DUPLICATE_ALERTS,
+    TRUNCATED_ALERTS,
+    ALERT_CACHE_REQUESTS,
+    ALERT_CACHE_PARSES_SAVED,
+    ALERT_CACHE_EVICTIONS,
+    ALERT_CACHE_ENTRIES,
+    ALERT_CACHE_APPROX_BYTES,
+    ALERT_QUARANTINE_SKIPPED,
+    ALERT_TIMEOUTS,
+    ALERT_QUARANTINES,
+    UNSCHEDULED_SKIPPED,
+    QUIET_HOURS_SKIPPED,
+    MATCHED,
+    NOT_FIRED,
+    SHADOW_EVAL_AGREEMENTS,
+    SHADOW_EVAL_DIVERGENCES,
+    BASELINE_CACHE_HITS,
+    BASELINE_CACHE_MISSES,
+    BASELINE_FAILURES,
+    BASELINE_STALE_SERVED,
+    BASELINE_OUTDATED,
+    BASELINE_REDIS_FAILURES,
+    MATERIA_PRICE_FAILURES,
+    TRAVEL_SUPPRESSED,
+    DEDUPLICATED,
+    LATE_DELIVERIES,
+    DELIVERY_SLO_RATIO,
+    DELIVERY_SLO_BURN_RATE,
+    STATE_OPERATIONS,
+    STATE_KEYS,
+    DESTINATION_DELIVERIES,
+    HISTORY_FAILURES,
+    SNAPSHOTS_SAVED,
+    SNAPSHOT_FAILURES,
+    SNAPSHOTS_EXPIRED,
+    OUTBOX_DELIVERED,
+    OUTBOX_FAILED,
+    OUTBOX_DROPPED,
+    OUTBOX_BACKLOG,
+    TIMEOUTS,
+    XIVAPI_REQUESTS,
+    XIVAPI_UNKNOWN_ITEMS,
+    WORLD_STATUS_REFRESHES,
+    WORLD_STATUS_RESTRICTED,
+    OPS_INCIDENTS,
+    OPS_INCIDENTS_SUPPRESSED,
+];
+
+/// Registers each metric's description with the installed recorder, so that
+/// exporters that support it (like Prometheus's `# HELP`) include them.
+pub fn describe_metrics() {
+    for metric in METRICS {
+        for name in metric.names() {
+            match metric.kind {
+                MetricKind::Counter => metrics::describe_counter!(name, metric.help),
+                MetricKind::Gauge => metrics::describe_gauge!(name, metric.help),
+            }
+        }
+    }
+}
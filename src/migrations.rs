@@ -0,0 +1,155 @@
+use std::env;
+
+use crate::errors::*;
+use itertools::Itertools;
+use mysql_async::{params, prelude::*, Pool};
+
+/// The tables this service owns, as migrations that are applied in order.
+/// New migrations go on the end; applied ones must never change.
+const MIGRATIONS: [(u32, &str, &str); 11] = [
+    (
+        1,
+        "create_outbox",
+        include_str!("../migrations/0001_create_outbox.sql"),
+    ),
+    (
+        2,
+        "create_history",
+        include_str!("../migrations/0002_create_history.sql"),
+    ),
+    (
+        3,
+        "add_history_message_ids",
+        include_str!("../migrations/0003_add_history_message_ids.sql"),
+    ),
+    (
+        4,
+        "create_mutes",
+        include_str!("../migrations/0004_create_mutes.sql"),
+    ),
+    (
+        5,
+        "create_snapshots",
+        include_str!("../migrations/0005_create_snapshots.sql"),
+    ),
+    (
+        6,
+        "add_history_notification_ids",
+        include_str!("../migrations/0006_add_history_notification_ids.sql"),
+    ),
+    (
+        7,
+        "create_daily_stats",
+        include_str!("../migrations/0007_create_daily_stats.sql"),
+    ),
+    (
+        8,
+        "create_trigger_stats",
+        include_str!("../migrations/0008_create_trigger_stats.sql"),
+    ),
+    (
+        9,
+        "create_item_lists",
+        include_str!("../migrations/0009_create_item_lists.sql"),
+    ),
+    (
+        10,
+        "create_alert_preferences",
+        include_str!("../migrations/0010_create_alert_preferences.sql"),
+    ),
+    (
+        11,
+        "add_outbox_received_at",
+        include_str!("../migrations/0011_add_outbox_received_at.sql"),
+    ),
+];
+
+/// MySQL's errors for a table or column that already exists.
+const ER_TABLE_EXISTS_ERROR: u16 = 1050;
+const ER_DUP_FIELDNAME: u16 = 1060;
+/// MySQL's error for a table that doesn't exist.
+const ER_NO_SUCH_TABLE: u16 = 1146;
+
+/// Returns whether migrations should be applied at startup, as set by
+/// `UNIVERSALIS_ALERTS_RUN_MIGRATIONS`. Otherwise, pending ones are only
+/// logged.
+pub fn should_run_migrations() -> bool {
+    env::var("UNIVERSALIS_ALERTS_RUN_MIGRATIONS")
+        .map(|v| v == "true")
+        .unwrap_or(false)
+}
+
+/// Returns the versions of the migrations that have been applied, or none if
+/// migrations have never been run.
+async fn applied_versions(conn: &mut mysql_async::Conn) -> Result<Vec<u32>> {
+    match r"SELECT `version` FROM `universalis_alerts_migrations`"
+        .fetch(conn)
+        .await
+    {
+        Ok(versions) => Ok(versions),
+        Err(mysql_async::Error::Server(err)) if err.code == ER_NO_SUCH_TABLE => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Applies the migrations that haven't been applied yet, returning their
+/// names. Tables and columns that already exist, e.g. because they were
+/// created by hand before migrations were added, count as applied.
+pub async fn run_migrations(pool: &Pool) -> Result<Vec<&'static str>> {
+    let mut conn = pool.get_conn().await?;
+    r"CREATE TABLE IF NOT EXISTS `universalis_alerts_migrations` (`version` INT UNSIGNED NOT NULL, `name` VARCHAR(255) NOT NULL, `applied_at` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, PRIMARY KEY (`version`))"
+        .ignore(&mut conn)
+        .await?;
+    let applied = applied_versions(&mut conn).await?;
+
+    let mut ran = Vec::new();
+    for (version, name, sql) in MIGRATIONS {
+        if applied.contains(&version) {
+            continue;
+        }
+
+        match conn.query_drop(sql).await {
+            Ok(()) => info!("Applied migration {} ({})", version, name),
+            Err(mysql_async::Error::Server(err))
+                if err.code == ER_TABLE_EXISTS_ERROR || err.code == ER_DUP_FIELDNAME =>
+            {
+                info!(
+                    "Migration {} ({}) was already applied by hand: {}",
+                    version, name, err.message
+                );
+            }
+            Err(err) => {
+                return Err(Error::from(err))
+                    .chain_err(|| format!("failed to apply migration {} ({})", version, name))
+            }
+        }
+        r"INSERT INTO `universalis_alerts_migrations` (`version`, `name`) VALUES (:version, :name)"
+            .with(params! {
+                "version" => version,
+                "name" => name,
+            })
+            .ignore(&mut conn)
+            .await?;
+        ran.push(name);
+    }
+    Ok(ran)
+}
+
+/// Logs the migrations that haven't been applied yet, without applying them.
+pub async fn check_migrations(pool: &Pool) -> Result<()> {
+    let mut conn = pool.get_conn().await?;
+    let applied = applied_versions(&mut conn).await?;
+    let pending = MIGRATIONS
+        .iter()
+        .filter(|(version, _, _)| !applied.contains(version))
+        .map(|(version, name, _)| format!("{} ({})", version, name))
+        .collect_vec();
+    if !pending.is_empty() {
+        warn!(
+            "{} migration(s) haven't been applied; set UNIVERSALIS_ALERTS_RUN_MIGRATIONS=true to apply them at startup: {}",
+            pending.len(),
+            pending.join(", ")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[cfg(feature = "chaos")]
+use crate::chaos::*;
+use crate::errors::*;
+use mysql_async::{params, prelude::*, Pool};
+use serde::Serialize;
+
+/// What a mute silences.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum MuteKind {
+    /// Every alert belonging to a user.
+    User,
+    /// Every notification sent to a webhook, whichever alert it's for.
+    Webhook,
+}
+
+impl MuteKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Webhook => "webhook",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "user" => Some(Self::User),
+            "webhook" => Some(Self::Webhook),
+            _ => None,
+        }
+    }
+}
+
+/// An administrative mute on a user or webhook.
+#[derive(Serialize, Debug, Clone)]
+pub struct Mute {
+    pub kind: MuteKind,
+    /// The user ID, or the webhook's ID.
+    pub target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub muted_at: i64,
+}
+
+/// Returns the ID of a Discord webhook from its URL, which identifies it
+/// without its token and stays the same across query parameters such as
+/// `thread_id`. Anything that doesn't look like a Discord webhook URL is
+/// used as-is.
+pub fn webhook_key(webhook: &str) -> &str {
+    const MARKER: &str = "/webhooks/";
+    let webhook = webhook.trim();
+    match webhook.find(MARKER) {
+        Some(start) => {
+            let id = &webhook[start + MARKER.len()..];
+            let id_len = id.find(|c: char| !c.is_ascii_digit()).unwrap_or(id.len());
+            if id_len > 0 {
+                &id[..id_len]
+            } else {
+                webhook
+            }
+        }
+        None => webhook,
+    }
+}
+
+/// The users and webhooks that are currently muted. Mutes made through the
+/// admin server apply immediately, and mutes in the database are picked up
+/// when it's reloaded.
+#[derive(Default)]
+pub struct MuteList {
+    mutes: RwLock<HashMap<(MuteKind, String), Mute>>,
+}
+
+impl MuteList {
+    pub fn is_user_muted(&self, user_id: Option<&str>) -> bool {
+        let mutes = self.mutes.read().unwrap();
+        !mutes.is_empty()
+            && user_id.is_some_and(|id| mutes.contains_key(&(MuteKind::User, id.to_owned())))
+    }
+
+    pub fn is_webhook_muted(&self, webhook: &str) -> bool {
+        let mutes = self.mutes.read().unwrap();
+        !mutes.is_empty()
+            && mutes.contains_key(&(MuteKind::Webhook, webhook_key(webhook).to_owned()))
+    }
+
+    pub fn mute(&self, mute: Mute) {
+        self.mutes
+            .write()
+            .unwrap()
+            .insert((mute.kind, mute.target.clone()), mute);
+    }
+
+    /// Lifts a mute, returning whether there was one.
+    pub fn unmute(&self, kind: MuteKind, target: &str) -> bool {
+        self.mutes
+            .write()
+            .unwrap()
+            .remove(&(kind, target.to_owned()))
+            .is_some()
+    }
+
+    /// Replaces every mute with the ones loaded from the database.
+    pub fn replace(&self, mutes: Vec<Mute>) {
+        *self.mutes.write().unwrap() = mutes
+            .into_iter()
+            .map(|mute| ((mute.kind, mute.target.clone()), mute))
+            .collect();
+    }
+
+    /// Returns every mute, most recent first.
+    pub fn list(&self) -> Vec<Mute> {
+        let mut mutes = self
+            .mutes
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        mutes.sort_by_key(|mute| std::cmp::Reverse(mute.muted_at));
+        mutes
+    }
+}
+
+/// Loads every mute from the mutes table.
+pub async fn load_mutes(pool: &Pool) -> Result<Vec<Mute>> {
+    #[cfg(feature = "chaos")]
+    database_fault()?;
+    let mut conn = pool.get_conn().await?;
+    let rows: Vec<(String, String, Option<String>, i64)> =
+        r"SELECT `kind`, `target`, `reason`, `muted_at` FROM `users_alerts_mutes`"
+            .fetch(&mut conn)
+            .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(kind, target, reason, muted_at)| {
+            Some(Mute {
+                kind: MuteKind::parse(&kind)?,
+                target,
+                reason,
+                muted_at,
+            })
+        })
+        .collect())
+}
+
+/// Adds a mute to the mutes table, replacing any existing mute on the same
+/// target.
+pub async fn save_mute(mute: &Mute, pool: &Pool) -> Result<()> {
+    #[cfg(feature = "chaos")]
+    database_fault()?;
+    let mut conn = pool.get_conn().await?;
+    r"REPLACE INTO `users_alerts_mutes` (`kind`, `target`, `reason`, `muted_at`) VALUES (:kind, :target, :reason, :muted_at)"
+        .with(params! {
+            "kind" => mute.kind.as_str(),
+            "target" => &mute.target,
+            "reason" => &mute.reason,
+            "muted_at" => mute.muted_at,
+        })
+        .ignore(&mut conn)
+        .await?;
+    Ok(())
+}
+
+/// Removes a mute from the mutes table.
+pub async fn delete_mute(kind: MuteKind, target: &str, pool: &Pool) -> Result<()> {
+    #[cfg(feature = "chaos")]
+    database_fault()?;
+    let mut conn = pool.get_conn().await?;
+    r"DELETE FROM `users_alerts_mutes` WHERE `kind` = :kind AND `target` = :target"
+        .with(params! {
+            "kind" => kind.as_str(),
+            "target" => target,
+        })
+        .ignore(&mut conn)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::state::*;
+use serde::{Deserialize, Serialize};
+
+/// The state store namespace ongoing messages are kept under.
+const NAMESPACE: &str = "ongoing";
+
+/// How long an ongoing message is kept without being updated. Conditions that
+/// match for longer than this without another event get a fresh message.
+const MAX_IDLE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The messages posted for an alert whose condition is still true.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OngoingMessage {
+    /// The message posted to each of the alert's webhooks, in order.
+    pub message_ids: Vec<Option<String>>,
+    /// When the condition started matching, in seconds since the Unix epoch.
+    pub since: i64,
+}
+
+/// Keeps track of the messages for alerts that edit their notification in
+/// place while their condition stays true, instead of posting new ones.
+/// These are kept in the state store; if it can't be reached, a fresh
+/// message is posted.
+pub struct OngoingMessages {
+    state: Arc<StateStore>,
+}
+
+impl OngoingMessages {
+    pub fn new(state: Arc<StateStore>) -> Self {
+        Self { state }
+    }
+
+    /// Returns the messages to edit for an alert, if its condition was
+    /// already matching.
+    pub async fn get(&self, alert_id: &str) -> Option<OngoingMessage> {
+        match self.state.get_json(NAMESPACE, alert_id).await {
+            Ok(message) => message,
+            Err(err) => {
+                warn!("failed to read ongoing message for {}: {:?}", alert_id, err);
+                None
+            }
+        }
+    }
+
+    /// Records the messages that are kept up to date for an alert.
+    pub async fn record(&self, alert_id: &str, message: OngoingMessage) {
+        let recorded = self
+            .state
+            .set_json(NAMESPACE, alert_id, &message, Some(MAX_IDLE))
+            .await;
+        if let Err(err) = recorded {
+            warn!(
+                "failed to record ongoing message for {}: {:?}",
+                alert_id, err
+            );
+        }
+    }
+
+    /// Forgets an alert's messages once its condition stops matching, so
+    /// that the next match is posted fresh.
+    pub async fn end(&self, alert_id: &str) {
+        if let Err(err) = self.state.delete(NAMESPACE, alert_id).await {
+            warn!("failed to end ongoing message for {}: {:?}", alert_id, err);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::discord::*;
+use crate::errors::*;
+use crate::metrics_registry::*;
+use crate::redact::*;
+use crate::timeouts::*;
+use metrics::counter;
+use reqwest::Client;
+
+/// Sends service-level messages to an operator webhook, separate from the
+/// webhooks that user alerts are delivered to.
+pub struct OpsNotifier {
+    webhook: Option<String>,
+    /// How long to wait before reporting the same kind of incident again.
+    cooldown: Duration,
+    last_incidents: Mutex<HashMap<String, Instant>>,
+}
+
+impl OpsNotifier {
+    /// Reads the operator webhook from `UNIVERSALIS_ALERTS_OPS_WEBHOOK`. If it
+    /// isn't set, messages are only logged. Repeat incidents are suppressed for
+    /// `UNIVERSALIS_ALERTS_OPS_COOLDOWN_SECS` seconds (15 minutes by default).
+    pub fn from_env() -> Self {
+        let cooldown = env::var("UNIVERSALIS_ALERTS_OPS_COOLDOWN_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(900);
+        Self {
+            webhook: env::var("UNIVERSALIS_ALERTS_OPS_WEBHOOK").ok(),
+            cooldown: Duration::from_secs(cooldown),
+            last_incidents: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reports a service-level incident, unless an incident with the same key
+    /// was reported within the cooldown. Failures to post are logged rather
+    /// than returned, since incidents are usually reported from error paths.
+    pub async fn incident(&self, key: &str, message: &str, client: &Client) {
+        {
+            let mut last_incidents = self.last_incidents.lock().unwrap();
+            if let Some(last) = last_incidents.get(key) {
+                if last.elapsed() < self.cooldown {
+                    counter!(OPS_INCIDENTS_SUPPRESSED.name, 1);
+                    return;
+                }
+            }
+            last_incidents.insert(key.to_owned(), Instant::now());
+        }
+
+        counter!(OPS_INCIDENTS.name, 1);
+        if let Err(err) = self.notify(message, client).await {
+            error!("failed to report incident {}: {:?}", key, err);
+        }
+    }
+
+    /// Posts a plain-text message to the operator webhook.
+    pub async fn notify(&self, message: &str, client: &Client) -> Result<()> {
+        let message = redact(message);
+        info!("ops: {}", message);
+
+        let webhook = match &self.webhook {
+            Some(webhook) => webhook,
+            None => return Ok(()),
+        };
+        let payload = DiscordWebhookPayload {
+            content: Some(&message),
+            embeds: Vec::new(),
+        };
+        client
+            .post(webhook)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&payload)?)
+            .send()
+            .await
+            .count_timeout(Service::Discord)?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+#[cfg(feature = "chaos")]
+use crate::chaos::*;
+use crate::delivery::*;
+use crate::errors::*;
+use crate::mutes::*;
+use mysql_async::{params, prelude::*, Pool};
+
+/// A notification waiting in the outbox.
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+    pub id: u64,
+    pub attempts: i32,
+    pub notification: Notification,
+    /// The user the alert belongs to, if it still exists.
+    pub user_id: Option<String>,
+    /// When the notification's event was received, in milliseconds since
+    /// the Unix epoch, if it was received from the websocket.
+    pub received_at: Option<i64>,
+}
+
+impl OutboxEntry {
+    /// Returns whether the entry's user or webhook has been muted since it
+    /// was queued.
+    pub fn is_muted(&self, mutes: &MuteList) -> bool {
+        mutes.is_user_muted(self.user_id.as_deref())
+            || mutes.is_webhook_muted(&self.notification.discord_webhook)
+    }
+}
+
+/// Adds a rendered notification to the outbox, with when its event was
+/// received in milliseconds since the Unix epoch.
+#[tracing::instrument(skip(notification, pool), fields(alert_id = notification.alert_id.as_str()))]
+pub async fn enqueue_notification(
+    notification: &Notification,
+    received_at: Option<i64>,
+    pool: &Pool,
+) -> Result<()> {
+    #[cfg(feature = "chaos")]
+    database_fault()?;
+    let mut conn = pool.get_conn().await?;
+    r"INSERT INTO `users_alerts_outbox` (`alert_id`, `discord_webhook`, `payload`, `received_at`) VALUES (:alert_id, :discord_webhook, :payload, :received_at)"
+        .with(params! {
+            "alert_id" => &notification.alert_id,
+            "discord_webhook" => &notification.discord_webhook,
+            "payload" => &notification.payload[..],
+            "received_at" => received_at,
+        })
+        .ignore(&mut conn)
+        .await?;
+    Ok(())
+}
+
+/// Claims up to `limit` due entries from the outbox for this worker. Claimed
+/// entries are leased for `lease_secs` seconds, after which another worker
+/// may pick them up if they haven't been completed.
+#[tracing::instrument(skip(pool))]
+pub async fn claim_notifications(
+    worker_id: &str,
+    limit: u32,
+    lease_secs: u32,
+    pool: &Pool,
+) -> Result<Vec<OutboxEntry>> {
+    #[cfg(feature = "chaos")]
+    database_fault()?;
+    let mut conn = pool.get_conn().await?;
+    r"UPDATE `users_alerts_outbox` SET `claimed_by` = :worker_id, `next_attempt_at` = NOW() + INTERVAL :lease_secs SECOND WHERE `next_attempt_at` <= NOW() ORDER BY `id` LIMIT :limit"
+        .with(params! {
+            "worker_id" => worker_id,
+            "lease_secs" => lease_secs,
+            "limit" => limit,
+        })
+        .ignore(&mut conn)
+        .await?;
+
+    let entries = r"SELECT o.`id`, o.`attempts`, o.`alert_id`, o.`discord_webhook`, o.`payload`, a.`user_id`, o.`received_at` FROM `users_alerts_outbox` o LEFT JOIN `users_alerts_next` a ON a.`id` = o.`alert_id` WHERE o.`claimed_by` = :worker_id AND o.`next_attempt_at` > NOW() ORDER BY o.`id`"
+        .with(params! {
+            "worker_id" => worker_id,
+        })
+        .map(&mut conn, |(id, attempts, alert_id, discord_webhook, payload, user_id, received_at): (_, _, _, _, String, _, _)| OutboxEntry {
+            id,
+            attempts,
+            notification: Notification {
+                alert_id,
+                discord_webhook,
+                payload: payload.into(),
+            },
+            user_id,
+            received_at,
+        })
+        .await?;
+    Ok(entries)
+}
+
+/// Removes a delivered entry from the outbox.
+pub async fn complete_notification(id: u64, pool: &Pool) -> Result<()> {
+    #[cfg(feature = "chaos")]
+    database_fault()?;
+    let mut conn = pool.get_conn().await?;
+    r"DELETE FROM `users_alerts_outbox` WHERE `id` = :id"
+        .with(params! { "id" => id })
+        .ignore(&mut conn)
+        .await?;
+    Ok(())
+}
+
+/// Records a failed delivery attempt, scheduling a retry after `retry_secs`
+/// seconds.
+pub async fn fail_notification(id: u64, retry_secs: u32, pool: &Pool) -> Result<()> {
+    #[cfg(feature = "chaos")]
+    database_fault()?;
+    let mut conn = pool.get_conn().await?;
+    r"UPDATE `users_alerts_outbox` SET `attempts` = `attempts` + 1, `claimed_by` = NULL, `next_attempt_at` = NOW() + INTERVAL :retry_secs SECOND WHERE `id` = :id"
+        .with(params! {
+            "id" => id,
+            "retry_secs" => retry_secs,
+        })
+        .ignore(&mut conn)
+        .await?;
+    Ok(())
+}
+
+/// Counts the entries that are due to be delivered.
+pub async fn count_due_notifications(pool: &Pool) -> Result<u64> {
+    #[cfg(feature = "chaos")]
+    database_fault()?;
+    let mut conn = pool.get_conn().await?;
+    let count: Option<u64> =
+        r"SELECT COUNT(*) FROM `users_alerts_outbox` WHERE `next_attempt_at` <= NOW()"
+            .first(&mut conn)
+            .await?;
+    Ok(count.unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(user_id: Option<&str>) -> OutboxEntry {
+        OutboxEntry {
+            id: 1,
+            attempts: 0,
+            notification: Notification {
+                alert_id: "alert".to_owned(),
+                discord_webhook: "https://discord.com/api/webhooks/123/token".to_owned(),
+                payload: Default::default(),
+            },
+            user_id: user_id.map(str::to_owned),
+            received_at: None,
+        }
+    }
+
+    fn mute(kind: MuteKind, target: &str) -> Mute {
+        Mute {
+            kind,
+            target: target.to_owned(),
+            reason: None,
+            muted_at: 0,
+        }
+    }
+
+    #[test]
+    fn entries_are_muted_by_user_or_webhook() {
+        let mutes = MuteList::default();
+        assert!(!entry(Some("user")).is_muted(&mutes));
+
+        mutes.mute(mute(MuteKind::User, "user"));
+        assert!(entry(Some("user")).is_muted(&mutes));
+        assert!(!entry(Some("other")).is_muted(&mutes));
+        assert!(!entry(None).is_muted(&mutes));
+
+        mutes.mute(mute(MuteKind::Webhook, "123"));
+        assert!(entry(None).is_muted(&mutes));
+    }
+}
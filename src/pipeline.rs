@@ -0,0 +1,975 @@
+use crate::alert_cache::*;
+use crate::alerts::*;
+use crate::baseline::*;
+use crate::channels::*;
+use crate::coalesce::*;
+use crate::connection_history::*;
+use crate::daily_summary::*;
+use crate::dedupe::*;
+use crate::delivery::*;
+use crate::errors::*;
+use crate::event_stats::*;
+use crate::history::*;
+use crate::item_lists::*;
+use crate::keylock::*;
+use crate::maintenance::*;
+use crate::materia::*;
+use crate::metrics_registry::*;
+use crate::mutes::*;
+use crate::ongoing::*;
+use crate::ops::*;
+use crate::outbox::*;
+use crate::poison::*;
+use crate::preferences::*;
+use crate::quarantine::*;
+use crate::redact::*;
+use crate::retry::*;
+use crate::scoreboard::*;
+use crate::shedding::*;
+use crate::slo::*;
+use crate::snapshots::*;
+use crate::standalone::*;
+use crate::state::*;
+use crate::subscriptions::*;
+use crate::timeouts::*;
+use crate::trigger::*;
+use crate::universalis::*;
+use crate::world_status::*;
+use crate::xivapi::*;
+use itertools::Itertools;
+use metrics::counter;
+use mysql_async::Pool;
+use reqwest::Client;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::error::Elapsed;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+/// Shared state used by every connection's processing pipeline.
+pub struct Context {
+    /// The database, which is never connected to in standalone mode.
+    pub pool: Pool,
+    pub alerts: Box<dyn AlertRepository>,
+    pub alert_cache: Arc<AlertCache>,
+    /// Whether the only alert is a standalone one from the environment.
+    pub standalone: bool,
+    /// The client for webhooks, with Discord's timeouts.
+    pub client: Client,
+    /// The client for the Universalis REST API.
+    pub universalis_client: Client,
+    pub quarantine: QuarantineConfig,
+    /// Alerts that were skipped when loaded because they can't be evaluated.
+    pub rejected_alerts: Arc<RejectedAlerts>,
+    pub price_guard: PriceGuard,
+    pub event_limits: EventLimits,
+    pub shadow_eval: bool,
+    pub shedder: LoadShedder,
+    pub delivery: DeliveryMode,
+    pub branding: Branding,
+    pub dedupe: NotificationDedupe,
+    pub maintenance: MaintenanceState,
+    pub ops: OpsNotifier,
+    pub poison: PoisonTracker,
+    pub baselines: Box<dyn BaselineProvider>,
+    pub world_status: WorldStatusFeed,
+    pub channels: ChannelSelector,
+    pub alert_limits: AlertLimits,
+    pub connections: ConnectionHistory,
+    pub subscriptions: SubscriptionTracker,
+    /// Whether sent notifications are recorded in the history table.
+    pub record_history: bool,
+    pub materia_prices: MateriaPrices,
+    pub ongoing: OngoingMessages,
+    pub key_locks: KeyLocks,
+    pub retries: RetryBuffer,
+    pub mutes: MuteList,
+    pub preferences: PreferenceStore,
+    pub item_lists: ItemLists,
+    pub event_stats: EventStats,
+    pub coalescer: EventCoalescer,
+    pub snapshots: Snapshots,
+    /// How long after an event is received its notifications may be sent
+    /// before they're marked as delayed.
+    pub event_deadline: Duration,
+    pub delivery_slo: DeliverySlo,
+    /// How deliveries to each webhook have been going.
+    pub scoreboard: DestinationScoreboard,
+    pub state: Arc<StateStore>,
+}
+
+impl Context {
+    /// Creates the pipeline's state, reading each component's configuration
+    /// from the environment.
+    pub fn from_env(pool: Pool) -> Result<Self> {
+        let standalone = StandaloneAlert::from_env()?;
+        let delivery = DeliveryMode::from_env()?;
+        let record_history = env::var("UNIVERSALIS_ALERTS_RECORD_HISTORY")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        if standalone.is_some() && (delivery == DeliveryMode::Outbox || record_history) {
+            return Err("the outbox and notification history need a database, so they can't be used in standalone mode".into());
+        }
+        let snapshots = Snapshots::from_env(&pool)?;
+        if standalone.is_some() && snapshots.in_database {
+            return Err("snapshots can't be kept in the database in standalone mode".into());
+        }
+
+        let state = Arc::new(StateStore::from_env()?);
+
+        let is_standalone = standalone.is_some();
+        let alert_cache = Arc::new(AlertCache::from_env());
+        let rejected_alerts = Arc::new(RejectedAlerts::default());
+        let database_alerts = DatabaseAlerts::new(pool.clone(), rejected_alerts.clone());
+        let alerts: Box<dyn AlertRepository> = match standalone {
+            Some(alert) => Box::new(alert),
+            None if alert_cache.is_enabled() => Box::new(CachedAlerts::new(
+                Box::new(database_alerts),
+                alert_cache.clone(),
+            )),
+            None => Box::new(database_alerts),
+        };
+
+        Ok(Self {
+            alerts,
+            alert_cache,
+            standalone: is_standalone,
+            pool,
+            client: timeouts().client(Service::Discord),
+            universalis_client: timeouts().client(Service::Universalis),
+            quarantine: QuarantineConfig::from_env(),
+            rejected_alerts,
+            price_guard: PriceGuard::from_env(),
+            event_limits: EventLimits::from_env(),
+            shadow_eval: env::var("UNIVERSALIS_ALERTS_SHADOW_EVAL")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            shedder: LoadShedder::from_env(),
+            delivery,
+            branding: Branding::from_env()?,
+            dedupe: NotificationDedupe::from_env(state.clone()),
+            maintenance: MaintenanceState::from_env(),
+            ops: OpsNotifier::from_env(),
+            poison: PoisonTracker::from_env(),
+            baselines: Box::new(MarketBaselines::from_env()?),
+            world_status: WorldStatusFeed::from_env(),
+            channels: ChannelSelector::from_env(),
+            alert_limits: AlertLimits::from_env(),
+            connections: ConnectionHistory::default(),
+            subscriptions: SubscriptionTracker::from_env(),
+            record_history,
+            materia_prices: MateriaPrices::from_env()?,
+            ongoing: OngoingMessages::new(state.clone()),
+            key_locks: KeyLocks::default(),
+            retries: RetryBuffer::from_env(),
+            mutes: MuteList::default(),
+            preferences: PreferenceStore::default(),
+            item_lists: ItemLists::default(),
+            event_stats: EventStats::from_env(),
+            coalescer: EventCoalescer::from_env(),
+            snapshots,
+            event_deadline: event_deadline_from_env(),
+            delivery_slo: DeliverySlo::from_env(),
+            scoreboard: DestinationScoreboard::default(),
+            state,
+        })
+    }
+}
+
+fn parse_event_from_message(data: &[u8]) -> Result<UniversalisEvent<'_>> {
+    let header: EventHeader = bson::from_slice(data)?;
+    match header.event.as_deref() {
+        Some(event) if SubscriptionReply::EVENTS.contains(&event) => {
+            Ok(UniversalisEvent::SubscriptionReply(bson::from_slice(data)?))
+        }
+        // Market events are named after their channel, e.g. "listings/add";
+        // anything else is a service announcement.
+        Some(event) if !event.contains('/') => {
+            Ok(UniversalisEvent::Broadcast(bson::from_slice(data)?))
+        }
+        event => {
+            let channel = MarketChannel::parse(event)
+                .ok_or_else(|| format!("unknown market channel: {}", event.unwrap_or_default()))?;
+            let mut ev: ListingsAddEvent = bson::from_slice(data)?;
+            ev.channel = channel;
+            Ok(UniversalisEvent::ListingsAdd(ev))
+        }
+    }
+}
+
+/// Handles a service announcement from Universalis.
+async fn process_broadcast(region: &str, broadcast: BroadcastEvent, ctx: &Context) -> Result<()> {
+    counter!(BROADCASTS.name, 1, "region" => region.to_owned());
+    ctx.maintenance.record(&broadcast);
+
+    let message = format!(
+        "[{}] Universalis broadcast ({}): {}",
+        region,
+        broadcast.event,
+        broadcast.message.as_deref().unwrap_or("(no message)")
+    );
+    ctx.ops.notify(&message, &ctx.client).await
+}
+
+/// Compares the result of the current trigger engine with the candidate
+/// engine, recording any divergence. This never affects what gets delivered.
+fn shadow_evaluate(
+    alert: &UserAlert,
+    trigger: &AlertTrigger,
+    listings: &[&Listing<'_>],
+    parameters: &TriggerParameters,
+    trigger_result: Option<f32>,
+) {
+    let candidate_result = trigger.evaluate_candidate(listings, parameters);
+    let diverged = match (trigger_result, candidate_result) {
+        // Reductions may sum in a different order, so allow for rounding
+        (Some(current), Some(candidate)) => {
+            (current - candidate).abs() > current.abs().max(1.0) * 1e-4
+        }
+        (None, None) => false,
+        _ => true,
+    };
+    if diverged {
+        counter!(SHADOW_EVAL_DIVERGENCES.name, 1);
+        warn!(
+            "shadow evaluation diverged for alert {}: current {:?}, candidate {:?}",
+            alert.id, trigger_result, candidate_result
+        );
+    } else {
+        counter!(SHADOW_EVAL_AGREEMENTS.name, 1);
+    }
+}
+
+/// The outcome of delivering a notification to one of an alert's webhooks.
+#[derive(Serialize, Debug, Clone)]
+pub struct DestinationOutcome {
+    /// The position of the webhook in the alert's list of webhooks.
+    pub destination: usize,
+    pub delivered: bool,
+    /// Whether the webhook is muted, so nothing was sent to it.
+    pub muted: bool,
+    /// The ID of the message the webhook posted, if it was sent directly.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The outcome of evaluating one alert against an event.
+#[derive(Serialize, Debug, Clone)]
+pub struct AlertOutcome {
+    pub alert_id: String,
+    pub name: String,
+    /// The trigger result, if the alert matched.
+    pub value: Option<f32>,
+    /// Whether a notification was sent or queued for any of the alert's webhooks.
+    pub delivered: bool,
+    /// Whether the alert's user is muted, so nothing was delivered.
+    pub muted: bool,
+    pub destinations: Vec<DestinationOutcome>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Sends a notification directly, editing the message that was posted for it
+/// before instead if there is one.
+async fn send_or_edit(
+    notification: &Notification,
+    message_id: Option<&str>,
+    ctx: &Context,
+) -> Result<Option<String>> {
+    let message_id = match message_id {
+        Some(message_id) => message_id,
+        None => return send_notification(notification, &ctx.client, Some(&ctx.scoreboard)).await,
+    };
+    match edit_notification(notification, message_id, &ctx.client, Some(&ctx.scoreboard)).await {
+        Ok(_) => Ok(Some(message_id.to_owned())),
+        // The message was deleted, so post a new one
+        Err(Error(ErrorKind::Webhook(404, _), _)) => {
+            send_notification(notification, &ctx.client, Some(&ctx.scoreboard)).await
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Renders the notification for a matched alert and either sends it or
+/// writes it to the outbox, depending on the delivery mode. Each of the
+/// alert's webhooks is attempted independently. Alerts that edit in place
+/// update the message from their previous match while their condition stays
+/// true; this only works with direct delivery. New notifications are saved
+/// with a snapshot of the listings that matched, if snapshots are enabled.
+async fn deliver(
+    region: &str,
+    ev: &ListingsAddEvent<'_>,
+    alert: &UserAlert,
+    trigger: &AlertTrigger,
+    trigger_result: f32,
+    snapshot: Option<&[Listing<'_>]>,
+    ctx: &Context,
+) -> Result<Vec<DestinationOutcome>> {
+    let (item_id, world_id) = (ev.item_id, ev.world_id);
+    let world_status = ctx.world_status.get(world_id);
+    let restricted = world_status.is_some_and(|s| s.is_restricted());
+    if restricted && alert.travel_policy == TravelPolicy::Suppress {
+        counter!(TRAVEL_SUPPRESSED.name, 1);
+        not_fired("travel_suppressed");
+        return Ok(Vec::new());
+    }
+
+    let state_key = alert.state_key(world_id, item_id);
+    let min_window = ctx.event_stats.dedupe_window(world_id, item_id);
+    if ctx
+        .dedupe
+        .is_repeat(&state_key, trigger_result, min_window)
+        .await
+    {
+        counter!(DEDUPLICATED.name, 1);
+        not_fired("cooldown_active");
+        return Ok(Vec::new());
+    }
+
+    // Notifications for events that sat in a backlog or were retried carry
+    // prices that may have changed since. Those that go through the outbox
+    // are checked by the delivery worker instead, once they're sent.
+    let late_by = ev
+        .received_at
+        .filter(|_| ctx.delivery == DeliveryMode::Direct)
+        .map(|received_at| received_at.elapsed())
+        .filter(|age| *age > ctx.event_deadline);
+    if late_by.is_some() {
+        counter!(LATE_DELIVERIES.name, 1);
+    }
+
+    let edit_in_place = alert.edit_in_place && ctx.delivery == DeliveryMode::Direct;
+    let ongoing = if edit_in_place {
+        ctx.ongoing.get(&state_key).await
+    } else {
+        None
+    };
+    let notifications = render_discord_message(
+        region,
+        item_id,
+        world_id,
+        ev.channel,
+        alert,
+        trigger,
+        trigger_result,
+        &ctx.branding,
+        world_status
+            .as_ref()
+            .filter(|_| alert.travel_policy == TravelPolicy::Annotate),
+        ongoing.as_ref().map(|o| o.since),
+        late_by,
+    )
+    .await?;
+    if notifications.is_empty() {
+        not_fired("delivery_disabled");
+    }
+
+    let mut outcomes = Vec::with_capacity(notifications.len());
+    for (destination, notification) in notifications.iter().enumerate() {
+        if ctx.mutes.is_webhook_muted(&notification.discord_webhook) {
+            counter!(DESTINATION_DELIVERIES.name, 1, "outcome" => "muted");
+            outcomes.push(DestinationOutcome {
+                destination,
+                delivered: false,
+                muted: true,
+                message_id: None,
+                error: None,
+            });
+            continue;
+        }
+
+        let sent = match ctx.delivery {
+            DeliveryMode::Direct => {
+                let message_id = ongoing
+                    .as_ref()
+                    .and_then(|o| o.message_ids.get(destination)?.as_deref());
+                send_or_edit(notification, message_id, ctx).await
+            }
+            DeliveryMode::Outbox => {
+                let received_at = ev
+                    .received_at
+                    .map(|r| unix_now_ms() - r.elapsed().as_millis() as i64);
+                with_timeout(
+                    Service::Database,
+                    enqueue_notification(notification, received_at, &ctx.pool),
+                )
+                .await
+                .map(|_| None)
+            }
+        };
+        let outcome = match sent {
+            Ok(message_id) => {
+                counter!(DESTINATION_DELIVERIES.name, 1, "outcome" => "delivered");
+                daily_stats().record_delivery(true);
+                DestinationOutcome {
+                    destination,
+                    delivered: true,
+                    muted: false,
+                    message_id,
+                    error: None,
+                }
+            }
+            Err(err) => {
+                counter!(DESTINATION_DELIVERIES.name, 1, "outcome" => "failed");
+                daily_stats().record_delivery(false);
+                error!("{:?}", err);
+                DestinationOutcome {
+                    destination,
+                    delivered: false,
+                    muted: false,
+                    message_id: None,
+                    error: Some(redact(&err.to_string()).into_owned()),
+                }
+            }
+        };
+        outcomes.push(outcome);
+    }
+    if !outcomes.is_empty() && outcomes.iter().all(|o| o.muted) {
+        not_fired("muted");
+    }
+    // Only notifications that got through hold back repeats, so failed
+    // sends are retried with the next event
+    if outcomes.iter().any(|o| o.delivered) {
+        ctx.dedupe
+            .record(&state_key, trigger_result, min_window)
+            .await;
+    }
+
+    let message_ids = outcomes.iter().map(|o| o.message_id.clone()).collect_vec();
+    if edit_in_place && message_ids.iter().any(Option::is_some) {
+        ctx.ongoing
+            .record(
+                &state_key,
+                OngoingMessage {
+                    message_ids: message_ids.clone(),
+                    since: ongoing.as_ref().map_or_else(unix_now, |o| o.since),
+                },
+            )
+            .await;
+    }
+
+    // Edits update a notification that's already been sent
+    let is_new = ongoing.is_none() && outcomes.iter().any(|o| o.delivered);
+    let mut notification_id = None;
+    if let Some(listings) = snapshot.filter(|_| is_new) {
+        let id = uuid::Uuid::new_v4().to_string();
+        let snapshot = EventSnapshot {
+            notification_id: &id,
+            alert_id: &alert.id,
+            item_id,
+            world_id,
+            value: trigger_result,
+            captured_at: unix_now(),
+            listings,
+        };
+        match ctx.snapshots.spawn_save(&snapshot) {
+            Ok(()) => notification_id = Some(id),
+            Err(err) => {
+                counter!(SNAPSHOT_FAILURES.name, 1);
+                error!("failed to encode event snapshot: {:?}", err);
+            }
+        }
+    }
+
+    if ctx.record_history && is_new {
+        let record = NotificationRecord {
+            alert_id: alert.id.clone(),
+            user_id: alert.user_id.clone(),
+            alert_name: alert.name.clone(),
+            item_id,
+            world_id,
+            value: trigger_result,
+            sent_at: unix_now(),
+            message_ids,
+            notification_id,
+        };
+        let recorded = with_timeout(Service::Database, record_notification(&record, &ctx.pool));
+        if let Err(err) = recorded.await {
+            counter!(HISTORY_FAILURES.name, 1);
+            error!("failed to record notification history: {:?}", err);
+        }
+    }
+    Ok(outcomes)
+}
+
+/// Drops the category-scoped alerts that don't apply to an item. The item's
+/// categories are only looked up if any of the alerts need them.
+async fn scope_to_item_categories(
+    item_id: i32,
+    alerts: Vec<(UserAlert, AlertTrigger)>,
+) -> Result<Vec<(UserAlert, AlertTrigger)>> {
+    if !alerts.iter().any(|(alert, _)| alert.has_category_scope()) {
+        return Ok(alerts);
+    }
+
+    let categories = get_item_categories(item_id).await?;
+    Ok(alerts
+        .into_iter()
+        .filter(|(alert, _)| alert.matches_categories(categories.as_ref()))
+        .collect_vec())
+}
+
+/// Resolves each baseline that the alerts' triggers are compared against.
+/// Baselines that can't be resolved are left out, so comparisons against
+/// them fail.
+async fn resolve_baselines(
+    alerts: &[(UserAlert, AlertTrigger)],
+    ev: &ListingsAddEvent<'_>,
+    ctx: &Context,
+) -> HashMap<Baseline, f32> {
+    let needed = alerts
+        .iter()
+        .filter_map(|(_, trigger)| trigger.baseline())
+        .unique()
+        .collect_vec();
+
+    let mut baselines = HashMap::new();
+    for baseline in needed {
+        match ctx
+            .baselines
+            .resolve(baseline, ev.world_id, ev.item_id)
+            .await
+        {
+            Ok(Some(value)) => {
+                baselines.insert(baseline, value);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                counter!(BASELINE_FAILURES.name, 1);
+                error!("failed to resolve {} baseline: {:?}", baseline, err);
+            }
+        }
+    }
+    baselines
+}
+
+/// Evaluates every alert for an event's world and item, delivering
+/// notifications for the ones that match unless `dry_run` is set. Events for
+/// the same world and item are evaluated one at a time.
+pub async fn evaluate_event(
+    region: &str,
+    ev: &ListingsAddEvent<'_>,
+    dry_run: bool,
+    ctx: &Context,
+) -> Result<Vec<AlertOutcome>> {
+    // Events for the same item on the same world are evaluated in order
+    let _key_guard = ctx.key_locks.lock(ev.world_id, ev.item_id).await;
+
+    // Fetch all matching alerts from the database
+    let alerts = match ctx
+        .alerts
+        .alerts_for_world_item(ev.world_id, ev.item_id, ctx.alert_limits)
+        .await
+    {
+        Ok(alerts) => {
+            ctx.retries.record_database_ok();
+            alerts
+        }
+        Err(err) => {
+            ctx.retries.record_database_failure();
+            let message = format!("[{}] Failed to load alerts: {}", region, err);
+            ctx.ops.incident("database", &message, &ctx.client).await;
+            return Err(err).chain_err(|| ErrorKind::AlertsUnavailable);
+        }
+    };
+    let alerts = scope_to_item_categories(ev.item_id, alerts)
+        .await?
+        .into_iter()
+        .map(|(mut alert, trigger)| {
+            ctx.preferences.apply(&mut alert);
+            (alert, trigger)
+        })
+        .collect_vec();
+    ctx.shedder
+        .record_alert_count(ev.world_id, ev.item_id, alerts.len());
+    let now = unix_now();
+    let alerts = alerts
+        .into_iter()
+        // Skip alerts that have repeatedly stalled processing
+        .filter(|(alert, _)| {
+            let quarantined = ctx.poison.is_quarantined(&alert.id);
+            if quarantined {
+                counter!(ALERT_QUARANTINE_SKIPPED.name, 1);
+                not_fired("quarantined");
+            }
+            !quarantined
+        })
+        // Skip alerts outside of their schedules
+        .filter(|(_, trigger)| {
+            let scheduled = trigger.is_scheduled(now);
+            if !scheduled {
+                counter!(UNSCHEDULED_SKIPPED.name, 1);
+                not_fired("unscheduled");
+            }
+            scheduled
+        })
+        // Skip alerts in their quiet hours
+        .filter(|(alert, _)| {
+            let quiet = alert.is_quiet(now);
+            if quiet {
+                counter!(QUIET_HOURS_SKIPPED.name, 1);
+                not_fired("quiet_hours");
+            }
+            !quiet
+        })
+        .collect_vec();
+    let baselines = resolve_baselines(&alerts, ev, ctx).await;
+    let materia_prices = ctx
+        .materia_prices
+        .resolve(&alerts, ev, ctx.baselines.as_ref())
+        .await;
+
+    let guarded_listings = ctx.price_guard.apply(&ev.listings);
+    let alerts = alerts
+        .into_iter()
+        .map(|(alert, trigger)| {
+            let listings = if alert.include_outliers {
+                &ev.listings[..]
+            } else {
+                &guarded_listings[..]
+            };
+            // Alerts for one quality of an item only see its listings
+            let listings = listings
+                .iter()
+                .filter(|listing| alert.item_quality.matches(listing))
+                .collect_vec();
+
+            let mut parameters = alert.trigger_parameters();
+            if trigger.baseline().is_some() {
+                parameters.baselines = baselines.clone();
+            }
+            if trigger.uses_materia() {
+                parameters.materia_prices = materia_prices.clone();
+            }
+
+            // Evaluate if all trigger conditions were met
+            let evaluation = listings
+                .iter()
+                .copied()
+                .apply_trigger_with(&trigger, &parameters);
+            match (evaluation.value, evaluation.matched) {
+                (None, _) => not_fired("no_listings_after_filters"),
+                (Some(_), false) => not_fired("comparison_false"),
+                (Some(_), true) => {}
+            }
+            let trigger_result = evaluation.result();
+            if ctx.shadow_eval {
+                shadow_evaluate(&alert, &trigger, &listings, &parameters, trigger_result);
+            }
+            let snapshot = (ctx.snapshots.is_enabled() && trigger_result.is_some()).then(|| {
+                trigger
+                    .filter_listings(&listings)
+                    .into_iter()
+                    .cloned()
+                    .collect_vec()
+            });
+            (alert, trigger, trigger_result, snapshot)
+        })
+        .collect_vec();
+
+    let matched = alerts.iter().filter(|(_, _, tr, _)| tr.is_some()).count();
+    counter!(
+        MATCHED.name,
+        matched as u64,
+        "region" => region.to_owned(),
+        "channel" => ev.channel.name()
+    );
+
+    // Send Discord notifications for each matching trigger
+    let mut outcomes = Vec::with_capacity(alerts.len());
+    for (alert, trigger, trigger_result, snapshot) in alerts {
+        let mut outcome = AlertOutcome {
+            alert_id: alert.id.clone(),
+            name: alert.name.clone(),
+            value: trigger_result,
+            delivered: false,
+            muted: ctx.mutes.is_user_muted(alert.user_id.as_deref()),
+            destinations: Vec::new(),
+            error: None,
+        };
+        if outcome.muted && trigger_result.is_some() {
+            not_fired("muted");
+        }
+
+        if trigger_result.is_none() && alert.edit_in_place {
+            ctx.ongoing
+                .end(&alert.state_key(ev.world_id, ev.item_id))
+                .await;
+        }
+        if let Some(tr) = trigger_result {
+            counter!(TRIGGER_VERSION_MATCHED.name, 1, "trigger_version" => alert.trigger_version.to_string());
+            daily_stats().record_match(&alert.id);
+            if !dry_run && !outcome.muted {
+                let sent = tokio::time::timeout(
+                    ctx.poison.timeout,
+                    deliver(region, ev, &alert, &trigger, tr, snapshot.as_deref(), ctx),
+                )
+                .await;
+                record_slo(ev, &sent, ctx);
+
+                // Log any errors that happened while sending the message
+                match sent {
+                    Ok(Ok(destinations)) => {
+                        outcome.delivered = destinations.iter().any(|d| d.delivered);
+                        outcome.destinations = destinations;
+                    }
+                    Ok(Err(err)) => {
+                        error!("{:?}", err);
+                        outcome.error = Some(redact(&err.to_string()).into_owned());
+                    }
+                    Err(_) => {
+                        warn!("delivery for alert {} timed out", alert.id);
+                        if ctx.poison.record_timeout(&alert.id) {
+                            let message = format!(
+                                "Alert {} was quarantined after its deliveries repeatedly timed out",
+                                alert.id
+                            );
+                            ctx.ops
+                                .incident("alert_quarantine", &message, &ctx.client)
+                                .await;
+                        }
+                        outcome.error = Some("delivery timed out".to_owned());
+                    }
+                }
+            }
+        }
+
+        outcomes.push(outcome);
+    }
+
+    Ok(outcomes)
+}
+
+/// The result of delivering an alert's notifications within the poison
+/// timeout.
+type TimedDelivery = std::result::Result<Result<Vec<DestinationOutcome>>, Elapsed>;
+
+/// Records a direct delivery against the SLO, counting deliveries that
+/// failed or timed out as late. In outbox mode, the delivery worker records
+/// notifications once it sends them instead.
+fn record_slo(ev: &ListingsAddEvent<'_>, sent: &TimedDelivery, ctx: &Context) {
+    // Events from on-demand evaluations weren't received from anywhere
+    let latency = match ev.received_at {
+        Some(received_at) if ctx.delivery == DeliveryMode::Direct => received_at.elapsed(),
+        _ => return,
+    };
+    match sent {
+        Ok(Ok(destinations)) => {
+            for destination in destinations.iter().filter(|d| !d.muted) {
+                ctx.delivery_slo.record(latency, destination.delivered);
+            }
+        }
+        Ok(Err(_)) | Err(_) => ctx.delivery_slo.record(latency, false),
+    }
+}
+
+/// Counts an alert that was evaluated against an event without sending a
+/// notification, labeled by the stage that stopped it.
+fn not_fired(reason: &'static str) {
+    counter!(NOT_FIRED.name, 1, "reason" => reason);
+    daily_stats().record_not_fired(reason, 1);
+}
+
+/// Processes a message from the websocket. If `dry_run` is set, alerts are
+/// evaluated but no notifications are delivered. Replies to subscription
+/// requests are returned rather than processed, so that the connection can
+/// verify its subscriptions.
+#[tracing::instrument(skip(message, ctx))]
+pub async fn process(
+    region: &str,
+    message: Message,
+    dry_run: bool,
+    ctx: &Context,
+) -> Result<Option<SubscriptionReply>> {
+    // Drop oversized events before they're decoded
+    let received_at = Instant::now();
+    let data = message.into_data();
+    if let Some(reason) = ctx.event_limits.check(&data) {
+        counter!(OVERSIZED_EVENTS.name, 1, "reason" => reason);
+        warn!("dropped oversized event ({} bytes): {}", data.len(), reason);
+        return Ok(None);
+    }
+
+    // Parse the message into an event
+    let mut ev = match parse_event_from_message(&data)? {
+        UniversalisEvent::ListingsAdd(ev) => ev,
+        UniversalisEvent::Broadcast(broadcast) => {
+            process_broadcast(region, broadcast, ctx).await?;
+            return Ok(None);
+        }
+        UniversalisEvent::SubscriptionReply(reply) => return Ok(Some(reply)),
+    };
+    ev.received_at = Some(received_at);
+    counter!(
+        EVENTS.name,
+        1,
+        "region" => region.to_owned(),
+        "channel" => ev.channel.name()
+    );
+    daily_stats().record_event(ev.world_id, ev.item_id);
+    ctx.subscriptions.record(region, &ev);
+
+    // Removed listings have left the board, so there's nothing in them to
+    // buy. They only keep the channel from looking quiet.
+    if ev.channel == MarketChannel::ListingsRemove {
+        return Ok(None);
+    }
+
+    // Skip events during announced maintenance, if configured to
+    if ctx.maintenance.is_paused() {
+        counter!(MAINTENANCE_SKIPPED_EVENTS.name, 1);
+        return Ok(None);
+    }
+
+    // Skip events for items that operators have kept out of matching
+    if let Some(entry) = ctx.item_lists.blocking(ev.item_id) {
+        counter!(ITEM_LIST_SUPPRESSED_EVENTS.name, 1, "list" => entry.list.as_str());
+        return Ok(None);
+    }
+
+    // Drop events that look like bad uploads
+    if let Some(reason) = ctx.quarantine.check(&ev) {
+        counter!(QUARANTINED_EVENTS.name, 1, "reason" => reason);
+        warn!(
+            "quarantined event for item {} on world {}: {}",
+            ev.item_id, ev.world_id, reason
+        );
+        return Ok(None);
+    }
+
+    let chatty = ctx.event_stats.record(ev.world_id, ev.item_id);
+
+    // Let a burst of events for the same item settle, and only evaluate the
+    // last of them
+    if !ctx
+        .coalescer
+        .settle(ev.world_id, ev.item_id, ev.channel)
+        .await
+    {
+        return Ok(None);
+    }
+
+    // Skip the event if the service is overloaded
+    let _in_flight = match ctx.shedder.admit(ev.world_id, ev.item_id, chatty) {
+        Some(in_flight) => in_flight,
+        None => return Ok(None),
+    };
+
+    let evaluated = evaluate_event(region, &ev, dry_run, ctx).await;
+    drop(ev);
+    let event = BufferedEvent {
+        region: region.to_owned(),
+        data,
+        dry_run,
+        attempts: 0,
+        received_at,
+    };
+    buffer_if_unavailable(event, evaluated, ctx)?;
+    Ok(None)
+}
+
+/// Buffers an event to be retried if its alerts couldn't be loaded, rather
+/// than dropping it. Other errors are returned as-is.
+fn buffer_if_unavailable(
+    event: BufferedEvent,
+    evaluated: Result<Vec<AlertOutcome>>,
+    ctx: &Context,
+) -> Result<()> {
+    match evaluated {
+        Ok(_) => Ok(()),
+        Err(err) if matches!(err.kind(), ErrorKind::AlertsUnavailable) => {
+            warn!("[{}] {:?}", event.region, err);
+            ctx.retries.push(BufferedEvent {
+                attempts: event.attempts + 1,
+                ..event
+            });
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Evaluates the events that were buffered while alerts couldn't be loaded.
+/// Events that fail again go back into the buffer, until they run out of
+/// attempts.
+pub async fn retry_buffered(ctx: &Context) {
+    for event in ctx.retries.drain() {
+        let mut ev = match parse_event_from_message(&event.data) {
+            Ok(UniversalisEvent::ListingsAdd(ev)) => ev,
+            // Only listing events are ever buffered
+            Ok(UniversalisEvent::Broadcast(_) | UniversalisEvent::SubscriptionReply(_)) => continue,
+            Err(err) => {
+                error!("{:?}", err);
+                continue;
+            }
+        };
+        ev.received_at = Some(event.received_at);
+        counter!(RETRIED_EVENTS.name, 1);
+        let evaluated = evaluate_event(&event.region, &ev, event.dry_run, ctx).await;
+        drop(ev);
+        if let Err(err) = buffer_if_unavailable(event, evaluated, ctx) {
+            error!("{:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xivapi::{Item, World, GET_ITEM, GET_WORLD};
+    use cached::Cached;
+    use mysql_async::Opts;
+
+    /// A context that never reaches the database, with item and world names
+    /// cached so that notifications can be rendered offline.
+    async fn offline_context() -> Context {
+        GET_ITEM.lock().await.cache_set(
+            5,
+            Some(Item {
+                name: "Test item".to_owned(),
+            }),
+        );
+        GET_WORLD.lock().await.cache_set(
+            73,
+            World {
+                name: "Adamantoise".to_owned(),
+            },
+        );
+        let pool = Pool::new(Opts::from_url("mysql://127.0.0.1:1/test").unwrap());
+        Context::from_env(pool).unwrap()
+    }
+
+    fn event<'a>(item_id: i32) -> ListingsAddEvent<'a> {
+        ListingsAddEvent {
+            item_id,
+            world_id: 73,
+            listings: Vec::new(),
+            channel: MarketChannel::default(),
+            received_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_sends_leave_no_dedupe_record() {
+        let mut ctx = offline_context().await;
+        ctx.dedupe = NotificationDedupe::new(Duration::from_secs(600), ctx.state.clone());
+        // Nothing listens on port 1, so the send fails
+        let alert = UserAlert::for_test("alert", "http://127.0.0.1:1/api/webhooks/1/token");
+        let trigger = parse_expression("min(pricePerUnit) < 1000").unwrap();
+
+        let outcomes = deliver("test", &event(5), &alert, &trigger, 500.0, None, &ctx)
+            .await
+            .unwrap();
+        assert_eq!(outcomes.len(), 1);
+        assert!(!outcomes[0].delivered);
+        assert!(
+            !ctx.dedupe
+                .is_repeat(&alert.state_key(73, 5), 500.0, Duration::ZERO)
+                .await
+        );
+    }
+}
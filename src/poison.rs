@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::env_or;
+use crate::metrics_registry::*;
+use metrics::counter;
+
+#[derive(Debug, Default)]
+struct AlertTimeouts {
+    count: u32,
+    quarantined_until: Option<Instant>,
+}
+
+/// Tracks alerts whose handling repeatedly times out, and quarantines them
+/// for a while so that they can't keep stalling event processing.
+pub struct PoisonTracker {
+    /// How long each alert's handling may take.
+    pub timeout: Duration,
+    max_timeouts: u32,
+    quarantine: Duration,
+    alerts: Mutex<HashMap<String, AlertTimeouts>>,
+}
+
+impl PoisonTracker {
+    /// Reads the per-alert timeout from `UNIVERSALIS_ALERTS_ALERT_TIMEOUT_SECS`,
+    /// the number of timeouts before an alert is quarantined from
+    /// `UNIVERSALIS_ALERTS_ALERT_MAX_TIMEOUTS`, and the quarantine duration from
+    /// `UNIVERSALIS_ALERTS_ALERT_QUARANTINE_SECS`.
+    pub fn from_env() -> Self {
+        Self {
+            timeout: Duration::from_secs(env_or("UNIVERSALIS_ALERTS_ALERT_TIMEOUT_SECS", 30)),
+            max_timeouts: env_or("UNIVERSALIS_ALERTS_ALERT_MAX_TIMEOUTS", 3),
+            quarantine: Duration::from_secs(env_or(
+                "UNIVERSALIS_ALERTS_ALERT_QUARANTINE_SECS",
+                3600,
+            )),
+            alerts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns whether an alert is currently quarantined.
+    pub fn is_quarantined(&self, alert_id: &str) -> bool {
+        let mut alerts = self.alerts.lock().unwrap();
+        match alerts.get(alert_id).and_then(|a| a.quarantined_until) {
+            Some(until) if until > Instant::now() => true,
+            Some(_) => {
+                // The quarantine has expired, so give the alert a fresh start
+                alerts.remove(alert_id);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Returns how much longer an alert is quarantined for, if it is.
+    pub fn quarantine_remaining(&self, alert_id: &str) -> Option<Duration> {
+        let alerts = self.alerts.lock().unwrap();
+        let until = alerts.get(alert_id)?.quarantined_until?;
+        Some(until.saturating_duration_since(Instant::now())).filter(|d| !d.is_zero())
+    }
+
+    /// Records that handling an alert timed out, quarantining it if it has
+    /// timed out too many times. Returns whether the alert was quarantined.
+    pub fn record_timeout(&self, alert_id: &str) -> bool {
+        counter!(ALERT_TIMEOUTS.name, 1);
+
+        let mut alerts = self.alerts.lock().unwrap();
+        let entry = alerts.entry(alert_id.to_owned()).or_default();
+        entry.count += 1;
+        if entry.count >= self.max_timeouts {
+            counter!(ALERT_QUARANTINES.name, 1);
+            warn!(
+                "quarantining alert {} after {} timeouts",
+                alert_id, entry.count
+            );
+            entry.quarantined_until = Some(Instant::now() + self.quarantine);
+            return true;
+        }
+        false
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::alerts::UserAlert;
+use crate::errors::*;
+use mysql_async::{prelude::*, Pool};
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+/// Who a notification mentions, so that it stands out in a busy channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MentionStyle {
+    /// No mentions.
+    #[default]
+    None,
+    /// The alert's owner, if they've linked a Discord account.
+    User,
+    /// Everyone in the channel who is online.
+    Here,
+}
+
+impl MentionStyle {
+    pub fn parse(value: Option<&str>) -> Option<Self> {
+        match value {
+            Some("none") => Some(Self::None),
+            Some("user") => Some(Self::User),
+            Some("here") => Some(Self::Here),
+            _ => None,
+        }
+    }
+}
+
+/// A daily window in which notifications aren't sent, in the user's time
+/// zone. A window that ends before it starts runs past midnight, and one
+/// that ends when it starts is never quiet, which lets an alert turn off its
+/// user's quiet hours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    /// Minutes since midnight.
+    pub start: u32,
+    pub end: u32,
+}
+
+impl QuietHours {
+    /// Parses a window written as `HH:MM-HH:MM`, or `off` for none.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if value == "off" {
+            return Some(Self { start: 0, end: 0 });
+        }
+        let minutes = |time: &str| {
+            let (hours, minutes) = time.trim().split_once(':')?;
+            let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+            (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+        };
+        let (start, end) = value.split_once('-')?;
+        Some(Self {
+            start: minutes(start)?,
+            end: minutes(end)?,
+        })
+    }
+
+    fn minute_of_day(now: i64, utc_offset_minutes: i32) -> i64 {
+        (now / 60 + utc_offset_minutes as i64).rem_euclid(MINUTES_PER_DAY)
+    }
+
+    /// Returns whether the window is in effect at a time, in seconds since
+    /// the Unix epoch.
+    pub fn is_quiet(&self, now: i64, utc_offset_minutes: i32) -> bool {
+        let minute = Self::minute_of_day(now, utc_offset_minutes);
+        let (start, end) = (self.start as i64, self.end as i64);
+        if start <= end {
+            start <= minute && minute < end
+        } else {
+            minute >= start || minute < end
+        }
+    }
+
+    /// Returns when the window in effect at a time ends, in seconds since the
+    /// Unix epoch.
+    pub fn ends_at(&self, now: i64, utc_offset_minutes: i32) -> i64 {
+        let minute = Self::minute_of_day(now, utc_offset_minutes);
+        let remaining = (self.end as i64 - minute).rem_euclid(MINUTES_PER_DAY);
+        now - now.rem_euclid(60) + remaining * 60
+    }
+}
+
+/// A user's defaults for every one of their alerts. Anything an alert sets
+/// itself takes precedence.
+#[derive(Debug, Clone, Default)]
+pub struct UserPreferences {
+    pub locale: Option<String>,
+    /// The offset of the user's time zone from UTC, in minutes.
+    pub utc_offset_minutes: Option<i32>,
+    pub quiet_hours: Option<QuietHours>,
+    pub mention_style: Option<MentionStyle>,
+    /// The user's linked Discord account, which is mentioned for them.
+    pub discord_id: Option<String>,
+}
+
+/// Every user's notification preferences, as of when they were last loaded
+/// from the database.
+#[derive(Default)]
+pub struct PreferenceStore {
+    preferences: RwLock<HashMap<String, UserPreferences>>,
+}
+
+impl PreferenceStore {
+    /// Replaces every user's preferences with the ones loaded from the
+    /// database.
+    pub fn replace(&self, preferences: HashMap<String, UserPreferences>) {
+        *self.preferences.write().unwrap() = preferences;
+    }
+
+    /// Fills in whatever an alert leaves unset from its user's preferences.
+    pub fn apply(&self, alert: &mut UserAlert) {
+        let preferences = self.preferences.read().unwrap();
+        let preferences = match alert.user_id.as_deref().and_then(|id| preferences.get(id)) {
+            Some(preferences) => preferences,
+            None => return,
+        };
+        if alert.locale.is_none() {
+            alert.locale = preferences.locale.clone();
+        }
+        alert.utc_offset_minutes = alert.utc_offset_minutes.or(preferences.utc_offset_minutes);
+        alert.quiet_hours = alert.quiet_hours.or(preferences.quiet_hours);
+        alert.mention_style = alert.mention_style.or(preferences.mention_style);
+        alert.discord_id = preferences.discord_id.clone();
+    }
+}
+
+/// A row of the preferences table: the user ID, locale, UTC offset, quiet
+/// hours, and mention style, with the user's Discord ID.
+type PreferencesRow = (
+    String,
+    Option<String>,
+    Option<i32>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+/// Loads every user's preferences from the preferences table. Settings that
+/// can't be parsed are left unset, so that the alert's own or the default
+/// applies.
+pub async fn load_preferences(pool: &Pool) -> Result<HashMap<String, UserPreferences>> {
+    let mut conn = pool.get_conn().await?;
+    let rows: Vec<PreferencesRow> =
+        r"SELECT p.`user_id`, p.`locale`, p.`utc_offset_minutes`, p.`quiet_hours`, p.`mention_style`, u.`sso_discord_id` FROM `users_alert_preferences` p LEFT JOIN `users` u ON u.`id` = p.`user_id`"
+            .fetch(&mut conn)
+            .await?;
+    Ok(rows
+        .into_iter()
+        .map(
+            |(user_id, locale, utc_offset_minutes, quiet_hours, mention_style, discord_id)| {
+                let preferences = UserPreferences {
+                    locale,
+                    utc_offset_minutes,
+                    quiet_hours: quiet_hours.as_deref().and_then(QuietHours::parse),
+                    mention_style: MentionStyle::parse(mention_style.as_deref()),
+                    discord_id,
+                };
+                (user_id, preferences)
+            },
+        )
+        .collect())
+}
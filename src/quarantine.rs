@@ -0,0 +1,252 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::sync::Mutex;
+
+use crate::config::env_or;
+use crate::metrics_registry::*;
+use crate::universalis::*;
+use metrics::counter;
+use serde::Serialize;
+
+/// How many rejected alerts are remembered.
+const MAX_REJECTED_ALERTS: usize = 1000;
+
+/// Service-level checks for events that look like bad uploads. Events that
+/// fail these checks are dropped before any alerts are evaluated.
+#[derive(Debug, Clone)]
+pub struct QuarantineConfig {
+    pub enabled: bool,
+    pub blocked_sellers: HashSet<String>,
+}
+
+impl QuarantineConfig {
+    /// Reads the quarantine configuration. Quarantining is enabled unless
+    /// `UNIVERSALIS_ALERTS_QUARANTINE` is set to `false`, and
+    /// `UNIVERSALIS_ALERTS_BLOCKED_SELLERS` may contain a comma-separated
+    /// list of seller IDs whose uploads should always be ignored.
+    pub fn from_env() -> Self {
+        let enabled = env::var("UNIVERSALIS_ALERTS_QUARANTINE")
+            .map(|v| v != "false")
+            .unwrap_or(true);
+        let blocked_sellers = env::var("UNIVERSALIS_ALERTS_BLOCKED_SELLERS")
+            .map(|v| {
+                v.split(',')
+                    .map(|s| s.trim().to_owned())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            enabled,
+            blocked_sellers,
+        }
+    }
+
+    /// Returns the reason an event should be quarantined, if any.
+    pub fn check(&self, ev: &ListingsAddEvent<'_>) -> Option<&'static str> {
+        if !self.enabled {
+            return None;
+        }
+
+        if ev
+            .listings
+            .iter()
+            .filter_map(|l| l.seller_id.as_deref())
+            .any(|id| self.blocked_sellers.contains(id))
+        {
+            return Some("blocked_seller");
+        }
+
+        // A full board where everything is listed at 1 gil is almost
+        // certainly a broken upload rather than a real market.
+        if ev.listings.len() > 1 && ev.listings.iter().all(|l| l.unit_price <= 1) {
+            return Some("all_one_gil");
+        }
+
+        let mut listing_ids = HashSet::new();
+        if ev
+            .listings
+            .iter()
+            .filter_map(|l| l.listing_id.as_deref())
+            .any(|id| !listing_ids.insert(id))
+        {
+            return Some("duplicate_listing_ids");
+        }
+
+        None
+    }
+}
+
+/// An alert that was skipped when it was loaded, and why.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RejectedAlert {
+    pub alert_id: String,
+    /// What kind of problem the alert has: `invalid_trigger` or
+    /// `feature_disabled`.
+    pub reason: &'static str,
+    /// The problem, as it can be explained to the alert's owner.
+    pub message: String,
+    /// When the alert was last rejected, in seconds since the Unix epoch.
+    pub rejected_at: i64,
+}
+
+/// The alerts that were most recently skipped when loaded because they
+/// can't be evaluated on this deployment. Like quarantined events, they're
+/// set aside rather than failing anything else, and kept here so that
+/// operators can tell users why their alert never fires.
+#[derive(Default)]
+pub struct RejectedAlerts {
+    alerts: Mutex<HashMap<String, RejectedAlert>>,
+}
+
+impl RejectedAlerts {
+    /// Records that an alert was rejected, returning whether this is news:
+    /// the first time it has been rejected, or for a different problem than
+    /// last time. Only the most recent [`MAX_REJECTED_ALERTS`] alerts are
+    /// kept.
+    pub fn record(&self, alert_id: &str, reason: &'static str, message: String) -> bool {
+        let mut alerts = self.alerts.lock().unwrap();
+        let rejected_at = unix_now();
+        if let Some(previous) = alerts.get_mut(alert_id) {
+            let is_news = previous.reason != reason || previous.message != message;
+            previous.reason = reason;
+            previous.message = message;
+            previous.rejected_at = rejected_at;
+            return is_news;
+        }
+        if alerts.len() >= MAX_REJECTED_ALERTS {
+            let oldest = alerts
+                .values()
+                .min_by_key(|alert| alert.rejected_at)
+                .map(|alert| alert.alert_id.clone());
+            if let Some(oldest) = oldest {
+                alerts.remove(&oldest);
+            }
+        }
+        let alert = RejectedAlert {
+            alert_id: alert_id.to_owned(),
+            reason,
+            message,
+            rejected_at,
+        };
+        alerts.insert(alert_id.to_owned(), alert);
+        true
+    }
+
+    /// Returns the rejected alerts, most recently rejected first.
+    pub fn list(&self) -> Vec<RejectedAlert> {
+        let mut alerts = self
+            .alerts
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        alerts.sort_by(|a, b| {
+            b.rejected_at
+                .cmp(&a.rejected_at)
+                .then_with(|| a.alert_id.cmp(&b.alert_id))
+        });
+        alerts
+    }
+}
+
+/// Service-level bounds on listing prices. Listings outside of these bounds
+/// are almost always trolls or mistakes, so they're hidden from alerts
+/// unless an alert opts in to seeing them.
+#[derive(Debug, Clone)]
+pub struct PriceGuard {
+    pub floor: i32,
+    pub ceiling: i32,
+}
+
+impl PriceGuard {
+    /// Reads the price bounds from `UNIVERSALIS_ALERTS_PRICE_FLOOR` and
+    /// `UNIVERSALIS_ALERTS_PRICE_CEILING`, which default to 2 and 999,999,000
+    /// gil per unit, respectively.
+    pub fn from_env() -> Self {
+        Self {
+            floor: env_or("UNIVERSALIS_ALERTS_PRICE_FLOOR", 2),
+            ceiling: env_or("UNIVERSALIS_ALERTS_PRICE_CEILING", 999_999_000),
+        }
+    }
+
+    /// Returns the listings that are within bounds, borrowing them all if
+    /// none had to be removed.
+    pub fn apply<'a, 'l>(&self, listings: &'a [Listing<'l>]) -> Cow<'a, [Listing<'l>]> {
+        let in_bounds = |l: &Listing| l.unit_price >= self.floor && l.unit_price <= self.ceiling;
+        if listings.iter().all(in_bounds) {
+            return Cow::Borrowed(listings);
+        }
+
+        let guarded = listings
+            .iter()
+            .filter(|l| in_bounds(l))
+            .cloned()
+            .collect::<Vec<_>>();
+        counter!(
+            PRICE_GUARD_FILTERED_LISTINGS.name,
+            (listings.len() - guarded.len()) as u64
+        );
+        Cow::Owned(guarded)
+    }
+}
+
+/// Bounds on the size of incoming events, checked before they're decoded so
+/// that a huge frame from a buggy or malicious upstream can't exhaust memory.
+#[derive(Debug, Clone, Copy)]
+pub struct EventLimits {
+    /// The largest websocket message accepted, in bytes. Connections that
+    /// receive anything larger are dropped by the websocket library.
+    pub max_message_bytes: usize,
+    /// The most listings an event may have.
+    pub max_listings: usize,
+}
+
+impl EventLimits {
+    /// Reads the limits from `UNIVERSALIS_ALERTS_MAX_MESSAGE_BYTES` (8 MiB by
+    /// default) and `UNIVERSALIS_ALERTS_MAX_EVENT_LISTINGS` (5000 by default).
+    pub fn from_env() -> Self {
+        Self {
+            max_message_bytes: env_or("UNIVERSALIS_ALERTS_MAX_MESSAGE_BYTES", 8 * 1024 * 1024),
+            max_listings: env_or("UNIVERSALIS_ALERTS_MAX_EVENT_LISTINGS", 5000),
+        }
+    }
+
+    /// Returns the reason an encoded event should be dropped, if any. Listings
+    /// are counted without being decoded.
+    pub fn check(&self, data: &[u8]) -> Option<&'static str> {
+        if data.len() > self.max_message_bytes {
+            return Some("too_large");
+        }
+        let listings = bson::RawDocument::from_bytes(data)
+            .ok()
+            .and_then(|doc| doc.get_array("listings").ok());
+        match listings {
+            Some(listings) if listings.into_iter().count() > self.max_listings => {
+                Some("too_many_listings")
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejected_alerts_are_only_news_once() {
+        let rejected = RejectedAlerts::default();
+        assert!(rejected.record("a", "feature_disabled", "'take' is disabled".to_owned()));
+        assert!(!rejected.record("a", "feature_disabled", "'take' is disabled".to_owned()));
+        assert!(rejected.record("a", "feature_disabled", "'schedule' is disabled".to_owned()));
+        assert!(rejected.record("b", "feature_disabled", "'take' is disabled".to_owned()));
+
+        let alerts = rejected.list();
+        assert_eq!(alerts.len(), 2);
+        assert_eq!(alerts[0].alert_id, "a");
+        assert_eq!(alerts[0].message, "'schedule' is disabled");
+    }
+}
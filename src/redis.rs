@@ -0,0 +1,303 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::errors::*;
+use crate::timeouts::*;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufStream,
+};
+use tokio::net::TcpStream;
+use url::Url;
+
+/// The most idle connections kept open for later commands.
+const MAX_IDLE_CONNECTIONS: usize = 8;
+
+/// A reply from Redis, of the kinds that the commands used here return.
+#[derive(Debug)]
+enum Reply {
+    /// A simple status, such as OK.
+    Status,
+    /// An error from Redis, which still leaves the connection usable.
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+}
+
+impl Reply {
+    /// Turns an error reply into an error.
+    fn check(self) -> Result<Self> {
+        match self {
+            Self::Error(message) => Err(format!("Redis error: {}", message).into()),
+            reply => Ok(reply),
+        }
+    }
+}
+
+/// A minimal Redis client for the handful of commands this service needs.
+/// Each command takes a connection for itself, so that commands run
+/// concurrently, and only gives it back once it's read the whole reply. A
+/// connection whose command failed or was cancelled partway is closed, so
+/// that the next command never reads a reply meant for another.
+pub struct RedisClient {
+    addr: String,
+    /// The username (if any) and password to authenticate with.
+    auth: Option<(Option<String>, String)>,
+    db: u32,
+    idle: Mutex<Vec<BufStream<TcpStream>>>,
+}
+
+impl RedisClient {
+    /// Parses a `redis://[[username]:password@]host[:port][/db]` URL.
+    /// Connections aren't opened until the first command.
+    pub fn from_url(url: &str) -> Result<Self> {
+        let url = Url::parse(url).chain_err(|| "invalid Redis URL")?;
+        if url.scheme() != "redis" {
+            return Err(format!("unsupported Redis URL scheme '{}'", url.scheme()).into());
+        }
+        let host = url.host_str().ok_or("Redis URL has no host")?;
+        let db = match url.path().trim_matches('/') {
+            "" => 0,
+            db => db
+                .parse()
+                .map_err(|_| format!("invalid Redis database '{}'", db))?,
+        };
+        let auth = url.password().map(|password| {
+            let username = Some(url.username()).filter(|u| !u.is_empty());
+            (username.map(str::to_owned), password.to_owned())
+        });
+        Ok(Self {
+            addr: format!("{}:{}", host, url.port().unwrap_or(6379)),
+            auth,
+            db,
+            idle: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.command(&[b"GET", key.as_bytes()]).await? {
+            Reply::Bulk(value) => Ok(value),
+            reply => Err(format!("unexpected reply to GET: {:?}", reply).into()),
+        }
+    }
+
+    /// Sets a key that expires after `ttl`.
+    pub async fn set_ex(&self, key: &str, value: &[u8], ttl: Duration) -> Result<()> {
+        let ttl = ttl.as_secs().max(1).to_string();
+        self.command(&[b"SET", key.as_bytes(), value, b"EX", ttl.as_bytes()])
+            .await
+            .map(|_| ())
+    }
+
+    /// Sets a key that never expires.
+    pub async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.command(&[b"SET", key.as_bytes(), value])
+            .await
+            .map(|_| ())
+    }
+
+    /// Deletes a key, returning whether it existed.
+    pub async fn del(&self, key: &str) -> Result<bool> {
+        match self.command(&[b"DEL", key.as_bytes()]).await? {
+            Reply::Integer(deleted) => Ok(deleted > 0),
+            reply => Err(format!("unexpected reply to DEL: {:?}", reply).into()),
+        }
+    }
+
+    async fn command(&self, args: &[&[u8]]) -> Result<Reply> {
+        with_timeout(Service::Redis, async {
+            let idle = self.idle.lock().unwrap().pop();
+            let mut stream = match idle {
+                Some(stream) => stream,
+                None => self.connect().await?,
+            };
+            send(&mut stream, args).await?;
+            let reply = read_reply(&mut stream).await?;
+            let mut idle = self.idle.lock().unwrap();
+            if idle.len() < MAX_IDLE_CONNECTIONS {
+                idle.push(stream);
+            }
+            reply.check()
+        })
+        .await
+    }
+
+    async fn connect(&self) -> Result<BufStream<TcpStream>> {
+        let connect_timeout = timeouts().get(Service::Redis).connect;
+        let stream = with_timeout_of(Service::Redis, connect_timeout, async {
+            Ok(TcpStream::connect(&self.addr).await?)
+        })
+        .await
+        .chain_err(|| format!("failed to connect to Redis at {}", self.addr))?;
+        let mut stream = BufStream::new(stream);
+
+        if let Some((username, password)) = &self.auth {
+            match username {
+                Some(username) => {
+                    send(
+                        &mut stream,
+                        &[b"AUTH", username.as_bytes(), password.as_bytes()],
+                    )
+                    .await?
+                }
+                None => send(&mut stream, &[b"AUTH", password.as_bytes()]).await?,
+            }
+            read_reply(&mut stream).await?.check()?;
+        }
+        if self.db != 0 {
+            send(&mut stream, &[b"SELECT", self.db.to_string().as_bytes()]).await?;
+            read_reply(&mut stream).await?.check()?;
+        }
+        Ok(stream)
+    }
+}
+
+/// Writes a command as an array of bulk strings.
+async fn send<S: AsyncWrite + Unpin>(stream: &mut S, args: &[&[u8]]) -> Result<()> {
+    let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        buf.extend_from_slice(arg);
+        buf.extend_from_slice(b"\r\n");
+    }
+    stream.write_all(&buf).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_line<S: AsyncBufRead + Unpin>(stream: &mut S) -> Result<String> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).await? == 0 {
+        return Err("Redis closed the connection".into());
+    }
+    Ok(line.trim_end_matches("\r\n").to_owned())
+}
+
+fn parse_integer(value: &str) -> Result<i64> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid integer in Redis reply: {}", value).into())
+}
+
+async fn read_reply<S: AsyncBufRead + Unpin>(stream: &mut S) -> Result<Reply> {
+    let line = read_line(stream).await?;
+    let (kind, rest) = line.split_at_checked(1).unwrap_or((&line, ""));
+    match kind {
+        "+" => Ok(Reply::Status),
+        "-" => Ok(Reply::Error(rest.to_owned())),
+        ":" => Ok(Reply::Integer(parse_integer(rest)?)),
+        "$" => match parse_integer(rest)? {
+            -1 => Ok(Reply::Bulk(None)),
+            len if len < 0 => Err(format!("invalid length in Redis reply: {}", len).into()),
+            len => {
+                // The value is followed by a CRLF
+                let mut value = vec![0; len as usize + 2];
+                stream.read_exact(&mut value).await?;
+                value.truncate(len as usize);
+                Ok(Reply::Bulk(Some(value)))
+            }
+        },
+        _ => Err(format!("unexpected Redis reply: {}", line).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    async fn parse(data: &[u8]) -> Result<Reply> {
+        let mut data = data;
+        read_reply(&mut data).await
+    }
+
+    #[tokio::test]
+    async fn parses_replies() {
+        assert!(matches!(parse(b"+OK\r\n").await, Ok(Reply::Status)));
+        assert!(matches!(parse(b":42\r\n").await, Ok(Reply::Integer(42))));
+        assert!(matches!(parse(b"$-1\r\n").await, Ok(Reply::Bulk(None))));
+        match parse(b"$5\r\nab\r\nc\r\n").await {
+            Ok(Reply::Bulk(Some(value))) => assert_eq!(value, b"ab\r\nc"),
+            reply => panic!("unexpected reply: {:?}", reply),
+        }
+        match parse(b"-ERR wrong type\r\n").await {
+            Ok(Reply::Error(message)) => assert_eq!(message, "ERR wrong type"),
+            reply => panic!("unexpected reply: {:?}", reply),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_replies() {
+        assert!(parse(b"").await.is_err());
+        assert!(parse(b"*1\r\n").await.is_err());
+        assert!(parse(b":forty\r\n").await.is_err());
+        assert!(parse(b"$-2\r\n").await.is_err());
+        // The value is cut short
+        assert!(parse(b"$10\r\nabc\r\n").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn writes_commands_as_bulk_strings() {
+        let mut buf = Vec::new();
+        send(&mut buf, &[b"GET", b"key"]).await.unwrap();
+        assert_eq!(buf, b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n");
+    }
+
+    /// Answers each GET with the key's own name, slowly for `slow`.
+    async fn echo_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut stream = BufStream::new(stream);
+                    loop {
+                        let mut lines = Vec::new();
+                        for _ in 0..5 {
+                            match read_line(&mut stream).await {
+                                Ok(line) => lines.push(line),
+                                Err(_) => return,
+                            }
+                        }
+                        let key = &lines[4];
+                        if key == "slow" {
+                            tokio::time::sleep(Duration::from_millis(200)).await;
+                        }
+                        let reply = format!("${}\r\n{}\r\n", key.len(), key);
+                        if stream.write_all(reply.as_bytes()).await.is_err() {
+                            return;
+                        }
+                        let _ = stream.flush().await;
+                    }
+                });
+            }
+        });
+        format!("redis://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn cancelled_commands_dont_leak_replies() {
+        let client = RedisClient::from_url(&echo_server().await).unwrap();
+        assert_eq!(client.get("first").await.unwrap().unwrap(), b"first");
+
+        let cancelled = tokio::time::timeout(Duration::from_millis(50), client.get("slow")).await;
+        assert!(cancelled.is_err());
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        assert_eq!(client.get("next").await.unwrap().unwrap(), b"next");
+    }
+
+    #[tokio::test]
+    async fn commands_run_concurrently() {
+        let client = RedisClient::from_url(&echo_server().await).unwrap();
+        let (slow, fast) = tokio::join!(client.get("slow"), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let started = std::time::Instant::now();
+            let value = client.get("fast").await;
+            (value, started.elapsed())
+        });
+        assert_eq!(slow.unwrap().unwrap(), b"slow");
+        assert_eq!(fast.0.unwrap().unwrap(), b"fast");
+        assert!(fast.1 < Duration::from_millis(150));
+    }
+}
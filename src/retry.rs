@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::env_or;
+use crate::metrics_registry::*;
+use metrics::{counter, gauge};
+
+/// An event that couldn't be evaluated because its alerts couldn't be loaded.
+pub struct BufferedEvent {
+    pub region: String,
+    /// The event's message, as it was received.
+    pub data: Vec<u8>,
+    pub dry_run: bool,
+    /// How many times the event's alerts have failed to load, including the
+    /// first time, before it was buffered.
+    pub attempts: u32,
+    /// When the event was first received.
+    pub received_at: Instant,
+}
+
+/// Holds events that failed because the database was unavailable, so that
+/// they can be retried instead of dropped, and tracks how long the database
+/// has been failing for readiness checks.
+pub struct RetryBuffer {
+    capacity: usize,
+    /// How many times an event is retried before it's dropped.
+    max_retries: u32,
+    /// How often buffered events are retried.
+    pub interval: Duration,
+    /// How long the database can fail for before the service reports that
+    /// it isn't ready.
+    unready_after: Duration,
+    events: Mutex<VecDeque<BufferedEvent>>,
+    failing_since: Mutex<Option<Instant>>,
+}
+
+impl RetryBuffer {
+    /// Reads the buffer's configuration from `UNIVERSALIS_ALERTS_RETRY_BUFFER_SIZE`
+    /// (default 100 events), `UNIVERSALIS_ALERTS_RETRY_ATTEMPTS` (default 3 retries),
+    /// `UNIVERSALIS_ALERTS_RETRY_INTERVAL_SECS` (default 5), and
+    /// `UNIVERSALIS_ALERTS_DB_UNREADY_SECS` (default 60).
+    pub fn from_env() -> Self {
+        Self {
+            capacity: env_or("UNIVERSALIS_ALERTS_RETRY_BUFFER_SIZE", 100),
+            max_retries: env_or("UNIVERSALIS_ALERTS_RETRY_ATTEMPTS", 3),
+            interval: Duration::from_secs(env_or("UNIVERSALIS_ALERTS_RETRY_INTERVAL_SECS", 5)),
+            unready_after: Duration::from_secs(env_or("UNIVERSALIS_ALERTS_DB_UNREADY_SECS", 60)),
+            events: Mutex::new(VecDeque::new()),
+            failing_since: Mutex::new(None),
+        }
+    }
+
+    /// Buffers an event to be retried, unless it has already been retried
+    /// too many times. The oldest event is dropped if the buffer is full.
+    pub fn push(&self, event: BufferedEvent) {
+        // The first attempt isn't a retry
+        if event.attempts > self.max_retries {
+            counter!(RETRY_DROPPED_EVENTS.name, 1, "reason" => "attempts");
+            warn!(
+                "[{}] dropped event after {} failed attempts",
+                event.region, event.attempts
+            );
+            return;
+        }
+
+        let mut events = self.events.lock().unwrap();
+        if self.capacity == 0 {
+            counter!(RETRY_DROPPED_EVENTS.name, 1, "reason" => "full");
+            return;
+        }
+        if events.len() >= self.capacity {
+            events.pop_front();
+            counter!(RETRY_DROPPED_EVENTS.name, 1, "reason" => "full");
+        }
+        events.push_back(event);
+        counter!(RETRY_BUFFERED_EVENTS.name, 1);
+        gauge!(RETRY_BUFFER_DEPTH.name, events.len() as f64);
+    }
+
+    /// Takes every buffered event, oldest first.
+    pub fn drain(&self) -> Vec<BufferedEvent> {
+        let mut events = self.events.lock().unwrap();
+        gauge!(RETRY_BUFFER_DEPTH.name, 0.0);
+        events.drain(..).collect()
+    }
+
+    /// Records that alerts were loaded from the database.
+    pub fn record_database_ok(&self) {
+        *self.failing_since.lock().unwrap() = None;
+    }
+
+    /// Records that alerts couldn't be loaded from the database.
+    pub fn record_database_failure(&self) {
+        self.failing_since
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Instant::now);
+    }
+
+    /// Returns whether the database has been failing for too long for the
+    /// service to be considered ready.
+    pub fn is_database_down(&self) -> bool {
+        self.failing_since
+            .lock()
+            .unwrap()
+            .is_some_and(|since| since.elapsed() >= self.unready_after)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(attempts: u32) -> BufferedEvent {
+        BufferedEvent {
+            region: "Europe".to_owned(),
+            data: Vec::new(),
+            dry_run: false,
+            attempts,
+            received_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn events_are_retried_the_configured_number_of_times() {
+        let buffer = RetryBuffer {
+            capacity: 10,
+            max_retries: 3,
+            interval: Duration::from_secs(5),
+            unready_after: Duration::from_secs(60),
+            events: Mutex::new(VecDeque::new()),
+            failing_since: Mutex::new(None),
+        };
+        // Failing the first attempt and each of the three retries
+        for attempts in 1..=4 {
+            buffer.push(event(attempts));
+        }
+        assert_eq!(buffer.drain().len(), 3);
+    }
+}
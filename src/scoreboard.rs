@@ -0,0 +1,115 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::redact::*;
+use crate::universalis::unix_now;
+use serde::Serialize;
+
+/// How many recent deliveries are kept for each destination.
+const WINDOW_SIZE: usize = 100;
+
+/// How many destinations are tracked at once. The destination that was used
+/// least recently is dropped to make room for a new one.
+const MAX_DESTINATIONS: usize = 10_000;
+
+/// An error from delivering to a destination.
+#[derive(Serialize, Debug, Clone)]
+pub struct DestinationError {
+    pub message: String,
+    /// Seconds since the Unix epoch.
+    pub at: i64,
+}
+
+#[derive(Default)]
+struct DestinationStats {
+    /// The latency of each recent delivery, and whether it succeeded.
+    recent: VecDeque<(Duration, bool)>,
+    last_used: i64,
+    last_error: Option<DestinationError>,
+}
+
+/// Rolling delivery stats for a destination, over its recent deliveries.
+#[derive(Serialize, Debug, Clone)]
+pub struct DestinationReport {
+    /// The webhook URL, with its token redacted.
+    pub destination: String,
+    pub deliveries: usize,
+    pub p95_latency_ms: f64,
+    /// The fraction of recent deliveries that failed, from 0 to 1.
+    pub failure_rate: f64,
+    pub last_used: i64,
+    pub last_error: Option<DestinationError>,
+}
+
+/// Keeps per-webhook delivery latency and failures, so that problems with
+/// particular destinations can be told apart from problems with Discord as
+/// a whole.
+#[derive(Default)]
+pub struct DestinationScoreboard {
+    destinations: Mutex<HashMap<String, DestinationStats>>,
+}
+
+impl DestinationScoreboard {
+    /// Records a delivery attempt to a webhook.
+    pub fn record(&self, webhook: &str, latency: Duration, error: Option<&str>) {
+        let now = unix_now();
+        let mut destinations = self.destinations.lock().unwrap();
+        let key = redact(webhook).into_owned();
+        if !destinations.contains_key(&key) && destinations.len() >= MAX_DESTINATIONS {
+            let least_recent = destinations
+                .iter()
+                .min_by_key(|(_, stats)| stats.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(least_recent) = least_recent {
+                destinations.remove(&least_recent);
+            }
+        }
+
+        let stats = destinations.entry(key).or_default();
+        if stats.recent.len() >= WINDOW_SIZE {
+            stats.recent.pop_front();
+        }
+        stats.recent.push_back((latency, error.is_none()));
+        stats.last_used = now;
+        if let Some(error) = error {
+            stats.last_error = Some(DestinationError {
+                message: redact(error).into_owned(),
+                at: now,
+            });
+        }
+    }
+
+    fn report_one(destination: &str, stats: &DestinationStats) -> DestinationReport {
+        let mut latencies = stats.recent.iter().map(|(l, _)| *l).collect::<Vec<_>>();
+        latencies.sort();
+        let p95 = latencies
+            .get((latencies.len() * 95).div_ceil(100).saturating_sub(1))
+            .copied()
+            .unwrap_or_default();
+        let failures = stats.recent.iter().filter(|(_, ok)| !ok).count();
+        DestinationReport {
+            destination: destination.to_owned(),
+            deliveries: stats.recent.len(),
+            p95_latency_ms: p95.as_secs_f64() * 1000.0,
+            failure_rate: failures as f64 / stats.recent.len().max(1) as f64,
+            last_used: stats.last_used,
+            last_error: stats.last_error.clone(),
+        }
+    }
+
+    /// Returns the stats of every tracked destination, worst first.
+    pub fn report(&self) -> Vec<DestinationReport> {
+        let destinations = self.destinations.lock().unwrap();
+        let mut reports = destinations
+            .iter()
+            .map(|(destination, stats)| Self::report_one(destination, stats))
+            .collect::<Vec<_>>();
+        reports.sort_by(|a, b| {
+            b.failure_rate
+                .total_cmp(&a.failure_rate)
+                .then(b.p95_latency_ms.total_cmp(&a.p95_latency_ms))
+        });
+        reports
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::env_or;
+use crate::metrics_registry::*;
+use metrics::{counter, gauge};
+
+/// How long a (world, item) pair is remembered as having no alerts.
+const EMPTY_KEY_TTL: Duration = Duration::from_secs(300);
+
+/// The number of remembered empty keys above which stale entries are pruned.
+const EMPTY_KEY_PRUNE_THRESHOLD: usize = 100_000;
+
+/// Decides which events to skip when the service is overloaded. Above the
+/// soft limit of in-flight events, events for (world, item) pairs that
+/// recently had no alerts are skipped, and only one in every `sample_rate`
+/// events for chatty items is processed. Above the hard limit, that applies
+/// to all of the remaining events.
+pub struct LoadShedder {
+    soft_limit: usize,
+    hard_limit: usize,
+    sample_rate: u64,
+    in_flight: AtomicUsize,
+    sampled: AtomicU64,
+    empty_keys: Mutex<HashMap<(i32, i32), Instant>>,
+}
+
+/// Tracks an event being processed, for as long as it is held.
+pub struct InFlightEvent<'a> {
+    shedder: &'a LoadShedder,
+}
+
+impl Drop for InFlightEvent<'_> {
+    fn drop(&mut self) {
+        let in_flight = self.shedder.in_flight.fetch_sub(1, Ordering::Relaxed) - 1;
+        gauge!(IN_FLIGHT_EVENTS.name, in_flight as f64);
+    }
+}
+
+impl LoadShedder {
+    /// Reads the shedding thresholds from `UNIVERSALIS_ALERTS_SHED_SOFT_LIMIT`,
+    /// `UNIVERSALIS_ALERTS_SHED_HARD_LIMIT`, and `UNIVERSALIS_ALERTS_SHED_SAMPLE_RATE`.
+    pub fn from_env() -> Self {
+        Self {
+            soft_limit: env_or("UNIVERSALIS_ALERTS_SHED_SOFT_LIMIT", 500),
+            hard_limit: env_or("UNIVERSALIS_ALERTS_SHED_HARD_LIMIT", 2000),
+            sample_rate: env_or("UNIVERSALIS_ALERTS_SHED_SAMPLE_RATE", 10u64).max(1),
+            in_flight: AtomicUsize::new(0),
+            sampled: AtomicU64::new(0),
+            empty_keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Admits an event for processing, or returns `None` if it was shed.
+    /// Chatty items are updated often enough that skipping some of their
+    /// events loses little.
+    pub fn admit(&self, world_id: i32, item_id: i32, chatty: bool) -> Option<InFlightEvent<'_>> {
+        let in_flight = self.in_flight.load(Ordering::Relaxed);
+        let chatty_overload = in_flight >= self.soft_limit && chatty;
+        let reason = if in_flight >= self.soft_limit && self.is_known_empty(world_id, item_id) {
+            Some("no_alerts")
+        } else if (chatty_overload || in_flight >= self.hard_limit)
+            && !self
+                .sampled
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(self.sample_rate)
+        {
+            Some(if chatty_overload { "chatty" } else { "sampled" })
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            counter!(SHED_EVENTS.name, 1, "reason" => reason);
+            return None;
+        }
+
+        let in_flight = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        gauge!(IN_FLIGHT_EVENTS.name, in_flight as f64);
+        Some(InFlightEvent { shedder: self })
+    }
+
+    /// Records how many alerts were loaded for a (world, item) pair, so that
+    /// pairs without any alerts can be shed first.
+    pub fn record_alert_count(&self, world_id: i32, item_id: i32, count: usize) {
+        let mut empty_keys = self.empty_keys.lock().unwrap();
+        if count == 0 {
+            if empty_keys.len() >= EMPTY_KEY_PRUNE_THRESHOLD {
+                empty_keys.retain(|_, seen| seen.elapsed() < EMPTY_KEY_TTL);
+            }
+            empty_keys.insert((world_id, item_id), Instant::now());
+        } else {
+            empty_keys.remove(&(world_id, item_id));
+        }
+    }
+
+    fn is_known_empty(&self, world_id: i32, item_id: i32) -> bool {
+        self.empty_keys
+            .lock()
+            .unwrap()
+            .get(&(world_id, item_id))
+            .map(|seen| seen.elapsed() < EMPTY_KEY_TTL)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overloaded_chatty_events_are_sampled_once() {
+        let shedder = LoadShedder {
+            soft_limit: 0,
+            hard_limit: 0,
+            sample_rate: 2,
+            in_flight: AtomicUsize::new(0),
+            sampled: AtomicU64::new(0),
+            empty_keys: Mutex::new(HashMap::new()),
+        };
+        let admitted = (0..4)
+            .filter(|_| shedder.admit(1, 2, true).is_some())
+            .count();
+        assert_eq!(admitted, 2);
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::metrics_registry::*;
+use metrics::gauge;
+use serde::Serialize;
+
+/// How many seconds of deliveries each bucket counts.
+const BUCKET_SECS: u64 = 10;
+
+/// The windows the SLO is reported over, by label.
+const WINDOWS: [(&str, u64); 2] = [("5m", 5 * 60), ("1h", 60 * 60)];
+
+/// How many seconds of buckets are kept, which is the longest window.
+const RETENTION_SECS: u64 = 60 * 60;
+
+struct Bucket {
+    index: u64,
+    good: u64,
+    total: u64,
+}
+
+/// How the SLO has done over one window.
+#[derive(Serialize, Debug, Clone)]
+pub struct SloWindow {
+    pub window: &'static str,
+    pub good: u64,
+    pub total: u64,
+    /// The fraction of notifications delivered in time, which is 1 if none
+    /// were sent.
+    pub ratio: f64,
+    /// How fast the error budget is being spent: 1 spends it exactly over
+    /// the SLO's period, and anything higher spends it sooner.
+    pub burn_rate: f64,
+}
+
+/// A rolling service level objective on how soon notifications are delivered
+/// after their event is received, so that deployments can alert on
+/// user-visible delays without assembling it from raw metrics. Failed
+/// deliveries count as late. In outbox mode, the delivery worker keeps its
+/// own, counting notifications once they're sent.
+pub struct DeliverySlo {
+    /// How soon after its event a notification must be delivered.
+    latency_target: Duration,
+    /// The fraction of notifications that should be delivered in time.
+    objective: f64,
+    started: Instant,
+    buckets: Mutex<VecDeque<Bucket>>,
+}
+
+impl DeliverySlo {
+    /// Reads the latency target from `UNIVERSALIS_ALERTS_SLO_LATENCY_SECS`
+    /// (10 seconds by default) and the objective from
+    /// `UNIVERSALIS_ALERTS_SLO_OBJECTIVE` (0.99 by default).
+    pub fn from_env() -> Self {
+        let latency_secs = env::var("UNIVERSALIS_ALERTS_SLO_LATENCY_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|secs: &f64| secs.is_finite() && *secs > 0.0)
+            .unwrap_or(10.0);
+        let objective = env::var("UNIVERSALIS_ALERTS_SLO_OBJECTIVE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|objective: &f64| *objective > 0.0 && *objective < 1.0)
+            .unwrap_or(0.99);
+        Self {
+            latency_target: Duration::from_secs_f64(latency_secs),
+            objective,
+            started: Instant::now(),
+            buckets: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn bucket_index(&self) -> u64 {
+        self.started.elapsed().as_secs() / BUCKET_SECS
+    }
+
+    /// Records a delivery attempt, made this long after its event was
+    /// received.
+    pub fn record(&self, latency: Duration, delivered: bool) {
+        let index = self.bucket_index();
+        let good = delivered && latency <= self.latency_target;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.back().is_none_or(|b| b.index != index) {
+            buckets.push_back(Bucket {
+                index,
+                good: 0,
+                total: 0,
+            });
+        }
+        let bucket = buckets.back_mut().unwrap();
+        bucket.good += good as u64;
+        bucket.total += 1;
+
+        while buckets
+            .front()
+            .is_some_and(|b| b.index + RETENTION_SECS / BUCKET_SECS <= index)
+        {
+            buckets.pop_front();
+        }
+    }
+
+    /// Returns how the SLO has done over each window.
+    pub fn windows(&self) -> Vec<SloWindow> {
+        let index = self.bucket_index();
+        let buckets = self.buckets.lock().unwrap();
+        WINDOWS
+            .iter()
+            .map(|(window, secs)| {
+                let (good, total) = buckets
+                    .iter()
+                    .filter(|b| b.index + secs / BUCKET_SECS > index)
+                    .fold((0, 0), |(good, total), b| (good + b.good, total + b.total));
+                let ratio = match total {
+                    0 => 1.0,
+                    total => good as f64 / total as f64,
+                };
+                SloWindow {
+                    window,
+                    good,
+                    total,
+                    ratio,
+                    burn_rate: (1.0 - ratio) / (1.0 - self.objective),
+                }
+            })
+            .collect()
+    }
+
+    /// Exports the SLO's ratio and burn rate over each window.
+    pub fn report(&self) {
+        for window in self.windows() {
+            gauge!(DELIVERY_SLO_RATIO.name, window.ratio, "window" => window.window);
+            gauge!(DELIVERY_SLO_BURN_RATE.name, window.burn_rate, "window" => window.window);
+        }
+    }
+}
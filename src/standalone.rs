@@ -0,0 +1,111 @@
+use std::env;
+
+use crate::alerts::*;
+use crate::errors::*;
+use crate::features::*;
+use crate::trigger::*;
+use crate::validate::*;
+use futures_util::future::BoxFuture;
+
+/// Returns whether the service is configured to run a single alert from the
+/// environment instead of loading alerts from the database.
+pub fn is_standalone() -> bool {
+    env::var("UNIVERSALIS_ALERTS_STANDALONE_TRIGGER").is_ok()
+}
+
+/// A single alert configured entirely from the environment, for running the
+/// service without a database, e.g. to watch one item from a Raspberry Pi.
+pub struct StandaloneAlert {
+    pub world_id: i32,
+    pub item_id: i32,
+    alert: UserAlert,
+    trigger: AlertTrigger,
+}
+
+impl StandaloneAlert {
+    /// Reads the alert from `UNIVERSALIS_ALERTS_STANDALONE_WORLD`,
+    /// `UNIVERSALIS_ALERTS_STANDALONE_ITEM`, `UNIVERSALIS_ALERTS_STANDALONE_TRIGGER`
+    /// (JSON or an expression) and `UNIVERSALIS_ALERTS_STANDALONE_WEBHOOK`,
+    /// with an optional `UNIVERSALIS_ALERTS_STANDALONE_NAME` and
+    /// `UNIVERSALIS_ALERTS_STANDALONE_EDIT_IN_PLACE`. Standalone mode
+    /// is only enabled if the trigger is set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let trigger = match env::var("UNIVERSALIS_ALERTS_STANDALONE_TRIGGER") {
+            Ok(trigger) => trigger,
+            Err(_) => return Ok(None),
+        };
+        let read_id = |name: &str| -> Result<i32> {
+            env::var(name)
+                .chain_err(|| format!("{} not set", name))?
+                .parse()
+                .chain_err(|| format!("failed to parse {}", name))
+        };
+        let world_id = read_id("UNIVERSALIS_ALERTS_STANDALONE_WORLD")?;
+        let item_id = read_id("UNIVERSALIS_ALERTS_STANDALONE_ITEM")?;
+        let discord_webhook = env::var("UNIVERSALIS_ALERTS_STANDALONE_WEBHOOK")
+            .chain_err(|| "UNIVERSALIS_ALERTS_STANDALONE_WEBHOOK not set")?;
+        let parsed = parse_trigger(&trigger, MAX_TRIGGER_VERSION)
+            .map_err(|err| format!("invalid UNIVERSALIS_ALERTS_STANDALONE_TRIGGER: {}", err))?;
+
+        let alert = UserAlert {
+            id: "standalone".to_owned(),
+            user_id: None,
+            name: env::var("UNIVERSALIS_ALERTS_STANDALONE_NAME")
+                .unwrap_or_else(|_| "Standalone alert".to_owned()),
+            discord_webhook: Some(discord_webhook),
+            trigger_version: MAX_TRIGGER_VERSION,
+            trigger,
+            locale: None,
+            reference_price: None,
+            structured_payload: false,
+            item_ui_category: None,
+            item_search_category: None,
+            include_outliers: false,
+            travel_policy: TravelPolicy::default(),
+            edit_in_place: env::var("UNIVERSALIS_ALERTS_STANDALONE_EDIT_IN_PLACE")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            note: env::var("UNIVERSALIS_ALERTS_STANDALONE_NOTE").ok(),
+            item_quality: ItemQuality::default(),
+            utc_offset_minutes: None,
+            quiet_hours: None,
+            mention_style: None,
+            discord_id: None,
+            duplicate_of: None,
+        };
+        features()
+            .check_alert(&alert, &parsed)
+            .map_err(|disabled| format!("invalid standalone alert: {}", disabled))?;
+        Ok(Some(Self {
+            world_id,
+            item_id,
+            alert,
+            trigger: parsed,
+        }))
+    }
+}
+
+impl AlertRepository for StandaloneAlert {
+    fn alerts_for_world_item(
+        &self,
+        world_id: i32,
+        item_id: i32,
+        _limits: AlertLimits,
+    ) -> BoxFuture<'_, Result<Vec<(UserAlert, AlertTrigger)>>> {
+        let alerts = if world_id == self.world_id && item_id == self.item_id {
+            vec![(self.alert.clone(), self.trigger.clone())]
+        } else {
+            Vec::new()
+        };
+        Box::pin(async move { Ok(alerts) })
+    }
+
+    fn alert(&self, alert_id: &str) -> BoxFuture<'_, Result<Option<StoredAlert>>> {
+        let alert = (alert_id == self.alert.id).then(|| StoredAlert {
+            world_id: self.world_id,
+            item_id: self.item_id,
+            alert: self.alert.clone(),
+        });
+        Box::pin(async move { Ok(alert) })
+    }
+}
@@ -0,0 +1,123 @@
+use std::env;
+use std::time::{Duration, Instant};
+
+use std::sync::Arc;
+
+use crate::admin::*;
+use crate::errors::*;
+use crate::metrics_export::*;
+use crate::telemetry::*;
+use crate::timeouts::*;
+use mysql_async::{prelude::*, Opts, Pool};
+
+/// The longest wait between attempts to reach the database at startup.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Logs the start of a startup phase, and how long it took when it's
+/// finished.
+pub struct StartupPhase {
+    name: &'static str,
+    started: Instant,
+}
+
+impl StartupPhase {
+    pub fn begin(name: &'static str) -> Self {
+        info!("Startup: {}", name);
+        Self {
+            name,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn finish(self) {
+        info!(
+            "Startup: {} done in {:.1}s",
+            self.name,
+            self.started.elapsed().as_secs_f64()
+        );
+    }
+}
+
+/// Starts metrics and the admin server, which run on their own thread, and
+/// tracing. The service works without them, so anything that can't be set up
+/// is logged and skipped.
+pub fn init_observability(service_name: &str, admin_state: Arc<AdminState>) {
+    let phase = StartupPhase::begin("observability");
+    let admin_config = AdminConfig::from_env().unwrap_or_else(|err| {
+        error!(
+            "invalid admin server configuration, not serving it: {:?}",
+            err
+        );
+        None
+    });
+    let metrics_exporter = MetricsExporter::from_env().unwrap_or_else(|err| {
+        error!(
+            "invalid metrics exporter configuration, disabling metrics: {:?}",
+            err
+        );
+        MetricsExporter::Disabled
+    });
+    if let Err(err) = spawn_observability_thread(metrics_exporter, admin_config, admin_state) {
+        error!("failed to install metrics: {:?}", err);
+    }
+    if let Err(err) = init_tracing(service_name) {
+        warn!("tracing is disabled: {:?}", err);
+    }
+    phase.finish();
+}
+
+/// Creates a pool for the database at `UNIVERSALIS_ALERTS_DB`. Pools only
+/// connect when they're first used.
+pub fn database_pool_from_env() -> Result<Pool> {
+    let database_url =
+        env::var("UNIVERSALIS_ALERTS_DB").chain_err(|| "UNIVERSALIS_ALERTS_DB not set")?;
+    let opts = Opts::from_url(&database_url).chain_err(|| "invalid UNIVERSALIS_ALERTS_DB")?;
+    Ok(Pool::new(opts))
+}
+
+/// How long to keep trying to reach the database at startup, from
+/// `UNIVERSALIS_ALERTS_DB_STARTUP_SECS` (2 minutes by default).
+pub fn database_startup_timeout() -> Duration {
+    Duration::from_secs(
+        env::var("UNIVERSALIS_ALERTS_DB_STARTUP_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(120),
+    )
+}
+
+/// Tries to reach the database until it answers or `timeout` passes, waiting
+/// twice as long after each failure, up to 30 seconds. Returns the last error
+/// if it never answers.
+pub async fn wait_for_database(pool: &Pool, timeout: Duration) -> Result<()> {
+    let started = Instant::now();
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        let pinged = with_timeout(Service::Database, async {
+            let mut conn = pool.get_conn().await?;
+            r"SELECT 1".ignore(&mut conn).await?;
+            Ok(())
+        })
+        .await;
+        let err = match pinged {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        let remaining = timeout.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            return Err(err)
+                .chain_err(|| format!("database unreachable after {} attempts", attempt));
+        }
+        let wait = delay.min(remaining);
+        warn!(
+            "failed to reach the database (attempt {}), retrying in {}s: {:?}",
+            attempt,
+            wait.as_secs_f64(),
+            err
+        );
+        tokio::time::sleep(wait).await;
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+        attempt += 1;
+    }
+}
@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::errors::*;
+use crate::metrics_registry::*;
+use crate::redis::RedisClient;
+use crate::universalis::unix_now;
+use base64::Engine;
+use futures_util::future::BoxFuture;
+use metrics::{counter, gauge};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Where small pieces of per-key state are kept, such as previous values,
+/// cooldowns and message IDs.
+pub trait StateBackend: Send + Sync {
+    /// Names the backend, for metrics.
+    fn name(&self) -> &'static str;
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>>;
+
+    /// Stores a value, which expires after `ttl` if it's set.
+    fn set<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, Result<()>>;
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>>;
+
+    /// Drops expired values and writes out anything that's only held in
+    /// memory, returning how many values are stored if the backend knows.
+    fn maintain(&self) -> BoxFuture<'_, Result<Option<usize>>> {
+        Box::pin(async { Ok(None) })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct StoredValue {
+    /// The value, in base64 so that the file backend can write it as JSON.
+    value: String,
+    /// When the value expires, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<i64>,
+}
+
+impl StoredValue {
+    fn is_live(&self, now: i64) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+/// Keeps state in memory, so that it's lost on restart.
+#[derive(Default)]
+pub struct MemoryState {
+    values: Mutex<HashMap<String, StoredValue>>,
+    /// Whether anything has changed since the values were last written out,
+    /// for the file backend.
+    dirty: AtomicBool,
+}
+
+impl MemoryState {
+    fn get_now(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let values = self.values.lock().unwrap();
+        match values.get(key).filter(|v| v.is_live(unix_now())) {
+            Some(stored) => Ok(Some(
+                base64::engine::general_purpose::STANDARD
+                    .decode(&stored.value)
+                    .chain_err(|| format!("invalid stored value for {}", key))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn set_now(&self, key: &str, value: &[u8], ttl: Option<Duration>) {
+        let stored = StoredValue {
+            value: base64::engine::general_purpose::STANDARD.encode(value),
+            expires_at: ttl.map(|ttl| unix_now() + ttl.as_secs().max(1) as i64),
+        };
+        self.values.lock().unwrap().insert(key.to_owned(), stored);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    fn delete_now(&self, key: &str) {
+        if self.values.lock().unwrap().remove(key).is_some() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Drops expired values, returning how many are left.
+    fn purge(&self) -> usize {
+        let now = unix_now();
+        let mut values = self.values.lock().unwrap();
+        let before = values.len();
+        values.retain(|_, v| v.is_live(now));
+        if values.len() != before {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        values.len()
+    }
+}
+
+impl StateBackend for MemoryState {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        Box::pin(async move { self.get_now(key) })
+    }
+
+    fn set<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.set_now(key, value, ttl);
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.delete_now(key);
+            Ok(())
+        })
+    }
+
+    fn maintain(&self) -> BoxFuture<'_, Result<Option<usize>>> {
+        Box::pin(async move { Ok(Some(self.purge())) })
+    }
+}
+
+/// Keeps state in memory and writes it to a JSON file whenever it's
+/// maintained, so that it survives restarts of a single instance. Anything
+/// changed since the last write is lost if the process dies.
+pub struct FileState {
+    path: PathBuf,
+    memory: MemoryState,
+}
+
+impl FileState {
+    /// Loads the state saved at `path`, starting empty if there's no file.
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let values = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .chain_err(|| format!("invalid state file {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                return Err(err)
+                    .chain_err(|| format!("failed to read state file {}", path.display()))
+            }
+        };
+        Ok(Self {
+            path,
+            memory: MemoryState {
+                values: Mutex::new(values),
+                dirty: AtomicBool::new(false),
+            },
+        })
+    }
+
+    /// Writes the state out if it's changed, through a temporary file so that
+    /// a crash mid-write doesn't corrupt it.
+    async fn save(&self) -> Result<()> {
+        if !self.memory.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let data = serde_json::to_vec(&*self.memory.values.lock().unwrap())?;
+        let temp = self.path.with_extension("tmp");
+        let written = async {
+            tokio::fs::write(&temp, data).await?;
+            tokio::fs::rename(&temp, &self.path).await?;
+            Ok::<_, std::io::Error>(())
+        };
+        if let Err(err) = written.await {
+            self.memory.dirty.store(true, Ordering::Relaxed);
+            return Err(err)
+                .chain_err(|| format!("failed to write state file {}", self.path.display()));
+        }
+        Ok(())
+    }
+}
+
+impl StateBackend for FileState {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        self.memory.get(key)
+    }
+
+    fn set<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.memory.set(key, value, ttl)
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>> {
+        self.memory.delete(key)
+    }
+
+    fn maintain(&self) -> BoxFuture<'_, Result<Option<usize>>> {
+        Box::pin(async move {
+            let stored = self.memory.purge();
+            self.save().await?;
+            Ok(Some(stored))
+        })
+    }
+}
+
+/// Keeps state in Redis, where it's shared by every instance and expired by
+/// Redis itself.
+pub struct RedisState {
+    client: RedisClient,
+}
+
+impl RedisState {
+    pub fn new(client: RedisClient) -> Self {
+        Self { client }
+    }
+
+    fn key(key: &str) -> String {
+        format!("universalis_alerts:state:{}", key)
+    }
+}
+
+impl StateBackend for RedisState {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        Box::pin(async move { self.client.get(&Self::key(key)).await })
+    }
+
+    fn set<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Option<Duration>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match ttl {
+                Some(ttl) => self.client.set_ex(&Self::key(key), value, ttl).await,
+                None => self.client.set(&Self::key(key), value).await,
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { self.client.del(&Self::key(key)).await.map(|_| ()) })
+    }
+}
+
+/// Small per-key state that features share instead of each keeping their own,
+/// in whichever backend is configured. Keys are namespaced by the feature
+/// they belong to, and every operation is counted by
+/// `universalis_alerts_state_operations`.
+pub struct StateStore {
+    backend: Box<dyn StateBackend>,
+}
+
+impl StateStore {
+    pub fn new(backend: Box<dyn StateBackend>) -> Self {
+        Self { backend }
+    }
+
+    /// Picks the backend from `UNIVERSALIS_ALERTS_STATE_STORE`: `memory` (the
+    /// default), `redis` with the server at `UNIVERSALIS_ALERTS_STATE_REDIS`,
+    /// or `file` with the file at `UNIVERSALIS_ALERTS_STATE_STORE_FILE`.
+    pub fn from_env() -> Result<Self> {
+        let backend: Box<dyn StateBackend> =
+            match env::var("UNIVERSALIS_ALERTS_STATE_STORE").as_deref() {
+                Err(_) | Ok("") | Ok("memory") => Box::<MemoryState>::default(),
+                Ok("redis") => {
+                    let url = env::var("UNIVERSALIS_ALERTS_STATE_REDIS")
+                        .chain_err(|| "UNIVERSALIS_ALERTS_STATE_REDIS not set")?;
+                    Box::new(RedisState::new(RedisClient::from_url(&url)?))
+                }
+                Ok("file") => {
+                    let path = env::var("UNIVERSALIS_ALERTS_STATE_STORE_FILE")
+                        .chain_err(|| "UNIVERSALIS_ALERTS_STATE_STORE_FILE not set")?;
+                    Box::new(FileState::open(PathBuf::from(path))?)
+                }
+                Ok(other) => {
+                    return Err(format!(
+                        "unknown state store '{}'; expected memory, redis, or file",
+                        other
+                    )
+                    .into())
+                }
+            };
+        Ok(Self::new(backend))
+    }
+
+    fn record(&self, operation: &'static str, outcome: &'static str) {
+        counter!(
+            STATE_OPERATIONS.name,
+            1,
+            "backend" => self.backend.name(),
+            "operation" => operation,
+            "outcome" => outcome
+        );
+    }
+
+    pub async fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        let result = self.backend.get(&format!("{}:{}", namespace, key)).await;
+        self.record(
+            "get",
+            match &result {
+                Ok(Some(_)) => "hit",
+                Ok(None) => "miss",
+                Err(_) => "error",
+            },
+        );
+        result
+    }
+
+    /// Stores a value, which expires after `ttl` if it's set.
+    pub async fn set(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> Result<()> {
+        let result = self
+            .backend
+            .set(&format!("{}:{}", namespace, key), value, ttl)
+            .await;
+        self.record("set", if result.is_ok() { "ok" } else { "error" });
+        result
+    }
+
+    pub async fn delete(&self, namespace: &str, key: &str) -> Result<()> {
+        let result = self.backend.delete(&format!("{}:{}", namespace, key)).await;
+        self.record("delete", if result.is_ok() { "ok" } else { "error" });
+        result
+    }
+
+    /// Reads a value stored by [`StateStore::set_json`]. Values that can't be
+    /// decoded (e.g. from an older version) are treated as missing.
+    pub async fn get_json<T: DeserializeOwned>(
+        &self,
+        namespace: &str,
+        key: &str,
+    ) -> Result<Option<T>> {
+        Ok(self
+            .get(namespace, key)
+            .await?
+            .and_then(|value| serde_json::from_slice(&value).ok()))
+    }
+
+    pub async fn set_json<T: Serialize>(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &T,
+        ttl: Option<Duration>,
+    ) -> Result<()> {
+        self.set(namespace, key, &serde_json::to_vec(value)?, ttl)
+            .await
+    }
+
+    /// Drops expired values, saves the state if the backend needs to, and
+    /// exports how many values are stored.
+    pub async fn maintain(&self) -> Result<()> {
+        let result = self.backend.maintain().await;
+        self.record("maintain", if result.is_ok() { "ok" } else { "error" });
+        if let Some(stored) = result? {
+            gauge!(STATE_KEYS.name, stored as f64, "backend" => self.backend.name());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_store() -> StateStore {
+        StateStore::new(Box::<MemoryState>::default())
+    }
+
+    #[tokio::test]
+    async fn values_are_namespaced() {
+        let state = memory_store();
+        state.set("dedupe", "alert", b"1", None).await.unwrap();
+        state.set("ongoing", "alert", b"2", None).await.unwrap();
+        assert_eq!(
+            state.get("dedupe", "alert").await.unwrap().as_deref(),
+            Some(&b"1"[..])
+        );
+        assert_eq!(
+            state.get("ongoing", "alert").await.unwrap().as_deref(),
+            Some(&b"2"[..])
+        );
+
+        state.delete("dedupe", "alert").await.unwrap();
+        assert_eq!(state.get("dedupe", "alert").await.unwrap(), None);
+        assert!(state.get("ongoing", "alert").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn undecodable_json_is_missing() {
+        let state = memory_store();
+        state.set("test", "key", b"not json", None).await.unwrap();
+        let value: Option<Vec<u32>> = state.get_json("test", "key").await.unwrap();
+        assert_eq!(value, None);
+
+        state
+            .set_json("test", "key", &vec![1u32, 2], None)
+            .await
+            .unwrap();
+        let value: Option<Vec<u32>> = state.get_json("test", "key").await.unwrap();
+        assert_eq!(value, Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn expired_values_are_hidden_and_purged() {
+        let memory = MemoryState::default();
+        memory.set_now("live", b"1", Some(Duration::from_secs(60)));
+        memory.values.lock().unwrap().insert(
+            "expired".to_owned(),
+            StoredValue {
+                value: base64::engine::general_purpose::STANDARD.encode(b"2"),
+                expires_at: Some(unix_now() - 1),
+            },
+        );
+        assert!(memory.get_now("live").unwrap().is_some());
+        assert_eq!(memory.get_now("expired").unwrap(), None);
+        assert_eq!(memory.purge(), 1);
+    }
+
+    #[tokio::test]
+    async fn file_state_survives_reopening() {
+        let path = env::temp_dir().join(format!("state-{}.json", uuid::Uuid::new_v4()));
+        let file = FileState::open(path.clone()).unwrap();
+        file.set("key", b"value", None).await.unwrap();
+        assert_eq!(file.maintain().await.unwrap(), Some(1));
+
+        let reopened = FileState::open(path.clone()).unwrap();
+        assert_eq!(
+            reopened.get("key").await.unwrap().as_deref(),
+            Some(&b"value"[..])
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn file_state_rejects_other_files() {
+        let path = env::temp_dir().join(format!("state-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"[1, 2, 3]").unwrap();
+        assert!(FileState::open(path.clone()).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::metrics_registry::*;
+use crate::universalis::*;
+use metrics::{counter, gauge};
+use serde::Serialize;
+
+/// How many average gaps between messages a channel may go without one
+/// before it's considered quiet.
+const QUIET_FACTOR: f64 = 10.0;
+
+/// How much each new gap between messages moves a channel's average gap.
+const GAP_SMOOTHING: f64 = 0.1;
+
+struct ChannelActivity {
+    channel: Channel,
+    subscribed_at: Instant,
+    last_received: Option<Instant>,
+    messages: u64,
+    /// A moving average of the time between messages, once there have been
+    /// at least two.
+    average_gap: Option<Duration>,
+}
+
+impl ChannelActivity {
+    fn new(channel: Channel) -> Self {
+        Self {
+            channel,
+            subscribed_at: Instant::now(),
+            last_received: None,
+            messages: 0,
+            average_gap: None,
+        }
+    }
+
+    /// How long it's been since the channel last sent anything, counting
+    /// from when it was subscribed to if it never has.
+    fn silent_for(&self) -> Duration {
+        self.last_received
+            .unwrap_or(self.subscribed_at)
+            .max(self.subscribed_at)
+            .elapsed()
+    }
+}
+
+/// What one of a region's subscriptions has received.
+#[derive(Serialize, Debug, Clone)]
+pub struct SubscriptionActivity {
+    pub region: String,
+    pub channel: String,
+    /// Messages received since the channel was subscribed to on the current
+    /// connection.
+    pub messages: u64,
+    pub silent_secs: u64,
+    pub average_gap_secs: Option<f64>,
+}
+
+/// Keeps track of what each channel a connection is subscribed to receives.
+/// Every channel shares the region's socket, so a channel that stops sending
+/// events (e.g. because the server dropped the subscription) wouldn't
+/// otherwise be noticed while the others are busy.
+pub struct SubscriptionTracker {
+    /// How long a channel may be silent before it's resubscribed to, at the
+    /// least. Channels that usually go a while between events are given
+    /// longer.
+    quiet_after: Option<Duration>,
+    channels: Mutex<HashMap<String, Vec<ChannelActivity>>>,
+}
+
+impl SubscriptionTracker {
+    /// Reads how long a channel may be silent from
+    /// `UNIVERSALIS_ALERTS_CHANNEL_QUIET_SECS` (10 minutes by default, or 0
+    /// to never resubscribe to quiet channels).
+    pub fn from_env() -> Self {
+        let quiet_secs = env::var("UNIVERSALIS_ALERTS_CHANNEL_QUIET_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(600);
+        Self {
+            quiet_after: Some(quiet_secs)
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            channels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How often channels should be checked for going quiet, if they are.
+    pub fn check_interval(&self) -> Option<Duration> {
+        self.quiet_after.map(|quiet_after| {
+            (quiet_after / 4).clamp(Duration::from_secs(1), Duration::from_secs(60))
+        })
+    }
+
+    /// Forgets a region's subscriptions, e.g. when it reconnects.
+    pub fn reset(&self, region: &str) {
+        self.channels.lock().unwrap().remove(region);
+    }
+
+    pub fn subscribe(&self, region: &str, channel: &Channel) {
+        let mut channels = self.channels.lock().unwrap();
+        let channels = channels.entry(region.to_owned()).or_default();
+        if !channels.iter().any(|a| a.channel == *channel) {
+            channels.push(ChannelActivity::new(channel.clone()));
+        }
+    }
+
+    pub fn unsubscribe(&self, region: &str, channel: &Channel) {
+        if let Some(channels) = self.channels.lock().unwrap().get_mut(region) {
+            channels.retain(|a| a.channel != *channel);
+        }
+    }
+
+    /// Records an event against each of the region's channels it could have
+    /// been sent on.
+    pub fn record(&self, region: &str, ev: &ListingsAddEvent) {
+        let mut channels = self.channels.lock().unwrap();
+        let channels = match channels.get_mut(region) {
+            Some(channels) => channels,
+            None => return,
+        };
+        let now = Instant::now();
+        for activity in channels.iter_mut().filter(|a| a.channel.matches(ev)) {
+            activity.messages += 1;
+            if let Some(last_received) = activity.last_received {
+                let gap = now.duration_since(last_received);
+                activity.average_gap = Some(match activity.average_gap {
+                    Some(average) => {
+                        average.mul_f64(1.0 - GAP_SMOOTHING) + gap.mul_f64(GAP_SMOOTHING)
+                    }
+                    None => gap,
+                });
+            }
+            activity.last_received = Some(now);
+            counter!(
+                WS_CHANNEL_MESSAGES.name,
+                1,
+                "region" => region.to_owned(),
+                "channel" => activity.channel.to_string()
+            );
+        }
+    }
+
+    /// Returns the region's channels that have been silent for longer than
+    /// expected, and starts their clocks over so that they're only returned
+    /// again if they stay silent after being resubscribed to.
+    pub fn take_quiet(&self, region: &str) -> Vec<Channel> {
+        let quiet_after = match self.quiet_after {
+            Some(quiet_after) => quiet_after,
+            None => return Vec::new(),
+        };
+        let mut channels = self.channels.lock().unwrap();
+        let channels = match channels.get_mut(region) {
+            Some(channels) => channels,
+            None => return Vec::new(),
+        };
+        let mut quiet = Vec::new();
+        for activity in channels.iter_mut() {
+            let expected = activity.average_gap.map_or(quiet_after, |gap| {
+                gap.mul_f64(QUIET_FACTOR).max(quiet_after)
+            });
+            if activity.silent_for() > expected {
+                activity.subscribed_at = Instant::now();
+                quiet.push(activity.channel.clone());
+            }
+        }
+        quiet
+    }
+
+    /// Returns what each region's channels have received.
+    pub fn activity(&self) -> Vec<SubscriptionActivity> {
+        let channels = self.channels.lock().unwrap();
+        let mut activity = channels
+            .iter()
+            .flat_map(|(region, channels)| {
+                channels.iter().map(|a| SubscriptionActivity {
+                    region: region.clone(),
+                    channel: a.channel.to_string(),
+                    messages: a.messages,
+                    silent_secs: a.silent_for().as_secs(),
+                    average_gap_secs: a.average_gap.map(|gap| gap.as_secs_f64()),
+                })
+            })
+            .collect::<Vec<_>>();
+        activity.sort_by(|a, b| (&a.region, &a.channel).cmp(&(&b.region, &b.channel)));
+        activity
+    }
+
+    /// Exports how long each channel has been silent.
+    pub fn report_silence(&self) {
+        for activity in self.activity() {
+            gauge!(
+                WS_CHANNEL_SILENCE_SECONDS.name,
+                activity.silent_secs as f64,
+                "region" => activity.region,
+                "channel" => activity.channel
+            );
+        }
+    }
+}
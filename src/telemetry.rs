@@ -0,0 +1,176 @@
+use std::borrow::Cow;
+use std::env;
+use std::io::Write;
+
+use crate::errors::*;
+use crate::redact::*;
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry::sdk::trace::{EvictedHashMap, EvictedQueue, TracerProvider};
+use opentelemetry::trace::{Event, TracerProvider as _};
+use opentelemetry::{global, KeyValue, Value};
+use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Configures logging, setting the log level to info if not specified.
+/// Webhook tokens are redacted from every log line.
+pub fn init_logging() {
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG", "info")
+    }
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "[{} {:<5} {}] {}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                redact(&record.args().to_string())
+            )
+        })
+        .init();
+}
+
+fn redact_cow(text: Cow<'static, str>) -> Cow<'static, str> {
+    match redact(&text) {
+        Cow::Borrowed(_) => text,
+        Cow::Owned(redacted) => Cow::Owned(redacted),
+    }
+}
+
+fn redact_attribute(attribute: KeyValue) -> KeyValue {
+    match attribute.value {
+        Value::String(value) => KeyValue::new(attribute.key, redact_cow(value)),
+        _ => attribute,
+    }
+}
+
+/// Removes credentials from a span's name, fields, events, and status, so
+/// that nothing recorded on a span can leak one whatever it's named.
+fn redact_span(mut span: SpanData) -> SpanData {
+    span.name = redact_cow(span.name);
+    span.status_message = redact_cow(span.status_message);
+
+    let mut attributes = EvictedHashMap::new(u32::MAX, span.attributes.len());
+    for (key, value) in span.attributes {
+        attributes.insert(redact_attribute(KeyValue::new(key, value)));
+    }
+    span.attributes = attributes;
+
+    let mut events = EvictedQueue::new(u32::MAX);
+    events.append_vec(
+        &mut span
+            .events
+            .into_iter()
+            .map(|event| Event {
+                name: redact_cow(event.name),
+                attributes: event.attributes.into_iter().map(redact_attribute).collect(),
+                ..event
+            })
+            .collect(),
+    );
+    span.events = events;
+    span
+}
+
+/// Exports spans after redacting them.
+#[derive(Debug)]
+struct RedactingExporter<E>(E);
+
+#[async_trait::async_trait]
+impl<E: SpanExporter> SpanExporter for RedactingExporter<E> {
+    async fn export(&mut self, batch: Vec<SpanData>) -> ExportResult {
+        let batch = batch.into_iter().map(redact_span).collect();
+        self.0.export(batch).await
+    }
+
+    fn shutdown(&mut self) {
+        self.0.shutdown();
+    }
+}
+
+/// Configures tracing to export spans to the Jaeger agent at
+/// `UNIVERSALIS_ALERTS_JAEGER_AGENT`. Credentials are redacted from every
+/// span before it's exported.
+pub fn init_tracing(service_name: &str) -> Result<()> {
+    let jaeger_agent_url = env::var("UNIVERSALIS_ALERTS_JAEGER_AGENT")
+        .chain_err(|| "UNIVERSALIS_ALERTS_JAEGER_AGENT not set")?;
+    global::set_text_map_propagator(opentelemetry_jaeger::Propagator::new());
+    let exporter = opentelemetry_jaeger::new_pipeline()
+        .with_agent_endpoint(jaeger_agent_url)
+        .with_service_name(service_name)
+        .init_sync_exporter()
+        .chain_err(|| "failed to install span processor")?;
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(RedactingExporter(exporter))
+        .build();
+    let tracer = provider.versioned_tracer(service_name.to_owned(), None, None);
+    let _ = global::set_tracer_provider(provider);
+    let opentelemetry = tracing_opentelemetry::layer().with_tracer(tracer);
+    tracing_subscriber::registry()
+        .with(opentelemetry)
+        .try_init()
+        .chain_err(|| "failed to install tracing subscriber")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::sdk::InstrumentationLibrary;
+    use opentelemetry::trace::{SpanContext, SpanId, SpanKind, StatusCode};
+    use std::time::SystemTime;
+
+    const WEBHOOK: &str = "https://discord.com/api/webhooks/123/token";
+    const REDACTED_WEBHOOK: &str = "https://discord.com/api/webhooks/123/[redacted]";
+
+    #[test]
+    fn spans_are_redacted_whatever_their_fields_are_named() {
+        let mut attributes = EvictedHashMap::new(16, 2);
+        attributes.insert(KeyValue::new("some_field", WEBHOOK));
+        attributes.insert(KeyValue::new("count", 3i64));
+        let mut events = EvictedQueue::new(16);
+        events.append_vec(&mut vec![Event::new(
+            format!("failed to send to {}", WEBHOOK),
+            SystemTime::UNIX_EPOCH,
+            vec![KeyValue::new("error", format!("posting to {}", WEBHOOK))],
+            0,
+        )]);
+        let span = SpanData {
+            span_context: SpanContext::empty_context(),
+            parent_span_id: SpanId::INVALID,
+            span_kind: SpanKind::Internal,
+            name: "send".into(),
+            start_time: SystemTime::UNIX_EPOCH,
+            end_time: SystemTime::UNIX_EPOCH,
+            attributes,
+            events,
+            links: EvictedQueue::new(16),
+            status_code: StatusCode::Error,
+            status_message: format!("error sending to {}", WEBHOOK).into(),
+            resource: None,
+            instrumentation_lib: InstrumentationLibrary::new("test", None),
+        };
+
+        let span = redact_span(span);
+        assert_eq!(span.name, "send");
+        assert_eq!(
+            span.status_message,
+            format!("error sending to {}", REDACTED_WEBHOOK)
+        );
+        assert_eq!(
+            span.attributes.get(&"some_field".into()),
+            Some(&Value::from(REDACTED_WEBHOOK))
+        );
+        assert_eq!(span.attributes.get(&"count".into()), Some(&Value::I64(3)));
+        let event = span.events.iter().next().unwrap();
+        assert_eq!(
+            event.name,
+            format!("failed to send to {}", REDACTED_WEBHOOK)
+        );
+        assert_eq!(
+            event.attributes[0].value,
+            Value::from(format!("posting to {}", REDACTED_WEBHOOK))
+        );
+    }
+}
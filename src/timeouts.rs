@@ -0,0 +1,205 @@
+use std::env;
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::errors::*;
+use crate::metrics_registry::*;
+use metrics::counter;
+use reqwest::Client;
+
+/// A service that this one makes requests to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    /// Discord webhooks, and any other webhooks notifications are sent to.
+    Discord,
+    Xivapi,
+    /// The Universalis REST API.
+    Universalis,
+    Database,
+    /// The Universalis websocket's handshake.
+    Websocket,
+    /// The object store that event snapshots are kept in.
+    ObjectStore,
+    /// The Redis server that market baselines are shared through.
+    Redis,
+}
+
+impl Service {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Discord => "discord",
+            Self::Xivapi => "xivapi",
+            Self::Universalis => "universalis",
+            Self::Database => "database",
+            Self::Websocket => "websocket",
+            Self::ObjectStore => "object_store",
+            Self::Redis => "redis",
+        }
+    }
+
+    fn env_name(&self) -> &'static str {
+        match self {
+            Self::Discord => "DISCORD",
+            Self::Xivapi => "XIVAPI",
+            Self::Universalis => "UNIVERSALIS",
+            Self::Database => "DB",
+            Self::Websocket => "WS",
+            Self::ObjectStore => "OBJECT_STORE",
+            Self::Redis => "REDIS",
+        }
+    }
+}
+
+/// Parses a timeout in seconds, which may be fractional. Negative, infinite,
+/// and unrepresentably long timeouts aren't valid.
+fn parse_secs(value: &str) -> Option<Duration> {
+    let secs = value.parse::<f64>().ok()?;
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// How long requests to a service may take.
+#[derive(Debug, Clone, Copy)]
+pub struct ServiceTimeouts {
+    /// How long connecting may take.
+    pub connect: Duration,
+    /// How long a whole request may take, from connecting to reading the
+    /// last of the response.
+    pub total: Duration,
+}
+
+/// The timeouts for every kind of outbound request, so that none of them can
+/// hold up event processing indefinitely.
+#[derive(Debug, Clone)]
+pub struct Timeouts {
+    discord: ServiceTimeouts,
+    xivapi: ServiceTimeouts,
+    universalis: ServiceTimeouts,
+    database: ServiceTimeouts,
+    websocket: ServiceTimeouts,
+    object_store: ServiceTimeouts,
+    redis: ServiceTimeouts,
+}
+
+impl Timeouts {
+    /// Reads each service's timeouts from
+    /// `UNIVERSALIS_ALERTS_TIMEOUT_<SERVICE>_CONNECT_SECS` and
+    /// `UNIVERSALIS_ALERTS_TIMEOUT_<SERVICE>_TOTAL_SECS`, where the service
+    /// is `DISCORD`, `XIVAPI`, `UNIVERSALIS`, `DB`, `WS`, `OBJECT_STORE`, or
+    /// `REDIS`. Connecting takes at most 5 seconds by default, and whole
+    /// requests 10 to 15 seconds (Redis commands 2 seconds).
+    pub fn from_env() -> Self {
+        let read = |service: Service, total: u64| {
+            let read_secs = |kind: &str, default: u64| {
+                let name = format!(
+                    "UNIVERSALIS_ALERTS_TIMEOUT_{}_{}_SECS",
+                    service.env_name(),
+                    kind
+                );
+                env::var(name)
+                    .ok()
+                    .and_then(|v| parse_secs(&v))
+                    .unwrap_or(Duration::from_secs(default))
+            };
+            ServiceTimeouts {
+                connect: read_secs("CONNECT", 5),
+                total: read_secs("TOTAL", total),
+            }
+        };
+        Self {
+            discord: read(Service::Discord, 15),
+            xivapi: read(Service::Xivapi, 10),
+            universalis: read(Service::Universalis, 15),
+            database: read(Service::Database, 10),
+            websocket: read(Service::Websocket, 15),
+            object_store: read(Service::ObjectStore, 15),
+            redis: read(Service::Redis, 2),
+        }
+    }
+
+    pub fn get(&self, service: Service) -> ServiceTimeouts {
+        match service {
+            Service::Discord => self.discord,
+            Service::Xivapi => self.xivapi,
+            Service::Universalis => self.universalis,
+            Service::Database => self.database,
+            Service::Websocket => self.websocket,
+            Service::ObjectStore => self.object_store,
+            Service::Redis => self.redis,
+        }
+    }
+
+    /// Builds an HTTP client with a service's timeouts. Individual requests
+    /// can override the total timeout with [`reqwest::RequestBuilder::timeout`].
+    pub fn client(&self, service: Service) -> Client {
+        let timeouts = self.get(service);
+        Client::builder()
+            .connect_timeout(timeouts.connect)
+            .timeout(timeouts.total)
+            .build()
+            // This only fails if the TLS backend can't be initialized, which
+            // the default client would fail on as well
+            .expect("failed to build HTTP client")
+    }
+}
+
+/// Returns the timeouts read from the environment.
+pub fn timeouts() -> &'static Timeouts {
+    static TIMEOUTS: OnceLock<Timeouts> = OnceLock::new();
+    TIMEOUTS.get_or_init(Timeouts::from_env)
+}
+
+fn record_timeout(service: Service) {
+    counter!(TIMEOUTS.name, 1, "service" => service.name());
+}
+
+/// Runs a future under a service's total timeout, failing and counting the
+/// timeout if it runs out.
+pub async fn with_timeout<T>(service: Service, fut: impl Future<Output = Result<T>>) -> Result<T> {
+    with_timeout_of(service, timeouts().get(service).total, fut).await
+}
+
+/// Runs a future under a timeout other than its service's usual one.
+pub async fn with_timeout_of<T>(
+    service: Service,
+    timeout: Duration,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match tokio::time::timeout(timeout, fut).await {
+        Ok(result) => result,
+        Err(_) => {
+            record_timeout(service);
+            Err(ErrorKind::TimedOut(service.name()).into())
+        }
+    }
+}
+
+/// Counts the HTTP requests that fail because they timed out. The total
+/// timeout also covers reading the response, so this should be applied to
+/// reading the body as well as to sending the request.
+pub trait CountTimeouts {
+    fn count_timeout(self, service: Service) -> Self;
+}
+
+impl<T> CountTimeouts for reqwest::Result<T> {
+    fn count_timeout(self, service: Service) -> Self {
+        if self.as_ref().is_err_and(reqwest::Error::is_timeout) {
+            record_timeout(service);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_finite_non_negative_timeouts_are_read() {
+        assert_eq!(parse_secs("2.5"), Some(Duration::from_millis(2500)));
+        assert_eq!(parse_secs("0"), Some(Duration::ZERO));
+        for invalid in ["-1", "inf", "NaN", "1e300", "soon"] {
+            assert_eq!(parse_secs(invalid), None, "{}", invalid);
+        }
+    }
+}
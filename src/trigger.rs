@@ -1,70 +1,326 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
 use std::fmt::{Display, Formatter};
+use std::iter;
+use std::marker::PhantomData;
 
+use crate::format::*;
 use crate::universalis::*;
 use itertools::Itertools;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+mod expression;
+mod locale;
+mod shape;
+pub use expression::*;
+pub use locale::escape_code_block;
+use locale::*;
+pub use shape::*;
 
 #[derive(Deserialize, Debug, Clone)]
 enum TriggerFilter {
     #[serde(rename = "hq")]
     Hq,
+    /// Only NQ listings, e.g. for crafting materials where HQ is a premium.
+    #[serde(rename = "nq")]
+    Nq,
+    #[serde(rename = "newerThan")]
+    NewerThan { minutes: u32 },
+    /// Only listings with a stack size in a range, e.g.
+    /// `{"quantity": {"min": 99}}` for full stacks.
+    #[serde(rename = "quantity")]
+    Quantity(QuantityRange),
+    /// Only listings with a unit price (including tax) in a range, e.g.
+    /// `{"pricePerUnit": {"max": 100000}}`, so that the reducer only sees
+    /// those listings whatever the comparison is.
+    #[serde(rename = "pricePerUnit")]
+    UnitPrice(PriceRange),
+    /// Only listings from a particular retainer, e.g.
+    /// `{"retainerName": {"equals": "Kupo"}}`.
+    #[serde(rename = "retainerName")]
+    RetainerName(NameMatch),
+    /// Only listings crafted by a particular character, e.g.
+    /// `{"creatorName": {"equals": "Kupo Nut"}}`.
+    #[serde(rename = "creatorName")]
+    CreatorName(NameMatch),
+    /// Leaves out listings crafted by a particular character. Listings that
+    /// weren't crafted by anyone pass.
+    #[serde(rename = "notCreatorName")]
+    NotCreatorName(NameMatch),
+    /// Only listings with at least this many materia melded, e.g.
+    /// `{"materiaCount": {"min": 5}}` for pentamelded gear.
+    #[serde(rename = "materiaCount")]
+    MateriaCount { min: u32 },
+    #[serde(rename = "onMannequin")]
+    OnMannequin,
+    /// Only listings that can be bought from the market board.
+    #[serde(rename = "notOnMannequin")]
+    NotOnMannequin,
+}
+
+/// How a retainer or crafter name filter matches names. Both ways ignore
+/// case, so the name is kept lowercase.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum NameMatch {
+    #[serde(rename = "equals", deserialize_with = "non_empty_name")]
+    Equals(String),
+    #[serde(rename = "contains", deserialize_with = "non_empty_name")]
+    Contains(String),
+}
+
+impl NameMatch {
+    fn name(&self) -> &str {
+        match self {
+            Self::Equals(name) | Self::Contains(name) => name,
+        }
+    }
+
+    fn matches(&self, other: &str) -> bool {
+        match self {
+            Self::Equals(name) => *name == other.to_lowercase(),
+            Self::Contains(name) => other.to_lowercase().contains(name.as_str()),
+        }
+    }
+}
+
+fn non_empty_name<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<String, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    if name.trim().is_empty() {
+        return Err(serde::de::Error::custom("name must not be empty"));
+    }
+    Ok(name.to_lowercase())
+}
+
+/// What a range filter bounds: the name it's given in messages about its
+/// bounds, and the least value any listing has.
+trait Bounded {
+    const NAME: &'static str;
+    const LEAST: u32;
+}
+
+/// The stack size of a listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct StackSize;
+
+impl Bounded for StackSize {
+    const NAME: &'static str = "quantity";
+    // Every stack has at least one item
+    const LEAST: u32 = 1;
+}
+
+/// The unit price of a listing, including tax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct PricePerUnit;
+
+impl Bounded for PricePerUnit {
+    const NAME: &'static str = "price";
+    const LEAST: u32 = 0;
+}
+
+/// The values a range filter lets through, including both bounds.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "RawBounds", bound = "T: Bounded")]
+struct Bounds<T> {
+    min: Option<u32>,
+    max: Option<u32>,
+    #[serde(skip)]
+    value: PhantomData<T>,
+}
+
+/// The stack sizes a quantity filter lets through.
+type QuantityRange = Bounds<StackSize>;
+/// The unit prices a price filter lets through.
+type PriceRange = Bounds<PricePerUnit>;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawBounds {
+    #[serde(default)]
+    min: Option<u32>,
+    #[serde(default)]
+    max: Option<u32>,
+}
+
+impl<T: Bounded> Bounds<T> {
+    fn new(min: Option<u32>, max: Option<u32>) -> Self {
+        Self {
+            min,
+            max,
+            value: PhantomData,
+        }
+    }
+
+    fn contains(&self, value: i64) -> bool {
+        self.min.is_none_or(|min| value >= min as i64)
+            && self.max.is_none_or(|max| value <= max as i64)
+    }
+
+    /// Returns the values both ranges let through, or `None` if they don't
+    /// overlap.
+    fn intersect(&self, other: &Self) -> Option<Self> {
+        let min = self.min.max(other.min);
+        let max = match (self.max, other.max) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        match (min, max) {
+            (Some(min), Some(max)) if min > max => None,
+            _ => Some(Self::new(min, max)),
+        }
+    }
+
+    /// Leaves out a min that every listing already has, unless it's the
+    /// only bound.
+    fn drop_least_min(&mut self) {
+        if self.max.is_some() {
+            self.min = self.min.filter(|min| *min > T::LEAST);
+        }
+    }
+
+    /// Whether every listing is in the range.
+    fn is_unbounded(&self) -> bool {
+        self.min.unwrap_or(0) <= T::LEAST && self.max.is_none()
+    }
+}
+
+impl<T: Bounded> TryFrom<RawBounds> for Bounds<T> {
+    type Error = String;
+
+    fn try_from(bounds: RawBounds) -> std::result::Result<Self, Self::Error> {
+        match (bounds.min, bounds.max) {
+            (None, None) => Err(format!("{} filter needs a min, a max, or both", T::NAME)),
+            (Some(min), Some(max)) if min > max => Err(format!(
+                "{} filter's min ({}) is greater than its max ({})",
+                T::NAME,
+                min,
+                max
+            )),
+            (min, max) => Ok(Self::new(min, max)),
+        }
+    }
 }
 
 trait TriggerFilterOp<T> {
     fn evaluate(&self, value: &T) -> bool;
 }
 
-impl TriggerFilterOp<Listing> for TriggerFilter {
-    fn evaluate(&self, value: &Listing) -> bool {
+impl TriggerFilterOp<Listing<'_>> for TriggerFilter {
+    fn evaluate(&self, value: &Listing<'_>) -> bool {
         match self {
             Self::Hq => value.hq,
+            Self::Nq => !value.hq,
+            Self::NewerThan { minutes } => value
+                .age_minutes(unix_now())
+                .is_some_and(|age| age < *minutes as f32),
+            Self::Quantity(range) => range.contains(value.quantity as i64),
+            Self::UnitPrice(range) => range.contains(value.unit_price_with_tax() as i64),
+            // Listings that don't say who's selling them can't be told apart
+            Self::RetainerName(retainer) => value
+                .retainer_name
+                .as_deref()
+                .is_some_and(|name| retainer.matches(name)),
+            Self::CreatorName(creator) => value.creator().is_some_and(|name| creator.matches(name)),
+            Self::NotCreatorName(creator) => {
+                !value.creator().is_some_and(|name| creator.matches(name))
+            }
+            Self::MateriaCount { min } => value.materia.len() >= *min as usize,
+            Self::OnMannequin => value.on_mannequin,
+            Self::NotOnMannequin => !value.on_mannequin,
         }
     }
 }
 
-impl Display for TriggerFilter {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+impl TriggerFilter {
+    /// Returns the kind of filter, as it's named in the trigger format.
+    fn name(&self) -> &'static str {
         match self {
-            Self::Hq => f.write_str("Item is HQ"),
+            Self::Hq => "hq",
+            Self::Nq => "nq",
+            Self::NewerThan { .. } => "newerThan",
+            Self::Quantity(_) => "quantity",
+            Self::UnitPrice(_) => "pricePerUnit",
+            Self::RetainerName(_) => "retainerName",
+            Self::CreatorName(_) => "creatorName",
+            Self::NotCreatorName(_) => "notCreatorName",
+            Self::MateriaCount { .. } => "materiaCount",
+            Self::OnMannequin => "onMannequin",
+            Self::NotOnMannequin => "notOnMannequin",
         }
     }
 }
 
+impl Display for TriggerFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        f.write_str(&self.describe(&EN))
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 enum TriggerMapper {
+    /// The unit price including tax, which is what every trigger version so
+    /// far has compared.
     #[serde(rename = "pricePerUnit")]
     UnitPrice,
+    /// The same unit price including tax, for triggers that want to say so.
+    #[serde(rename = "pricePerUnitWithTax")]
+    UnitPriceWithTax,
     #[serde(rename = "quantity")]
     Quantity,
     #[serde(rename = "total")]
     Total,
+    #[serde(rename = "age")]
+    Age,
+    /// The unit price, less the price of any melded materia.
+    #[serde(rename = "pricePerUnitLessMateria")]
+    UnitPriceLessMateria,
 }
 
 trait TriggerMapOp<TItem, TResult> {
-    fn evaluate(&self, item: &TItem) -> TResult;
+    fn evaluate(&self, item: &TItem, parameters: &TriggerParameters) -> TResult;
 }
 
-impl TriggerMapOp<Listing, f32> for TriggerMapper {
-    fn evaluate(&self, listing: &Listing) -> f32 {
+impl TriggerMapOp<Listing<'_>, f32> for TriggerMapper {
+    fn evaluate(&self, listing: &Listing<'_>, parameters: &TriggerParameters) -> f32 {
         match self {
-            // Apply GST
-            Self::UnitPrice => (listing.unit_price as f32 * 1.05).ceil(),
+            Self::UnitPrice | Self::UnitPriceWithTax => listing.unit_price_with_tax(),
+            Self::UnitPriceLessMateria => {
+                let materia = listing
+                    .materia
+                    .iter()
+                    .filter_map(|m| parameters.materia_prices.get(&m.materia_id))
+                    .sum::<f32>();
+                (listing.unit_price_with_tax() - materia).max(0.0)
+            }
             Self::Quantity => listing.quantity as f32,
-            Self::Total => (listing.total as f32 * 1.05).ceil(),
+            Self::Total => listing.total_with_tax(),
+            // Listings without a review time are treated as brand new
+            Self::Age => listing.age_minutes(unix_now()).unwrap_or(0.0),
         }
     }
 }
 
-impl Display for TriggerMapper {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+impl TriggerMapper {
+    /// Returns the mapper as it's named in the trigger format.
+    fn name(&self) -> &'static str {
         match self {
-            Self::UnitPrice => f.write_str("Unit price"),
-            Self::Quantity => f.write_str("Quantity"),
-            Self::Total => f.write_str("Total"),
+            Self::UnitPrice => "pricePerUnit",
+            Self::UnitPriceWithTax => "pricePerUnitWithTax",
+            Self::Quantity => "quantity",
+            Self::Total => "total",
+            Self::Age => "age",
+            Self::UnitPriceLessMateria => "pricePerUnitLessMateria",
         }
     }
 }
 
+impl Display for TriggerMapper {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        f.write_str(self.describe(&EN))
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 enum TriggerReducer {
     #[serde(rename = "min")]
@@ -73,14 +329,83 @@ enum TriggerReducer {
     Max,
     #[serde(rename = "mean")]
     Mean,
+    /// All of the values added together, e.g. the total gil value of the
+    /// listings on the board. A board with nothing on it adds up to zero.
+    #[serde(rename = "sum")]
+    Sum,
+    /// The number of listings that passed the filters (after the take
+    /// stage), whatever their mapped values are.
+    #[serde(rename = "count")]
+    Count,
+    /// The middle value, or the mean of the two middle values. Unlike the
+    /// mean, this isn't thrown off by a few listings at absurd prices.
+    #[serde(rename = "median")]
+    Median,
+    /// How widely the values are spread around their mean, as the
+    /// population standard deviation. A sudden jump is often a sign that
+    /// someone is resetting the market.
+    #[serde(rename = "stddev")]
+    StdDev,
+    /// The value that the given percentage of values are at or below,
+    /// interpolating between the two nearest values, e.g. `{"percentile": 90}`.
+    #[serde(rename = "percentile")]
+    Percentile(PercentileRank),
+    /// How far below the second-lowest value the lowest value is, as a
+    /// percentage of the second-lowest value.
+    #[serde(rename = "gap")]
+    Gap,
+}
+
+/// A percentile, from 0 to 100.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "u8")]
+struct PercentileRank(u8);
+
+impl PercentileRank {
+    fn fraction(&self) -> f32 {
+        self.0 as f32 / 100.0
+    }
+}
+
+impl TryFrom<u8> for PercentileRank {
+    type Error = String;
+
+    fn try_from(rank: u8) -> std::result::Result<Self, Self::Error> {
+        if rank > 100 {
+            return Err(format!(
+                "percentile must be between 0 and 100, got {}",
+                rank
+            ));
+        }
+        Ok(Self(rank))
+    }
+}
+
+/// Returns the value at a fraction of the way through the values once
+/// they're sorted, interpolating linearly between the two nearest values.
+fn quantile(values: &mut [f32], q: f32) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let position = q * (values.len() - 1) as f32;
+    let (lower, upper) = (position.floor() as usize, position.ceil() as usize);
+    let weight = position - lower as f32;
+    Some(values[lower] + (values[upper] - values[lower]) * weight)
 }
 
 struct ReducerContext<T> {
     stack: Vec<T>,
+    /// The running total for sums, which is kept as an f64 since the totals
+    /// of big stacks of expensive items run past what an f32 holds exactly.
+    sum: Option<f64>,
 }
 
 trait TriggerReduceOp<T> {
     fn evaluate(&self, context: &mut ReducerContext<T>, accum: &T, item: &T) -> T;
+
+    /// Produces the result from the final accumulator value, if there is one.
+    fn finish(&self, context: &ReducerContext<T>, accum: T) -> Option<T>;
 }
 
 impl TriggerReduceOp<f32> for TriggerReducer {
@@ -88,6 +413,19 @@ impl TriggerReduceOp<f32> for TriggerReducer {
         match self {
             Self::Min => (*accum).min(*item),
             Self::Max => (*accum).max(*item),
+            Self::Sum => {
+                // The initial accumulator value is the first element
+                let sum = context.sum.unwrap_or(*accum as f64) + *item as f64;
+                context.sum = Some(sum);
+                sum as f32
+            }
+            Self::Count => {
+                // The initial accumulator value is the first element, so
+                // it's the running count on the stack that matters
+                let n = context.stack.pop().unwrap_or(1.0);
+                context.stack.push(n + 1.0);
+                *accum
+            }
             Self::Mean => {
                 // The initial accumulator value is the first element
                 // of the iterator, so n should begin at 1.
@@ -95,82 +433,1255 @@ impl TriggerReduceOp<f32> for TriggerReducer {
                 context.stack.push(n + 1.0);
                 (n * *accum + *item) / (n + 1.0)
             }
+            Self::StdDev => {
+                // Welford's algorithm: the accumulator is the running mean,
+                // and the count and the sum of squared differences from the
+                // mean are kept on the stack
+                let (n, m2) = match context.stack[..] {
+                    [n, m2] => (n, m2),
+                    _ => (1.0, 0.0),
+                };
+                let n = n + 1.0;
+                let delta = *item - *accum;
+                let mean = *accum + delta / n;
+                context.stack = vec![n, m2 + delta * (*item - mean)];
+                mean
+            }
+            Self::Median | Self::Percentile(_) => {
+                // Every value is kept on the stack, including the initial
+                // accumulator value, and the result is picked at the end
+                if context.stack.is_empty() {
+                    context.stack.push(*accum);
+                }
+                context.stack.push(*item);
+                *accum
+            }
+            Self::Gap => {
+                // The accumulator is the lowest value, and the second-lowest
+                // value is kept on the stack.
+                let (lowest, other) = if item < accum {
+                    (*item, *accum)
+                } else {
+                    (*accum, *item)
+                };
+                let second = context.stack.pop().map_or(other, |s| s.min(other));
+                context.stack.push(second);
+                lowest
+            }
+        }
+    }
+
+    fn finish(&self, context: &ReducerContext<f32>, accum: f32) -> Option<f32> {
+        match self {
+            // There's no gap without at least two values
+            Self::Gap => context.stack.last().map(|second| {
+                if *second > 0.0 {
+                    (second - accum) / second * 100.0
+                } else {
+                    0.0
+                }
+            }),
+            Self::Count => Some(context.stack.last().copied().unwrap_or(1.0)),
+            // A single value doesn't vary at all
+            Self::StdDev => Some(match context.stack[..] {
+                [n, m2] => (m2 / n).max(0.0).sqrt(),
+                _ => 0.0,
+            }),
+            // A single value is left in the accumulator
+            Self::Median | Self::Percentile(_) if context.stack.is_empty() => Some(accum),
+            Self::Median => quantile(&mut context.stack.clone(), 0.5),
+            Self::Percentile(rank) => quantile(&mut context.stack.clone(), rank.fraction()),
+            _ => Some(accum),
+        }
+    }
+}
+
+impl TriggerReducer {
+    /// Returns the kind of reducer, which is `percentile` for every
+    /// percentile.
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Mean => "mean",
+            Self::Sum => "sum",
+            Self::Count => "count",
+            Self::Median => "median",
+            Self::StdDev => "stddev",
+            Self::Percentile(_) => "percentile",
+            Self::Gap => "gap",
+        }
+    }
+
+    /// Returns the reducer as it's named in the trigger format, e.g. `p90`.
+    fn name(&self) -> String {
+        match self {
+            Self::Percentile(rank) => format!("p{}", rank.0),
+            _ => self.kind().to_owned(),
+        }
+    }
+
+    /// Reduces values to a single one. Every evaluation path goes through
+    /// this, so that they agree on what nothing reduces to.
+    fn reduce(&self, values: impl Iterator<Item = f32>) -> Option<f32> {
+        let mut context = ReducerContext::<f32> {
+            stack: Vec::new(),
+            sum: None,
+        };
+        match values.reduce(|accum, item| self.evaluate(&mut context, &accum, &item)) {
+            Some(accum) => self.finish(&context, accum),
+            // Even when nothing is left to count or add up, the result is known
+            None => match self {
+                Self::Count | Self::Sum => Some(0.0),
+                _ => None,
+            },
         }
     }
 }
 
 impl Display for TriggerReducer {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        f.write_str(&self.describe(&EN))
+    }
+}
+
+/// Values supplied alongside a trigger at evaluation time, rather than
+/// being stored in the trigger itself.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TriggerParameters {
+    /// The alert's user-supplied reference price, if any.
+    pub reference: Option<f32>,
+    /// Market values resolved from a [`crate::baseline::BaselineProvider`].
+    pub baselines: HashMap<Baseline, f32>,
+    /// The prices of melded materia, by item ID. Materia without a price
+    /// are treated as worthless.
+    pub materia_prices: HashMap<i32, f32>,
+}
+
+/// A market value that a trigger can be compared against, which is looked up
+/// when the trigger is evaluated.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Baseline {
+    /// The average price per unit of sales over the last 7 days.
+    #[serde(rename = "7d_avg_sale_price")]
+    SevenDayAverageSalePrice,
+    /// The price the item sells for at NPC vendors.
+    #[serde(rename = "vendor_price")]
+    VendorPrice,
+    /// The lowest price per unit across the world's whole region.
+    #[serde(rename = "global_min")]
+    GlobalMin,
+    /// The lowest price per unit across the world's data center.
+    #[serde(rename = "dc_min")]
+    DataCenterMin,
+    /// The recent average sale price per unit on the world, as Universalis
+    /// aggregates it.
+    #[serde(rename = "avg_sale_price")]
+    AverageSalePrice,
+    /// How many units of the item sell on the world each day.
+    #[serde(rename = "sale_velocity")]
+    SaleVelocity,
+}
+
+impl Baseline {
+    /// Returns the baseline as it's named in the trigger format.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SevenDayAverageSalePrice => "7d_avg_sale_price",
+            Self::VendorPrice => "vendor_price",
+            Self::GlobalMin => "global_min",
+            Self::DataCenterMin => "dc_min",
+            Self::AverageSalePrice => "avg_sale_price",
+            Self::SaleVelocity => "sale_velocity",
+        }
+    }
+}
+
+impl Display for Baseline {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        f.write_str(self.describe(&EN))
+    }
+}
+
+fn default_multiplier() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize, Debug, Clone)]
+enum NamedTarget {
+    #[serde(rename = "reference")]
+    Reference,
+}
+
+/// A stat of the same event's listings, which are run through their own
+/// filters, mapper, take stage, and reducer, e.g. the mean price of every
+/// listing on the board.
+#[derive(Deserialize, Debug, Clone)]
+struct EventStat {
+    #[serde(default)]
+    filters: Vec<TriggerFilter>,
+    #[serde(default)]
+    filter_mode: FilterMode,
+    mapper: TriggerMapper,
+    #[serde(default)]
+    take: Option<usize>,
+    reducer: TriggerReducer,
+}
+
+impl EventStat {
+    fn evaluate(&self, listings: &[&Listing<'_>], parameters: &TriggerParameters) -> Option<f32> {
+        let mut values = listings
+            .iter()
+            .filter(|l| passes_filters(&self.filters, self.filter_mode, l))
+            .map(|l| self.mapper.evaluate(l, parameters))
+            .collect::<Vec<_>>();
+        if let Some(k) = self.take {
+            if values.len() > k {
+                values.select_nth_unstable_by(k, |a, b| a.total_cmp(b));
+                values.truncate(k);
+            }
+        }
+        self.reducer.reduce(values.into_iter())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum ComparisonTarget {
+    Constant(f32),
+    Named(NamedTarget),
+    Baseline {
+        baseline: Baseline,
+        #[serde(default = "default_multiplier")]
+        multiplier: f32,
+    },
+    Stat {
+        stat: Box<EventStat>,
+        #[serde(default = "default_multiplier")]
+        multiplier: f32,
+    },
+}
+
+impl ComparisonTarget {
+    /// Resolves the target value, if it is available. `listings` are every
+    /// listing in the event, for stat targets; it's empty for the others.
+    fn resolve(&self, parameters: &TriggerParameters, listings: &[&Listing<'_>]) -> Option<f32> {
         match self {
-            Self::Min => f.write_str("Min"),
-            Self::Max => f.write_str("Max"),
-            Self::Mean => f.write_str("Mean"),
+            Self::Constant(target) => Some(*target),
+            Self::Named(NamedTarget::Reference) => parameters.reference,
+            Self::Baseline {
+                baseline,
+                multiplier,
+            } => parameters.baselines.get(baseline).map(|v| v * multiplier),
+            Self::Stat { stat, multiplier } => {
+                stat.evaluate(listings, parameters).map(|v| v * multiplier)
+            }
         }
     }
 }
 
+impl Display for ComparisonTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        f.write_str(&self.describe(&EN))
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 enum Comparison {
     #[serde(rename = "lt")]
-    LessThan { target: f32 },
+    LessThan { target: ComparisonTarget },
     #[serde(rename = "gt")]
-    GreaterThan { target: f32 },
+    GreaterThan { target: ComparisonTarget },
+    /// Fewer than `percent` percent of the event's values are below the value.
+    #[serde(rename = "bottom_percent")]
+    BottomPercent {
+        #[serde(deserialize_with = "percentage")]
+        percent: f32,
+    },
+    /// The value is at least `percent` percent below every other value in
+    /// the event.
+    #[serde(rename = "below_rest")]
+    BelowRest {
+        #[serde(deserialize_with = "percentage")]
+        percent: f32,
+    },
+}
+
+fn percentage<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<f32, D::Error> {
+    let percent = f32::deserialize(deserializer)?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(serde::de::Error::custom(
+            "percentage must be between 0 and 100",
+        ));
+    }
+    Ok(percent)
 }
 
 trait ComparisonOp<T> {
-    fn evaluate(&self, value: &T) -> bool;
+    /// Compares a reduced value. `event` holds the mapped values of every
+    /// listing that passed the filters, for comparisons that are relative to
+    /// the event, and `listings` holds every listing in the event, for
+    /// comparisons against a stat of them; each is empty otherwise.
+    fn evaluate(
+        &self,
+        value: &T,
+        parameters: &TriggerParameters,
+        event: &[T],
+        listings: &[&Listing<'_>],
+    ) -> bool;
 }
 
 impl ComparisonOp<f32> for Comparison {
-    fn evaluate(&self, value: &f32) -> bool {
+    fn evaluate(
+        &self,
+        value: &f32,
+        parameters: &TriggerParameters,
+        event: &[f32],
+        listings: &[&Listing<'_>],
+    ) -> bool {
+        // A comparison against a value that isn't available never succeeds
+        match self {
+            Self::LessThan { target } => target
+                .resolve(parameters, listings)
+                .is_some_and(|target| *value < target),
+            Self::GreaterThan { target } => target
+                .resolve(parameters, listings)
+                .is_some_and(|target| *value > target),
+            Self::BottomPercent { percent } => {
+                if event.is_empty() {
+                    return false;
+                }
+                let below = event.iter().filter(|v| **v < *value).count();
+                (below as f32 / event.len() as f32) * 100.0 < *percent
+            }
+            Self::BelowRest { percent } => {
+                // The value itself is one of the event's values, unless it
+                // was reduced into something new (e.g. a mean)
+                let mut skipped = false;
+                let rest = event
+                    .iter()
+                    .filter(|v| {
+                        if !skipped && **v == *value {
+                            skipped = true;
+                            false
+                        } else {
+                            true
+                        }
+                    })
+                    .copied()
+                    .reduce(f32::min);
+                rest.is_some_and(|rest| *value <= rest * (1.0 - percent / 100.0))
+            }
+        }
+    }
+}
+
+impl Comparison {
+    /// Returns the kind of comparison, as it's named in the trigger format.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::LessThan { .. } => "lt",
+            Self::GreaterThan { .. } => "gt",
+            Self::BottomPercent { .. } => "bottom_percent",
+            Self::BelowRest { .. } => "below_rest",
+        }
+    }
+
+    fn target(&self) -> Option<&ComparisonTarget> {
         match self {
-            Self::LessThan { target } => *value < *target,
-            Self::GreaterThan { target } => *value > *target,
+            Self::LessThan { target } | Self::GreaterThan { target } => Some(target),
+            Self::BottomPercent { .. } | Self::BelowRest { .. } => None,
         }
     }
+
+    /// Returns whether the comparison needs every value in the event, rather
+    /// than only the reduced one.
+    fn is_event_relative(&self) -> bool {
+        self.target().is_none()
+    }
+
+    /// Returns whether the comparison is against a stat of the event's
+    /// listings, which needs all of them.
+    fn uses_listings(&self) -> bool {
+        matches!(self.target(), Some(ComparisonTarget::Stat { .. }))
+    }
 }
 
 impl Display for Comparison {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-        match self {
-            Self::LessThan { target } => f.write_fmt(format_args!("Less than {}", target)),
-            Self::GreaterThan { target } => f.write_fmt(format_args!("Greater than {}", target)),
+        f.write_str(&self.describe(&EN))
+    }
+}
+
+/// How the filters of a trigger are combined.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum FilterMode {
+    /// Listings must pass every filter.
+    #[default]
+    #[serde(rename = "all")]
+    All,
+    /// Listings must pass at least one filter.
+    #[serde(rename = "any")]
+    Any,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Weekday {
+    Sun,
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [
+        Self::Sun,
+        Self::Mon,
+        Self::Tue,
+        Self::Wed,
+        Self::Thu,
+        Self::Fri,
+        Self::Sat,
+    ];
+}
+
+impl Display for Weekday {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        f.write_str(EN.weekdays[*self as usize])
+    }
+}
+
+/// A time of day, written as "HH:MM".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TimeOfDay {
+    minutes: u32,
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        let parsed = value
+            .split_once(':')
+            .and_then(|(h, m)| Some((h.parse::<u32>().ok()?, m.parse::<u32>().ok()?)))
+            .filter(|(h, m)| *h < 24 && *m < 60);
+        match parsed {
+            Some((h, m)) => Ok(Self {
+                minutes: h * 60 + m,
+            }),
+            None => Err(serde::de::Error::custom(format!(
+                "invalid time of day '{}', expected HH:MM",
+                value
+            ))),
         }
     }
 }
 
+impl Display for TimeOfDay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        f.write_fmt(format_args!(
+            "{:02}:{:02}",
+            self.minutes / 60,
+            self.minutes % 60
+        ))
+    }
+}
+
+/// When a trigger is evaluated. Outside of its schedule, a trigger never
+/// matches, no matter what the market looks like.
+#[derive(Deserialize, Debug, Clone)]
+struct Schedule {
+    /// The days the trigger is evaluated on; every day if empty.
+    #[serde(default)]
+    days: Vec<Weekday>,
+    /// The start of the daily window. A window that ends before it starts
+    /// runs past midnight.
+    #[serde(default)]
+    start: Option<TimeOfDay>,
+    #[serde(default)]
+    end: Option<TimeOfDay>,
+    /// The offset of the schedule's time zone from UTC, in minutes.
+    #[serde(default)]
+    utc_offset_minutes: i32,
+}
+
+impl Schedule {
+    fn is_active(&self, now: i64) -> bool {
+        let local = now + self.utc_offset_minutes as i64 * 60;
+        let days_since_epoch = local.div_euclid(86400);
+        let minute_of_day = (local.rem_euclid(86400) / 60) as u32;
+        // The Unix epoch was a Thursday
+        let weekday = Weekday::ALL[(days_since_epoch + 4).rem_euclid(7) as usize];
+
+        if !self.days.is_empty() && !self.days.contains(&weekday) {
+            return false;
+        }
+        match (self.start, self.end) {
+            (Some(start), Some(end)) if start.minutes <= end.minutes => {
+                start.minutes <= minute_of_day && minute_of_day < end.minutes
+            }
+            (Some(start), Some(end)) => {
+                minute_of_day >= start.minutes || minute_of_day < end.minutes
+            }
+            (Some(start), None) => minute_of_day >= start.minutes,
+            (None, Some(end)) => minute_of_day < end.minutes,
+            (None, None) => true,
+        }
+    }
+}
+
+impl Display for Schedule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        f.write_str(&self.describe(&EN))
+    }
+}
+
+/// A totally-ordered wrapper around mapped values, for use in heaps.
+#[derive(PartialEq)]
+struct OrderedValue(f32);
+
+impl Eq for OrderedValue {}
+
+impl PartialOrd for OrderedValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrderedValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// The result of running listings through a trigger, along with how many
+/// listings made it through each stage.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct TriggerEvaluation {
+    /// The number of listings the trigger was run over.
+    pub listings: usize,
+    /// The number of listings that passed the filters.
+    pub passed_filters: usize,
+    /// The number of mapped values that were reduced, after the take stage.
+    pub reduced: usize,
+    /// The reduced value, whether or not it satisfied the comparison.
+    pub value: Option<f32>,
+    /// Whether the reduced value satisfied the comparison.
+    pub matched: bool,
+}
+
+impl TriggerEvaluation {
+    /// Returns the reduced value if the trigger matched, the same as
+    /// [`AlertTrigger::evaluate`].
+    pub fn result(&self) -> Option<f32> {
+        self.value.filter(|_| self.matched)
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct AlertTrigger {
     filters: Vec<TriggerFilter>,
+    #[serde(default)]
+    filter_mode: FilterMode,
     mapper: TriggerMapper,
+    /// If set, only the lowest `take` mapped values are reduced.
+    #[serde(default)]
+    take: Option<usize>,
     reducer: TriggerReducer,
     comparison: Comparison,
+    /// If set, the trigger is only evaluated during this schedule.
+    #[serde(default)]
+    schedule: Option<Schedule>,
+    /// Fields that aren't part of the trigger format, which are rejected
+    /// for trigger versions that are parsed strictly.
+    #[serde(flatten)]
+    unknown_fields: HashMap<String, serde_json::Value>,
 }
 
 impl AlertTrigger {
-    pub fn evaluate(&self, listings: &[Listing]) -> Option<f32> {
-        let mut context = ReducerContext::<f32> { stack: Vec::new() };
-        listings
+    /// Returns the market value this trigger is compared against, if any,
+    /// which must be resolved into its parameters before evaluation.
+    pub fn baseline(&self) -> Option<Baseline> {
+        match self.comparison.target()? {
+            ComparisonTarget::Baseline { baseline, .. } => Some(*baseline),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the trigger needs materia prices in its parameters.
+    pub fn uses_materia(&self) -> bool {
+        matches!(self.mapper, TriggerMapper::UnitPriceLessMateria)
+    }
+
+    /// Returns whether the trigger's schedule, if any, allows it to be
+    /// evaluated at `now` (in seconds since the Unix epoch).
+    pub fn is_scheduled(&self, now: i64) -> bool {
+        self.schedule.as_ref().is_none_or(|s| s.is_active(now))
+    }
+
+    /// Returns when the trigger's schedule next allows it to be evaluated,
+    /// which is `now` if it already does. Schedules repeat every week, so a
+    /// schedule that isn't active within a week never is.
+    pub fn next_scheduled(&self, now: i64) -> Option<i64> {
+        if self.is_scheduled(now) {
+            return Some(now);
+        }
+        // Schedules change on minute boundaries
+        let next_minute = (now.div_euclid(60) + 1) * 60;
+        (0..8 * 24 * 60)
+            .map(|minute| next_minute + minute * 60)
+            .find(|t| self.is_scheduled(*t))
+    }
+
+    /// Returns the capabilities this trigger uses, named the way they're
+    /// written in the trigger format (e.g. `reducer:gap` or
+    /// `comparison:below_rest`), so that deployments can turn them off.
+    pub fn features(&self) -> Vec<String> {
+        let mut features = Vec::new();
+        for filter in &self.filters {
+            features.push(format!("filter:{}", filter.name()));
+        }
+        if self.filter_mode == FilterMode::Any {
+            features.push("filter_mode:any".to_owned());
+        }
+        features.push(format!("mapper:{}", self.mapper.name()));
+        if self.take.is_some() {
+            features.push("take".to_owned());
+        }
+        features.push(format!("reducer:{}", self.reducer.kind()));
+        features.push(format!("comparison:{}", self.comparison.name()));
+        match self.comparison.target() {
+            Some(ComparisonTarget::Named(NamedTarget::Reference)) => {
+                features.push("target:reference".to_owned())
+            }
+            Some(ComparisonTarget::Baseline { baseline, .. }) => {
+                features.push(format!("baseline:{}", baseline.name()))
+            }
+            Some(ComparisonTarget::Stat { .. }) => features.push("target:stat".to_owned()),
+            _ => {}
+        }
+        if self.schedule.is_some() {
+            features.push("schedule".to_owned());
+        }
+        features.into_iter().unique().collect()
+    }
+
+    /// Rewrites the trigger into a normalized form that evaluates the same
+    /// way: filters are sorted and duplicates merged, take stages that can't
+    /// change the result are dropped, schedules that are always active are
+    /// removed, and constant comparisons against whole-number values (e.g.
+    /// `min(pricePerUnit) < 999.5`) are rounded to the equivalent whole
+    /// number. Triggers that are written differently but mean the same thing
+    /// canonicalize to the same trigger.
+    pub fn canonicalize(&self) -> AlertTrigger {
+        let mut canonical = self.clone();
+        canonical.unknown_fields.clear();
+
+        // The extreme percentiles and the 50th are reducers of their own
+        if let TriggerReducer::Percentile(rank) = self.reducer {
+            canonical.reducer = match rank.0 {
+                0 => TriggerReducer::Min,
+                50 => TriggerReducer::Median,
+                100 => TriggerReducer::Max,
+                _ => TriggerReducer::Percentile(rank),
+            };
+        }
+
+        // Within each mode, one newerThan or materiaCount filter subsumes the
+        // others. Quantity and price ranges that must all match are one range,
+        // but ranges that may match are only deduplicated, since they might
+        // not overlap.
+        let mut newest: Option<u32> = None;
+        let mut melded: Option<u32> = None;
+        let mut hq = false;
+        let mut nq = false;
+        let mut on_mannequin = false;
+        let mut not_on_mannequin = false;
+        let mut quantities: Vec<QuantityRange> = Vec::new();
+        let mut prices: Vec<PriceRange> = Vec::new();
+        let mut retainers: Vec<NameMatch> = Vec::new();
+        let mut creators: Vec<NameMatch> = Vec::new();
+        let mut not_creators: Vec<NameMatch> = Vec::new();
+        // Whether ranges that must all match don't overlap
+        let mut disjoint = false;
+        for filter in &self.filters {
+            match filter {
+                TriggerFilter::Hq => hq = true,
+                TriggerFilter::Nq => nq = true,
+                TriggerFilter::OnMannequin => on_mannequin = true,
+                TriggerFilter::NotOnMannequin => not_on_mannequin = true,
+                TriggerFilter::NewerThan { minutes } => {
+                    newest = Some(match (newest, self.filter_mode) {
+                        (None, _) => *minutes,
+                        (Some(m), FilterMode::All) => m.min(*minutes),
+                        (Some(m), FilterMode::Any) => m.max(*minutes),
+                    })
+                }
+                TriggerFilter::MateriaCount { min } => {
+                    melded = Some(match (melded, self.filter_mode) {
+                        (None, _) => *min,
+                        (Some(m), FilterMode::All) => m.max(*min),
+                        (Some(m), FilterMode::Any) => m.min(*min),
+                    })
+                }
+                TriggerFilter::RetainerName(retainer) => retainers.push(retainer.clone()),
+                TriggerFilter::CreatorName(creator) => creators.push(creator.clone()),
+                TriggerFilter::NotCreatorName(creator) => not_creators.push(creator.clone()),
+                TriggerFilter::Quantity(range) => {
+                    match (quantities.first_mut(), self.filter_mode) {
+                        (Some(merged), FilterMode::All) => match merged.intersect(range) {
+                            Some(range) => *merged = range,
+                            None => disjoint = true,
+                        },
+                        _ => quantities.push(*range),
+                    }
+                }
+                TriggerFilter::UnitPrice(range) => match (prices.first_mut(), self.filter_mode) {
+                    (Some(merged), FilterMode::All) => match merged.intersect(range) {
+                        Some(range) => *merged = range,
+                        None => disjoint = true,
+                    },
+                    _ => prices.push(*range),
+                },
+            }
+        }
+        quantities
+            .iter_mut()
+            .for_each(QuantityRange::drop_least_min);
+        prices.iter_mut().for_each(PriceRange::drop_least_min);
+        if self.filter_mode == FilterMode::All {
+            quantities.retain(|range| !range.is_unbounded());
+            prices.retain(|range| !range.is_unbounded());
+            melded = melded.filter(|min| *min > 0);
+        }
+        quantities.sort();
+        quantities.dedup();
+        prices.sort();
+        prices.dedup();
+        let any_range = quantities.iter().any(|range| range.is_unbounded())
+            || prices.iter().any(|range| range.is_unbounded());
+        for names in [&mut retainers, &mut creators, &mut not_creators] {
+            names.sort();
+            names.dedup();
+        }
+        canonical.filters = hq
+            .then_some(TriggerFilter::Hq)
             .into_iter()
+            .chain(nq.then_some(TriggerFilter::Nq))
+            .chain(on_mannequin.then_some(TriggerFilter::OnMannequin))
+            .chain(not_on_mannequin.then_some(TriggerFilter::NotOnMannequin))
+            .chain(newest.map(|minutes| TriggerFilter::NewerThan { minutes }))
+            .chain(quantities.into_iter().map(TriggerFilter::Quantity))
+            .chain(prices.into_iter().map(TriggerFilter::UnitPrice))
+            .chain(retainers.into_iter().map(TriggerFilter::RetainerName))
+            .chain(creators.into_iter().map(TriggerFilter::CreatorName))
+            .chain(not_creators.into_iter().map(TriggerFilter::NotCreatorName))
+            .chain(melded.map(|min| TriggerFilter::MateriaCount { min }))
+            .collect();
+        // Every listing is either HQ or NQ, is either on a mannequin or not,
+        // has at least zero materia, and is in any range without bounds past
+        // the least value, so any listing may match these
+        if self.filter_mode == FilterMode::Any
+            && (hq && nq || on_mannequin && not_on_mannequin || melded == Some(0) || any_range)
+        {
+            canonical.filters.clear();
+        }
+        // No listing is in ranges that don't overlap, and every stack has
+        // at least one item, so an empty stack is the one filter that
+        // matches nothing
+        if disjoint {
+            canonical.filters = vec![TriggerFilter::Quantity(QuantityRange::new(None, Some(0)))];
+        }
+        if canonical.filters.len() <= 1 {
+            canonical.filter_mode = FilterMode::All;
+        }
+
+        // Counts don't depend on the mapped values, unless the comparison does
+        if matches!(canonical.reducer, TriggerReducer::Count)
+            && !self.comparison.is_event_relative()
+        {
+            canonical.mapper = TriggerMapper::UnitPrice;
+        }
+
+        // The minimum of the lowest values is the overall minimum
+        if matches!(canonical.reducer, TriggerReducer::Min) && self.take.is_some_and(|k| k > 0) {
+            canonical.take = None;
+        }
+
+        // The minimum, maximum or sum of whole numbers is a whole number, so
+        // only the whole part of the target matters. Adding zero turns -0 into 0.
+        let whole = matches!(canonical.reducer, TriggerReducer::Count)
+            || matches!(
+                canonical.reducer,
+                TriggerReducer::Min | TriggerReducer::Max | TriggerReducer::Sum
+            ) && matches!(
+                self.mapper,
+                TriggerMapper::UnitPrice
+                    | TriggerMapper::UnitPriceWithTax
+                    | TriggerMapper::Quantity
+                    | TriggerMapper::Total
+            );
+        if whole {
+            match &mut canonical.comparison {
+                Comparison::LessThan {
+                    target: ComparisonTarget::Constant(target),
+                } => *target = target.ceil() + 0.0,
+                Comparison::GreaterThan {
+                    target: ComparisonTarget::Constant(target),
+                } => *target = target.floor() + 0.0,
+                _ => {}
+            }
+        }
+
+        if let Some(schedule) = &mut canonical.schedule {
+            schedule.days.sort_by_key(|day| *day as u8);
+            schedule.days.dedup();
+            if schedule.days.len() == Weekday::ALL.len() {
+                schedule.days.clear();
+            }
+            if schedule.days.is_empty() && schedule.start.is_none() && schedule.end.is_none() {
+                canonical.schedule = None;
+            }
+        }
+        canonical
+    }
+
+    /// Returns a key that is the same for any two triggers with the same
+    /// canonical form, for finding identical alerts and keying caches.
+    pub fn canonical_key(&self) -> String {
+        let mut canonical = self.canonicalize();
+        let schedule = canonical.schedule.take();
+        // Without a schedule, every trigger has an expression form
+        let expression = canonical
+            .to_expression()
+            .unwrap_or_else(|| canonical.to_string());
+        match schedule {
+            Some(schedule) => format!("{} during {}", expression, schedule),
+            None => expression,
+        }
+    }
+
+    /// Returns the names of any fields that aren't part of the trigger format.
+    pub fn unknown_fields(&self) -> impl Iterator<Item = &str> {
+        self.unknown_fields.keys().map(String::as_str)
+    }
+
+    /// Returns whether only HQ listings can ever pass this trigger's filters.
+    pub fn is_hq_only(&self) -> bool {
+        let is_hq = |f: &TriggerFilter| matches!(f, TriggerFilter::Hq);
+        match self.filter_mode {
+            FilterMode::All => self.filters.iter().any(is_hq),
+            FilterMode::Any => !self.filters.is_empty() && self.filters.iter().all(is_hq),
+        }
+    }
+
+    /// Returns the listings that pass this trigger's filters.
+    pub fn filter_listings<'a, 'l>(&self, listings: &[&'a Listing<'l>]) -> Vec<&'a Listing<'l>> {
+        listings
+            .iter()
+            .copied()
+            .filter(|l| self.passes_filters(l))
+            .collect()
+    }
+
+    pub fn evaluate(
+        &self,
+        listings: &[Listing<'_>],
+        parameters: &TriggerParameters,
+    ) -> Option<f32> {
+        self.run(listings.iter(), parameters).result()
+    }
+
+    /// Runs listings through each stage of the trigger, keeping track of how
+    /// many listings made it through each one.
+    fn run<'a, 'l: 'a>(
+        &self,
+        listings: impl Iterator<Item = &'a Listing<'l>>,
+        parameters: &TriggerParameters,
+    ) -> TriggerEvaluation {
+        let mut seen = 0;
+        let mut passed_filters = 0;
+        // Comparisons relative to the event need all of its values, so those
+        // are buffered as they go by
+        let buffer_event = self.comparison.is_event_relative();
+        let mut event = Vec::new();
+        // Likewise for every listing, for comparisons against a stat of them
+        let buffer_listings = self.comparison.uses_listings();
+        let mut all_listings = Vec::new();
+        let values = listings
+            .inspect(|_| seen += 1)
+            .inspect(|l| {
+                if buffer_listings {
+                    all_listings.push(*l);
+                }
+            })
             // Execute all filters on each listing
-            .filter(|l| self.filters.iter().all(|f| f.evaluate(l)))
+            .filter(|l| self.passes_filters(l))
+            .inspect(|_| passed_filters += 1)
             // Map each listing to a scalar
-            .map(|l| self.mapper.evaluate(l))
-            // Execute the specified reducer
-            .reduce(|accum, item| self.reducer.evaluate(&mut context, &accum, &item))
-            // Check if the result satisfies the final comparison
-            .filter(|result| self.comparison.evaluate(result))
+            .map(|l| self.mapper.evaluate(l, parameters))
+            .inspect(|value| {
+                if buffer_event {
+                    event.push(*value);
+                }
+            });
+
+        // Execute the take stage, if any, and then the specified reducer
+        let (reduced, value) = match (self.take, &self.reducer) {
+            (Some(0), _) => (0, self.reducer.reduce(iter::empty())),
+            // The minimum of the lowest values is the overall minimum,
+            // so the take stage can be skipped entirely.
+            (_, TriggerReducer::Min) | (None, _) => {
+                let mut reduced = 0;
+                let value = self.reducer.reduce(values.inspect(|_| reduced += 1));
+                (reduced, value)
+            }
+            // The maximum of the lowest k values is the top of a max-heap
+            // holding the k smallest values seen so far.
+            (Some(k), TriggerReducer::Max) => {
+                let mut heap = BinaryHeap::with_capacity(k + 1);
+                for value in values {
+                    heap.push(OrderedValue(value));
+                    if heap.len() > k {
+                        heap.pop();
+                    }
+                }
+                (heap.len(), heap.peek().map(|v| v.0))
+            }
+            (Some(k), _) => {
+                let mut values = values.collect::<Vec<_>>();
+                if values.len() > k {
+                    values.select_nth_unstable_by(k, |a, b| a.total_cmp(b));
+                    values.truncate(k);
+                }
+                (values.len(), self.reducer.reduce(values.into_iter()))
+            }
+        };
+        // Check if the result satisfies the final comparison
+        let matched = value.is_some_and(|value| {
+            self.comparison
+                .evaluate(&value, parameters, &event, &all_listings)
+        });
+        TriggerEvaluation {
+            listings: seen,
+            passed_filters,
+            reduced,
+            value,
+            matched,
+        }
+    }
+
+    /// Evaluates the trigger with the candidate engine. This is run alongside
+    /// [`AlertTrigger::evaluate`] in shadow-eval mode to catch changes in
+    /// semantics before they are shipped; it currently holds a naive
+    /// sort-based implementation of the take stage.
+    pub fn evaluate_candidate(
+        &self,
+        listings: &[&Listing<'_>],
+        parameters: &TriggerParameters,
+    ) -> Option<f32> {
+        let mut values = listings
+            .iter()
+            .filter(|l| self.passes_filters(l))
+            .map(|l| self.mapper.evaluate(l, parameters))
+            .collect::<Vec<_>>();
+        let event = if self.comparison.is_event_relative() {
+            values.clone()
+        } else {
+            Vec::new()
+        };
+        let all_listings = if self.comparison.uses_listings() {
+            listings.to_vec()
+        } else {
+            Vec::new()
+        };
+        if let Some(k) = self.take {
+            values.sort_by(|a, b| a.total_cmp(b));
+            values.truncate(k);
+        }
+        self.reducer.reduce(values.into_iter()).filter(|result| {
+            self.comparison
+                .evaluate(result, parameters, &event, &all_listings)
+        })
+    }
+
+    fn passes_filters(&self, listing: &Listing<'_>) -> bool {
+        passes_filters(&self.filters, self.filter_mode, listing)
+    }
+
+    /// Returns what the evaluated value of this trigger measures.
+    pub fn value_kind(&self) -> ValueKind {
+        match self.reducer {
+            TriggerReducer::Gap => return ValueKind::Percent,
+            TriggerReducer::Count => return ValueKind::Count,
+            _ => {}
+        }
+        match self.mapper {
+            TriggerMapper::Quantity => ValueKind::Count,
+            TriggerMapper::Age => ValueKind::Minutes,
+            TriggerMapper::UnitPrice
+            | TriggerMapper::UnitPriceWithTax
+            | TriggerMapper::UnitPriceLessMateria
+            | TriggerMapper::Total => ValueKind::Gil,
+        }
+    }
+}
+
+fn passes_filters(filters: &[TriggerFilter], mode: FilterMode, listing: &Listing<'_>) -> bool {
+    match mode {
+        FilterMode::All => filters.iter().all(|f| f.evaluate(listing)),
+        // With no filters at all, nothing is filtered out
+        FilterMode::Any => filters.is_empty() || filters.iter().any(|f| f.evaluate(listing)),
     }
 }
 
 impl Display for AlertTrigger {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-        let formatted_filters = self.filters.iter().map(|filter| format!("{}", filter));
-        let formatted_filters =
-            Itertools::intersperse(formatted_filters, "\n".to_string()).collect::<String>();
-        f.write_fmt(format_args!(
-            "{}\n\nField: {}\nStat: {}\nComparison: {}",
-            formatted_filters, self.mapper, self.reducer, self.comparison
-        ))
+        f.write_str(&self.describe_with(&EN))
+    }
+}
+
+/// Iterator adaptors for running listings through the stages of a trigger
+/// with the same semantics as the alert service, e.g. over historical data:
+///
+/// ```ignore
+/// let evaluation = listings.iter().apply_trigger(&trigger);
+/// ```
+pub trait ListingsExt<'a, 'l: 'a>: Iterator<Item = &'a Listing<'l>> + Sized {
+    /// Keeps only the listings that pass the trigger's filters.
+    fn filter_trigger<'t>(
+        self,
+        trigger: &'t AlertTrigger,
+    ) -> impl Iterator<Item = &'a Listing<'l>> + 't
+    where
+        Self: 't,
+    {
+        self.filter(move |l| trigger.passes_filters(l))
+    }
+
+    /// Maps each listing to the trigger's value, without filtering or
+    /// parameters.
+    fn map_trigger<'t>(self, trigger: &'t AlertTrigger) -> impl Iterator<Item = f32> + 't
+    where
+        Self: 't,
+    {
+        let parameters = TriggerParameters::default();
+        self.map(move |l| trigger.mapper.evaluate(l, &parameters))
+    }
+
+    /// Runs every stage of the trigger over the listings, with no parameters.
+    fn apply_trigger(self, trigger: &AlertTrigger) -> TriggerEvaluation {
+        trigger.run(self, &TriggerParameters::default())
+    }
+
+    /// Runs every stage of the trigger over the listings.
+    fn apply_trigger_with(
+        self,
+        trigger: &AlertTrigger,
+        parameters: &TriggerParameters,
+    ) -> TriggerEvaluation {
+        trigger.run(self, parameters)
+    }
+}
+
+impl<'a, 'l: 'a, I: Iterator<Item = &'a Listing<'l>>> ListingsExt<'a, 'l> for I {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::parse_trigger;
+    use std::borrow::Cow;
+
+    pub(super) fn listings(prices: &[i32], hq: bool) -> Vec<Listing<'static>> {
+        prices
+            .iter()
+            .map(|price| Listing {
+                unit_price: *price,
+                quantity: 1,
+                total: *price as i64,
+                tax: Some(0),
+                hq,
+                listing_id: None,
+                seller_id: None,
+                retainer_name: None,
+                creator_name: None,
+                last_review_time: None,
+                materia: Vec::new(),
+                on_mannequin: false,
+            })
+            .collect()
+    }
+
+    fn evaluate(expression: &str, listings: &[Listing<'_>]) -> Option<f32> {
+        let trigger = parse_expression(expression).unwrap();
+        let parameters = TriggerParameters::default();
+        let evaluation = trigger.run(listings.iter(), &parameters);
+        let value = evaluation.matched.then_some(()).and(evaluation.value);
+        // Both engines reduce the same way
+        assert_eq!(
+            trigger.evaluate_candidate(&listings.iter().collect_vec(), &parameters),
+            value
+        );
+        value
+    }
+
+    #[test]
+    fn sums_agree_with_the_mean_and_count() {
+        let listings = listings(&[100, 250, 400, 1000], false);
+        let sum = evaluate("sum(pricePerUnit) > 0", &listings).unwrap();
+        let mean = evaluate("mean(pricePerUnit) > 0", &listings).unwrap();
+        let count = evaluate("count(pricePerUnit) > 0", &listings).unwrap();
+        assert_eq!(sum, 1750.0);
+        assert_eq!(sum, mean * count);
+
+        // Only the lowest values are added up after a take stage
+        assert_eq!(
+            evaluate("sum(pricePerUnit take 2) > 0", &listings),
+            Some(350.0)
+        );
+        assert_eq!(
+            evaluate("sum(pricePerUnit take 2) > 0", &listings),
+            evaluate("mean(pricePerUnit take 2) > 0", &listings).map(|mean| mean * 2.0)
+        );
+    }
+
+    #[test]
+    fn sums_and_counts_of_nothing_are_zero() {
+        let listings = listings(&[100, 250], false);
+        assert_eq!(
+            evaluate("sum(pricePerUnit where hq) < 1", &listings),
+            Some(0.0)
+        );
+        assert_eq!(
+            evaluate("sum(pricePerUnit take 0) < 1", &listings),
+            Some(0.0)
+        );
+        assert_eq!(evaluate("sum(pricePerUnit) < 1", &[]), Some(0.0));
+        assert_eq!(
+            evaluate("count(pricePerUnit where hq) < 1", &listings),
+            Some(0.0)
+        );
+        assert_eq!(evaluate("count(pricePerUnit) < 1", &[]), Some(0.0));
+    }
+
+    #[test]
+    fn sums_are_accumulated_in_f64() {
+        // Adding 1 to 2^24 in an f32 rounds back down to 2^24
+        let listings = listings(&[16_777_216, 1, 1], false);
+        assert_eq!(
+            evaluate("sum(pricePerUnit) > 0", &listings),
+            Some(16_777_218.0)
+        );
+    }
+
+    #[test]
+    fn prices_include_tax() {
+        let mut listings = listings(&[100], false);
+        listings[0].quantity = 4;
+        listings[0].total = 400;
+        listings[0].tax = Some(20);
+        assert_eq!(evaluate("min(pricePerUnit) > 0", &listings), Some(105.0));
+        assert_eq!(
+            evaluate("min(pricePerUnitWithTax) > 0", &listings),
+            Some(105.0)
+        );
+        assert_eq!(evaluate("min(total) > 0", &listings), Some(420.0));
+        // Price filters compare the price with tax too
+        assert_eq!(
+            evaluate(
+                "count(pricePerUnit where pricePerUnit(max 100)) > 0",
+                &listings
+            ),
+            None
+        );
+
+        // Listings without a reported tax are charged the usual 5%
+        listings[0].tax = None;
+        assert_eq!(evaluate("min(pricePerUnit) > 0", &listings), Some(105.0));
+    }
+
+    #[test]
+    fn stored_v1_triggers_keep_comparing_prices_with_tax() {
+        let trigger = parse_trigger(
+            r#"{"filters": [], "mapper": "pricePerUnit", "reducer": "min", "comparison": {"lt": {"target": 104}}}"#,
+            1,
+        )
+        .unwrap();
+        let mut listings = listings(&[100], false);
+        listings[0].tax = None;
+        let evaluation = trigger.run(listings.iter(), &TriggerParameters::default());
+        // 100 gil before tax is 105 with it, which isn't below 104
+        assert_eq!(evaluation.value, Some(105.0));
+        assert!(!evaluation.matched);
+    }
+
+    #[test]
+    fn ranges_that_dont_overlap_never_match() {
+        let listings = listings(&[100, 250], false);
+        for expression in [
+            "min(pricePerUnit where quantity(min 10) and quantity(max 5)) > 0",
+            "min(pricePerUnit where pricePerUnit(max 100) and pricePerUnit(min 200)) > 0",
+        ] {
+            let trigger = parse_expression(expression).unwrap();
+            assert_eq!(evaluate(expression, &listings), None);
+            let key = trigger.canonical_key();
+            assert_eq!(key, "min(pricePerUnit where quantity(max 0)) > 0");
+            assert_eq!(parse_expression(&key).unwrap().canonical_key(), key);
+        }
+    }
+
+    #[test]
+    fn any_unbounded_range_lets_every_listing_through() {
+        for expression in [
+            "min(pricePerUnit where hq or quantity(min 1)) > 0",
+            "min(pricePerUnit where hq or pricePerUnit(min 0)) > 0",
+        ] {
+            let trigger = parse_expression(expression).unwrap();
+            assert_eq!(trigger.canonical_key(), "min(pricePerUnit) > 0");
+        }
+    }
+
+    #[test]
+    fn names_that_differ_only_in_case_are_the_same() {
+        let mut listings = listings(&[100], false);
+        listings[0].retainer_name = Some(Cow::Borrowed("KUPO"));
+        let lower = r#"min(pricePerUnit where retainerName("kupo")) > 0"#;
+        let mixed = r#"min(pricePerUnit where retainerName("Kupo")) > 0"#;
+        assert_eq!(evaluate(mixed, &listings), Some(100.0));
+        assert_eq!(
+            parse_expression(mixed).unwrap().canonical_key(),
+            parse_expression(lower).unwrap().canonical_key()
+        );
+
+        let json: AlertTrigger = serde_json::from_str(
+            r#"{"filters": [{"retainerName": {"contains": "KuPo"}}], "mapper": "pricePerUnit", "reducer": "min", "comparison": {"gt": {"target": 0}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            json.canonical_key(),
+            r#"min(pricePerUnit where retainerName(contains "kupo")) > 0"#
+        );
+    }
+
+    #[test]
+    fn percentages_outside_0_to_100_are_rejected() {
+        for comparison in ["bottom_percent", "below_rest"] {
+            for percent in ["-5", "150"] {
+                let json = format!(
+                    r#"{{"filters": [], "mapper": "pricePerUnit", "reducer": "min", "comparison": {{"{}": {{"percent": {}}}}}}}"#,
+                    comparison, percent
+                );
+                let err = serde_json::from_str::<AlertTrigger>(&json).unwrap_err();
+                assert!(err.to_string().contains("between 0 and 100"), "{}", err);
+            }
+        }
     }
 }
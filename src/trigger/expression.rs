@@ -0,0 +1,955 @@
+//! A compact text form of triggers, for people who would rather not write
+//! the JSON format by hand, e.g.:
+//!
+//! ```text
+//! min(pricePerUnit where hq and newerThan(60)) < 0.8 * 7d_avg_sale_price
+//! mean(pricePerUnit take 5) < reference
+//! p90(pricePerUnit) > 50000
+//! count(pricePerUnit where hq) < 3
+//! min(pricePerUnit where quantity(min 99)) < 1000
+//! mean(pricePerUnit where pricePerUnit(max 100000)) > 50000
+//! min(pricePerUnit where retainerName(contains "kupo")) < 1000
+//! min(pricePerUnit where hq and notCreatorName("Kupo Nut")) < 50000
+//! min(pricePerUnit where materiaCount(min 5)) < 200000
+//! stddev(pricePerUnit take 10) > 20000
+//! min(pricePerUnit) below rest by 10%
+//! min(pricePerUnit where hq) < 0.8 * mean(pricePerUnit)
+//! ```
+//!
+//! A trigger can be compared against a stat of the same event's listings,
+//! written like the trigger's own stages, e.g. `mean(pricePerUnit where nq)`.
+//!
+//! Expressions parse into the same [`AlertTrigger`] as the JSON format, and
+//! [`AlertTrigger::to_expression`] formats a trigger back into one.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+use super::*;
+use crate::validate::suggest;
+
+const REDUCERS: [&str; 9] = [
+    "min", "max", "mean", "sum", "count", "median", "stddev", "p90", "gap",
+];
+const MAPPERS: [&str; 6] = [
+    "pricePerUnit",
+    "pricePerUnitWithTax",
+    "quantity",
+    "total",
+    "age",
+    "pricePerUnitLessMateria",
+];
+const FILTERS: [&str; 11] = [
+    "hq",
+    "nq",
+    "newerThan",
+    "quantity",
+    "pricePerUnit",
+    "retainerName",
+    "creatorName",
+    "notCreatorName",
+    "materiaCount",
+    "onMannequin",
+    "notOnMannequin",
+];
+const BASELINES: [&str; 6] = [
+    "7d_avg_sale_price",
+    "vendor_price",
+    "global_min",
+    "dc_min",
+    "avg_sale_price",
+    "sale_velocity",
+];
+
+/// Why an expression couldn't be parsed, and where in it the problem is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpressionError {
+    /// The byte range of the expression that the error refers to.
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+impl ExpressionError {
+    fn new(span: Range<usize>, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+
+    /// Renders the error under the expression, with the problem underlined.
+    pub fn render(&self, expression: &str) -> String {
+        let start = expression[..self.span.start].chars().count();
+        let width = expression[self.span.clone()].chars().count().max(1);
+        format!(
+            "{}\n{}{} {}",
+            expression,
+            " ".repeat(start),
+            "^".repeat(width),
+            self.message
+        )
+    }
+}
+
+impl Display for ExpressionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        f.write_fmt(format_args!(
+            "{} (at column {})",
+            self.message,
+            self.span.start + 1
+        ))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    Number(f32),
+    /// Text in double quotes, which can't contain any.
+    Text(&'a str),
+    LeftParen,
+    RightParen,
+    LessThan,
+    GreaterThan,
+    Star,
+    Percent,
+    End,
+}
+
+impl Display for Token<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Self::Word(word) => f.write_fmt(format_args!("'{}'", word)),
+            Self::Number(number) => f.write_fmt(format_args!("{}", number)),
+            Self::Text(text) => f.write_fmt(format_args!("\"{}\"", text)),
+            Self::LeftParen => f.write_str("'('"),
+            Self::RightParen => f.write_str("')'"),
+            Self::LessThan => f.write_str("'<'"),
+            Self::GreaterThan => f.write_str("'>'"),
+            Self::Star => f.write_str("'*'"),
+            Self::Percent => f.write_str("'%'"),
+            Self::End => f.write_str("end of expression"),
+        }
+    }
+}
+
+fn tokenize(
+    expression: &str,
+) -> std::result::Result<Vec<(Token<'_>, Range<usize>)>, ExpressionError> {
+    let is_word_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+    let mut tokens = Vec::new();
+    let mut chars = expression.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            '<' => Token::LessThan,
+            '>' => Token::GreaterThan,
+            '*' => Token::Star,
+            '%' => Token::Percent,
+            '"' => {
+                let text_start = start + 1;
+                let end = match expression[text_start..].find('"') {
+                    Some(length) => text_start + length,
+                    None => {
+                        return Err(ExpressionError::new(
+                            start..expression.len(),
+                            "unterminated text, expected a closing '\"'",
+                        ))
+                    }
+                };
+                // Skip over the text and its closing quote
+                while chars.next_if(|(i, _)| *i <= end).is_some() {}
+                tokens.push((Token::Text(&expression[text_start..end]), start..end + 1));
+                continue;
+            }
+            c if is_word_char(c) || c == '-' => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|(_, c)| is_word_char(*c)) {
+                    end = i + c.len_utf8();
+                }
+                let text = &expression[start..end];
+                // Baselines like 7d_avg_sale_price start with a digit, so
+                // anything that isn't entirely a number is a word
+                let is_number = text
+                    .trim_start_matches('-')
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || c == '.');
+                let token = if is_number {
+                    Token::Number(text.parse().map_err(|_| {
+                        ExpressionError::new(start..end, format!("invalid number '{}'", text))
+                    })?)
+                } else if c == '-' {
+                    return Err(ExpressionError::new(start..end, "unexpected '-'"));
+                } else {
+                    Token::Word(text)
+                };
+                tokens.push((token, start..end));
+                continue;
+            }
+            c => {
+                return Err(ExpressionError::new(
+                    start..start + c.len_utf8(),
+                    format!("unexpected character '{}'", c),
+                ))
+            }
+        };
+        tokens.push((token, start..start + c.len_utf8()));
+    }
+    tokens.push((Token::End, expression.len()..expression.len()));
+    Ok(tokens)
+}
+
+/// A range filter's lower and upper bounds, and where they were written.
+type BoundsSpan = (Option<u32>, Option<u32>, Range<usize>);
+
+struct Parser<'a> {
+    tokens: Vec<(Token<'a>, Range<usize>)>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &(Token<'a>, Range<usize>) {
+        &self.tokens[self.position]
+    }
+
+    fn next(&mut self) -> (Token<'a>, Range<usize>) {
+        let token = self.tokens[self.position].clone();
+        if token.0 != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn unexpected(&self, expected: &str) -> ExpressionError {
+        let (token, span) = self.peek();
+        ExpressionError::new(
+            span.clone(),
+            format!("expected {}, found {}", expected, token),
+        )
+    }
+
+    fn expect(&mut self, expected: Token<'a>) -> std::result::Result<(), ExpressionError> {
+        if self.peek().0 == expected {
+            self.next();
+            Ok(())
+        } else {
+            Err(self.unexpected(&expected.to_string()))
+        }
+    }
+
+    fn accept_word(&mut self, word: &str) -> bool {
+        if self.peek().0 == Token::Word(word) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn word(
+        &mut self,
+        kind: &str,
+    ) -> std::result::Result<(&'a str, Range<usize>), ExpressionError> {
+        match self.peek().clone() {
+            (Token::Word(word), span) => {
+                self.next();
+                Ok((word, span))
+            }
+            _ => Err(self.unexpected(kind)),
+        }
+    }
+
+    fn number(&mut self, kind: &str) -> std::result::Result<(f32, Range<usize>), ExpressionError> {
+        match self.peek().clone() {
+            (Token::Number(number), span) => {
+                self.next();
+                Ok((number, span))
+            }
+            _ => Err(self.unexpected(kind)),
+        }
+    }
+
+    fn count(&mut self, kind: &str) -> std::result::Result<u32, ExpressionError> {
+        let (number, span) = self.number(kind)?;
+        if number < 0.0 || number.fract() != 0.0 || number > u32::MAX as f32 {
+            return Err(ExpressionError::new(
+                span,
+                format!("{} must be a whole number", kind),
+            ));
+        }
+        Ok(number as u32)
+    }
+
+    fn trigger(&mut self) -> std::result::Result<AlertTrigger, ExpressionError> {
+        let stat = self.stat()?;
+        let comparison = self.comparison()?;
+        if self.peek().0 != Token::End {
+            return Err(self.unexpected("end of expression"));
+        }
+        Ok(AlertTrigger {
+            filters: stat.filters,
+            filter_mode: stat.filter_mode,
+            mapper: stat.mapper,
+            take: stat.take,
+            reducer: stat.reducer,
+            comparison,
+            schedule: None,
+            unknown_fields: HashMap::new(),
+        })
+    }
+
+    /// Parses the stages of a trigger before its comparison, which are
+    /// written the same way when a stat of the event is compared against.
+    fn stat(&mut self) -> std::result::Result<EventStat, ExpressionError> {
+        let reducer = self.reducer()?;
+        self.expect(Token::LeftParen)?;
+        let mapper = self.mapper()?;
+        let (filters, filter_mode) = if self.accept_word("where") {
+            self.filters()?
+        } else {
+            (Vec::new(), FilterMode::All)
+        };
+        let take = if self.accept_word("take") {
+            Some(self.count("number of values to take")? as usize)
+        } else {
+            None
+        };
+        self.expect(Token::RightParen)?;
+        Ok(EventStat {
+            filters,
+            filter_mode,
+            mapper,
+            take,
+            reducer,
+        })
+    }
+
+    fn reducer(&mut self) -> std::result::Result<TriggerReducer, ExpressionError> {
+        let (word, span) = self.word("reducer")?;
+        match word {
+            "min" => Ok(TriggerReducer::Min),
+            "max" => Ok(TriggerReducer::Max),
+            "mean" => Ok(TriggerReducer::Mean),
+            "sum" => Ok(TriggerReducer::Sum),
+            "count" => Ok(TriggerReducer::Count),
+            "median" => Ok(TriggerReducer::Median),
+            "stddev" => Ok(TriggerReducer::StdDev),
+            "gap" => Ok(TriggerReducer::Gap),
+            // Percentiles are written as e.g. p90
+            _ if word.len() > 1
+                && word.starts_with('p')
+                && word[1..].chars().all(|c| c.is_ascii_digit()) =>
+            {
+                word[1..]
+                    .parse::<u8>()
+                    .map_err(|err| err.to_string())
+                    .and_then(PercentileRank::try_from)
+                    .map(TriggerReducer::Percentile)
+                    .map_err(|_| {
+                        ExpressionError::new(span, "percentile must be between p0 and p100")
+                    })
+            }
+            _ => Err(unknown(span, "reducer", word, &REDUCERS)),
+        }
+    }
+
+    fn mapper(&mut self) -> std::result::Result<TriggerMapper, ExpressionError> {
+        let (word, span) = self.word("mapper")?;
+        match word {
+            "pricePerUnit" => Ok(TriggerMapper::UnitPrice),
+            "pricePerUnitWithTax" => Ok(TriggerMapper::UnitPriceWithTax),
+            "quantity" => Ok(TriggerMapper::Quantity),
+            "total" => Ok(TriggerMapper::Total),
+            "age" => Ok(TriggerMapper::Age),
+            "pricePerUnitLessMateria" => Ok(TriggerMapper::UnitPriceLessMateria),
+            _ => Err(unknown(span, "mapper", word, &MAPPERS)),
+        }
+    }
+
+    fn filters(
+        &mut self,
+    ) -> std::result::Result<(Vec<TriggerFilter>, FilterMode), ExpressionError> {
+        let mut filters = vec![self.filter()?];
+        let mut mode = None;
+        loop {
+            let next_mode = match self.peek() {
+                (Token::Word("and"), _) => FilterMode::All,
+                (Token::Word("or"), _) => FilterMode::Any,
+                _ => break,
+            };
+            let (_, span) = self.next();
+            // Without grouping, mixing the two would be ambiguous
+            if mode.is_some_and(|mode| mode != next_mode) {
+                return Err(ExpressionError::new(
+                    span,
+                    "filters can be combined with 'and' or 'or', but not both",
+                ));
+            }
+            mode = Some(next_mode);
+            filters.push(self.filter()?);
+        }
+        Ok((filters, mode.unwrap_or_default()))
+    }
+
+    fn filter(&mut self) -> std::result::Result<TriggerFilter, ExpressionError> {
+        let (word, span) = self.word("filter")?;
+        match word {
+            "hq" => Ok(TriggerFilter::Hq),
+            "nq" => Ok(TriggerFilter::Nq),
+            "onMannequin" => Ok(TriggerFilter::OnMannequin),
+            "notOnMannequin" => Ok(TriggerFilter::NotOnMannequin),
+            "newerThan" => {
+                self.expect(Token::LeftParen)?;
+                let minutes = self.count("number of minutes")?;
+                self.expect(Token::RightParen)?;
+                Ok(TriggerFilter::NewerThan { minutes })
+            }
+            "quantity" => {
+                let (min, max, span) = self.bounds("quantity")?;
+                QuantityRange::try_from(RawBounds { min, max })
+                    .map(TriggerFilter::Quantity)
+                    .map_err(|message| ExpressionError::new(span, message))
+            }
+            "pricePerUnit" => {
+                let (min, max, span) = self.bounds("price")?;
+                PriceRange::try_from(RawBounds { min, max })
+                    .map(TriggerFilter::UnitPrice)
+                    .map_err(|message| ExpressionError::new(span, message))
+            }
+            "retainerName" => Ok(TriggerFilter::RetainerName(self.name_match("retainer")?)),
+            "creatorName" => Ok(TriggerFilter::CreatorName(self.name_match("crafter")?)),
+            "notCreatorName" => Ok(TriggerFilter::NotCreatorName(self.name_match("crafter")?)),
+            "materiaCount" => {
+                // Written like quantity, so that a max can be added later
+                self.expect(Token::LeftParen)?;
+                if !self.accept_word("min") {
+                    return Err(self.unexpected("'min'"));
+                }
+                let min = self.count("number of materia")?;
+                self.expect(Token::RightParen)?;
+                Ok(TriggerFilter::MateriaCount { min })
+            }
+            _ => Err(unknown(span, "filter", word, &FILTERS)),
+        }
+    }
+
+    /// Parses a name filter's name in parentheses: an exact name, or part of
+    /// one after 'contains'.
+    fn name_match(&mut self, kind: &str) -> std::result::Result<NameMatch, ExpressionError> {
+        self.expect(Token::LeftParen)?;
+        let contains = self.accept_word("contains");
+        let (name, span) = match self.peek().clone() {
+            (Token::Text(name), span) => {
+                self.next();
+                (name, span)
+            }
+            _ => return Err(self.unexpected(&format!("a {} name in double quotes", kind))),
+        };
+        if name.trim().is_empty() {
+            return Err(ExpressionError::new(
+                span,
+                format!("{} name must not be empty", kind),
+            ));
+        }
+        self.expect(Token::RightParen)?;
+        let name = name.to_lowercase();
+        Ok(if contains {
+            NameMatch::Contains(name)
+        } else {
+            NameMatch::Equals(name)
+        })
+    }
+
+    /// Parses a range filter's bounds in parentheses, either of which may be
+    /// left out, e.g. `(min 99)`. Returns them with the span between the
+    /// parentheses.
+    fn bounds(&mut self, kind: &str) -> std::result::Result<BoundsSpan, ExpressionError> {
+        self.expect(Token::LeftParen)?;
+        let start = self.peek().1.start;
+        let min = if self.accept_word("min") {
+            Some(self.count(&format!("minimum {}", kind))?)
+        } else {
+            None
+        };
+        let max = if self.accept_word("max") {
+            Some(self.count(&format!("maximum {}", kind))?)
+        } else {
+            None
+        };
+        if min.is_none() && max.is_none() {
+            return Err(self.unexpected("'min' or 'max'"));
+        }
+        let end = self.peek().1.start;
+        self.expect(Token::RightParen)?;
+        Ok((min, max, start..end))
+    }
+
+    fn comparison(&mut self) -> std::result::Result<Comparison, ExpressionError> {
+        // Comparisons relative to the event's own listings read as words,
+        // e.g. "in bottom 5%" or "below rest by 10%"
+        if self.accept_word("in") {
+            self.expect(Token::Word("bottom"))?;
+            let percent = self.percent()?;
+            return Ok(Comparison::BottomPercent { percent });
+        }
+        if self.accept_word("below") {
+            self.expect(Token::Word("rest"))?;
+            self.expect(Token::Word("by"))?;
+            let percent = self.percent()?;
+            return Ok(Comparison::BelowRest { percent });
+        }
+
+        let less_than = match self.peek().0 {
+            Token::LessThan => true,
+            Token::GreaterThan => false,
+            _ => return Err(self.unexpected("'<', '>', 'in bottom', or 'below rest by'")),
+        };
+        self.next();
+        let target = self.target()?;
+        Ok(if less_than {
+            Comparison::LessThan { target }
+        } else {
+            Comparison::GreaterThan { target }
+        })
+    }
+
+    fn percent(&mut self) -> std::result::Result<f32, ExpressionError> {
+        let (percent, span) = self.number("percentage")?;
+        self.expect(Token::Percent)?;
+        if !(0.0..=100.0).contains(&percent) {
+            return Err(ExpressionError::new(
+                span,
+                "percentage must be between 0 and 100",
+            ));
+        }
+        Ok(percent)
+    }
+
+    fn target(&mut self) -> std::result::Result<ComparisonTarget, ExpressionError> {
+        match self.peek().clone() {
+            (Token::Number(number), _) => {
+                self.next();
+                if self.peek().0 == Token::Star {
+                    self.next();
+                    self.scaled_target(number)
+                } else {
+                    Ok(ComparisonTarget::Constant(number))
+                }
+            }
+            (Token::Word("reference"), _) => {
+                self.next();
+                Ok(ComparisonTarget::Named(NamedTarget::Reference))
+            }
+            (Token::Word(_), _) => self.scaled_target(default_multiplier()),
+            _ => Err(self.unexpected("a number, 'reference', a baseline, or a stat")),
+        }
+    }
+
+    /// Parses a baseline or a stat of the event after its multiplier, if
+    /// any. Stats are told apart by the parenthesis after their reducer.
+    fn scaled_target(
+        &mut self,
+        multiplier: f32,
+    ) -> std::result::Result<ComparisonTarget, ExpressionError> {
+        let is_stat = self
+            .tokens
+            .get(self.position + 1)
+            .is_some_and(|(token, _)| *token == Token::LeftParen);
+        if is_stat {
+            Ok(ComparisonTarget::Stat {
+                stat: Box::new(self.stat()?),
+                multiplier,
+            })
+        } else {
+            Ok(ComparisonTarget::Baseline {
+                baseline: self.baseline()?,
+                multiplier,
+            })
+        }
+    }
+
+    fn baseline(&mut self) -> std::result::Result<Baseline, ExpressionError> {
+        let (word, span) = self.word("baseline")?;
+        match word {
+            "7d_avg_sale_price" => Ok(Baseline::SevenDayAverageSalePrice),
+            "vendor_price" => Ok(Baseline::VendorPrice),
+            "global_min" => Ok(Baseline::GlobalMin),
+            "dc_min" => Ok(Baseline::DataCenterMin),
+            "avg_sale_price" => Ok(Baseline::AverageSalePrice),
+            "sale_velocity" => Ok(Baseline::SaleVelocity),
+            // Stats need their listings in parentheses
+            _ if REDUCERS.contains(&word) => Err(ExpressionError::new(
+                span,
+                format!("expected '(' after '{}' to compare against a stat", word),
+            )),
+            _ => Err(unknown(span, "baseline", word, &BASELINES)),
+        }
+    }
+}
+
+fn unknown(span: Range<usize>, kind: &str, word: &str, expected: &[&str]) -> ExpressionError {
+    let message = match suggest(word, expected) {
+        Some(suggestion) => format!(
+            "unknown {} '{}', did you mean '{}'?",
+            kind, word, suggestion
+        ),
+        None => format!(
+            "unknown {} '{}', expected one of: {}",
+            kind,
+            word,
+            expected.join(", ")
+        ),
+    };
+    ExpressionError::new(span, message)
+}
+
+/// Formats a range filter's bounds, e.g. `min 10 max 20`.
+fn format_bounds(min: Option<u32>, max: Option<u32>) -> String {
+    min.map(|min| format!("min {}", min))
+        .into_iter()
+        .chain(max.map(|max| format!("max {}", max)))
+        .join(" ")
+}
+
+/// Formats a name filter's name, e.g. `contains "kupo"`.
+fn format_name(name: &NameMatch) -> String {
+    match name {
+        NameMatch::Equals(name) => format!("\"{}\"", name),
+        NameMatch::Contains(name) => format!("contains \"{}\"", name),
+    }
+}
+
+/// Parses a trigger expression.
+pub fn parse_expression(expression: &str) -> std::result::Result<AlertTrigger, ExpressionError> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        position: 0,
+    };
+    parser.trigger()
+}
+
+/// Returns whether any name filter's name has a quote in it, which can't be
+/// written inside text in expressions.
+fn has_quoted_name(filters: &[TriggerFilter]) -> bool {
+    filters.iter().any(|filter| match filter {
+        TriggerFilter::RetainerName(name)
+        | TriggerFilter::CreatorName(name)
+        | TriggerFilter::NotCreatorName(name) => name.name().contains('"'),
+        _ => false,
+    })
+}
+
+/// Formats the stages of a trigger or stat up to its comparison, e.g.
+/// `min(pricePerUnit where hq take 5)`.
+fn format_stages(
+    reducer: &TriggerReducer,
+    mapper: &TriggerMapper,
+    filters: &[TriggerFilter],
+    filter_mode: FilterMode,
+    take: Option<usize>,
+) -> String {
+    let mut expression = format!("{}({}", reducer.name(), mapper.name());
+    if !filters.is_empty() {
+        let separator = match filter_mode {
+            FilterMode::All => " and ",
+            FilterMode::Any => " or ",
+        };
+        let filters = filters
+            .iter()
+            .map(|filter| {
+                let arguments = match filter {
+                    TriggerFilter::NewerThan { minutes } => minutes.to_string(),
+                    TriggerFilter::Quantity(range) => format_bounds(range.min, range.max),
+                    TriggerFilter::UnitPrice(range) => format_bounds(range.min, range.max),
+                    TriggerFilter::RetainerName(name)
+                    | TriggerFilter::CreatorName(name)
+                    | TriggerFilter::NotCreatorName(name) => format_name(name),
+                    TriggerFilter::MateriaCount { min } => format!("min {}", min),
+                    TriggerFilter::Hq
+                    | TriggerFilter::Nq
+                    | TriggerFilter::OnMannequin
+                    | TriggerFilter::NotOnMannequin => return filter.name().to_owned(),
+                };
+                format!("{}({})", filter.name(), arguments)
+            })
+            .join(separator);
+        expression.push_str(&format!(" where {}", filters));
+    }
+    if let Some(take) = take {
+        expression.push_str(&format!(" take {}", take));
+    }
+    expression.push(')');
+    expression
+}
+
+impl AlertTrigger {
+    /// Formats the trigger as an expression that parses back into the same
+    /// trigger. Schedules have no expression form, so triggers with one
+    /// can only be written as JSON.
+    pub fn to_expression(&self) -> Option<String> {
+        if self.schedule.is_some() || has_quoted_name(&self.filters) {
+            return None;
+        }
+        let expression = format_stages(
+            &self.reducer,
+            &self.mapper,
+            &self.filters,
+            self.filter_mode,
+            self.take,
+        );
+
+        let (operator, target) = match &self.comparison {
+            Comparison::LessThan { target } => ("<", target),
+            Comparison::GreaterThan { target } => (">", target),
+            Comparison::BottomPercent { percent } => {
+                return Some(format!("{} in bottom {}%", expression, percent));
+            }
+            Comparison::BelowRest { percent } => {
+                return Some(format!("{} below rest by {}%", expression, percent));
+            }
+        };
+        let (multiplier, target) = match target {
+            ComparisonTarget::Constant(target) => (1.0, target.to_string()),
+            ComparisonTarget::Named(NamedTarget::Reference) => (1.0, "reference".to_owned()),
+            ComparisonTarget::Baseline {
+                baseline,
+                multiplier,
+            } => (*multiplier, baseline.name().to_owned()),
+            ComparisonTarget::Stat { stat, multiplier } => {
+                if has_quoted_name(&stat.filters) {
+                    return None;
+                }
+                let stat = format_stages(
+                    &stat.reducer,
+                    &stat.mapper,
+                    &stat.filters,
+                    stat.filter_mode,
+                    stat.take,
+                );
+                (*multiplier, stat)
+            }
+        };
+        Some(if multiplier == 1.0 {
+            format!("{} {} {}", expression, operator, target)
+        } else {
+            format!("{} {} {} * {}", expression, operator, multiplier, target)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::listings;
+    use super::*;
+
+    fn error(expression: &str) -> ExpressionError {
+        parse_expression(expression).unwrap_err()
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let cases = [
+            (
+                "mn(pricePerUnit) < 5",
+                0..2,
+                "unknown reducer 'mn', did you mean 'min'?",
+            ),
+            (
+                "min(price) < 5",
+                4..9,
+                "unknown mapper 'price', did you mean 'pricePerUnit'?",
+            ),
+            ("min(pricePerUnit < 5", 17..18, "expected ')', found '<'"),
+            (
+                "min(pricePerUnit) < 5 extra",
+                22..27,
+                "expected end of expression, found 'extra'",
+            ),
+            (
+                "min(pricePerUnit)",
+                17..17,
+                "expected '<', '>', 'in bottom', or 'below rest by', found end of expression",
+            ),
+            (
+                "min(pricePerUnit where hq and nq or hq) < 5",
+                33..35,
+                "filters can be combined with 'and' or 'or', but not both",
+            ),
+            (
+                "min(pricePerUnit where quantity(min 10 max 5)) < 5",
+                32..44,
+                "quantity filter's min (10) is greater than its max (5)",
+            ),
+            (
+                "min(pricePerUnit where quantity()) < 5",
+                32..33,
+                "expected 'min' or 'max', found ')'",
+            ),
+            (
+                "min(pricePerUnit where newerThan(1.5)) < 5",
+                33..36,
+                "number of minutes must be a whole number",
+            ),
+            (
+                "min(pricePerUnit where retainerName(\"kupo)) < 5",
+                36..47,
+                "unterminated text, expected a closing '\"'",
+            ),
+            (
+                "min(pricePerUnit where retainerName(\" \")) < 5",
+                36..39,
+                "retainer name must not be empty",
+            ),
+            (
+                "p101(pricePerUnit) < 5",
+                0..4,
+                "percentile must be between p0 and p100",
+            ),
+            (
+                "min(pricePerUnit) in bottom 150%",
+                28..31,
+                "percentage must be between 0 and 100",
+            ),
+            (
+                "min(pricePerUnit) < vendor_prce",
+                20..31,
+                "unknown baseline 'vendor_prce', did you mean 'vendor_price'?",
+            ),
+            (
+                "min(pricePerUnit) < 0.8 * mean",
+                26..30,
+                "expected '(' after 'mean' to compare against a stat",
+            ),
+            (
+                "min(pricePerUnit) < 0.8 * mean(pricePerUnit",
+                43..43,
+                "expected ')', found end of expression",
+            ),
+            ("min(pricePerUnit) < #", 20..21, "unexpected character '#'"),
+        ];
+        for (expression, span, message) in cases {
+            let error = error(expression);
+            assert_eq!(error.message, message, "{}", expression);
+            assert_eq!(error.span, span, "{}", expression);
+        }
+    }
+
+    #[test]
+    fn renders_errors_under_the_expression() {
+        let expression = "min(pricePerUnit) < vendor_prce";
+        assert_eq!(
+            error(expression).render(expression),
+            "min(pricePerUnit) < vendor_prce\n                    ^^^^^^^^^^^ unknown baseline 'vendor_prce', did you mean 'vendor_price'?"
+        );
+    }
+
+    #[test]
+    fn every_reducer_mapper_and_filter_round_trips() {
+        let reducers = [
+            "min", "max", "mean", "sum", "count", "median", "stddev", "p90", "gap",
+        ];
+        let filters = [
+            "hq",
+            "nq",
+            "newerThan(60)",
+            "quantity(min 99)",
+            "quantity(max 5)",
+            "quantity(min 1 max 5)",
+            "pricePerUnit(min 100 max 200)",
+            "retainerName(\"kupo\")",
+            "retainerName(contains \"kupo\")",
+            "creatorName(\"kupo nut\")",
+            "creatorName(contains \"kupo\")",
+            "notCreatorName(\"kupo nut\")",
+            "notCreatorName(contains \"kupo\")",
+            "materiaCount(min 5)",
+            "onMannequin",
+            "notOnMannequin",
+        ];
+        let mut expressions = Vec::new();
+        for reducer in reducers {
+            for mapper in MAPPERS {
+                expressions.push(format!("{}({}) < 1000", reducer, mapper));
+            }
+        }
+        for filter in filters {
+            expressions.push(format!("min(pricePerUnit where {}) < 1000", filter));
+            expressions.push(format!(
+                "min(pricePerUnit) < 0.8 * mean(pricePerUnit where {})",
+                filter
+            ));
+        }
+        expressions.extend(
+            [
+                "min(pricePerUnit where hq and newerThan(60)) < 0.8 * 7d_avg_sale_price",
+                "min(pricePerUnit where hq or onMannequin) > 2.5",
+                "mean(pricePerUnit take 5) < reference",
+                "max(quantity) > vendor_price",
+                "min(pricePerUnit) < global_min",
+                "min(pricePerUnit) < 1.5 * dc_min",
+                "min(pricePerUnit) < avg_sale_price",
+                "sum(quantity) > sale_velocity",
+                "min(pricePerUnit) in bottom 5%",
+                "min(pricePerUnit) below rest by 10%",
+                "min(pricePerUnit where hq) < 0.8 * mean(pricePerUnit)",
+                "median(total) > p90(total where nq or hq take 10)",
+            ]
+            .map(str::to_owned),
+        );
+
+        for expression in expressions {
+            let trigger = parse_expression(&expression).unwrap();
+            assert_eq!(
+                trigger.to_expression().as_deref(),
+                Some(expression.as_str())
+            );
+        }
+    }
+
+    #[test]
+    fn stats_parse_the_same_as_json() {
+        let expression = parse_expression(
+            "min(pricePerUnit where hq) < 0.8 * mean(pricePerUnit where nq take 3)",
+        )
+        .unwrap();
+        let json: AlertTrigger = serde_json::from_str(
+            r#"{
+                "filters": ["hq"],
+                "mapper": "pricePerUnit",
+                "reducer": "min",
+                "comparison": {"lt": {"target": {
+                    "stat": {"filters": ["nq"], "mapper": "pricePerUnit", "take": 3, "reducer": "mean"},
+                    "multiplier": 0.8
+                }}}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(json.to_expression(), expression.to_expression());
+    }
+
+    #[test]
+    fn compares_against_a_stat_of_the_event() {
+        let trigger =
+            parse_expression("min(pricePerUnit where hq) < 0.8 * mean(pricePerUnit)").unwrap();
+        let parameters = TriggerParameters::default();
+        let mut board = listings(&[1000, 1000, 1000], false);
+        board.extend(listings(&[700], true));
+        // The mean of every listing is 925, so the threshold is 740
+        assert_eq!(trigger.evaluate(&board, &parameters), Some(700.0));
+        assert_eq!(
+            trigger.evaluate_candidate(&board.iter().collect_vec(), &parameters),
+            Some(700.0)
+        );
+
+        // Now it's 950, so the threshold is 760
+        board[3].unit_price = 800;
+        assert_eq!(trigger.evaluate(&board, &parameters), None);
+        assert_eq!(
+            trigger.evaluate_candidate(&board.iter().collect_vec(), &parameters),
+            None
+        );
+    }
+}
@@ -0,0 +1,425 @@
+//! The text that triggers are described with in notifications, by language.
+//!
+//! Each language has a [`TriggerStrings`] table, picked by the language part
+//! of an alert's locale. Languages without a table are described in English,
+//! which is also what the `Display` impls of triggers produce.
+
+use super::*;
+
+/// The most characters of a trigger description that go in an embed. The
+/// whole embed description is capped at 4096 characters by Discord.
+const MAX_EMBED_DESCRIPTION_CHARS: usize = 2000;
+
+/// How triggers are described in one language.
+pub(super) struct TriggerStrings {
+    pub hq: &'static str,
+    pub nq: &'static str,
+    /// Takes the formatted duration.
+    pub newer_than: fn(&str) -> String,
+    /// Takes the lowest and highest stack sizes, either of which may be
+    /// unbounded.
+    pub quantity_range: fn(Option<u32>, Option<u32>) -> String,
+    /// Takes the lowest and highest unit prices, either of which may be
+    /// unbounded.
+    pub price_range: fn(Option<u32>, Option<u32>) -> String,
+    /// These take the retainer name, or the part of it.
+    pub retainer_name_equals: fn(&str) -> String,
+    pub retainer_name_contains: fn(&str) -> String,
+    /// These take the crafter's name, or the part of it.
+    pub creator_name_equals: fn(&str) -> String,
+    pub creator_name_contains: fn(&str) -> String,
+    pub not_creator_name_equals: fn(&str) -> String,
+    pub not_creator_name_contains: fn(&str) -> String,
+    /// Takes the fewest materia melded.
+    pub materia_count: fn(u32) -> String,
+    pub on_mannequin: &'static str,
+    pub not_on_mannequin: &'static str,
+
+    pub unit_price: &'static str,
+    pub unit_price_with_tax: &'static str,
+    pub quantity: &'static str,
+    pub total: &'static str,
+    pub age: &'static str,
+    pub unit_price_less_materia: &'static str,
+
+    pub min: &'static str,
+    pub max: &'static str,
+    pub mean: &'static str,
+    pub sum: &'static str,
+    pub count: &'static str,
+    pub median: &'static str,
+    pub stddev: &'static str,
+    pub percentile: fn(u8) -> String,
+    pub gap: &'static str,
+
+    pub seven_day_average_sale_price: &'static str,
+    pub vendor_price: &'static str,
+    pub global_min: &'static str,
+    pub data_center_min: &'static str,
+    pub average_sale_price: &'static str,
+    pub sale_velocity: &'static str,
+    pub reference_price: &'static str,
+    /// Takes the reducer and the field of a stat of the event's listings.
+    pub event_stat: fn(&str, &str) -> String,
+
+    /// These take the formatted target.
+    pub less_than: fn(&str) -> String,
+    pub greater_than: fn(&str) -> String,
+    /// These take the percentage.
+    pub bottom_percent: fn(f32) -> String,
+    pub below_rest: fn(f32) -> String,
+
+    pub every_day: &'static str,
+    /// From Sunday to Saturday.
+    pub weekdays: [&'static str; 7],
+
+    /// Goes before every filter but the first, when any filter may match.
+    pub or: &'static str,
+    pub field: &'static str,
+    pub take_lowest: fn(usize) -> String,
+    pub stat: &'static str,
+    pub comparison: &'static str,
+    pub schedule: &'static str,
+
+    /// Takes the reducer, the field, and the formatted value.
+    pub result: fn(&str, &str, &str) -> String,
+    /// Takes the formatted number of listings.
+    pub listings: fn(&str) -> String,
+}
+
+pub(super) const EN: TriggerStrings = TriggerStrings {
+    hq: "Item is HQ",
+    nq: "Item is NQ",
+    newer_than: |duration| format!("Listed within the last {}", duration),
+    quantity_range: |min, max| match (min, max) {
+        (Some(min), Some(max)) if min == max => format!("Quantity is {}", min),
+        (Some(min), Some(max)) => format!("Quantity is between {} and {}", min, max),
+        (Some(min), None) => format!("Quantity is at least {}", min),
+        (None, Some(max)) => format!("Quantity is at most {}", max),
+        (None, None) => "Any quantity".to_owned(),
+    },
+    price_range: |min, max| match (min, max) {
+        (Some(min), Some(max)) if min == max => format!("Unit price is {} gil", min),
+        (Some(min), Some(max)) => format!("Unit price is between {} and {} gil", min, max),
+        (Some(min), None) => format!("Unit price is at least {} gil", min),
+        (None, Some(max)) => format!("Unit price is at most {} gil", max),
+        (None, None) => "Any unit price".to_owned(),
+    },
+    retainer_name_equals: |name| format!("Retainer is {}", name),
+    retainer_name_contains: |name| format!("Retainer name contains \"{}\"", name),
+    creator_name_equals: |name| format!("Crafted by {}", name),
+    creator_name_contains: |name| format!("Crafter name contains \"{}\"", name),
+    not_creator_name_equals: |name| format!("Not crafted by {}", name),
+    not_creator_name_contains: |name| format!("Crafter name doesn't contain \"{}\"", name),
+    materia_count: |min| format!("At least {} materia melded", min),
+    on_mannequin: "Listed on a mannequin",
+    not_on_mannequin: "Not listed on a mannequin",
+
+    unit_price: "Unit price",
+    unit_price_with_tax: "Unit price with tax",
+    quantity: "Quantity",
+    total: "Total",
+    age: "Listing age",
+    unit_price_less_materia: "Unit price less materia",
+
+    min: "Min",
+    max: "Max",
+    mean: "Mean",
+    sum: "Sum",
+    count: "Count",
+    median: "Median",
+    stddev: "Std. dev.",
+    percentile: |rank| {
+        let suffix = match (rank % 10, rank % 100) {
+            (_, 11..=13) => "th",
+            (1, _) => "st",
+            (2, _) => "nd",
+            (3, _) => "rd",
+            _ => "th",
+        };
+        format!("{}{} percentile", rank, suffix)
+    },
+    gap: "Gap to second lowest",
+
+    seven_day_average_sale_price: "7-day average sale price",
+    vendor_price: "vendor price",
+    global_min: "lowest price in region",
+    data_center_min: "lowest price in data center",
+    average_sale_price: "average sale price",
+    sale_velocity: "daily sale velocity",
+    reference_price: "reference price",
+    event_stat: |reducer, field| {
+        format!(
+            "{} {} of the listings",
+            reducer.to_lowercase(),
+            field.to_lowercase()
+        )
+    },
+
+    less_than: |target| format!("Less than {}", target),
+    greater_than: |target| format!("Greater than {}", target),
+    bottom_percent: |percent| format!("In the bottom {}% of listings", percent),
+    below_rest: |percent| format!("At least {}% below the rest of the listings", percent),
+
+    every_day: "Every day",
+    weekdays: ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"],
+
+    or: "OR ",
+    field: "Field",
+    take_lowest: |k| format!("Take: lowest {}", k),
+    stat: "Stat",
+    comparison: "Comparison",
+    schedule: "Schedule",
+
+    result: |reducer, field, value| format!("{} {}: {}", reducer, field.to_lowercase(), value),
+    listings: |count| format!("Listings: {}", count),
+};
+
+/// The languages that triggers can be described in, by ISO 639-1 code.
+const LANGUAGES: [(&str, &TriggerStrings); 1] = [("en", &EN)];
+
+/// Returns the strings for a locale, falling back to English.
+pub(super) fn trigger_strings(locale: &str) -> &'static TriggerStrings {
+    // Only the language part of the locale matters here
+    let language = locale.split(['-', '_']).next().unwrap_or("");
+    LANGUAGES
+        .iter()
+        .find(|(code, _)| code.eq_ignore_ascii_case(language))
+        .map_or(&EN, |(_, strings)| strings)
+}
+
+/// Makes text safe to put in a code block: backticks can't close the block
+/// early, and overly long text is cut short.
+pub fn escape_code_block(text: &str, max_chars: usize) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for (i, c) in text.chars().enumerate() {
+        if i == max_chars {
+            escaped.push('\u{2026}');
+            break;
+        }
+        escaped.push(c);
+        // A zero-width space keeps runs of backticks from forming a fence
+        if c == '`' {
+            escaped.push('\u{200B}');
+        }
+    }
+    escaped
+}
+
+impl TriggerFilter {
+    pub(super) fn describe(&self, strings: &TriggerStrings) -> String {
+        match self {
+            Self::Hq => strings.hq.to_owned(),
+            Self::Nq => strings.nq.to_owned(),
+            Self::NewerThan { minutes } => {
+                (strings.newer_than)(&format_duration_minutes(*minutes as f32))
+            }
+            Self::Quantity(range) => (strings.quantity_range)(range.min, range.max),
+            Self::UnitPrice(range) => (strings.price_range)(range.min, range.max),
+            Self::RetainerName(NameMatch::Equals(name)) => (strings.retainer_name_equals)(name),
+            Self::RetainerName(NameMatch::Contains(name)) => (strings.retainer_name_contains)(name),
+            Self::CreatorName(NameMatch::Equals(name)) => (strings.creator_name_equals)(name),
+            Self::CreatorName(NameMatch::Contains(name)) => (strings.creator_name_contains)(name),
+            Self::NotCreatorName(NameMatch::Equals(name)) => {
+                (strings.not_creator_name_equals)(name)
+            }
+            Self::NotCreatorName(NameMatch::Contains(name)) => {
+                (strings.not_creator_name_contains)(name)
+            }
+            Self::MateriaCount { min } => (strings.materia_count)(*min),
+            Self::OnMannequin => strings.on_mannequin.to_owned(),
+            Self::NotOnMannequin => strings.not_on_mannequin.to_owned(),
+        }
+    }
+}
+
+impl TriggerMapper {
+    pub(super) fn describe(&self, strings: &TriggerStrings) -> &'static str {
+        match self {
+            Self::UnitPrice => strings.unit_price,
+            Self::UnitPriceWithTax => strings.unit_price_with_tax,
+            Self::Quantity => strings.quantity,
+            Self::Total => strings.total,
+            Self::Age => strings.age,
+            Self::UnitPriceLessMateria => strings.unit_price_less_materia,
+        }
+    }
+}
+
+impl TriggerReducer {
+    pub(super) fn describe(&self, strings: &TriggerStrings) -> String {
+        match self {
+            Self::Min => strings.min.to_owned(),
+            Self::Max => strings.max.to_owned(),
+            Self::Mean => strings.mean.to_owned(),
+            Self::Sum => strings.sum.to_owned(),
+            Self::Count => strings.count.to_owned(),
+            Self::Median => strings.median.to_owned(),
+            Self::StdDev => strings.stddev.to_owned(),
+            Self::Percentile(rank) => (strings.percentile)(rank.0),
+            Self::Gap => strings.gap.to_owned(),
+        }
+    }
+}
+
+impl Baseline {
+    pub(super) fn describe(&self, strings: &TriggerStrings) -> &'static str {
+        match self {
+            Self::SevenDayAverageSalePrice => strings.seven_day_average_sale_price,
+            Self::VendorPrice => strings.vendor_price,
+            Self::GlobalMin => strings.global_min,
+            Self::DataCenterMin => strings.data_center_min,
+            Self::AverageSalePrice => strings.average_sale_price,
+            Self::SaleVelocity => strings.sale_velocity,
+        }
+    }
+}
+
+impl ComparisonTarget {
+    pub(super) fn describe(&self, strings: &TriggerStrings) -> String {
+        match self {
+            Self::Constant(target) => target.to_string(),
+            Self::Named(NamedTarget::Reference) => strings.reference_price.to_owned(),
+            Self::Baseline {
+                baseline,
+                multiplier,
+            } if *multiplier == 1.0 => baseline.describe(strings).to_owned(),
+            Self::Baseline {
+                baseline,
+                multiplier,
+            } => format!("{} * {}", multiplier, baseline.describe(strings)),
+            Self::Stat { stat, multiplier } if *multiplier == 1.0 => stat.describe(strings),
+            Self::Stat { stat, multiplier } => {
+                format!("{} * {}", multiplier, stat.describe(strings))
+            }
+        }
+    }
+}
+
+impl EventStat {
+    pub(super) fn describe(&self, strings: &TriggerStrings) -> String {
+        let stat = (strings.event_stat)(
+            &self.reducer.describe(strings),
+            self.mapper.describe(strings),
+        );
+        let separator = match self.filter_mode {
+            FilterMode::All => "; ".to_owned(),
+            FilterMode::Any => format!("; {}", strings.or),
+        };
+        let filters = self.filters.iter().map(|filter| filter.describe(strings));
+        let mut conditions = Itertools::intersperse(filters, separator).collect::<String>();
+        if let Some(k) = self.take {
+            if !conditions.is_empty() {
+                conditions.push_str("; ");
+            }
+            conditions.push_str(&(strings.take_lowest)(k));
+        }
+        if conditions.is_empty() {
+            stat
+        } else {
+            format!("{} ({})", stat, conditions)
+        }
+    }
+}
+
+impl Comparison {
+    pub(super) fn describe(&self, strings: &TriggerStrings) -> String {
+        match self {
+            Self::LessThan { target } => (strings.less_than)(&target.describe(strings)),
+            Self::GreaterThan { target } => (strings.greater_than)(&target.describe(strings)),
+            Self::BottomPercent { percent } => (strings.bottom_percent)(*percent),
+            Self::BelowRest { percent } => (strings.below_rest)(*percent),
+        }
+    }
+}
+
+impl Schedule {
+    pub(super) fn describe(&self, strings: &TriggerStrings) -> String {
+        let days = if self.days.is_empty() {
+            strings.every_day.to_owned()
+        } else {
+            self.days
+                .iter()
+                .map(|day| strings.weekdays[*day as usize])
+                .join(", ")
+        };
+        let window = match (self.start, self.end) {
+            (None, None) => String::new(),
+            (start, end) => format!(
+                " {}-{}",
+                start.unwrap_or(TimeOfDay { minutes: 0 }),
+                end.map(|e| e.to_string())
+                    .unwrap_or_else(|| "24:00".to_owned())
+            ),
+        };
+        let zone = match self.utc_offset_minutes {
+            0 => "UTC".to_owned(),
+            offset => format!(
+                "UTC{}{:02}:{:02}",
+                if offset < 0 { '-' } else { '+' },
+                offset.abs() / 60,
+                offset.abs() % 60
+            ),
+        };
+        format!("{}{} ({})", days, window, zone)
+    }
+}
+
+impl AlertTrigger {
+    pub(super) fn describe_with(&self, strings: &TriggerStrings) -> String {
+        let formatted_filters = self.filters.iter().map(|filter| filter.describe(strings));
+        let separator = match self.filter_mode {
+            FilterMode::All => "\n".to_owned(),
+            FilterMode::Any => format!("\n{}", strings.or),
+        };
+        let formatted_filters =
+            Itertools::intersperse(formatted_filters, separator).collect::<String>();
+        let formatted_take = self
+            .take
+            .map(|k| format!("\n{}", (strings.take_lowest)(k)))
+            .unwrap_or_default();
+        let formatted_schedule = self
+            .schedule
+            .as_ref()
+            .map(|s| format!("\n{}: {}", strings.schedule, s.describe(strings)))
+            .unwrap_or_default();
+        format!(
+            "{}\n\n{}: {}{}\n{}: {}\n{}: {}{}",
+            formatted_filters,
+            strings.field,
+            self.mapper.describe(strings),
+            formatted_take,
+            strings.stat,
+            self.reducer.describe(strings),
+            strings.comparison,
+            self.comparison.describe(strings),
+            formatted_schedule
+        )
+    }
+
+    /// Describes the stages of this trigger in the language of a locale,
+    /// e.g. for notifications or custom templates.
+    pub fn describe(&self, locale: &str) -> String {
+        self.describe_with(trigger_strings(locale))
+    }
+
+    /// Describes this trigger for the code block in a notification embed.
+    pub fn describe_for_embed(&self, locale: &str) -> String {
+        escape_code_block(&self.describe(locale), MAX_EMBED_DESCRIPTION_CHARS)
+    }
+
+    /// Renders an evaluation result of this trigger in the language of a
+    /// locale, e.g. "Mean unit price: 1,017 gil".
+    pub fn format_result(&self, value: f32, locale: &str) -> String {
+        let strings = trigger_strings(locale);
+        let value = format_value(value, self.value_kind(), locale);
+        if let TriggerReducer::Count = self.reducer {
+            return (strings.listings)(&value);
+        }
+        (strings.result)(
+            &self.reducer.describe(strings),
+            self.mapper.describe(strings),
+            &value,
+        )
+    }
+}
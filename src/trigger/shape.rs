@@ -0,0 +1,107 @@
+//! The outline of a trigger, with its thresholds coarsened into ranges, for
+//! aggregating how triggers are written without keeping any one of them.
+
+use super::*;
+
+/// What a trigger looks like, without its exact thresholds. Stages are named
+/// the way they're written in the trigger format.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TriggerShape {
+    /// The kinds of filters, e.g. `hq,newerThan`, or `hq|newerThan` if any
+    /// of them may match. Empty if there are none.
+    pub filters: String,
+    pub mapper: &'static str,
+    /// Whether only the lowest values are reduced.
+    pub take: bool,
+    pub reducer: String,
+    pub comparison: &'static str,
+    /// What the value is compared against: `constant`, `reference`, a
+    /// baseline, or `event` for comparisons against the rest of the listings.
+    pub target: &'static str,
+    /// The range the threshold falls in, e.g. `1k-10k` for a constant,
+    /// `x0.8` for a baseline multiplier, or `10%` for a percentage.
+    pub threshold_range: Option<String>,
+    pub scheduled: bool,
+}
+
+/// Formats a power of ten compactly, e.g. 10000 as "10k".
+fn format_magnitude(value: f64) -> String {
+    match value {
+        v if v >= 1e9 => format!("{}B", v / 1e9),
+        v if v >= 1e6 => format!("{}M", v / 1e6),
+        v if v >= 1e3 => format!("{}k", v / 1e3),
+        v => v.to_string(),
+    }
+}
+
+/// Buckets a constant threshold by its order of magnitude.
+fn constant_range(target: f32) -> String {
+    if target < 0.0 {
+        return "<0".to_owned();
+    }
+    if target < 1.0 {
+        return "0-1".to_owned();
+    }
+    let lower = 10f64.powi((target as f64).log10().floor() as i32);
+    format!(
+        "{}-{}",
+        format_magnitude(lower),
+        format_magnitude(lower * 10.0)
+    )
+}
+
+impl AlertTrigger {
+    /// Returns the shape of this trigger in its canonical form, so that
+    /// triggers that mean the same thing have the same shape.
+    pub fn shape(&self) -> TriggerShape {
+        let canonical = self.canonicalize();
+        let separator = match canonical.filter_mode {
+            FilterMode::All => ",",
+            FilterMode::Any => "|",
+        };
+        let filters = canonical
+            .filters
+            .iter()
+            // Only the kind of name filter is kept, never the name
+            .map(TriggerFilter::name)
+            .join(separator);
+        let mapper = canonical.mapper.name();
+        let reducer = canonical.reducer.name();
+        let comparison = canonical.comparison.name();
+        let percent = match &canonical.comparison {
+            Comparison::BottomPercent { percent } | Comparison::BelowRest { percent } => {
+                Some(*percent)
+            }
+            Comparison::LessThan { .. } | Comparison::GreaterThan { .. } => None,
+        };
+        let (target, threshold_range) = match canonical.comparison.target() {
+            Some(ComparisonTarget::Constant(target)) => ("constant", Some(constant_range(*target))),
+            Some(ComparisonTarget::Named(NamedTarget::Reference)) => ("reference", None),
+            Some(ComparisonTarget::Stat { .. }) => ("stat", None),
+            Some(ComparisonTarget::Baseline {
+                baseline,
+                multiplier,
+            }) => {
+                let target = baseline.name();
+                // Multipliers are rounded to the nearest tenth
+                let multiplier = (multiplier * 10.0).round() / 10.0 + 0.0;
+                (target, Some(format!("x{}", multiplier)))
+            }
+            // Percentages are rounded to the nearest 5%
+            None => (
+                "event",
+                percent.map(|p| format!("{}%", (p / 5.0).round() * 5.0 + 0.0)),
+            ),
+        };
+        TriggerShape {
+            filters,
+            mapper,
+            take: canonical.take.is_some(),
+            reducer,
+            comparison,
+            target,
+            threshold_range,
+            scheduled: canonical.schedule.is_some(),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::time::Duration;
+
+use crate::alerts::{MAX_TRIGGER_VERSION, MIN_TRIGGER_VERSION};
+use crate::config::env_or;
+use crate::errors::*;
+use crate::trigger::*;
+use crate::universalis::unix_now;
+use crate::validate::*;
+use crate::xivapi::*;
+use mysql_async::{params, prelude::*, Pool, TxOpts};
+
+/// The item category recorded for wildcard alerts, which watch every item.
+const WILDCARD_CATEGORY: i32 = -1;
+
+#[derive(Default)]
+struct ShapeCount {
+    alerts: u64,
+    users: HashSet<String>,
+}
+
+/// An opt-in job that counts how triggers are written for each item category
+/// (which mappers, reducers, comparisons and threshold ranges are used), to
+/// help design default templates. Only the item IDs, triggers and user IDs of
+/// alerts are read, and user IDs are only used to count each shape's users;
+/// nothing but [`TriggerShape`]s and counts is stored. Shapes used by too few
+/// users are left out, since they could point to someone in particular.
+pub struct TriggerAnalytics {
+    pub interval: Duration,
+    min_users: usize,
+}
+
+impl TriggerAnalytics {
+    /// Returns the job if `UNIVERSALIS_ALERTS_TRIGGER_ANALYTICS` is `true`.
+    /// It runs every `UNIVERSALIS_ALERTS_TRIGGER_ANALYTICS_INTERVAL_SECS`
+    /// seconds (a day by default), recording shapes used by at least
+    /// `UNIVERSALIS_ALERTS_TRIGGER_ANALYTICS_MIN_USERS` users (5 by default).
+    pub fn from_env() -> Option<Self> {
+        let enabled = env::var("UNIVERSALIS_ALERTS_TRIGGER_ANALYTICS")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+        Some(Self {
+            interval: Duration::from_secs(
+                env_or(
+                    "UNIVERSALIS_ALERTS_TRIGGER_ANALYTICS_INTERVAL_SECS",
+                    86400u64,
+                )
+                .max(60),
+            ),
+            min_users: env_or("UNIVERSALIS_ALERTS_TRIGGER_ANALYTICS_MIN_USERS", 5usize).max(1),
+        })
+    }
+
+    /// Replaces the contents of `users_alerts_trigger_stats` with the current
+    /// shapes of every supported alert, returning how many rows were written.
+    pub async fn run(&self, pool: &Pool) -> Result<usize> {
+        let alerts: Vec<(Option<String>, i32, i32, String)> = {
+            let mut conn = pool.get_conn().await?;
+            r"SELECT `user_id`, `item_id`, `trigger_version`, `trigger` FROM `users_alerts_next` WHERE `trigger_version` >= :min_trigger_version AND `trigger_version` <= :max_trigger_version"
+                .with(params! {
+                    "min_trigger_version" => MIN_TRIGGER_VERSION,
+                    "max_trigger_version" => MAX_TRIGGER_VERSION,
+                })
+                .fetch(&mut conn)
+                .await?
+        };
+
+        // Categories are cached for a day, so this mostly hits the cache
+        let item_ids = alerts
+            .iter()
+            .map(|(_, item_id, _, _)| *item_id)
+            .filter(|item_id| *item_id != -1)
+            .collect::<Vec<_>>();
+        let categories = match get_items_categories(&item_ids).await {
+            Ok(categories) => categories,
+            Err(err) => {
+                warn!("failed to look up item categories: {:?}", err);
+                HashMap::new()
+            }
+        };
+        let mut shapes: HashMap<(Option<i32>, TriggerShape), ShapeCount> = HashMap::new();
+        for (user_id, item_id, trigger_version, trigger) in alerts {
+            let shape = match parse_trigger(&trigger, trigger_version) {
+                Ok(trigger) => trigger.shape(),
+                Err(_) => continue,
+            };
+            let category = match item_id {
+                -1 => Some(WILDCARD_CATEGORY),
+                _ => categories
+                    .get(&item_id)
+                    .and_then(|found| found.as_ref())
+                    .and_then(|found| found.ui_category),
+            };
+            let count = shapes.entry((category, shape)).or_default();
+            count.alerts += 1;
+            // Alerts without a user don't count towards anyone
+            count.users.extend(user_id);
+        }
+
+        let computed_at = unix_now();
+        let rows = shapes
+            .into_iter()
+            .filter(|(_, count)| count.users.len() >= self.min_users)
+            .map(|((category, shape), count)| {
+                params! {
+                    "computed_at" => computed_at,
+                    "item_category" => category,
+                    "filters" => shape.filters,
+                    "mapper" => shape.mapper,
+                    "take" => shape.take,
+                    "reducer" => shape.reducer,
+                    "comparison" => shape.comparison,
+                    "target" => shape.target,
+                    "threshold_range" => shape.threshold_range,
+                    "scheduled" => shape.scheduled,
+                    "alerts" => count.alerts,
+                    "users" => count.users.len() as u64,
+                }
+            })
+            .collect::<Vec<_>>();
+        let written = rows.len();
+
+        let mut conn = pool.get_conn().await?;
+        let mut tx = conn.start_transaction(TxOpts::default()).await?;
+        tx.query_drop(r"DELETE FROM `users_alerts_trigger_stats`")
+            .await?;
+        tx.exec_batch(
+            r"INSERT INTO `users_alerts_trigger_stats` (`computed_at`, `item_category`, `filters`, `mapper`, `take`, `reducer`, `comparison`, `target`, `threshold_range`, `scheduled`, `alerts`, `users`) VALUES (:computed_at, :item_category, :filters, :mapper, :take, :reducer, :comparison, :target, :threshold_range, :scheduled, :alerts, :users)",
+            rows,
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(written)
+    }
+}
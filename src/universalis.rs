@@ -1,3 +1,12 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::time::Instant;
+
+use crate::errors::*;
+use crate::timeouts::*;
+use itertools::Itertools;
+use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Debug, Clone)]
@@ -6,20 +15,640 @@ pub struct SubscribeEvent<'a> {
     pub channel: &'a str,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct Listing {
+/// A materia melded into a listed item.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Materia {
+    #[serde(rename = "slotID")]
+    pub slot_id: i32,
+    /// The item ID of the materia.
+    #[serde(rename = "materiaID")]
+    pub materia_id: i32,
+}
+
+/// A market listing. String fields borrow from the message they were parsed
+/// from where possible, so that decoding an event doesn't allocate for them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Listing<'a> {
     #[serde(rename = "pricePerUnit")]
     pub unit_price: i32,
     pub quantity: i32,
-    pub total: i32,
+    /// The unit price times the quantity, which can be well past the range
+    /// of an i32 for large stacks of expensive items.
+    pub total: i64,
+    /// The tax on the whole listing, which depends on the retainer's city.
+    /// Older events don't include it.
+    #[serde(default)]
+    pub tax: Option<i64>,
     pub hq: bool,
+    #[serde(rename = "listingID", default, borrow)]
+    pub listing_id: Option<Cow<'a, str>>,
+    #[serde(rename = "sellerID", default, borrow)]
+    pub seller_id: Option<Cow<'a, str>>,
+    #[serde(rename = "retainerName", default, borrow)]
+    pub retainer_name: Option<Cow<'a, str>>,
+    /// The character who crafted the item, which is empty or missing for
+    /// items that weren't crafted or don't bear a signature.
+    #[serde(rename = "creatorName", default, borrow)]
+    pub creator_name: Option<Cow<'a, str>>,
+    /// When the listing was last seen by an uploader, in seconds since the Unix epoch.
+    #[serde(rename = "lastReviewTime", default)]
+    pub last_review_time: Option<i64>,
+    #[serde(default)]
+    pub materia: Vec<Materia>,
+    /// Whether the item is displayed on a mannequin in a housing ward, where
+    /// it can't be bought from the market board. Older events don't say.
+    #[serde(rename = "onMannequin", default)]
+    pub on_mannequin: bool,
+}
+
+/// The tax charged on listings whose tax isn't reported.
+const DEFAULT_TAX_RATE: f32 = 0.05;
+
+impl Listing<'_> {
+    /// Returns the price per unit including tax, in whole gil.
+    pub fn unit_price_with_tax(&self) -> f32 {
+        match self.tax {
+            Some(tax) if self.quantity > 0 => {
+                (self.unit_price as f32 + tax as f32 / self.quantity as f32).ceil()
+            }
+            _ => (self.unit_price as f32 * (1.0 + DEFAULT_TAX_RATE)).ceil(),
+        }
+    }
+
+    /// Returns the price of the whole listing including tax, in whole gil.
+    pub fn total_with_tax(&self) -> f32 {
+        match self.tax {
+            Some(tax) => (self.total + tax) as f32,
+            None => (self.total as f32 * (1.0 + DEFAULT_TAX_RATE)).ceil(),
+        }
+    }
+
+    /// Returns how long ago the listing was last reviewed, in minutes.
+    pub fn age_minutes(&self, now: i64) -> Option<f32> {
+        self.last_review_time
+            .map(|t| (now - t).max(0) as f32 / 60.0)
+    }
+
+    /// Returns the name of the character who crafted the item, if it bears
+    /// a signature.
+    pub fn creator(&self) -> Option<&str> {
+        self.creator_name.as_deref().filter(|name| !name.is_empty())
+    }
+
+    /// Copies any borrowed fields, detaching the listing from its message.
+    pub fn into_owned(self) -> Listing<'static> {
+        Listing {
+            unit_price: self.unit_price,
+            quantity: self.quantity,
+            total: self.total,
+            tax: self.tax,
+            hq: self.hq,
+            listing_id: self.listing_id.map(|id| Cow::Owned(id.into_owned())),
+            seller_id: self.seller_id.map(|id| Cow::Owned(id.into_owned())),
+            retainer_name: self.retainer_name.map(|name| Cow::Owned(name.into_owned())),
+            creator_name: self.creator_name.map(|name| Cow::Owned(name.into_owned())),
+            last_review_time: self.last_review_time,
+            materia: self.materia,
+            on_mannequin: self.on_mannequin,
+        }
+    }
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+pub fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+pub fn unix_now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// The fields shared by every message from the websocket, used to work out
+/// how to parse the rest of it.
+#[derive(Deserialize, Debug, Clone)]
+pub struct EventHeader<'a> {
+    #[serde(default, borrow)]
+    pub event: Option<Cow<'a, str>>,
+}
+
+/// A service announcement from Universalis, such as a maintenance notice.
+#[derive(Deserialize, Debug, Clone)]
+pub struct BroadcastEvent {
+    pub event: String,
+    #[serde(default)]
+    pub message: Option<String>,
+    /// The start of an announced maintenance window, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub start: Option<i64>,
+    /// The end of an announced maintenance window, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub end: Option<i64>,
+}
+
+/// The server's reply to a subscribe or unsubscribe request.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SubscriptionReply {
+    pub event: String,
+    #[serde(default)]
+    pub channel: Option<String>,
+    /// Why the request was rejected, for errors.
+    #[serde(default, alias = "error", alias = "reason")]
+    pub message: Option<String>,
+}
+
+impl SubscriptionReply {
+    /// The names of the events that reply to subscription requests.
+    pub const EVENTS: [&'static str; 3] = ["subscribed", "unsubscribed", "error"];
+
+    pub fn is_error(&self) -> bool {
+        self.event == "error"
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum UniversalisEvent<'a> {
+    ListingsAdd(ListingsAddEvent<'a>),
+    Broadcast(BroadcastEvent),
+    SubscriptionReply(SubscriptionReply),
+}
+
+/// The kind of market activity an event reports, named after the channel it
+/// was sent on.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MarketChannel {
+    #[default]
+    #[serde(rename = "listings/add")]
+    ListingsAdd,
+    #[serde(rename = "listings/remove")]
+    ListingsRemove,
+    #[serde(rename = "sales/add")]
+    SalesAdd,
+}
+
+impl MarketChannel {
+    /// Reads the channel from an event's name, e.g. "sales/add". Events
+    /// without a name come from the listings channel, which is the only one
+    /// older servers send.
+    pub fn parse(event: Option<&str>) -> Option<Self> {
+        match event {
+            None | Some("listings/add") => Some(Self::ListingsAdd),
+            Some("listings/remove") => Some(Self::ListingsRemove),
+            Some("sales/add") => Some(Self::SalesAdd),
+            Some(_) => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ListingsAdd => "listings/add",
+            Self::ListingsRemove => "listings/remove",
+            Self::SalesAdd => "sales/add",
+        }
+    }
+
+    /// Describes the activity that triggered an alert, for notifications.
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Self::ListingsAdd => "new listings",
+            Self::ListingsRemove => "listings being removed",
+            Self::SalesAdd => "a new sale",
+        }
+    }
+}
+
+/// A channel on the Universalis websocket, e.g. `listings/add{world=74}`,
+/// built up from the kind of event and the filters on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Channel {
+    pub kind: MarketChannel,
+    /// Only events for this world.
+    pub world: Option<i32>,
+    /// Only events for HQ (or NQ) listings.
+    pub hq: Option<bool>,
+}
+
+impl Channel {
+    pub fn new(kind: MarketChannel) -> Self {
+        Self {
+            kind,
+            world: None,
+            hq: None,
+        }
+    }
+
+    pub fn world(mut self, world_id: i32) -> Self {
+        self.world = Some(world_id);
+        self
+    }
+
+    pub fn hq(mut self, hq: bool) -> Self {
+        self.hq = Some(hq);
+        self
+    }
+
+    /// Parses a channel written the way Universalis expects it. Anything
+    /// Universalis wouldn't understand is rejected, since subscribing to it
+    /// would succeed without any events ever arriving.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        let (name, filters) = match spec.split_once('{') {
+            Some((name, filters)) => match filters.strip_suffix('}') {
+                Some(filters) => (name, Some(filters)),
+                None => return Err(format!("channel '{}' is missing a closing '}}'", spec).into()),
+            },
+            None => (spec, None),
+        };
+        let kind = MarketChannel::parse(Some(name)).ok_or_else(|| {
+            format!(
+                "unknown channel '{}'; expected listings/add, listings/remove, or sales/add",
+                name
+            )
+        })?;
+
+        let mut channel = Self::new(kind);
+        for filter in filters
+            .into_iter()
+            .flat_map(|f| f.split(','))
+            .filter(|f| !f.trim().is_empty())
+        {
+            let (key, value) = filter
+                .split_once('=')
+                .ok_or_else(|| format!("channel filter '{}' should look like key=value", filter))?;
+            let repeated = match key.trim() {
+                "world" => channel.world.is_some(),
+                "hq" => channel.hq.is_some(),
+                _ => false,
+            };
+            if repeated {
+                return Err(
+                    format!("channel '{}' sets {} more than once", spec, key.trim()).into(),
+                );
+            }
+            match (key.trim(), value.trim()) {
+                ("world", world_id) => {
+                    let world_id = world_id
+                        .parse()
+                        .chain_err(|| format!("invalid world in channel '{}'", spec))?;
+                    channel = channel.world(world_id);
+                }
+                ("hq", "true") => channel = channel.hq(true),
+                ("hq", "false") => channel = channel.hq(false),
+                (key, value) => {
+                    return Err(format!(
+                        "unknown channel filter '{}={}'; expected world or hq",
+                        key, value
+                    )
+                    .into())
+                }
+            }
+        }
+        Ok(channel)
+    }
+
+    /// Whether an event could have been sent on this channel. Events with
+    /// both HQ and NQ listings match either filter.
+    pub fn matches(&self, ev: &ListingsAddEvent) -> bool {
+        self.kind == ev.channel
+            && self.world.is_none_or(|world_id| world_id == ev.world_id)
+            && self
+                .hq
+                .is_none_or(|hq| ev.listings.iter().any(|l| l.hq == hq))
+    }
+}
+
+impl Display for Channel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.kind.name())?;
+        let filters = self
+            .world
+            .map(|world_id| format!("world={}", world_id))
+            .into_iter()
+            .chain(self.hq.map(|hq| format!("hq={}", hq)))
+            .collect::<Vec<_>>();
+        if !filters.is_empty() {
+            write!(f, "{{{}}}", filters.join(","))?;
+        }
+        Ok(())
+    }
 }
 
+/// A batch of listings from a market channel. Sales are read as listings,
+/// since they have the same price, quantity, and quality fields.
 #[derive(Deserialize, Debug, Clone)]
-pub struct ListingsAddEvent {
+pub struct ListingsAddEvent<'a> {
     #[serde(rename = "item")]
     pub item_id: i32,
     #[serde(rename = "world")]
     pub world_id: i32,
-    pub listings: Vec<Listing>,
+    #[serde(borrow, alias = "sales")]
+    pub listings: Vec<Listing<'a>>,
+    /// The channel the event was sent on, which is set once it's parsed.
+    #[serde(skip)]
+    pub channel: MarketChannel,
+    /// When the event was received from the websocket, if it was.
+    #[serde(skip)]
+    pub received_at: Option<Instant>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct CurrentData<'a> {
+    #[serde(borrow)]
+    listings: Vec<Listing<'a>>,
+}
+
+/// Fetches the current listings for an item on a world from the Universalis REST API.
+pub async fn get_current_listings(
+    world_id: i32,
+    item_id: i32,
+    client: &Client,
+) -> Result<Vec<Listing<'static>>> {
+    let url = format!("https://universalis.app/api/v2/{}/{}", world_id, item_id);
+    let res = client
+        .get(url)
+        .send()
+        .await
+        .count_timeout(Service::Universalis)?
+        .error_for_status()?;
+    let response_text = res.text().await.count_timeout(Service::Universalis)?;
+    let data: CurrentData = serde_json::from_str(&response_text)?;
+    Ok(data.listings.into_iter().map(Listing::into_owned).collect())
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Sale {
+    #[serde(rename = "pricePerUnit")]
+    unit_price: i32,
+    quantity: i32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct HistoryData {
+    entries: Vec<Sale>,
+}
+
+/// Fetches the average price per unit of an item's sales on a world over the
+/// last `within_secs` seconds, weighted by quantity. Returns `None` if there
+/// were no sales.
+pub async fn get_average_sale_price(
+    world_id: i32,
+    item_id: i32,
+    within_secs: u64,
+    client: &Client,
+) -> Result<Option<f32>> {
+    let url = format!(
+        "https://universalis.app/api/v2/history/{}/{}?entriesWithin={}",
+        world_id, item_id, within_secs
+    );
+    let res = client
+        .get(url)
+        .send()
+        .await
+        .count_timeout(Service::Universalis)?
+        .error_for_status()?;
+    let response_text = res.text().await.count_timeout(Service::Universalis)?;
+    let data: HistoryData = serde_json::from_str(&response_text)?;
+
+    let quantity: i64 = data.entries.iter().map(|s| s.quantity as i64).sum();
+    if quantity == 0 {
+        return Ok(None);
+    }
+    let total: i64 = data
+        .entries
+        .iter()
+        .map(|s| s.unit_price as i64 * s.quantity as i64)
+        .sum();
+    Ok(Some(total as f32 / quantity as f32))
+}
+
+/// The most items the aggregated endpoint accepts in one request.
+const MAX_AGGREGATED_ITEMS: usize = 100;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+struct AggregatedPrice {
+    price: f32,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+struct AggregatedVelocity {
+    quantity: f32,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+struct AggregatedScopes<T> {
+    #[serde(default)]
+    world: Option<T>,
+    #[serde(default)]
+    dc: Option<T>,
+    #[serde(default)]
+    region: Option<T>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+struct AggregatedQuality {
+    #[serde(rename = "minListing", default)]
+    min_listing: AggregatedScopes<AggregatedPrice>,
+    #[serde(rename = "averageSalePrice", default)]
+    average_sale_price: AggregatedScopes<AggregatedPrice>,
+    #[serde(rename = "dailySaleVelocity", default)]
+    daily_sale_velocity: AggregatedScopes<AggregatedVelocity>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct AggregatedUploadTime {
+    #[serde(rename = "worldId")]
+    world_id: i32,
+    /// Milliseconds since the Unix epoch.
+    timestamp: i64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct AggregatedItem {
+    #[serde(rename = "itemId")]
+    item_id: i32,
+    #[serde(default)]
+    nq: AggregatedQuality,
+    #[serde(default)]
+    hq: AggregatedQuality,
+    #[serde(rename = "worldUploadTimes", default)]
+    world_upload_times: Vec<AggregatedUploadTime>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct AggregatedData {
+    results: Vec<AggregatedItem>,
+}
+
+/// Market statistics for an item on a world, across both qualities, from the
+/// Universalis aggregated endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct AggregatedMarketData {
+    /// The lowest price per unit on the world.
+    pub world_min: Option<f32>,
+    /// The lowest price per unit in the world's data center.
+    pub dc_min: Option<f32>,
+    /// The lowest price per unit in the world's region.
+    pub region_min: Option<f32>,
+    /// The recent average sale price per unit on the world.
+    pub average_sale_price: Option<f32>,
+    /// How many units sell on the world per day.
+    pub daily_sale_velocity: Option<f32>,
+    /// When the world's market data was last uploaded, in seconds since the
+    /// Unix epoch.
+    pub uploaded_at: Option<i64>,
+}
+
+impl AggregatedMarketData {
+    fn from_item(item: &AggregatedItem, world_id: i32) -> Self {
+        let min = |scope: fn(&AggregatedScopes<AggregatedPrice>) -> Option<AggregatedPrice>| {
+            [scope(&item.nq.min_listing), scope(&item.hq.min_listing)]
+                .into_iter()
+                .flatten()
+                .map(|p| p.price)
+                .filter(|p| *p > 0.0)
+                .reduce(f32::min)
+        };
+        let nq_velocity = item.nq.daily_sale_velocity.world.map(|v| v.quantity);
+        let hq_velocity = item.hq.daily_sale_velocity.world.map(|v| v.quantity);
+        let daily_sale_velocity = match (nq_velocity, hq_velocity) {
+            (None, None) => None,
+            (nq, hq) => Some(nq.unwrap_or(0.0) + hq.unwrap_or(0.0)),
+        };
+        // Each quality's average is weighted by how much of it sells
+        let averages = [
+            (item.nq.average_sale_price.world, nq_velocity),
+            (item.hq.average_sale_price.world, hq_velocity),
+        ];
+        let (total, weight) = averages
+            .into_iter()
+            .filter_map(|(price, velocity)| Some((price?.price, velocity.unwrap_or(0.0))))
+            .filter(|(price, _)| *price > 0.0)
+            .fold((0.0, 0.0), |(total, weight), (price, velocity)| {
+                (total + price * velocity, weight + velocity)
+            });
+        let average_sale_price = if weight > 0.0 {
+            Some(total / weight)
+        } else {
+            // Nothing has sold recently enough to have a velocity
+            averages
+                .into_iter()
+                .filter_map(|(price, _)| price.map(|p| p.price))
+                .find(|p| *p > 0.0)
+        };
+
+        Self {
+            world_min: min(|s| s.world),
+            dc_min: min(|s| s.dc),
+            region_min: min(|s| s.region),
+            average_sale_price,
+            daily_sale_velocity,
+            uploaded_at: item
+                .world_upload_times
+                .iter()
+                .find(|t| t.world_id == world_id)
+                .map(|t| t.timestamp / 1000),
+        }
+    }
+}
+
+/// Fetches aggregated market statistics for items on a world, in as few
+/// requests as possible. Items Universalis has no data for are left out.
+pub async fn get_aggregated_market_data(
+    world_id: i32,
+    item_ids: &[i32],
+    client: &Client,
+) -> Result<HashMap<i32, AggregatedMarketData>> {
+    let mut data = HashMap::with_capacity(item_ids.len());
+    for chunk in item_ids.chunks(MAX_AGGREGATED_ITEMS) {
+        let url = format!(
+            "https://universalis.app/api/v2/aggregated/{}/{}",
+            world_id,
+            chunk.iter().join(",")
+        );
+        let res = client
+            .get(url)
+            .send()
+            .await
+            .count_timeout(Service::Universalis)?
+            .error_for_status()?;
+        let response_text = res.text().await.count_timeout(Service::Universalis)?;
+        let aggregated: AggregatedData = serde_json::from_str(&response_text)?;
+        data.extend(aggregated.results.iter().map(|item| {
+            (
+                item.item_id,
+                AggregatedMarketData::from_item(item, world_id),
+            )
+        }));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_round_trip_through_display() {
+        for kind in [
+            MarketChannel::ListingsAdd,
+            MarketChannel::ListingsRemove,
+            MarketChannel::SalesAdd,
+        ] {
+            for channel in [
+                Channel::new(kind),
+                Channel::new(kind).world(74),
+                Channel::new(kind).hq(true),
+                Channel::new(kind).hq(false),
+                Channel::new(kind).world(74).hq(true),
+            ] {
+                let spec = channel.to_string();
+                assert_eq!(Channel::parse(&spec).unwrap(), channel, "{}", spec);
+            }
+        }
+    }
+
+    #[test]
+    fn parses_channels_as_universalis_writes_them() {
+        assert_eq!(
+            Channel::parse(" sales/add{hq=false, world=74} ").unwrap(),
+            Channel::new(MarketChannel::SalesAdd).world(74).hq(false)
+        );
+        assert_eq!(
+            Channel::parse("listings/add{}").unwrap(),
+            Channel::new(MarketChannel::ListingsAdd)
+        );
+        assert_eq!(
+            Channel::new(MarketChannel::ListingsAdd)
+                .world(74)
+                .hq(true)
+                .to_string(),
+            "listings/add{world=74,hq=true}"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_channels() {
+        for spec in [
+            "",
+            "listings",
+            "listings/update",
+            "listings/add{world=74",
+            "listings/add{world=74}x",
+            "listings/add{world}",
+            "listings/add{world=}",
+            "listings/add{world=Adamantoise}",
+            "listings/add{hq=yes}",
+            "listings/add{dc=Aether}",
+            "listings/add{world=74,world=75}",
+            "listings/add{hq=true,hq=false}",
+        ] {
+            assert!(Channel::parse(spec).is_err(), "{}", spec);
+        }
+    }
 }
@@ -0,0 +1,330 @@
+use crate::trigger::*;
+use serde_json::{Map, Value};
+
+/// The first trigger version that is parsed strictly, rejecting fields that
+/// aren't part of the trigger format.
+pub const STRICT_TRIGGER_VERSION: i32 = 1;
+
+/// The top-level fields of a trigger, for suggesting corrections.
+const TRIGGER_FIELDS: [&str; 7] = [
+    "filters",
+    "filter_mode",
+    "mapper",
+    "take",
+    "reducer",
+    "comparison",
+    "schedule",
+];
+
+/// The fields of each filter that takes an object, by the filter's name.
+/// Name filters hold an enum, which serde already checks.
+const FILTER_FIELDS: [(&str, &[&str]); 4] = [
+    ("newerThan", &["minutes"]),
+    ("quantity", &["min", "max"]),
+    ("pricePerUnit", &["min", "max"]),
+    ("materiaCount", &["min"]),
+];
+
+/// The fields of each comparison, by the comparison's name.
+const COMPARISON_FIELDS: [(&str, &[&str]); 4] = [
+    ("lt", &["target"]),
+    ("gt", &["target"]),
+    ("bottom_percent", &["percent"]),
+    ("below_rest", &["percent"]),
+];
+
+/// The fields of a stat of the event that a comparison targets.
+const STAT_FIELDS: [&str; 5] = ["filters", "filter_mode", "mapper", "take", "reducer"];
+
+const SCHEDULE_FIELDS: [&str; 4] = ["days", "start", "end", "utc_offset_minutes"];
+
+/// Common names for things that the trigger format calls something else.
+const SYNONYMS: [(&str, &str); 8] = [
+    ("avg", "mean"),
+    ("average", "mean"),
+    ("minimum", "min"),
+    ("maximum", "max"),
+    ("price", "pricePerUnit"),
+    ("unitPrice", "pricePerUnit"),
+    ("lessThan", "lt"),
+    ("greaterThan", "gt"),
+];
+
+/// Counts the single-character edits needed to turn one string into another.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Returns the candidate closest to `input`, if any is close enough to
+/// plausibly be what was meant.
+pub(crate) fn suggest<'a>(input: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let synonym = SYNONYMS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(input))
+        .and_then(|(_, meant)| candidates.iter().find(|c| *c == meant));
+    if let Some(meant) = synonym {
+        return Some(meant);
+    }
+
+    let input = input.to_lowercase();
+    candidates
+        .iter()
+        .map(|c| (edit_distance(&input, &c.to_lowercase()), *c))
+        .filter(|(distance, c)| *distance <= (c.len() / 3).max(2))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, c)| c)
+}
+
+/// Names the part of a trigger that an enum's variants belong to.
+fn describe_variants(expected: &[&str]) -> &'static str {
+    let has = |v: &str| expected.contains(&v);
+    if has("mean") {
+        "reducer"
+    } else if has("pricePerUnit") {
+        "mapper"
+    } else if has("newerThan") {
+        "filter"
+    } else if has("lt") {
+        "comparison"
+    } else if has("any") {
+        "filter mode"
+    } else if has("vendor_price") {
+        "baseline"
+    } else {
+        "value"
+    }
+}
+
+/// Extracts the quoted names from a list such as "`min`, `max`, `mean`".
+fn quoted_names(list: &str) -> Vec<&str> {
+    list.split('`').skip(1).step_by(2).collect()
+}
+
+/// Turns a serde error into a message that points at the likely mistake.
+fn humanize(err: &serde_json::Error) -> String {
+    let message = err.to_string();
+    // serde_json appends the position of the error, which is kept, but after
+    // the explanation rather than in the middle of it
+    let (message, position) = match message.rsplit_once(" at line ") {
+        Some((message, position)) => (message.to_owned(), format!(" (line {})", position)),
+        None => (message, String::new()),
+    };
+
+    let explanation = if let Some(rest) = message.strip_prefix("unknown variant `") {
+        let (variant, expected) = rest.split_once('`').unwrap_or((rest, ""));
+        let expected = quoted_names(expected);
+        let kind = describe_variants(&expected);
+        match suggest(variant, &expected) {
+            Some(suggestion) => format!(
+                "unknown {} '{}', did you mean '{}'?",
+                kind, variant, suggestion
+            ),
+            None => format!(
+                "unknown {} '{}', expected one of: {}",
+                kind,
+                variant,
+                expected.join(", ")
+            ),
+        }
+    } else if let Some(rest) = message.strip_prefix("unknown field `") {
+        let (field, expected) = rest.split_once('`').unwrap_or((rest, ""));
+        unknown_field(field, &quoted_names(expected), None)
+    } else if let Some(rest) = message.strip_prefix("missing field `") {
+        format!("missing field '{}'", rest.trim_end_matches('`'))
+    } else if message.contains("untagged enum ComparisonTarget") {
+        "comparison target must be a number, \"reference\", an object like {\"baseline\": \"vendor_price\", \"multiplier\": 0.8}, or one like {\"stat\": {\"mapper\": \"pricePerUnit\", \"reducer\": \"mean\"}, \"multiplier\": 0.8}".to_owned()
+    } else {
+        message
+    };
+    format!("{}{}", explanation, position)
+}
+
+/// Describes an unknown field, suggesting the expected one it's closest to.
+fn unknown_field(field: &str, expected: &[&str], within: Option<&str>) -> String {
+    let within = within.map_or(String::new(), |within| format!(" in {}", within));
+    match suggest(field, expected) {
+        Some(suggestion) => format!(
+            "unknown field '{}'{}, did you mean '{}'?",
+            field, within, suggestion
+        ),
+        None => format!("unknown field '{}'{}", field, within),
+    }
+}
+
+/// Checks that an object only has the expected fields.
+fn check_fields(
+    object: &Map<String, Value>,
+    expected: &[&str],
+    within: &str,
+) -> std::result::Result<(), String> {
+    match object
+        .keys()
+        .find(|field| !expected.contains(&field.as_str()))
+    {
+        Some(field) => Err(unknown_field(field, expected, Some(within))),
+        None => Ok(()),
+    }
+}
+
+fn check_filter_fields(filters: &[Value]) -> std::result::Result<(), String> {
+    let objects = filters.iter().filter_map(Value::as_object).flatten();
+    for (name, filter) in objects {
+        let fields = FILTER_FIELDS.iter().find(|(n, _)| n == name);
+        if let (Some((_, fields)), Some(filter)) = (fields, filter.as_object()) {
+            check_fields(filter, fields, &format!("the '{}' filter", name))?;
+        }
+    }
+    Ok(())
+}
+
+fn check_target_fields(target: &Map<String, Value>) -> std::result::Result<(), String> {
+    if let Some(stat) = target.get("stat") {
+        check_fields(target, &["stat", "multiplier"], "the comparison target")?;
+        if let Some(stat) = stat.as_object() {
+            check_fields(stat, &STAT_FIELDS, "the stat")?;
+            if let Some(filters) = stat.get("filters").and_then(Value::as_array) {
+                check_filter_fields(filters)?;
+            }
+        }
+    } else if target.contains_key("baseline") {
+        check_fields(target, &["baseline", "multiplier"], "the comparison target")?;
+    }
+    Ok(())
+}
+
+/// Checks the objects nested in a trigger for fields that aren't part of
+/// the trigger format. Serde ignores these, since triggers from before
+/// [`STRICT_TRIGGER_VERSION`] are still parsed leniently, so strict
+/// triggers are checked separately.
+fn check_nested_fields(trigger: &Value) -> std::result::Result<(), String> {
+    if let Some(filters) = trigger.get("filters").and_then(Value::as_array) {
+        check_filter_fields(filters)?;
+    }
+    let comparisons = trigger
+        .get("comparison")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten();
+    for (name, comparison) in comparisons {
+        let fields = COMPARISON_FIELDS.iter().find(|(n, _)| n == name);
+        if let (Some((_, fields)), Some(comparison)) = (fields, comparison.as_object()) {
+            check_fields(comparison, fields, &format!("the '{}' comparison", name))?;
+            if let Some(target) = comparison.get("target").and_then(Value::as_object) {
+                check_target_fields(target)?;
+            }
+        }
+    }
+    if let Some(schedule) = trigger.get("schedule").and_then(Value::as_object) {
+        check_fields(schedule, &SCHEDULE_FIELDS, "the schedule")?;
+    }
+    Ok(())
+}
+
+/// Parses a trigger, describing what's wrong with it if it's invalid.
+/// Triggers may be written as JSON or as an expression (see
+/// [`parse_expression`]). Triggers from [`STRICT_TRIGGER_VERSION`] onwards
+/// may not contain fields that aren't part of the trigger format, at any
+/// depth.
+pub fn parse_trigger(
+    trigger: &str,
+    trigger_version: i32,
+) -> std::result::Result<AlertTrigger, String> {
+    if !trigger.trim_start().starts_with('{') {
+        return parse_expression(trigger).map_err(|err| err.to_string());
+    }
+    // Unknown fields are checked first, since a misspelled field would
+    // otherwise be reported as a missing one
+    if trigger_version >= STRICT_TRIGGER_VERSION {
+        let value = serde_json::from_str::<Value>(trigger).map_err(|err| humanize(&err))?;
+        if let Some(root) = value.as_object() {
+            if let Some(field) = root.keys().find(|f| !TRIGGER_FIELDS.contains(&f.as_str())) {
+                return Err(unknown_field(field, &TRIGGER_FIELDS, None));
+            }
+        }
+        check_nested_fields(&value)?;
+    }
+    let parsed = serde_json::from_str::<AlertTrigger>(trigger).map_err(|err| humanize(&err))?;
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strict(trigger: &str) -> std::result::Result<AlertTrigger, String> {
+        parse_trigger(trigger, STRICT_TRIGGER_VERSION)
+    }
+
+    #[test]
+    fn strict_triggers_reject_nested_typos() {
+        let cases = [
+            (
+                r#"{"filters": [{"newerThan": {"minutse": 5}}], "mapper": "pricePerUnit", "reducer": "min", "comparison": {"lt": {"target": 5}}}"#,
+                "unknown field 'minutse' in the 'newerThan' filter, did you mean 'minutes'?",
+            ),
+            (
+                r#"{"filters": [{"quantity": {"mx": 5}}], "mapper": "pricePerUnit", "reducer": "min", "comparison": {"lt": {"target": 5}}}"#,
+                "unknown field 'mx' in the 'quantity' filter, did you mean 'max'?",
+            ),
+            (
+                r#"{"mapper": "pricePerUnit", "reducer": "min", "comparison": {"lt": {"target": 5, "targte": 6}}}"#,
+                "unknown field 'targte' in the 'lt' comparison, did you mean 'target'?",
+            ),
+            (
+                r#"{"mapper": "pricePerUnit", "reducer": "min", "comparison": {"lt": {"target": {"baseline": "vendor_price", "multiplir": 0.8}}}}"#,
+                "unknown field 'multiplir' in the comparison target, did you mean 'multiplier'?",
+            ),
+            (
+                r#"{"mapper": "pricePerUnit", "reducer": "min", "comparison": {"lt": {"target": {"stat": {"mapper": "pricePerUnit", "reducer": "mean", "filter": []}}}}}"#,
+                "unknown field 'filter' in the stat, did you mean 'filters'?",
+            ),
+            (
+                r#"{"mapper": "pricePerUnit", "reducer": "min", "comparison": {"lt": {"target": 5}}, "schedule": {"day": ["mon"]}}"#,
+                "unknown field 'day' in the schedule, did you mean 'days'?",
+            ),
+        ];
+        for (trigger, message) in cases {
+            assert_eq!(strict(trigger).unwrap_err(), message, "{}", trigger);
+        }
+
+        // Older triggers are still parsed leniently
+        let trigger = r#"{"filters": [], "mapper": "pricePerUnit", "reducer": "min", "comparison": {"lt": {"target": 5, "targte": 6}}}"#;
+        assert!(strict(trigger).is_err());
+        assert!(parse_trigger(trigger, STRICT_TRIGGER_VERSION - 1).is_ok());
+        // Apart from where serde itself is strict, which is explained the same way
+        let trigger = r#"{"filters": [{"quantity": {"mx": 5}}], "mapper": "pricePerUnit", "reducer": "min", "comparison": {"lt": {"target": 5}}}"#;
+        assert_eq!(
+            parse_trigger(trigger, STRICT_TRIGGER_VERSION - 1).unwrap_err(),
+            "unknown field 'mx', did you mean 'max'? (line 1 column 31)"
+        );
+    }
+
+    #[test]
+    fn strict_triggers_accept_every_field() {
+        let trigger = r#"{
+            "filters": [{"newerThan": {"minutes": 5}}, {"quantity": {"min": 1, "max": 99}}, {"materiaCount": {"min": 1}}],
+            "filter_mode": "all",
+            "mapper": "pricePerUnit",
+            "take": 3,
+            "reducer": "min",
+            "comparison": {"lt": {"target": {"stat": {"filters": ["hq"], "filter_mode": "any", "mapper": "pricePerUnit", "take": 2, "reducer": "mean"}, "multiplier": 0.8}}},
+            "schedule": {"days": ["mon"], "start": "09:00", "end": "17:00", "utc_offset_minutes": 60}
+        }"#;
+        strict(trigger).unwrap();
+        assert_eq!(
+            strict(r#"{"mapper": "pricePerUnit", "reducer": "min", "comparison": {"lt": {"target": 5}}, "reducr": "max"}"#)
+                .unwrap_err(),
+            "unknown field 'reducr', did you mean 'reducer'?"
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::errors::*;
+use crate::metrics_registry::*;
+use metrics::{counter, gauge};
+use reqwest::Client;
+use serde::Deserialize;
+
+/// A world's population category, as shown on the Lodestone's world status
+/// page.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WorldCategory {
+    #[default]
+    Standard,
+    Preferred,
+    Congested,
+    New,
+}
+
+/// Whether players can currently travel to a world.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct WorldStatus {
+    pub world_id: i32,
+    /// Whether data center travel to the world is open.
+    #[serde(default = "default_allowed")]
+    pub travel_allowed: bool,
+    #[serde(default)]
+    pub congested: bool,
+    #[serde(default)]
+    pub category: WorldCategory,
+    #[serde(default = "default_allowed")]
+    pub character_creation_allowed: bool,
+}
+
+fn default_allowed() -> bool {
+    true
+}
+
+impl WorldStatus {
+    /// Returns whether a player from elsewhere may be unable to get to the
+    /// world to buy something. Data center travel to congested and new worlds
+    /// is restricted.
+    pub fn is_restricted(&self) -> bool {
+        !self.travel_allowed || self.is_congested() || self.category == WorldCategory::New
+    }
+
+    /// Returns whether the world is congested, either by the feed's flag or
+    /// its category.
+    pub fn is_congested(&self) -> bool {
+        self.congested || self.category == WorldCategory::Congested
+    }
+}
+
+/// The latest world statuses from a configurable feed, which is polled in
+/// the background.
+pub struct WorldStatusFeed {
+    url: Option<String>,
+    pub interval: Duration,
+    statuses: RwLock<HashMap<i32, WorldStatus>>,
+}
+
+impl WorldStatusFeed {
+    /// Reads the feed URL from `UNIVERSALIS_ALERTS_WORLD_STATUS_URL` and the
+    /// polling interval from `UNIVERSALIS_ALERTS_WORLD_STATUS_INTERVAL_SECS`
+    /// (5 minutes by default). The feed should return a JSON array of world
+    /// statuses. If no URL is set, every world is treated as open.
+    pub fn from_env() -> Self {
+        let interval = env::var("UNIVERSALIS_ALERTS_WORLD_STATUS_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300);
+        Self {
+            url: env::var("UNIVERSALIS_ALERTS_WORLD_STATUS_URL").ok(),
+            interval: Duration::from_secs(interval),
+            statuses: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.url.is_some()
+    }
+
+    /// Fetches the latest statuses from the feed, replacing the previous ones.
+    pub async fn refresh(&self, client: &Client) -> Result<()> {
+        let url = match &self.url {
+            Some(url) => url,
+            None => return Ok(()),
+        };
+
+        let res = client.get(url).send().await?.error_for_status()?;
+        let response_text = res.text().await?;
+        let statuses: Vec<WorldStatus> = serde_json::from_str(&response_text)?;
+
+        counter!(WORLD_STATUS_REFRESHES.name, 1);
+        gauge!(
+            WORLD_STATUS_RESTRICTED.name,
+            statuses.iter().filter(|s| s.is_restricted()).count() as f64
+        );
+        *self.statuses.write().unwrap() = statuses.into_iter().map(|s| (s.world_id, s)).collect();
+        Ok(())
+    }
+
+    /// Returns the latest status of a world, if the feed included it.
+    pub fn get(&self, world_id: i32) -> Option<WorldStatus> {
+        self.statuses.read().unwrap().get(&world_id).copied()
+    }
+}
@@ -1,6 +1,14 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
 use crate::errors::*;
+use crate::metrics_registry::*;
+use crate::timeouts::*;
 use cached::proc_macro::cached;
+use cached::Cached;
+use itertools::Itertools;
 use metrics::counter;
+use reqwest::{Client, StatusCode};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone)]
@@ -15,33 +23,370 @@ pub struct World {
     pub name: String,
 }
 
-// Unfortunately, it's not possible to reuse the client here,
-// since the function arguments are being used as a cache key.
+#[derive(Deserialize, Debug, Clone)]
+struct CategoryRef {
+    #[serde(rename = "ID")]
+    id: i32,
+}
 
+#[derive(Deserialize, Debug, Clone)]
+struct ItemCategoryRefs {
+    #[serde(rename = "ItemUICategory", default)]
+    ui_category: Option<CategoryRef>,
+    #[serde(rename = "ItemSearchCategory", default)]
+    search_category: Option<CategoryRef>,
+}
+
+/// The categories an item belongs to.
+#[derive(Debug, Clone)]
+pub struct ItemCategories {
+    pub ui_category: Option<i32>,
+    pub search_category: Option<i32>,
+}
+
+impl From<ItemCategoryRefs> for ItemCategories {
+    fn from(refs: ItemCategoryRefs) -> Self {
+        Self {
+            ui_category: refs.ui_category.map(|c| c.id),
+            search_category: refs.search_category.map(|c| c.id),
+        }
+    }
+}
+
+/// Returns the client for the cached functions below, which can't take one
+/// as an argument since their arguments are used as the cache key.
+fn xivapi_client() -> &'static Client {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    CLIENT.get_or_init(|| timeouts().client(Service::Xivapi))
+}
+
+/// Fetches an item from XIVAPI, returning `None` if XIVAPI doesn't know about
+/// it. Unknown items are cached like any other result, so that they aren't
+/// requested again for every event.
 #[cached(size = 500, time = 60, result = true)]
-pub async fn get_item(id: i32) -> Result<Item> {
+pub async fn get_item(id: i32) -> Result<Option<Item>> {
     let url = format!("https://xivapi.com/Item/{}?columns=Name", id);
-    let client = reqwest::Client::new();
+    let client = xivapi_client();
+
+    let res = client
+        .get(url)
+        .send()
+        .await
+        .count_timeout(Service::Xivapi)?;
+    counter!(XIVAPI_REQUESTS.name, 1);
+
+    if res.status() == StatusCode::NOT_FOUND {
+        counter!(XIVAPI_UNKNOWN_ITEMS.name, 1, "reason" => "not_found");
+        warn!("XIVAPI does not know about item {}", id);
+        return Ok(None);
+    }
+
+    let response_text = res
+        .error_for_status()?
+        .text()
+        .await
+        .count_timeout(Service::Xivapi)?;
+    match serde_json::from_str(&response_text) {
+        Ok(item) => Ok(Some(item)),
+        Err(err) => {
+            counter!(XIVAPI_UNKNOWN_ITEMS.name, 1, "reason" => "unexpected_body");
+            warn!("unexpected XIVAPI response for item {}: {}", id, err);
+            Ok(None)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct ItemRow {
+    #[serde(rename = "ID")]
+    id: i32,
+    #[serde(flatten)]
+    item: Item,
+}
+
+/// The most items requested from XIVAPI at once, which is also the most it
+/// returns on one page.
+const MAX_ITEMS_PER_REQUEST: usize = 100;
 
-    let res = client.get(url).send().await?;
-    let response_text = res.text().await?;
-    let item = serde_json::from_str(&response_text)?;
+/// Fetches several items from XIVAPI in as few requests as possible, for
+/// rendering many items at once. Items that [`get_item`] has cached aren't
+/// requested again, and the ones that are fetched are added to its cache,
+/// including the ones XIVAPI doesn't know about.
+pub async fn get_items(ids: &[i32]) -> Result<HashMap<i32, Option<Item>>> {
+    let mut items = HashMap::with_capacity(ids.len());
+    let mut missing = Vec::new();
+    {
+        let mut cache = GET_ITEM.lock().await;
+        for &id in ids.iter().unique() {
+            match cache.cache_get(&id) {
+                Some(item) => {
+                    items.insert(id, item.clone());
+                }
+                None => missing.push(id),
+            }
+        }
+    }
 
-    counter!("universalis_alerts_xivapi_requests", 1);
+    let client = xivapi_client();
+    for chunk in missing.chunks(MAX_ITEMS_PER_REQUEST) {
+        let res = client
+            .get("https://xivapi.com/Item")
+            .query(&[
+                ("ids", chunk.iter().join(",").as_str()),
+                ("columns", "ID,Name"),
+            ])
+            .send()
+            .await
+            .count_timeout(Service::Xivapi)?;
+        counter!(XIVAPI_REQUESTS.name, 1);
 
-    Ok(item)
+        let response_text = res
+            .error_for_status()?
+            .text()
+            .await
+            .count_timeout(Service::Xivapi)?;
+        let rows: SearchResults<ItemRow> = serde_json::from_str(&response_text)?;
+        let mut fetched: HashMap<_, _> = rows.results.into_iter().map(|r| (r.id, r.item)).collect();
+
+        let mut cache = GET_ITEM.lock().await;
+        for &id in chunk {
+            let item = fetched.remove(&id);
+            if item.is_none() {
+                counter!(XIVAPI_UNKNOWN_ITEMS.name, 1, "reason" => "not_found");
+                warn!("XIVAPI does not know about item {}", id);
+            }
+            cache.cache_set(id, item.clone());
+            items.insert(id, item);
+        }
+    }
+    Ok(items)
 }
 
 #[cached(size = 500, time = 60, result = true)]
 pub async fn get_world(id: i32) -> Result<World> {
     let url = format!("https://xivapi.com/World/{}?columns=Name", id);
-    let client = reqwest::Client::new();
+    let client = xivapi_client();
 
-    let res = client.get(url).send().await?;
-    let response_text = res.text().await?;
+    let res = client
+        .get(url)
+        .send()
+        .await
+        .count_timeout(Service::Xivapi)?;
+    let response_text = res.text().await.count_timeout(Service::Xivapi)?;
     let world = serde_json::from_str(&response_text)?;
 
-    counter!("universalis_alerts_xivapi_requests", 1);
+    counter!(XIVAPI_REQUESTS.name, 1);
 
     Ok(world)
 }
+
+/// Fetches the categories an item belongs to, returning `None` if XIVAPI
+/// doesn't know about it. Categories only change with game patches, so these
+/// are cached for much longer than names.
+#[cached(size = 50000, time = 86400, result = true)]
+pub async fn get_item_categories(id: i32) -> Result<Option<ItemCategories>> {
+    let url = format!(
+        "https://xivapi.com/Item/{}?columns=ItemUICategory.ID,ItemSearchCategory.ID",
+        id
+    );
+    let client = xivapi_client();
+
+    let res = client
+        .get(url)
+        .send()
+        .await
+        .count_timeout(Service::Xivapi)?;
+    counter!(XIVAPI_REQUESTS.name, 1);
+
+    if res.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    let response_text = res
+        .error_for_status()?
+        .text()
+        .await
+        .count_timeout(Service::Xivapi)?;
+    let refs: ItemCategoryRefs = serde_json::from_str(&response_text)?;
+    Ok(Some(refs.into()))
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct ItemCategoryRow {
+    #[serde(rename = "ID")]
+    id: i32,
+    #[serde(flatten)]
+    refs: ItemCategoryRefs,
+}
+
+/// Fetches the categories of several items in as few requests as possible,
+/// in the same way as [`get_items`]. Categories that
+/// [`get_item_categories`] has cached aren't requested again, and the ones
+/// that are fetched are added to its cache.
+pub async fn get_items_categories(ids: &[i32]) -> Result<HashMap<i32, Option<ItemCategories>>> {
+    let mut categories = HashMap::with_capacity(ids.len());
+    let mut missing = Vec::new();
+    {
+        let mut cache = GET_ITEM_CATEGORIES.lock().await;
+        for &id in ids.iter().unique() {
+            match cache.cache_get(&id) {
+                Some(found) => {
+                    categories.insert(id, found.clone());
+                }
+                None => missing.push(id),
+            }
+        }
+    }
+
+    let client = xivapi_client();
+    for chunk in missing.chunks(MAX_ITEMS_PER_REQUEST) {
+        let res = client
+            .get("https://xivapi.com/Item")
+            .query(&[
+                ("ids", chunk.iter().join(",").as_str()),
+                ("columns", "ID,ItemUICategory.ID,ItemSearchCategory.ID"),
+            ])
+            .send()
+            .await
+            .count_timeout(Service::Xivapi)?;
+        counter!(XIVAPI_REQUESTS.name, 1);
+
+        let response_text = res
+            .error_for_status()?
+            .text()
+            .await
+            .count_timeout(Service::Xivapi)?;
+        let rows: SearchResults<ItemCategoryRow> = serde_json::from_str(&response_text)?;
+        let mut fetched: HashMap<_, _> = rows.results.into_iter().map(|r| (r.id, r.refs)).collect();
+
+        let mut cache = GET_ITEM_CATEGORIES.lock().await;
+        for &id in chunk {
+            let found = fetched.remove(&id).map(ItemCategories::from);
+            cache.cache_set(id, found.clone());
+            categories.insert(id, found);
+        }
+    }
+    Ok(categories)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct VendorPrice {
+    #[serde(rename = "PriceMid")]
+    price_mid: Option<i32>,
+}
+
+/// Fetches the price an item sells for at NPC vendors, returning `None` if
+/// the item isn't sold by vendors or XIVAPI doesn't know about it.
+#[cached(size = 5000, time = 86400, result = true)]
+pub async fn get_vendor_price(id: i32) -> Result<Option<i32>> {
+    let url = format!("https://xivapi.com/Item/{}?columns=PriceMid", id);
+    let client = xivapi_client();
+
+    let res = client
+        .get(url)
+        .send()
+        .await
+        .count_timeout(Service::Xivapi)?;
+    counter!(XIVAPI_REQUESTS.name, 1);
+
+    if res.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    let response_text = res
+        .error_for_status()?
+        .text()
+        .await
+        .count_timeout(Service::Xivapi)?;
+    let price: VendorPrice = serde_json::from_str(&response_text)?;
+    Ok(price.price_mid.filter(|p| *p > 0))
+}
+
+/// An item found by [`search_items`].
+#[derive(Deserialize, Debug, Clone)]
+pub struct ItemSearchResult {
+    #[serde(rename = "ID")]
+    pub id: i32,
+    #[serde(rename = "Name")]
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct SearchResults<T> {
+    #[serde(rename = "Results")]
+    results: Vec<T>,
+}
+
+/// Searches XIVAPI for items whose names contain `name`.
+pub async fn search_items(name: &str, client: &reqwest::Client) -> Result<Vec<ItemSearchResult>> {
+    let res = client
+        .get("https://xivapi.com/search")
+        .query(&[
+            ("indexes", "Item"),
+            ("string", name),
+            ("columns", "ID,Name"),
+        ])
+        .send()
+        .await
+        .count_timeout(Service::Xivapi)?;
+    counter!(XIVAPI_REQUESTS.name, 1);
+
+    let response_text = res
+        .error_for_status()?
+        .text()
+        .await
+        .count_timeout(Service::Xivapi)?;
+    let results: SearchResults<ItemSearchResult> = serde_json::from_str(&response_text)?;
+    Ok(results.results)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct DataCenterName {
+    #[serde(rename = "Name")]
+    name: String,
+}
+
+/// A world, as listed by [`get_worlds`].
+#[derive(Deserialize, Debug, Clone)]
+pub struct WorldSummary {
+    #[serde(rename = "ID")]
+    pub id: i32,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "IsPublic", default)]
+    pub is_public: bool,
+    #[serde(rename = "DataCenter", default)]
+    data_center: Option<DataCenterName>,
+}
+
+impl WorldSummary {
+    pub fn data_center(&self) -> Option<&str> {
+        self.data_center.as_ref().map(|dc| dc.name.as_str())
+    }
+}
+
+/// Fetches every public world.
+pub async fn get_worlds(client: &reqwest::Client) -> Result<Vec<WorldSummary>> {
+    let res = client
+        .get("https://xivapi.com/World")
+        .query(&[
+            ("columns", "ID,Name,IsPublic,DataCenter.Name"),
+            ("limit", "3000"),
+        ])
+        .send()
+        .await
+        .count_timeout(Service::Xivapi)?;
+    counter!(XIVAPI_REQUESTS.name, 1);
+
+    let response_text = res
+        .error_for_status()?
+        .text()
+        .await
+        .count_timeout(Service::Xivapi)?;
+    let results: SearchResults<WorldSummary> = serde_json::from_str(&response_text)?;
+    Ok(results
+        .results
+        .into_iter()
+        .filter(|world| world.is_public)
+        .collect())
+}